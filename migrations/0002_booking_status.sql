@@ -0,0 +1,15 @@
+-- booking lifecycle, bookings made before this existed were live reservations
+
+ALTER TABLE bookings ADD COLUMN status TEXT NOT NULL DEFAULT 'tentative';
+
+UPDATE bookings
+SET status = CASE WHEN check_out <= date('now') THEN 'checked_out' ELSE 'confirmed' END;
+
+CREATE TABLE booking_status_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    booking_id TEXT NOT NULL,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    changed_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY(booking_id) REFERENCES bookings(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- room occupancy is now derived from checked-in bookings, only housekeeping is stored
+
+ALTER TABLE rooms ADD COLUMN housekeeping TEXT NOT NULL DEFAULT 'clean';
+
+ALTER TABLE rooms DROP COLUMN status;
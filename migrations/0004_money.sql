@@ -0,0 +1,11 @@
+-- prices and payments become integer minor units with a currency, existing REAL amounts were euros
+
+ALTER TABLE rooms ADD COLUMN price_minor INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE rooms ADD COLUMN currency TEXT NOT NULL DEFAULT 'EUR';
+UPDATE rooms SET price_minor = CAST(ROUND(price * 100) AS INTEGER) WHERE price IS NOT NULL;
+ALTER TABLE rooms DROP COLUMN price;
+
+ALTER TABLE payments ADD COLUMN amount_minor INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE payments ADD COLUMN currency TEXT NOT NULL DEFAULT 'EUR';
+UPDATE payments SET amount_minor = CAST(ROUND(amount * 100) AS INTEGER) WHERE amount IS NOT NULL;
+ALTER TABLE payments DROP COLUMN amount;
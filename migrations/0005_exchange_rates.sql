@@ -0,0 +1,20 @@
+-- hotels get a base currency, taken from their rooms where they already have some
+
+ALTER TABLE hotels ADD COLUMN currency TEXT NOT NULL DEFAULT 'EUR';
+UPDATE hotels SET currency = COALESCE((SELECT currency FROM rooms WHERE rooms.hotel_id = hotels.id LIMIT 1), 'EUR');
+
+CREATE TABLE exchange_rates (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    valid_on DATE NOT NULL,
+    base TEXT NOT NULL,
+    quote TEXT NOT NULL,
+    rate TEXT NOT NULL,
+    UNIQUE(base, quote, valid_on)
+);
+
+-- payments keep amount_minor/currency in the booking currency and remember what was tendered
+ALTER TABLE payments ADD COLUMN tendered_minor INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE payments ADD COLUMN tendered_currency TEXT NOT NULL DEFAULT 'EUR';
+ALTER TABLE payments ADD COLUMN exchange_rate TEXT NOT NULL DEFAULT '1';
+ALTER TABLE payments ADD COLUMN paid_on DATE;
+UPDATE payments SET tendered_minor = amount_minor, tendered_currency = currency;
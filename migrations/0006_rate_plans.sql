@@ -0,0 +1,45 @@
+-- rate plans with seasonal prices per room type, and the nightly price breakdown of each booking
+
+CREATE TABLE rate_plans (
+    id TEXT PRIMARY KEY,
+    hotel_id TEXT NOT NULL,
+    code TEXT NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    UNIQUE(hotel_id, code),
+    FOREIGN KEY(hotel_id) REFERENCES hotels(id)
+);
+
+-- end_date is exclusive like check_out, weekend prices apply to Friday and Saturday nights
+CREATE TABLE rate_seasons (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    rate_plan_id TEXT NOT NULL,
+    room_type TEXT NOT NULL,
+    start_date DATE NOT NULL,
+    end_date DATE NOT NULL,
+    weekday_minor INTEGER NOT NULL,
+    weekend_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    min_stay INTEGER NOT NULL DEFAULT 1,
+    closed_to_arrival INTEGER NOT NULL DEFAULT 0,
+    closed_to_departure INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY(rate_plan_id) REFERENCES rate_plans(id) ON DELETE CASCADE
+);
+
+CREATE INDEX rate_seasons_lookup ON rate_seasons(rate_plan_id, room_type, start_date);
+
+ALTER TABLE bookings ADD COLUMN rate_plan_id TEXT REFERENCES rate_plans(id);
+ALTER TABLE bookings ADD COLUMN total_minor INTEGER;
+ALTER TABLE bookings ADD COLUMN currency TEXT;
+
+-- rate_season_id is informational only, seasons can be edited after the booking was priced
+CREATE TABLE booking_nights (
+    booking_id TEXT NOT NULL,
+    night DATE NOT NULL,
+    price_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    weekend INTEGER NOT NULL,
+    rate_season_id INTEGER,
+    PRIMARY KEY(booking_id, night),
+    FOREIGN KEY(booking_id) REFERENCES bookings(id) ON DELETE CASCADE
+);
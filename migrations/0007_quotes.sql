@@ -0,0 +1,35 @@
+-- priced offers shown to a guest before booking, honoured by create_booking until they expire
+
+CREATE TABLE quotes (
+    id TEXT PRIMARY KEY,
+    hotel_id TEXT NOT NULL,
+    room_type TEXT NOT NULL,
+    check_in DATE NOT NULL,
+    check_out DATE NOT NULL,
+    guests INTEGER NOT NULL,
+    rate_plan_id TEXT,
+    subtotal_minor INTEGER NOT NULL,
+    taxes_minor INTEGER NOT NULL,
+    fees_minor INTEGER NOT NULL,
+    total_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    expires_at DATETIME NOT NULL,
+    FOREIGN KEY(hotel_id) REFERENCES hotels(id),
+    FOREIGN KEY(rate_plan_id) REFERENCES rate_plans(id)
+);
+
+CREATE TABLE quote_nights (
+    quote_id TEXT NOT NULL,
+    night DATE NOT NULL,
+    price_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    weekend INTEGER NOT NULL,
+    rate_season_id INTEGER,
+    PRIMARY KEY(quote_id, night),
+    FOREIGN KEY(quote_id) REFERENCES quotes(id) ON DELETE CASCADE
+);
+
+-- a quote can be turned into one booking only
+ALTER TABLE bookings ADD COLUMN quote_id TEXT REFERENCES quotes(id);
+CREATE UNIQUE INDEX bookings_quote_id ON bookings(quote_id) WHERE quote_id IS NOT NULL;
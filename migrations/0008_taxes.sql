@@ -0,0 +1,58 @@
+-- tax and fee rules per hotel, and the charge lines they add to quotes and bookings
+
+-- percent rules take a share of the room subtotal, the others charge amount_minor per night
+-- or per guest and night, for at most max_nights nights when set
+CREATE TABLE tax_rules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    hotel_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    category TEXT NOT NULL,
+    basis TEXT NOT NULL,
+    percent TEXT,
+    amount_minor INTEGER,
+    currency TEXT,
+    max_nights INTEGER,
+    -- JSON array of guest types the rule is not charged to
+    exempt_guest_types TEXT NOT NULL DEFAULT '[]',
+    FOREIGN KEY(hotel_id) REFERENCES hotels(id)
+);
+
+CREATE INDEX tax_rules_hotel ON tax_rules(hotel_id);
+
+ALTER TABLE guests ADD COLUMN guest_type TEXT NOT NULL DEFAULT 'standard';
+ALTER TABLE bookings ADD COLUMN guests INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE quotes ADD COLUMN guest_type TEXT NOT NULL DEFAULT 'standard';
+
+-- tax_rule_id is informational only, rules can be edited after the stay was priced;
+-- name, rate and amount are what was charged and what gets filed
+CREATE TABLE booking_charges (
+    booking_id TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    tax_rule_id INTEGER,
+    name TEXT NOT NULL,
+    category TEXT NOT NULL,
+    basis TEXT NOT NULL,
+    percent TEXT,
+    unit_minor INTEGER,
+    quantity INTEGER,
+    amount_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    PRIMARY KEY(booking_id, line),
+    FOREIGN KEY(booking_id) REFERENCES bookings(id) ON DELETE CASCADE
+);
+
+CREATE TABLE quote_charges (
+    quote_id TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    tax_rule_id INTEGER,
+    name TEXT NOT NULL,
+    category TEXT NOT NULL,
+    basis TEXT NOT NULL,
+    percent TEXT,
+    unit_minor INTEGER,
+    quantity INTEGER,
+    amount_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    PRIMARY KEY(quote_id, line),
+    FOREIGN KEY(quote_id) REFERENCES quotes(id) ON DELETE CASCADE
+);
@@ -0,0 +1,49 @@
+-- promo codes, and the discounts they gave to quotes and bookings
+
+-- codes are stored upper case; hotel_ids and room_types are JSON arrays, empty for all of them;
+-- valid_until is exclusive like end_date, both are compared with the day the code is used
+CREATE TABLE promotions (
+    id TEXT PRIMARY KEY,
+    code TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    percent TEXT,
+    amount_minor INTEGER,
+    currency TEXT,
+    valid_from DATE NOT NULL,
+    valid_until DATE NOT NULL,
+    hotel_ids TEXT NOT NULL DEFAULT '[]',
+    room_types TEXT NOT NULL DEFAULT '[]',
+    min_nights INTEGER NOT NULL DEFAULT 1,
+    max_uses INTEGER,
+    max_uses_per_guest INTEGER,
+    stackable INTEGER NOT NULL DEFAULT 0
+);
+
+-- a promotion with redemptions cannot be deleted, only expired
+CREATE TABLE promotion_redemptions (
+    promotion_id TEXT NOT NULL,
+    booking_id TEXT NOT NULL,
+    code TEXT NOT NULL,
+    discount_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    redeemed_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY(promotion_id, booking_id),
+    FOREIGN KEY(promotion_id) REFERENCES promotions(id),
+    FOREIGN KEY(booking_id) REFERENCES bookings(id) ON DELETE CASCADE
+);
+
+CREATE INDEX promotion_redemptions_booking ON promotion_redemptions(booking_id);
+
+ALTER TABLE quotes ADD COLUMN discount_minor INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE quote_promotions (
+    quote_id TEXT NOT NULL,
+    promotion_id TEXT NOT NULL,
+    code TEXT NOT NULL,
+    discount_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    PRIMARY KEY(quote_id, promotion_id),
+    FOREIGN KEY(quote_id) REFERENCES quotes(id) ON DELETE CASCADE,
+    FOREIGN KEY(promotion_id) REFERENCES promotions(id)
+);
@@ -0,0 +1,60 @@
+-- guest folios: an append-only ledger per booking, charges are positive and payments negative,
+-- so the balance owed is always the sum of the entries
+
+CREATE TABLE folio_entries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    booking_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    description TEXT NOT NULL,
+    amount_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    posted_on DATE NOT NULL DEFAULT (date('now')),
+    posted_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    -- informational like rate_season_id, the payment row itself can be removed
+    payment_id TEXT,
+    reverses_id INTEGER,
+    FOREIGN KEY(booking_id) REFERENCES bookings(id),
+    FOREIGN KEY(reverses_id) REFERENCES folio_entries(id)
+);
+
+CREATE INDEX folio_entries_booking ON folio_entries(booking_id);
+CREATE INDEX folio_entries_payment ON folio_entries(payment_id) WHERE payment_id IS NOT NULL;
+-- an entry is reversed at most once
+CREATE UNIQUE INDEX folio_entries_reverses ON folio_entries(reverses_id) WHERE reverses_id IS NOT NULL;
+
+CREATE TRIGGER folio_entries_no_update BEFORE UPDATE ON folio_entries
+BEGIN
+    SELECT RAISE(ABORT, 'folio entries cannot be changed, post a reversal instead');
+END;
+
+CREATE TRIGGER folio_entries_no_delete BEFORE DELETE ON folio_entries
+BEGIN
+    SELECT RAISE(ABORT, 'folio entries cannot be deleted, post a reversal instead');
+END;
+
+-- stays already checked in are charged what was stored for them
+INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency, posted_on)
+SELECT n.booking_id, 'room', 'Room night ' || n.night, n.price_minor, n.currency, b.check_in
+FROM booking_nights n
+JOIN bookings b ON b.id = n.booking_id
+WHERE b.status IN ('checked_in', 'checked_out')
+ORDER BY n.booking_id, n.night;
+
+INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency, posted_on)
+SELECT r.booking_id, 'discount', 'Promo code ' || r.code, -r.discount_minor, r.currency, b.check_in
+FROM promotion_redemptions r
+JOIN bookings b ON b.id = r.booking_id
+WHERE b.status IN ('checked_in', 'checked_out');
+
+INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency, posted_on)
+SELECT c.booking_id, c.category, c.name, c.amount_minor, c.currency, b.check_in
+FROM booking_charges c
+JOIN bookings b ON b.id = c.booking_id
+WHERE b.status IN ('checked_in', 'checked_out')
+ORDER BY c.booking_id, c.line;
+
+INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency, posted_on, payment_id)
+SELECT p.booking_id, 'payment', 'Payment by ' || COALESCE(p.method, 'unknown method'), -p.amount_minor, p.currency,
+       COALESCE(p.paid_on, date('now')), p.id
+FROM payments p
+JOIN bookings b ON b.id = p.booking_id;
@@ -0,0 +1,21 @@
+-- payments are never deleted: they are refunded, possibly in parts, or voided on the day they were taken
+
+ALTER TABLE payments ADD COLUMN status TEXT NOT NULL DEFAULT 'captured';
+ALTER TABLE payments ADD COLUMN void_reason TEXT;
+ALTER TABLE payments ADD COLUMN voided_at DATETIME;
+
+-- amount_minor/currency are in the tendered currency, settled_minor/settled_currency in the booking's,
+-- converted at the rate of the original payment
+CREATE TABLE payment_refunds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    payment_id TEXT NOT NULL,
+    amount_minor INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    settled_minor INTEGER NOT NULL,
+    settled_currency TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    refunded_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY(payment_id) REFERENCES payments(id)
+);
+
+CREATE INDEX payment_refunds_payment ON payment_refunds(payment_id);
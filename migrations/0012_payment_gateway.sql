@@ -0,0 +1,22 @@
+-- card payments go through a payment gateway: authorized first, captured later, refunded and voided through it
+
+ALTER TABLE payments ADD COLUMN gateway TEXT;
+ALTER TABLE payments ADD COLUMN gateway_reference TEXT;
+ALTER TABLE payments ADD COLUMN card_last4 TEXT;
+
+CREATE UNIQUE INDEX payments_gateway_reference ON payments(gateway, gateway_reference) WHERE gateway_reference IS NOT NULL;
+
+-- webhook events received from a gateway, kept once per provider event id so redeliveries are ignored
+CREATE TABLE gateway_events (
+    provider TEXT NOT NULL,
+    id TEXT NOT NULL,
+    payment_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    amount_minor INTEGER,
+    currency TEXT,
+    received_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (provider, id),
+    FOREIGN KEY(payment_id) REFERENCES payments(id)
+);
+
+CREATE INDEX gateway_events_payment ON gateway_events(payment_id);
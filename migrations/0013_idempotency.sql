@@ -0,0 +1,15 @@
+-- responses of POST requests sent with an Idempotency-Key header, replayed when a client retries with the same key
+
+CREATE TABLE idempotency_keys (
+    key TEXT PRIMARY KEY,
+    -- sha1 of method, path and body, a retry must send the same request
+    fingerprint TEXT NOT NULL,
+    -- NULL while the first request is still being handled
+    status INTEGER,
+    content_type TEXT,
+    body BLOB,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    completed_at DATETIME
+);
+
+CREATE INDEX idempotency_keys_created ON idempotency_keys(created_at);
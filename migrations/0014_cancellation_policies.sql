@@ -0,0 +1,32 @@
+-- named cancellation policies attached to hotels and rate plans; bookings and quotes keep a copy of the
+-- policy they were sold with, later edits of a policy never change what a guest agreed to
+
+CREATE TABLE cancellation_policies (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    -- cancelling at least this many days before check-in is free, NULL when it never is
+    free_until_days INTEGER,
+    -- first_night, percent or full_stay
+    penalty TEXT NOT NULL,
+    percent TEXT
+);
+
+ALTER TABLE hotels ADD COLUMN cancellation_policy_id TEXT REFERENCES cancellation_policies(id);
+ALTER TABLE rate_plans ADD COLUMN cancellation_policy_id TEXT REFERENCES cancellation_policies(id);
+
+-- JSON copy of the policy
+ALTER TABLE bookings ADD COLUMN cancellation_policy TEXT;
+ALTER TABLE quotes ADD COLUMN cancellation_policy TEXT;
+
+-- what bookings and quotes promised so far: free until check-in, except on non-refundable rate plans
+UPDATE bookings SET cancellation_policy = CASE
+    WHEN rate_plan_id IN (SELECT id FROM rate_plans WHERE kind = 'non_refundable')
+        THEN '{"id":null,"name":"Non-refundable","free_until_days":null,"penalty":"full_stay","percent":null}'
+    ELSE '{"id":null,"name":"Free cancellation","free_until_days":0,"penalty":"first_night","percent":null}'
+END;
+
+UPDATE quotes SET cancellation_policy = CASE
+    WHEN rate_plan_id IN (SELECT id FROM rate_plans WHERE kind = 'non_refundable')
+        THEN '{"id":null,"name":"Non-refundable","free_until_days":null,"penalty":"full_stay","percent":null}'
+    ELSE '{"id":null,"name":"Free cancellation","free_until_days":0,"penalty":"first_night","percent":null}'
+END;
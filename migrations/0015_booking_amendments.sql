@@ -0,0 +1,19 @@
+-- every change of a booking's room, dates or guests, with what the booking looked like before and after it
+
+CREATE TABLE booking_amendments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    booking_id TEXT NOT NULL,
+    -- JSON copies of room, dates, guests, rate plan, nights and total
+    before TEXT NOT NULL,
+    after TEXT NOT NULL,
+    -- current or original, which prices the nights of the changed stay were given
+    rates TEXT NOT NULL,
+    -- new total less the old one, NULL when the booking had no stored total before
+    difference_minor INTEGER,
+    currency TEXT,
+    reason TEXT,
+    amended_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY(booking_id) REFERENCES bookings(id) ON DELETE CASCADE
+);
+
+CREATE INDEX booking_amendments_booking ON booking_amendments(booking_id);
@@ -0,0 +1,17 @@
+-- a reservation groups the room-stays of a family or tour group under one billing guest;
+-- every room-stay is a booking of its own with its occupant, room, dates, prices and folio
+
+CREATE TABLE reservations (
+    id TEXT PRIMARY KEY,
+    hotel_id TEXT NOT NULL,
+    billing_guest_id TEXT NOT NULL,
+    -- group or party name shown to staff
+    name TEXT,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY(hotel_id) REFERENCES hotels(id),
+    FOREIGN KEY(billing_guest_id) REFERENCES guests(id)
+);
+
+ALTER TABLE bookings ADD COLUMN reservation_id TEXT REFERENCES reservations(id);
+
+CREATE INDEX bookings_reservation ON bookings(reservation_id);
@@ -0,0 +1,29 @@
+-- room blocks hold a number of rooms of one type for an event or tour operator before guests are known;
+-- bookings made with the block's code draw it down, whatever is not picked up goes back on sale at the cutoff
+
+CREATE TABLE room_blocks (
+    id TEXT PRIMARY KEY,
+    hotel_id TEXT NOT NULL,
+    room_type TEXT NOT NULL,
+    -- upper case, what bookings quote to draw on the block
+    code TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- nights held, end_date excluded like a check-out
+    start_date TEXT NOT NULL,
+    end_date TEXT NOT NULL,
+    -- rooms held each night
+    rooms INTEGER NOT NULL,
+    -- unpicked rooms are released from this day on
+    cutoff_date TEXT NOT NULL,
+    -- set when the block is released by hand before its cutoff
+    released_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY(hotel_id) REFERENCES hotels(id),
+    UNIQUE(hotel_id, code)
+);
+
+CREATE INDEX room_blocks_inventory ON room_blocks(hotel_id, room_type, start_date);
+
+ALTER TABLE bookings ADD COLUMN block_id TEXT REFERENCES room_blocks(id);
+
+CREATE INDEX bookings_block ON bookings(block_id);
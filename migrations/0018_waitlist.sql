@@ -0,0 +1,39 @@
+-- guests waiting for a sold-out room type; when a room frees up the first eligible guest is offered it and
+-- the room is held for them until the offer expires, then it passes to the next guest
+
+CREATE TABLE waitlist_entries (
+    id TEXT PRIMARY KEY,
+    hotel_id TEXT NOT NULL,
+    guest_id TEXT NOT NULL,
+    room_type TEXT NOT NULL,
+    check_in TEXT NOT NULL,
+    check_out TEXT NOT NULL,
+    guests INTEGER NOT NULL,
+    -- waiting, offered, booked, declined, expired or withdrawn
+    status TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY(hotel_id) REFERENCES hotels(id),
+    FOREIGN KEY(guest_id) REFERENCES guests(id)
+);
+
+CREATE INDEX waitlist_queue ON waitlist_entries(hotel_id, room_type, status, created_at);
+
+-- every offer made, kept after it is answered or expires
+CREATE TABLE waitlist_offers (
+    id TEXT PRIMARY KEY,
+    entry_id TEXT NOT NULL,
+    room_id TEXT NOT NULL,
+    offered_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    expires_at DATETIME NOT NULL,
+    -- pending, accepted, declined, expired or withdrawn
+    status TEXT NOT NULL,
+    closed_at DATETIME,
+    -- booking made when the guest accepted
+    booking_id TEXT,
+    FOREIGN KEY(entry_id) REFERENCES waitlist_entries(id),
+    FOREIGN KEY(room_id) REFERENCES rooms(id),
+    FOREIGN KEY(booking_id) REFERENCES bookings(id)
+);
+
+CREATE INDEX waitlist_offers_entry ON waitlist_offers(entry_id);
+CREATE INDEX waitlist_offers_pending ON waitlist_offers(status, expires_at);
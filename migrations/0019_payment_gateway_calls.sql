@@ -0,0 +1,6 @@
+-- gateway calls are made outside database transactions: a payment records the call in flight so no other request
+-- acts on it meanwhile, and a call a crash left behind is taken over once it is a few minutes old.
+-- card payments are 'pending' until the gateway authorized them
+
+ALTER TABLE payments ADD COLUMN gateway_call TEXT;
+ALTER TABLE payments ADD COLUMN gateway_call_at DATETIME;
@@ -0,0 +1,29 @@
+-- idempotency keys belong to the method and path they were sent to, so two endpoints never share one; a claim
+-- whose request never finished (the server stopped while handling it) is taken over once claimed_at is old.
+-- responses kept before keys were scoped are replayed for any path until they expire, unfinished claims are dropped
+
+CREATE TABLE idempotency_keys_scoped (
+    -- method and path, e.g. 'POST /payments'; '' for responses kept before keys were scoped
+    scope TEXT NOT NULL,
+    key TEXT NOT NULL,
+    -- sha1 of method, path and body, a retry must send the same request
+    fingerprint TEXT NOT NULL,
+    -- NULL while the first request is still being handled
+    status INTEGER,
+    content_type TEXT,
+    body BLOB,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    -- when the request currently handling the key took it
+    claimed_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    completed_at DATETIME,
+    PRIMARY KEY (scope, key)
+);
+
+INSERT INTO idempotency_keys_scoped (scope, key, fingerprint, status, content_type, body, created_at, claimed_at, completed_at)
+SELECT '', key, fingerprint, status, content_type, body, created_at, created_at, completed_at
+FROM idempotency_keys WHERE status IS NOT NULL;
+
+DROP TABLE idempotency_keys;
+ALTER TABLE idempotency_keys_scoped RENAME TO idempotency_keys;
+
+CREATE INDEX idempotency_keys_created ON idempotency_keys(created_at);
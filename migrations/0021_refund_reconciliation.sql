@@ -0,0 +1,9 @@
+-- a refund through a gateway is stored as pending before the gateway is asked, under a key the gateway recognises it
+-- by, so a refund whose answer was lost is asked for again under that key and never made twice.
+-- a void the gateway carried out but that could not be recorded is marked on its payment until it is
+
+ALTER TABLE payment_refunds ADD COLUMN status TEXT NOT NULL DEFAULT 'completed';
+ALTER TABLE payment_refunds ADD COLUMN gateway_key TEXT;
+ALTER TABLE payment_refunds ADD COLUMN gateway_reference TEXT;
+
+ALTER TABLE payments ADD COLUMN gateway_call_done INTEGER NOT NULL DEFAULT 0;
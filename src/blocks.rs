@@ -0,0 +1,336 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::error::ApiError;
+use crate::models::{RoomBlock, RoomBlockStatus};
+
+//a block is released once staff let it go or its cutoff day has come
+pub const BLOCK_SELECT: &str = "
+    SELECT id, hotel_id, room_type, code, name, start_date, end_date, rooms, cutoff_date,
+           (SELECT COUNT(*) FROM bookings b
+            WHERE b.block_id = room_blocks.id AND b.status NOT IN ('cancelled', 'no_show')) AS picked_up,
+           CASE WHEN released_at IS NOT NULL OR cutoff_date <= date('now') THEN 'released' ELSE 'open' END AS status,
+           COALESCE(released_at, CASE WHEN cutoff_date <= date('now') THEN cutoff_date END) AS released_at
+    FROM room_blocks
+";
+
+pub fn block_from_row(row: &rusqlite::Row) -> rusqlite::Result<RoomBlock> {
+    Ok(RoomBlock {
+        id: Some(row.get(0)?),
+        hotel_id: row.get(1)?,
+        room_type: row.get(2)?,
+        code: row.get(3)?,
+        name: row.get(4)?,
+        start_date: row.get(5)?,
+        end_date: row.get(6)?,
+        rooms: row.get(7)?,
+        cutoff_date: row.get(8)?,
+        picked_up: row.get(9)?,
+        status: row.get(10)?,
+        released_at: row.get(11)?,
+    })
+}
+
+//one night of a room type's inventory
+#[derive(Serialize)]
+pub struct NightInventory {
+    pub night: String,
+    //rooms in service and not booked
+    pub free: i64,
+    //rooms open blocks still hold for guests who have not booked yet, and rooms offered to waitlisted guests
+    pub held: i64,
+    //bookings made on the block asked about
+    pub picked: i64,
+}
+
+impl NightInventory {
+    //rooms anyone can book
+    pub fn public(&self) -> i64 {
+        (self.free - self.held).max(0)
+    }
+}
+
+//inventory of a room type for each night of [start, end); `exclude_booking` is left out of every count so a
+//booking being changed does not stand in its own way, `block_id` is left out of the holds and counted in `picked`
+pub fn nights(
+    conn: &Connection,
+    hotel_id: &str,
+    room_type: &str,
+    start: &str,
+    end: &str,
+    exclude_booking: Option<&str>,
+    block_id: Option<&str>,
+) -> rusqlite::Result<Vec<NightInventory>> {
+    let mut stmt = conn.prepare(
+        "WITH RECURSIVE nights(night) AS (
+             SELECT date(?3)
+             UNION ALL
+             SELECT date(night, '+1 day') FROM nights WHERE date(night, '+1 day') < ?4
+         )
+         SELECT night,
+                (SELECT COUNT(*) FROM rooms r
+                 WHERE r.hotel_id = ?1 AND r.room_type = ?2 AND r.housekeeping != 'out_of_order'
+                   AND NOT EXISTS (
+                        SELECT 1 FROM bookings b
+                        WHERE b.room_id = r.id AND b.check_in <= night AND b.check_out > night
+                          AND b.status NOT IN ('cancelled', 'no_show') AND (?5 IS NULL OR b.id != ?5)
+                   )),
+                (SELECT COALESCE(SUM(MAX(k.rooms - (
+                            SELECT COUNT(*) FROM bookings b
+                            WHERE b.block_id = k.id AND b.check_in <= night AND b.check_out > night
+                              AND b.status NOT IN ('cancelled', 'no_show') AND (?5 IS NULL OR b.id != ?5)
+                        ), 0)), 0)
+                 FROM room_blocks k
+                 WHERE k.hotel_id = ?1 AND k.room_type = ?2 AND k.start_date <= night AND k.end_date > night
+                   AND k.released_at IS NULL AND k.cutoff_date > date('now') AND (?6 IS NULL OR k.id != ?6))
+                + (SELECT COUNT(*) FROM waitlist_offers o JOIN waitlist_entries w ON w.id = o.entry_id
+                   WHERE w.hotel_id = ?1 AND w.room_type = ?2 AND w.check_in <= night AND w.check_out > night
+                     AND o.status = 'pending' AND o.expires_at > datetime('now')),
+                (SELECT COUNT(*) FROM bookings b
+                 WHERE b.block_id = ?6 AND b.check_in <= night AND b.check_out > night
+                   AND b.status NOT IN ('cancelled', 'no_show') AND (?5 IS NULL OR b.id != ?5))
+         FROM nights",
+    )?;
+    let nights = stmt.query_map((hotel_id, room_type, start, end, exclude_booking, block_id), |row| {
+        Ok(NightInventory { night: row.get(0)?, free: row.get(1)?, held: row.get(2)?, picked: row.get(3)? })
+    })?;
+    nights.collect()
+}
+
+//rooms of a type anyone can book for every night of a stay
+pub fn public_rooms(conn: &Connection, hotel_id: &str, room_type: &str, check_in: &str, check_out: &str) -> rusqlite::Result<i64> {
+    let nights = nights(conn, hotel_id, room_type, check_in, check_out, None, None)?;
+    Ok(nights.iter().map(NightInventory::public).min().unwrap_or(0))
+}
+
+//id of the block a new booking quotes, codes are matched like promo codes
+pub fn block_for_code(conn: &Connection, hotel_id: &str, code: &str) -> Result<String, ApiError> {
+    conn.query_row(
+        "SELECT id FROM room_blocks WHERE hotel_id = ?1 AND code = ?2",
+        (hotel_id, crate::promotions::normalize_code(code)),
+        |row| row.get(0),
+    ).optional()?.ok_or(ApiError::NotFound("room block"))
+}
+
+//checks a stay has a room to take: a stay on a block needs the block to have a room left on each of its nights,
+//any other stay a room of the type that no block or waitlist offer holds
+pub fn check_inventory(
+    conn: &Connection,
+    room_id: &str,
+    check_in: &str,
+    check_out: &str,
+    block_id: Option<&str>,
+    exclude_booking: Option<&str>,
+) -> Result<(), ApiError> {
+    let (hotel_id, room_type): (String, String) = conn.query_row(
+        "SELECT hotel_id, room_type FROM rooms WHERE id = ?1",
+        [room_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.ok_or(ApiError::NotFound("room"))?;
+
+    let Some(block_id) = block_id else {
+        let nights = nights(conn, &hotel_id, &room_type, check_in, check_out, exclude_booking, None)?;
+        let short: Vec<&str> = nights.iter().filter(|n| n.public() < 1).map(|n| n.night.as_str()).collect();
+        if !short.is_empty() {
+            return Err(ApiError::conflict_with(
+                "rooms_held",
+                "the rooms of this type left on these nights are held for room blocks or waitlisted guests",
+                json!({"room_type": room_type, "nights": short}),
+            ));
+        }
+        return Ok(());
+    };
+
+    let block = conn.query_row(&format!("{BLOCK_SELECT} WHERE id = ?1"), [block_id], block_from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("room block"))?;
+    if block.status == RoomBlockStatus::Released {
+        return Err(ApiError::conflict_with(
+            "block_released",
+            "the room block has been released",
+            json!({"code": block.code, "released_at": block.released_at}),
+        ));
+    }
+    if block.room_type != room_type {
+        return Err(ApiError::invalid("room_id", format!("block {} holds {} rooms", block.code, block.room_type)));
+    }
+    if check_in < block.start_date.as_str() || check_out > block.end_date.as_str() {
+        return Err(ApiError::conflict_with(
+            "outside_block",
+            "the stay falls outside the nights the room block holds",
+            json!({"code": block.code, "start_date": block.start_date, "end_date": block.end_date}),
+        ));
+    }
+
+    let nights = nights(conn, &hotel_id, &room_type, check_in, check_out, exclude_booking, Some(block_id))?;
+    let full: Vec<&str> = nights.iter().filter(|n| n.picked >= block.rooms).map(|n| n.night.as_str()).collect();
+    if !full.is_empty() {
+        return Err(ApiError::conflict_with(
+            "block_full",
+            "every room of the block is picked up on these nights",
+            json!({"code": block.code, "rooms": block.rooms, "nights": full}),
+        ));
+    }
+    Ok(())
+}
+
+//checks a block can hold its rooms on every night next to the bookings and the other blocks
+pub fn check_capacity(conn: &Connection, block_id: &str, block: &RoomBlock) -> Result<(), ApiError> {
+    let nights = nights(conn, &block.hotel_id, &block.room_type, &block.start_date, &block.end_date, None, Some(block_id))?;
+
+    if let Some(night) = nights.iter().find(|n| n.picked > block.rooms) {
+        return Err(ApiError::invalid(
+            "rooms",
+            format!("{} rooms are already picked up on {}", night.picked, night.night),
+        ));
+    }
+    let short: Vec<_> = nights
+        .iter()
+        .filter(|n| n.public() < block.rooms - n.picked)
+        .map(|n| json!({"night": n.night, "available": n.public()}))
+        .collect();
+    if !short.is_empty() {
+        return Err(ApiError::conflict_with(
+            "not_enough_rooms",
+            "the hotel does not have enough rooms of this type left to hold",
+            json!({"room_type": block.room_type, "rooms": block.rooms, "nights": short}),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    //the test hotel; block k1 holds two doubles for the nights 10 to 13 days from now until 5 days from now
+    fn hotel() -> Connection {
+        let conn = db::test_hotel();
+        conn.execute_batch(
+            "INSERT INTO room_blocks (id, hotel_id, room_type, code, name, start_date, end_date, rooms, cutoff_date)
+             VALUES ('k1', 'h1', 'double', 'FAIR', 'Fair', date('now', '+10 days'), date('now', '+13 days'), 2,
+                     date('now', '+5 days'));",
+        ).unwrap();
+        conn
+    }
+
+    //the date `days` from today
+    fn day(conn: &Connection, days: i64) -> String {
+        conn.query_row("SELECT date('now', ?1)", [format!("{days:+} days")], |row| row.get(0)).unwrap()
+    }
+
+    fn book(conn: &Connection, id: &str, room_id: &str, check_in: i64, check_out: i64, block_id: Option<&str>) {
+        conn.execute(
+            "INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, status, block_id)
+             VALUES (?1, 'g1', ?2, 'h1', ?3, ?4, 'confirmed', ?5)",
+            (id, room_id, day(conn, check_in), day(conn, check_out), block_id),
+        ).unwrap();
+    }
+
+    fn inventory(conn: &Connection, block_id: Option<&str>) -> Vec<(i64, i64, i64, i64)> {
+        nights(conn, "h1", "double", &day(conn, 9), &day(conn, 14), None, block_id)
+            .unwrap()
+            .iter()
+            .map(|n| (n.free, n.held, n.picked, n.public()))
+            .collect()
+    }
+
+    fn check(conn: &Connection, room_id: &str, check_in: i64, check_out: i64, block_id: Option<&str>) -> Result<(), ApiError> {
+        check_inventory(conn, room_id, &day(conn, check_in), &day(conn, check_out), block_id, None)
+    }
+
+    fn block(conn: &Connection) -> RoomBlock {
+        conn.query_row(&format!("{BLOCK_SELECT} WHERE id = 'k1'"), [], block_from_row).unwrap()
+    }
+
+    #[test]
+    fn holds_the_rooms_of_a_block_until_they_are_picked_up() {
+        let conn = hotel();
+        assert_eq!(inventory(&conn, None), [(3, 0, 0, 3), (3, 2, 0, 1), (3, 2, 0, 1), (3, 2, 0, 1), (3, 0, 0, 3)]);
+
+        book(&conn, "b1", "r1", 10, 12, Some("k1"));
+        book(&conn, "b2", "r2", 12, 14, None);
+        assert_eq!(inventory(&conn, None), [(3, 0, 0, 3), (2, 1, 0, 1), (2, 1, 0, 1), (2, 2, 0, 0), (2, 0, 0, 2)]);
+        //asked about the block itself its holds are left out and its bookings counted
+        assert_eq!(inventory(&conn, Some("k1")), [(3, 0, 0, 3), (2, 0, 1, 2), (2, 0, 1, 2), (2, 0, 0, 2), (2, 0, 0, 2)]);
+        assert_eq!(public_rooms(&conn, "h1", "double", &day(&conn, 9), &day(&conn, 14)).unwrap(), 0);
+    }
+
+    #[test]
+    fn public_rooms_never_go_below_zero() {
+        let night = NightInventory { night: "2027-03-01".into(), free: 1, held: 2, picked: 0 };
+        assert_eq!(night.public(), 0);
+    }
+
+    #[test]
+    fn refuses_public_stays_on_nights_blocks_hold_the_rest_of() {
+        let conn = hotel();
+        assert!(check(&conn, "r1", 11, 12, None).is_ok());
+        book(&conn, "b1", "r1", 11, 12, None);
+        let Err(ApiError::Conflict { code: "rooms_held", details: Some(details), .. }) = check(&conn, "r2", 9, 13, None) else {
+            panic!("the held rooms were sold");
+        };
+        assert_eq!(details["nights"], json!([day(&conn, 11)]));
+        //the booking being changed does not stand in its own way
+        assert!(check_inventory(&conn, "r1", &day(&conn, 11), &day(&conn, 12), None, Some("b1")).is_ok());
+    }
+
+    #[test]
+    fn sells_a_block_until_every_room_is_picked_up() {
+        let conn = hotel();
+        book(&conn, "b1", "r1", 10, 13, None);
+        book(&conn, "b2", "r2", 10, 12, Some("k1"));
+        assert!(check(&conn, "r3", 10, 13, Some("k1")).is_ok());
+        book(&conn, "b3", "r3", 11, 13, Some("k1"));
+        let Err(ApiError::Conflict { code: "block_full", details: Some(details), .. }) = check(&conn, "r3", 10, 13, Some("k1")) else {
+            panic!("an extra room was picked up");
+        };
+        assert_eq!(details["nights"], json!([day(&conn, 11)]));
+    }
+
+    #[test]
+    fn refuses_stays_the_block_does_not_cover() {
+        let conn = hotel();
+        assert_eq!(check(&conn, "r1", 9, 12, Some("k1")).err().unwrap().code(), "outside_block");
+        assert_eq!(check(&conn, "r1", 12, 14, Some("k1")).err().unwrap().code(), "outside_block");
+        assert_eq!(check(&conn, "s1", 10, 12, Some("k1")).err().unwrap().code(), "validation_failed");
+    }
+
+    #[test]
+    fn gives_the_rooms_back_once_the_block_is_released() {
+        let conn = hotel();
+        conn.execute("UPDATE room_blocks SET released_at = datetime('now')", []).unwrap();
+        assert_eq!(check(&conn, "r1", 10, 12, Some("k1")).err().unwrap().code(), "block_released");
+        assert_eq!(inventory(&conn, None)[1], (3, 0, 0, 3));
+
+        //the cutoff releases it too
+        conn.execute("UPDATE room_blocks SET released_at = NULL, cutoff_date = date('now')", []).unwrap();
+        assert!(block(&conn).status == RoomBlockStatus::Released);
+        assert_eq!(check(&conn, "r1", 10, 12, Some("k1")).err().unwrap().code(), "block_released");
+        assert_eq!(inventory(&conn, None)[1], (3, 0, 0, 3));
+    }
+
+    #[test]
+    fn holds_a_block_only_when_the_rooms_are_there() {
+        let conn = hotel();
+        book(&conn, "b1", "r1", 12, 13, None);
+        assert!(check_capacity(&conn, "k1", &block(&conn)).is_ok());
+
+        book(&conn, "b2", "r2", 12, 13, None);
+        let Err(ApiError::Conflict { code: "not_enough_rooms", details: Some(details), .. }) =
+            check_capacity(&conn, "k1", &block(&conn))
+        else {
+            panic!("the block holds rooms the hotel does not have");
+        };
+        assert_eq!(details["nights"], json!([{"night": day(&conn, 12), "available": 1}]));
+
+        //picked up rooms already count for the block
+        conn.execute("UPDATE bookings SET block_id = 'k1' WHERE id = 'b2'", []).unwrap();
+        assert!(check_capacity(&conn, "k1", &block(&conn)).is_ok());
+        book(&conn, "b3", "r3", 12, 13, Some("k1"));
+        let shrunk = RoomBlock { rooms: 1, ..block(&conn) };
+        assert_eq!(check_capacity(&conn, "k1", &shrunk).err().unwrap().code(), "validation_failed");
+    }
+}
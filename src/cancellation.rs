@@ -0,0 +1,311 @@
+use rusqlite::types::Type;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::error::ApiError;
+use crate::folio;
+use crate::models::{CancellationPenalty, CancellationPolicy, FolioEntryKind, RatePlanKind};
+use crate::money::{Money, MoneyError};
+
+pub const POLICY_SELECT: &str = "SELECT id, name, free_until_days, penalty, percent FROM cancellation_policies";
+
+pub fn policy_from_row(row: &rusqlite::Row) -> rusqlite::Result<CancellationPolicy> {
+    Ok(with_summary(CancellationPolicy {
+        id: Some(row.get(0)?),
+        name: row.get(1)?,
+        free_until_days: row.get(2)?,
+        penalty: row.get(3)?,
+        percent: row.get(4)?,
+        summary: String::new(),
+    }))
+}
+
+//the terms of a policy in words, as shown to guests
+fn with_summary(mut policy: CancellationPolicy) -> CancellationPolicy {
+    let penalty = match (policy.penalty, policy.percent) {
+        (CancellationPenalty::FirstNight, _) => "the first night".to_string(),
+        (CancellationPenalty::Percent, Some(percent)) => format!("{percent}% of the total"),
+        (CancellationPenalty::Percent, None) | (CancellationPenalty::FullStay, _) => "the full amount".to_string(),
+    };
+    policy.summary = match policy.free_until_days {
+        None => format!("non-refundable, {penalty} is charged on cancellation"),
+        Some(0) => format!("free cancellation until check-in, then {penalty} is charged"),
+        Some(1) => format!("free cancellation until 1 day before check-in, then {penalty} is charged"),
+        Some(days) => format!("free cancellation until {days} days before check-in, then {penalty} is charged"),
+    };
+    policy
+}
+
+//policy of hotels and plans without one: free until check-in, non-refundable plans are never free
+fn built_in(kind: Option<RatePlanKind>) -> CancellationPolicy {
+    let policy = if kind == Some(RatePlanKind::NonRefundable) {
+        CancellationPolicy {
+            id: None,
+            name: "Non-refundable".to_string(),
+            free_until_days: None,
+            penalty: CancellationPenalty::FullStay,
+            percent: None,
+            summary: String::new(),
+        }
+    } else {
+        CancellationPolicy {
+            id: None,
+            name: "Free cancellation".to_string(),
+            free_until_days: Some(0),
+            penalty: CancellationPenalty::FirstNight,
+            percent: None,
+            summary: String::new(),
+        }
+    };
+    with_summary(policy)
+}
+
+//checks a policy a hotel or rate plan points at exists
+pub fn check_exists(conn: &Connection, policy_id: Option<&str>) -> Result<(), ApiError> {
+    let Some(policy_id) = policy_id else {
+        return Ok(());
+    };
+    conn.query_row("SELECT 1 FROM cancellation_policies WHERE id = ?1", [policy_id], |_| Ok(()))
+        .optional()?
+        .ok_or(ApiError::NotFound("cancellation policy"))
+}
+
+//policy a stay is sold with: the rate plan's, else the hotel's, else the built-in one
+pub fn policy_for(conn: &Connection, hotel_id: &str, rate_plan_id: Option<&str>) -> Result<CancellationPolicy, ApiError> {
+    let (plan_policy, kind): (Option<String>, Option<RatePlanKind>) = match rate_plan_id {
+        Some(plan_id) => conn.query_row(
+            "SELECT cancellation_policy_id, kind FROM rate_plans WHERE id = ?1",
+            [plan_id],
+            |row| Ok((row.get(0)?, Some(row.get(1)?))),
+        ).optional()?.ok_or(ApiError::NotFound("rate plan"))?,
+        None => (None, None),
+    };
+    let policy_id = match plan_policy {
+        Some(policy_id) => Some(policy_id),
+        None => conn.query_row("SELECT cancellation_policy_id FROM hotels WHERE id = ?1", [hotel_id], |row| row.get(0))
+            .optional()?
+            .flatten(),
+    };
+
+    match policy_id {
+        Some(policy_id) => Ok(conn.query_row(&format!("{POLICY_SELECT} WHERE id = ?1"), [policy_id], policy_from_row)?),
+        None => Ok(built_in(kind)),
+    }
+}
+
+//the copy of a policy bookings and quotes keep
+pub fn to_snapshot(policy: &CancellationPolicy) -> Result<String, ApiError> {
+    serde_json::to_string(policy).map_err(|err| ApiError::Internal(err.to_string()))
+}
+
+pub fn snapshot_column(row: &rusqlite::Row, index: usize) -> rusqlite::Result<CancellationPolicy> {
+    let text: String = row.get(index)?;
+    let policy = serde_json::from_str(&text)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(err)))?;
+    Ok(with_summary(policy))
+}
+
+//what cancelling a booking cost under its policy
+#[derive(Serialize)]
+pub struct CancellationCharge {
+    pub policy: CancellationPolicy,
+    //last moment the booking could be cancelled for free, none for non-refundable policies
+    pub free_until: Option<String>,
+    pub cancelled_at: String,
+    pub penalty: Money,
+}
+
+fn penalty(conn: &Connection, booking_id: &str, policy: &CancellationPolicy, total: Money) -> Result<Money, ApiError> {
+    let penalty = match policy.penalty {
+        CancellationPenalty::FirstNight => conn.query_row(
+            "SELECT price_minor, currency FROM booking_nights WHERE booking_id = ?1 ORDER BY night LIMIT 1",
+            [booking_id],
+            |row| Ok(Money::new(row.get(0)?, row.get(1)?)),
+        ).optional()?.unwrap_or(total),
+        CancellationPenalty::Percent => match policy.percent {
+            Some(percent) => total.percent(percent)?,
+            None => total,
+        },
+        CancellationPenalty::FullStay => total,
+    };
+    penalty.ensure_currency(total.currency)?;
+    //a discounted stay can cost less than its first night
+    Ok(if penalty.minor_units > total.minor_units { total } else { penalty })
+}
+
+//works out the penalty of cancelling a booking now from the policy it was sold with and its check-in,
+//nothing is charged
+pub fn quote_cancellation(conn: &Connection, booking_id: &str) -> Result<CancellationCharge, ApiError> {
+    let (check_in, total_minor, policy): (String, Option<i64>, CancellationPolicy) = conn.query_row(
+        "SELECT check_in, total_minor, cancellation_policy FROM bookings WHERE id = ?1",
+        [booking_id],
+        |row| Ok((row.get(0)?, row.get(1)?, snapshot_column(row, 2)?)),
+    ).optional()?.ok_or(ApiError::NotFound("booking"))?;
+
+    let (cancelled_at, free_until, free): (String, Option<String>, bool) = conn.query_row(
+        "SELECT datetime('now'), datetime(?1, '-' || ?2 || ' days'),
+                COALESCE(datetime('now') < datetime(?1, '-' || ?2 || ' days'), 0)",
+        (&check_in, policy.free_until_days),
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+
+    //bookings made before rate plans have no stored total and nothing to charge on
+    let currency = folio::folio_currency(conn, booking_id)?;
+    let total = Money::new(total_minor.unwrap_or(0), currency);
+    let penalty = if free { Money::zero(currency) } else { penalty(conn, booking_id, &policy, total)? };
+    Ok(CancellationCharge { policy, free_until, cancelled_at, penalty })
+}
+
+//`quote_cancellation`, with the penalty charged to the folio
+pub fn charge(conn: &Connection, booking_id: &str) -> Result<CancellationCharge, ApiError> {
+    let charge = quote_cancellation(conn, booking_id)?;
+    if charge.penalty.is_positive() {
+        let description = format!("Cancellation fee: {}", charge.policy.name);
+        folio::post(conn, booking_id, FolioEntryKind::CancellationFee, &description, charge.penalty, None)?;
+    }
+    Ok(charge)
+}
+
+//what the guest paid towards a booking, net of refunds
+pub fn paid(conn: &Connection, booking_id: &str) -> Result<Money, ApiError> {
+    let currency = folio::folio_currency(conn, booking_id)?;
+    let credited: i64 = conn.query_row(
+        "SELECT COALESCE(SUM(amount_minor), 0) FROM folio_entries WHERE booking_id = ?1 AND kind IN ('payment', 'refund')",
+        [booking_id],
+        |row| row.get(0),
+    )?;
+    Ok(Money::new(-credited, currency))
+}
+
+//paid money the penalty does not take, what can go back to the guest
+pub fn refundable(paid: Money, penalty: Money) -> Result<Money, MoneyError> {
+    let rest = paid.checked_sub(penalty)?;
+    Ok(if rest.is_positive() { rest } else { Money::zero(rest.currency) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, eur};
+
+    fn policy(free_until_days: Option<i64>, penalty: CancellationPenalty, percent: Option<&str>) -> CancellationPolicy {
+        with_summary(CancellationPolicy {
+            id: None,
+            name: "Test".to_string(),
+            free_until_days,
+            penalty,
+            percent: percent.map(|percent| percent.parse().unwrap()),
+            summary: String::new(),
+        })
+    }
+
+    //a booking checking in `days` from today for 120.00, 90.00 and 90.00 a night, sold at `total_minor`
+    fn booking(conn: &Connection, days: i64, total_minor: Option<i64>, policy: &CancellationPolicy) -> &'static str {
+        conn.execute(
+            "INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, status, total_minor, currency,
+                                   cancellation_policy)
+             VALUES ('b1', 'g1', 'r1', 'h1', date('now', ?1), date('now', ?2), 'confirmed', ?3, 'EUR', ?4)",
+            (format!("+{days} days"), format!("+{} days", days + 3), total_minor, to_snapshot(policy).unwrap()),
+        ).unwrap();
+        conn.execute(
+            "INSERT INTO booking_nights (booking_id, night, price_minor, currency, weekend)
+             SELECT 'b1', date(check_in, '+' || n.value || ' days'), n.price, 'EUR', 0
+             FROM bookings, (SELECT 0 AS value, 12000 AS price UNION ALL SELECT 1, 9000 UNION ALL SELECT 2, 9000) n
+             WHERE id = 'b1'",
+            [],
+        ).unwrap();
+        "b1"
+    }
+
+    fn fees(conn: &Connection) -> Vec<i64> {
+        let mut stmt = conn.prepare("SELECT amount_minor FROM folio_entries WHERE kind = 'cancellation_fee'").unwrap();
+        stmt.query_map([], |row| row.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn cancelling_before_the_deadline_is_free() {
+        let conn = db::test_hotel();
+        let id = booking(&conn, 10, Some(30000), &policy(Some(3), CancellationPenalty::FullStay, None));
+        let charge = charge(&conn, id).unwrap();
+        assert_eq!(charge.penalty, eur(0));
+        let expected: String = conn.query_row("SELECT datetime(date('now', '+7 days'))", [], |row| row.get(0)).unwrap();
+        assert_eq!(charge.free_until, Some(expected));
+        assert!(fees(&conn).is_empty());
+    }
+
+    #[test]
+    fn charges_the_first_night_after_the_deadline() {
+        let conn = db::test_hotel();
+        let id = booking(&conn, 2, Some(30000), &policy(Some(3), CancellationPenalty::FirstNight, None));
+        //a quote charges nothing
+        assert_eq!(quote_cancellation(&conn, id).unwrap().penalty, eur(12000));
+        assert!(fees(&conn).is_empty());
+        assert_eq!(charge(&conn, id).unwrap().penalty, eur(12000));
+        assert_eq!(fees(&conn), [12000]);
+    }
+
+    #[test]
+    fn charges_a_percentage_of_the_total() {
+        let conn = db::test_hotel();
+        let id = booking(&conn, 0, Some(30000), &policy(Some(0), CancellationPenalty::Percent, Some("25")));
+        assert_eq!(charge(&conn, id).unwrap().penalty, eur(7500));
+    }
+
+    #[test]
+    fn charges_non_refundable_stays_in_full() {
+        let conn = db::test_hotel();
+        let id = booking(&conn, 30, Some(30000), &policy(None, CancellationPenalty::FullStay, None));
+        let charge = charge(&conn, id).unwrap();
+        assert_eq!((charge.penalty, charge.free_until), (eur(30000), None));
+        assert_eq!(fees(&conn), [30000]);
+    }
+
+    #[test]
+    fn never_charges_more_than_the_total() {
+        let conn = db::test_hotel();
+        //a promo code took the stay below its first night
+        let id = booking(&conn, 1, Some(10000), &policy(Some(3), CancellationPenalty::FirstNight, None));
+        assert_eq!(charge(&conn, id).unwrap().penalty, eur(10000));
+    }
+
+    #[test]
+    fn charges_nothing_on_bookings_without_a_total() {
+        let conn = db::test_hotel();
+        let id = booking(&conn, 1, None, &policy(None, CancellationPenalty::FullStay, None));
+        assert_eq!(charge(&conn, id).unwrap().penalty, eur(0));
+        assert!(fees(&conn).is_empty());
+    }
+
+    #[test]
+    fn refunds_what_was_paid_beyond_the_penalty() {
+        let conn = db::test_hotel();
+        let id = booking(&conn, 1, Some(30000), &policy(None, CancellationPenalty::FullStay, None));
+        folio::post(&conn, id, FolioEntryKind::Payment, "Payment", eur(-20000), None).unwrap();
+        folio::post(&conn, id, FolioEntryKind::Refund, "Refund", eur(5000), None).unwrap();
+        let paid = paid(&conn, id).unwrap();
+        assert_eq!(paid, eur(15000));
+        assert_eq!(refundable(paid, eur(12000)).unwrap(), eur(3000));
+        assert_eq!(refundable(paid, eur(30000)).unwrap(), eur(0));
+    }
+
+    #[test]
+    fn takes_the_plan_policy_then_the_hotel_policy_then_the_built_in_one() {
+        let conn = db::test_hotel();
+        conn.execute_batch(
+            "INSERT INTO cancellation_policies (id, name, free_until_days, penalty) VALUES ('c1', 'Hotel', 7, 'first_night');
+             INSERT INTO cancellation_policies (id, name, free_until_days, penalty, percent) VALUES ('c2', 'Plan', 1, 'percent', '50');
+             INSERT INTO rate_plans (id, hotel_id, code, name, kind) VALUES ('bar', 'h1', 'BAR', 'Best available', 'bar');
+             INSERT INTO rate_plans (id, hotel_id, code, name, kind) VALUES ('nr', 'h1', 'NR', 'Prepaid', 'non_refundable');",
+        ).unwrap();
+        assert_eq!(policy_for(&conn, "h1", Some("bar")).unwrap().free_until_days, Some(0));
+        let built_in = policy_for(&conn, "h1", Some("nr")).unwrap();
+        assert_eq!((built_in.free_until_days, built_in.penalty), (None, CancellationPenalty::FullStay));
+
+        conn.execute("UPDATE hotels SET cancellation_policy_id = 'c1'", []).unwrap();
+        assert_eq!(policy_for(&conn, "h1", Some("nr")).unwrap().id.as_deref(), Some("c1"));
+        conn.execute("UPDATE rate_plans SET cancellation_policy_id = 'c2' WHERE id = 'bar'", []).unwrap();
+        let plan = policy_for(&conn, "h1", Some("bar")).unwrap();
+        assert_eq!(plan.id.as_deref(), Some("c2"));
+        assert_eq!(plan.summary, "free cancellation until 1 day before check-in, then 50% of the total is charged");
+    }
+}
@@ -0,0 +1,446 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::models::ModificationRates;
+
+//config file read when neither --config nor HOTEL_CONFIG points somewhere else
+const DEFAULT_CONFIG_FILE: &str = "hotel.toml";
+
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+const GATEWAY_PROVIDERS: &[&str] = &["mock"];
+
+//settings are layered: defaults < TOML file < HOTEL_* environment variables < command-line flags
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub db_path: String,
+    pub bind: String,
+    pub port: u16,
+    pub workers: usize,
+    pub log_level: String,
+    //how long responses to requests with an Idempotency-Key are kept for replay
+    pub idempotency_retention_hours: u32,
+    //whether modified bookings keep the prices their nights were sold for
+    pub modification_rates: ModificationRates,
+    //how long a room offered to a waitlisted guest is held before it passes to the next guest
+    pub waitlist_hold_minutes: u32,
+    pub features: Features,
+    pub gateway: GatewayConfig,
+}
+
+//optional groups of endpoints that a property can switch off
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    pub availability_search: bool,
+    pub analytics: bool,
+}
+
+//payment gateway card payments go through, only the in-process mock exists so far
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatewayConfig {
+    pub provider: String,
+    pub mock: MockGatewayConfig,
+}
+
+//outcomes of the mock gateway are decided by card number alone, so every flow can be replayed offline
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockGatewayConfig {
+    //when not empty, any other card is declined
+    pub approve_cards: Vec<String>,
+    pub decline_cards: Vec<String>,
+    pub insufficient_funds_cards: Vec<String>,
+    //cards the provider fails on as if it were down
+    pub error_cards: Vec<String>,
+    //time every call takes
+    pub latency_ms: u64,
+    pub webhooks: bool,
+    //time between a call and the delivery of its webhook
+    pub webhook_delay_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            db_path: "hotel.db".to_string(),
+            bind: "127.0.0.1".to_string(),
+            port: 3000,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            log_level: "info".to_string(),
+            idempotency_retention_hours: 24,
+            modification_rates: ModificationRates::Current,
+            waitlist_hold_minutes: 60,
+            features: Features::default(),
+            gateway: GatewayConfig::default(),
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features { availability_search: true, analytics: true }
+    }
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        GatewayConfig { provider: "mock".to_string(), mock: MockGatewayConfig::default() }
+    }
+}
+
+impl Default for MockGatewayConfig {
+    fn default() -> Self {
+        MockGatewayConfig {
+            approve_cards: Vec::new(),
+            decline_cards: vec!["4000000000000002".to_string()],
+            insufficient_funds_cards: vec!["4000000000009995".to_string()],
+            error_cards: vec!["4000000000000119".to_string()],
+            latency_ms: 0,
+            webhooks: true,
+            webhook_delay_ms: 0,
+        }
+    }
+}
+
+//every problem found while loading, reported together at startup
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+pub const USAGE: &str = "usage: hotel_project [options] [migrate <status|up> | rates load <file>]
+
+options:
+  --config <file>           TOML config file (default: hotel.toml if present, env HOTEL_CONFIG)
+  --db-path <path>          SQLite database file (env HOTEL_DB_PATH)
+  --bind <address>          address to listen on (env HOTEL_BIND)
+  --port <port>             port to listen on (env HOTEL_PORT)
+  --workers <n>             number of HTTP workers (env HOTEL_WORKERS)
+  --log-level <level>       off, error, warn, info, debug or trace (env HOTEL_LOG_LEVEL)
+  --idempotency-retention-hours <n>
+                            hours an Idempotency-Key can be replayed (env HOTEL_IDEMPOTENCY_RETENTION_HOURS)
+  --modification-rates <current|original>
+                            prices of nights a modified booking already had (env HOTEL_MODIFICATION_RATES)
+  --waitlist-hold-minutes <n>
+                            minutes a waitlist offer holds a room (env HOTEL_WAITLIST_HOLD_MINUTES)
+  --feature <name>=<bool>   toggle a feature, e.g. analytics=false (env HOTEL_FEATURE_<NAME>)
+  --gateway-provider <name> payment gateway, only mock so far (env HOTEL_GATEWAY_PROVIDER)
+  --gateway-mock-<setting> <value>
+                            a setting of the mock gateway, e.g. --gateway-mock-latency-ms 200
+                            or --gateway-mock-decline-cards 4000000000000002,4000000000000069
+                            (env HOTEL_GATEWAY_MOCK_<SETTING>)
+  --help                    show this message";
+
+//what the command line asked for besides the settings themselves
+pub struct Cli {
+    pub config: Config,
+    pub command: Vec<String>,
+    pub help: bool,
+    //environment variables that looked like settings but were not, reported once logging is up
+    pub warnings: Vec<String>,
+}
+
+impl Config {
+    //builds the config from all layers; `args` excludes the program name
+    pub fn load(args: &[String]) -> Result<Cli, ConfigError> {
+        Self::load_from(args, std::env::vars().collect())
+    }
+
+    //`load` with the environment given; unknown HOTEL_* variables only warn because other tools share the prefix,
+    //unknown flags and file keys are mistakes and fail
+    fn load_from(args: &[String], env: Vec<(String, String)>) -> Result<Cli, ConfigError> {
+        let mut problems = Vec::new();
+        let mut warnings = Vec::new();
+
+        //flags are collected first because --config decides which file is read
+        let mut flags: Vec<(String, String)> = Vec::new();
+        let mut command = Vec::new();
+        let mut help = false;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--help" || arg == "-h" {
+                help = true;
+            } else if let Some(name) = arg.strip_prefix("--") {
+                let (name, value) = match name.split_once('=') {
+                    Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                    None => (name.to_string(), iter.next().cloned()),
+                };
+                match value {
+                    Some(value) => flags.push((name, value)),
+                    None => problems.push(format!("--{name} needs a value")),
+                }
+            } else {
+                command.push(arg.clone());
+            }
+        }
+
+        let config_file = flags
+            .iter()
+            .rev()
+            .find(|(name, _)| name == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| env.iter().find(|(key, _)| key == "HOTEL_CONFIG").map(|(_, value)| value.clone()));
+
+        let mut config = match &config_file {
+            Some(path) => Self::from_file(Path::new(path), &mut problems),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE), &mut problems)
+            }
+            None => Config::default(),
+        };
+
+        for (key, value) in &env {
+            let known = if let Some(name) = key.strip_prefix("HOTEL_FEATURE_") {
+                config.set_feature(&name.to_lowercase(), value, key, &mut problems)
+            } else if let Some(name) = key.strip_prefix("HOTEL_")
+                && name != "CONFIG"
+            {
+                config.set(&name.to_lowercase(), value, key, &mut problems)
+            } else {
+                true
+            };
+            if !known {
+                warnings.push(format!("{key} is not a setting, ignored"));
+            }
+        }
+
+        for (name, value) in &flags {
+            let source = format!("--{name}");
+            match name.as_str() {
+                "config" => {}
+                "feature" => match value.split_once('=') {
+                    Some((feature, enabled)) => {
+                        if !config.set_feature(feature, enabled, &source, &mut problems) {
+                            problems.push(format!("--feature: unknown feature {feature:?}"));
+                        }
+                    }
+                    None => problems.push(format!("--feature expects <name>=<bool>, got {value:?}")),
+                },
+                other => {
+                    if !config.set(&other.replace('-', "_"), value, &source, &mut problems) {
+                        problems.push(format!("{source}: unknown setting"));
+                    }
+                }
+            }
+        }
+
+        config.validate(&mut problems);
+
+        if problems.is_empty() {
+            Ok(Cli { config, command, help, warnings })
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    fn from_file(path: &Path, problems: &mut Vec<String>) -> Config {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => {
+                problems.push(format!("cannot read config file {}: {err}", path.display()));
+                return Config::default();
+            }
+        };
+
+        toml::from_str(&text).unwrap_or_else(|err| {
+            problems.push(format!("config file {}: {}", path.display(), err.message()));
+            Config::default()
+        })
+    }
+
+    //applies one override from the environment or the command line, false when there is no such setting
+    fn set(&mut self, name: &str, value: &str, source: &str, problems: &mut Vec<String>) -> bool {
+        match name {
+            "db_path" => self.db_path = value.to_string(),
+            "bind" => self.bind = value.to_string(),
+            "log_level" => self.log_level = value.to_lowercase(),
+            "port" => match value.parse() {
+                Ok(port) => self.port = port,
+                Err(_) => problems.push(format!("{source}: port must be a number between 1 and 65535, got {value:?}")),
+            },
+            "workers" => match value.parse() {
+                Ok(workers) => self.workers = workers,
+                Err(_) => problems.push(format!("{source}: workers must be a positive number, got {value:?}")),
+            },
+            "idempotency_retention_hours" => match value.parse() {
+                Ok(hours) => self.idempotency_retention_hours = hours,
+                Err(_) => problems.push(format!("{source}: idempotency_retention_hours must be a positive number, got {value:?}")),
+            },
+            "modification_rates" => match value.to_lowercase().as_str() {
+                "current" => self.modification_rates = ModificationRates::Current,
+                "original" => self.modification_rates = ModificationRates::Original,
+                _ => problems.push(format!("{source}: modification_rates must be current or original, got {value:?}")),
+            },
+            "waitlist_hold_minutes" => match value.parse() {
+                Ok(minutes) => self.waitlist_hold_minutes = minutes,
+                Err(_) => problems.push(format!("{source}: waitlist_hold_minutes must be a positive number, got {value:?}")),
+            },
+            "gateway_provider" => self.gateway.provider = value.to_lowercase(),
+            "gateway_mock_approve_cards" => self.gateway.mock.approve_cards = card_list(value),
+            "gateway_mock_decline_cards" => self.gateway.mock.decline_cards = card_list(value),
+            "gateway_mock_insufficient_funds_cards" => self.gateway.mock.insufficient_funds_cards = card_list(value),
+            "gateway_mock_error_cards" => self.gateway.mock.error_cards = card_list(value),
+            "gateway_mock_latency_ms" => match value.parse() {
+                Ok(ms) => self.gateway.mock.latency_ms = ms,
+                Err(_) => problems.push(format!("{source}: gateway_mock_latency_ms must be a positive number, got {value:?}")),
+            },
+            "gateway_mock_webhooks" => match parse_bool(value) {
+                Some(webhooks) => self.gateway.mock.webhooks = webhooks,
+                None => problems.push(format!("{source}: gateway_mock_webhooks must be true or false, got {value:?}")),
+            },
+            "gateway_mock_webhook_delay_ms" => match value.parse() {
+                Ok(ms) => self.gateway.mock.webhook_delay_ms = ms,
+                Err(_) => problems.push(format!("{source}: gateway_mock_webhook_delay_ms must be a positive number, got {value:?}")),
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    //switches a feature on or off, false when there is no such feature
+    fn set_feature(&mut self, name: &str, value: &str, source: &str, problems: &mut Vec<String>) -> bool {
+        let feature = match name {
+            "availability_search" => &mut self.features.availability_search,
+            "analytics" => &mut self.features.analytics,
+            _ => return false,
+        };
+        match parse_bool(value) {
+            Some(enabled) => *feature = enabled,
+            None => problems.push(format!("{source}: feature {name} must be true or false, got {value:?}")),
+        }
+        true
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) {
+        if self.db_path.trim().is_empty() {
+            problems.push("db_path must not be empty".to_string());
+        }
+        if self.bind.parse::<IpAddr>().is_err() {
+            problems.push(format!("bind must be an IP address, got {:?}", self.bind));
+        }
+        if self.port == 0 {
+            problems.push("port must be between 1 and 65535".to_string());
+        }
+        if self.workers == 0 {
+            problems.push("workers must be at least 1".to_string());
+        }
+        if self.idempotency_retention_hours == 0 {
+            problems.push("idempotency_retention_hours must be at least 1".to_string());
+        }
+        if self.waitlist_hold_minutes == 0 {
+            problems.push("waitlist_hold_minutes must be at least 1".to_string());
+        }
+        if !GATEWAY_PROVIDERS.contains(&self.gateway.provider.as_str()) {
+            problems.push(format!(
+                "gateway.provider must be one of {}, got {:?}",
+                GATEWAY_PROVIDERS.join(", "),
+                self.gateway.provider
+            ));
+        }
+        if !LOG_LEVELS.contains(&self.log_level.as_str()) {
+            problems.push(format!("log_level must be one of {}, got {:?}", LOG_LEVELS.join(", "), self.log_level));
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "on" | "1" | "yes" => Some(true),
+        "false" | "off" | "0" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+//card numbers given as one comma-separated value, an empty value clears the list
+fn card_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|card| !card.is_empty()).map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //loads with `file` as the config file named by HOTEL_CONFIG, the file is gone again afterwards
+    fn load(file: &str, args: &[&str], env: &[(&str, &str)]) -> Result<Cli, ConfigError> {
+        let path = std::env::temp_dir().join(format!("hotel-{}.toml", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&path, file).unwrap();
+        let mut vars = vec![("HOTEL_CONFIG".to_string(), path.display().to_string())];
+        vars.extend(env.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let loaded = Config::load_from(&args, vars);
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    fn problems(loaded: Result<Cli, ConfigError>) -> Vec<String> {
+        match loaded {
+            Err(ConfigError(problems)) => problems,
+            Ok(_) => panic!("the config loaded"),
+        }
+    }
+
+    #[test]
+    fn layers_flags_over_environment_over_file_over_defaults() {
+        let file = "port = 4000\nworkers = 2\nlog_level = \"debug\"\n[features]\nanalytics = false\n[gateway.mock]\nlatency_ms = 5";
+        let env = [("HOTEL_PORT", "5000"), ("HOTEL_WORKERS", "3"), ("HOTEL_FEATURE_ANALYTICS", "yes")];
+        let cli = load(file, &["--port", "6000", "migrate", "status"], &env).unwrap();
+
+        let config = cli.config;
+        assert_eq!((config.port, config.workers, config.log_level.as_str()), (6000, 3, "debug"));
+        assert_eq!((config.db_path.as_str(), config.waitlist_hold_minutes), ("hotel.db", 60));
+        assert!(config.features.analytics);
+        assert_eq!(config.gateway.mock.latency_ms, 5);
+        assert_eq!(cli.command, ["migrate", "status"]);
+        assert!(cli.warnings.is_empty());
+    }
+
+    #[test]
+    fn reads_the_file_named_by_the_flag_before_the_environment() {
+        let loaded = load("", &["--config", "/nonexistent/hotel.toml"], &[]);
+        assert!(problems(loaded)[0].starts_with("cannot read config file /nonexistent/hotel.toml"));
+    }
+
+    #[test]
+    fn warns_about_unknown_environment_variables_only() {
+        let cli = load("", &[], &[("HOTEL_ENV", "production"), ("HOTEL_FEATURE_BETA", "on"), ("HOME", "/root")]).unwrap();
+        assert_eq!(cli.warnings, ["HOTEL_ENV is not a setting, ignored", "HOTEL_FEATURE_BETA is not a setting, ignored"]);
+
+        let loaded = load("", &["--colour", "red", "--feature", "beta=on"], &[]);
+        assert_eq!(problems(loaded), ["--colour: unknown setting", "--feature: unknown feature \"beta\""]);
+        assert!(problems(load("colour = \"red\"", &[], &[]))[0].contains("unknown field `colour`"));
+    }
+
+    #[test]
+    fn sets_the_gateway_from_the_environment_and_flags() {
+        let env = [
+            ("HOTEL_GATEWAY_MOCK_DECLINE_CARDS", "4000000000000069, 4000000000000002"),
+            ("HOTEL_GATEWAY_MOCK_WEBHOOKS", "off"),
+        ];
+        let args = ["--gateway-mock-latency-ms", "200", "--gateway-mock-error-cards="];
+        let mock = load("[gateway.mock]\nwebhook_delay_ms = 50", &args, &env).unwrap().config.gateway.mock;
+        assert_eq!(mock.decline_cards, ["4000000000000069", "4000000000000002"]);
+        assert!(mock.error_cards.is_empty());
+        assert_eq!(mock.insufficient_funds_cards, ["4000000000009995"]);
+        assert_eq!((mock.latency_ms, mock.webhooks, mock.webhook_delay_ms), (200, false, 50));
+
+        let loaded = load("", &["--gateway-provider", "acme", "--gateway-mock-webhooks", "maybe"], &[]);
+        assert_eq!(problems(loaded), [
+            "--gateway-mock-webhooks: gateway_mock_webhooks must be true or false, got \"maybe\"",
+            "gateway.provider must be one of mock, got \"acme\"",
+        ]);
+    }
+}
@@ -1,54 +1,172 @@
-use rusqlite::{Connection, Result};
+use std::time::Duration;
 
-pub fn init_db() -> Result<Connection> {
-    let conn = Connection::open("hotel.db")?;
+use actix_web::web;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
 
+use crate::error::ApiError;
+use crate::migrations::{self, MigrationError};
+
+pub type DbPool = r2d2::Pool<SqliteConnectionManager>;
+
+//how long a connection waits on a locked database before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+//builds the shared connection pool, every pooled connection gets the same pragmas
+pub fn create_pool(path: &str) -> Result<DbPool, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Ok(())
+    });
+
+    r2d2::Pool::builder().build(manager)
+}
+
+//runs blocking database work on the blocking thread pool with a pooled connection
+pub async fn run<F, T>(pool: &DbPool, f: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&mut Connection) -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool.clone();
+    web::block(move || {
+        let mut conn = pool.get()?;
+        f(&mut conn)
+    })
+    .await?
+}
+
+//prepares the database file and brings the schema up to date, see migrations.rs
+pub fn init_db(pool: &DbPool) -> Result<(), MigrationError> {
+    let mut conn = pool.get().expect("could not get a database connection");
+
+    //WAL is stored in the database file, so setting it once is enough for every connection
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+
+    let applied = migrations::run_pending(&mut conn)?;
+    for version in applied {
+        println!("⬆️  applied migration {version:04}");
+    }
+
+    Ok(())
+}
+
+//a private in-memory database with the pragmas of the pool and every migration applied, for tests
+#[cfg(test)]
+pub fn test_conn() -> Connection {
+    let mut conn = Connection::open_in_memory().expect("could not open an in-memory database");
+    conn.pragma_update(None, "foreign_keys", "ON").expect("could not turn on foreign keys");
+    migrations::run_pending(&mut conn).expect("migrations failed");
+    conn
+}
+
+//a pool over a private in-memory database shared by its connections, every migration applied, for route tests
+#[cfg(test)]
+pub fn test_pool() -> DbPool {
+    let name = uuid::Uuid::new_v4().simple();
+    let pool = create_pool(&format!("file:test-{name}?mode=memory&cache=shared")).expect("could not create a test pool");
+    migrations::run_pending(&mut pool.get().expect("could not get a test connection")).expect("migrations failed");
+    pool
+}
+
+//hotel h1, four stars in Lisbon and charging EUR, with double rooms r1, r2 and r3 at 100.00, 120.00 and 150.00,
+//suite s1 at 250.00 and guests g1 to g3; the common ground of the tests
+#[cfg(test)]
+pub fn seed_hotel(conn: &Connection) {
     conn.execute_batch(
-        "
-        CREATE TABLE IF NOT EXISTS hotels (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            location TEXT,
-            stars INTEGER
-        );
-
-        CREATE TABLE IF NOT EXISTS rooms (
-            id TEXT PRIMARY KEY,
-            hotel_id TEXT NOT NULL,
-            room_type TEXT,
-            price REAL,
-            status TEXT,
-            FOREIGN KEY(hotel_id) REFERENCES hotels(id)
-        );
-
-        CREATE TABLE IF NOT EXISTS guests (
-            id TEXT PRIMARY KEY,
-            name TEXT,
-            phone TEXT,
-            email TEXT
-        );
-
-        CREATE TABLE IF NOT EXISTS bookings (
-            id TEXT PRIMARY KEY,
-            guest_id TEXT,
-            room_id TEXT,
-            hotel_id TEXT,
-            check_in DATE,
-            check_out DATE,
-            FOREIGN KEY(guest_id) REFERENCES guests(id),
-            FOREIGN KEY(room_id) REFERENCES rooms(id),
-            FOREIGN KEY(hotel_id) REFERENCES hotels(id)
-        );
-
-        CREATE TABLE IF NOT EXISTS payments (
-            id TEXT PRIMARY KEY,
-            booking_id TEXT,
-            amount REAL,
-            method TEXT,
-            FOREIGN KEY(booking_id) REFERENCES bookings(id)
-        );
-        "
-    )?;
-
-    Ok(conn)
+        "INSERT INTO hotels (id, name, location, stars, currency) VALUES ('h1', 'Hotel', 'Lisbon', 4, 'EUR');
+         INSERT INTO rooms (id, hotel_id, room_type, price_minor, currency)
+         VALUES ('r1', 'h1', 'double', 10000, 'EUR'), ('r2', 'h1', 'double', 12000, 'EUR'),
+                ('r3', 'h1', 'double', 15000, 'EUR'), ('s1', 'h1', 'suite', 25000, 'EUR');
+         INSERT INTO guests (id, name) VALUES ('g1', 'Ann'), ('g2', 'Bo'), ('g3', 'Cy');",
+    ).expect("could not seed the test hotel");
+}
+
+//`test_conn` with `seed_hotel` in it
+#[cfg(test)]
+pub fn test_hotel() -> Connection {
+    let conn = test_conn();
+    seed_hotel(&conn);
+    conn
+}
+
+#[cfg(test)]
+pub fn eur(minor_units: i64) -> crate::money::Money {
+    crate::money::Money::new(minor_units, "EUR".parse().expect("EUR is a currency"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a pool over a database file of its own, the files are gone again once `f` returns
+    fn with_file_pool(f: impl FnOnce(&DbPool)) {
+        let path = std::env::temp_dir().join(format!("hotel-{}.db", uuid::Uuid::new_v4().simple()));
+        let pool = create_pool(path.to_str().unwrap()).unwrap();
+        f(&pool);
+        drop(pool);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    fn pragma(conn: &Connection, name: &str) -> String {
+        conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get::<_, rusqlite::types::Value>(0))
+            .map(|value| match value {
+                rusqlite::types::Value::Integer(n) => n.to_string(),
+                rusqlite::types::Value::Text(text) => text,
+                other => format!("{other:?}"),
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn sets_up_every_pooled_connection_the_same() {
+        with_file_pool(|pool| {
+            let (first, second) = (pool.get().unwrap(), pool.get().unwrap());
+            for conn in [&first, &second] {
+                assert_eq!(pragma(conn, "foreign_keys"), "1");
+                assert_eq!(pragma(conn, "busy_timeout"), "5000");
+                assert_eq!(pragma(conn, "synchronous"), "1");
+            }
+        });
+    }
+
+    #[test]
+    fn switches_the_file_to_wal_and_migrates_it_once() {
+        with_file_pool(|pool| {
+            init_db(pool).unwrap();
+            assert_eq!(pragma(&pool.get().unwrap(), "journal_mode"), "wal");
+            assert!(migrations::run_pending(&mut pool.get().unwrap()).unwrap().is_empty());
+            init_db(pool).unwrap();
+        });
+    }
+
+    #[actix_web::test]
+    async fn runs_work_off_the_executor_on_pooled_connections() {
+        let pool = test_pool();
+        let inserts: Vec<_> = (0..8)
+            .map(|n| {
+                let pool = pool.clone();
+                actix_web::rt::spawn(async move {
+                    run(&pool, move |conn| {
+                        conn.execute("INSERT INTO guests (id, name) VALUES (?1, 'Guest')", [format!("g{n}")])?;
+                        Ok(n)
+                    }).await
+                })
+            })
+            .collect();
+        let mut done = 0;
+        for insert in inserts {
+            done += insert.await.unwrap().unwrap();
+        }
+        assert_eq!(done, 28);
+
+        let guests = run(&pool, |conn| Ok(conn.query_row("SELECT COUNT(*) FROM guests", [], |row| row.get::<_, i64>(0))?)).await;
+        assert_eq!(guests.unwrap(), 8);
+        let failed = run(&pool, |conn| Ok(conn.execute("INSERT INTO rooms (id, hotel_id) VALUES ('r1', 'nowhere')", [])?)).await;
+        assert_eq!(failed.unwrap_err().code(), "constraint_violation");
+    }
 }
@@ -0,0 +1,213 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use rusqlite::ErrorCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+//a single rejected field in a validation failure
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+//every handler returns Result<HttpResponse, ApiError>, the variant decides the status code
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(&'static str),
+    Conflict {
+        code: &'static str,
+        message: String,
+        details: Option<Value>,
+    },
+    Validation(Vec<FieldError>),
+    //a payment gateway refused the card or the operation
+    PaymentDeclined {
+        code: &'static str,
+        message: String,
+    },
+    BadRequest(String),
+    Database(rusqlite::Error),
+    Unavailable(String),
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn conflict(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Conflict { code, message: message.into(), details: None }
+    }
+
+    pub fn conflict_with(code: &'static str, message: impl Into<String>, details: Value) -> Self {
+        ApiError::Conflict { code, message: message.into(), details: Some(details) }
+    }
+
+    //shorthand for a validation failure on one field
+    pub fn invalid(field: &str, message: impl Into<String>) -> Self {
+        ApiError::Validation(vec![FieldError { field: field.to_string(), message: message.into() }])
+    }
+
+    //machine-readable code sent next to the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict { code, .. } => code,
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::PaymentDeclined { code, .. } => code,
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Database(err) if is_constraint_violation(err) => "constraint_violation",
+            ApiError::Database(_) => "database_error",
+            ApiError::Unavailable(_) => "service_unavailable",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+fn is_constraint_violation(err: &rusqlite::Error) -> bool {
+    matches!(err.sqlite_error_code(), Some(ErrorCode::ConstraintViolation))
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound(resource) => write!(f, "{resource} not found"),
+            ApiError::Conflict { message, .. } => write!(f, "{message}"),
+            ApiError::Validation(errors) => write!(f, "{} field(s) failed validation", errors.len()),
+            ApiError::PaymentDeclined { message, .. } => write!(f, "{message}"),
+            ApiError::BadRequest(message) => write!(f, "{message}"),
+            ApiError::Database(err) if is_constraint_violation(err) => match err {
+                rusqlite::Error::SqliteFailure(_, Some(message)) => write!(f, "{message}"),
+                _ => write!(f, "constraint violation"),
+            },
+            //internal details stay in the server log, not in the response
+            ApiError::Database(_) => write!(f, "database error"),
+            ApiError::Unavailable(message) => write!(f, "{message}"),
+            ApiError::Internal(_) => write!(f, "internal server error"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict { .. } => StatusCode::CONFLICT,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PaymentDeclined { .. } => StatusCode::PAYMENT_REQUIRED,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Database(err) if is_constraint_violation(err) => StatusCode::CONFLICT,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        match self {
+            ApiError::Database(err) if status.is_server_error() => log::error!("database error: {err}"),
+            ApiError::Internal(message) => log::error!("internal error: {message}"),
+            _ => {}
+        }
+
+        let mut body = json!({
+            "error": self.to_string(),
+            "code": self.code(),
+        });
+        match self {
+            ApiError::Conflict { details: Some(details), .. } => body["details"] = details.clone(),
+            ApiError::Validation(errors) => body["details"] = json!(errors),
+            _ => {}
+        }
+
+        HttpResponse::build(status).json(body)
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> Self {
+        match err {
+            rusqlite::Error::QueryReturnedNoRows => ApiError::NotFound("resource"),
+            rusqlite::Error::SqliteFailure(ref e, _)
+                if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) =>
+            {
+                ApiError::Unavailable("database is busy, try again".to_string())
+            }
+            err => ApiError::Database(err),
+        }
+    }
+}
+
+impl From<r2d2::Error> for ApiError {
+    fn from(err: r2d2::Error) -> Self {
+        ApiError::Unavailable(format!("no database connection available: {err}"))
+    }
+}
+
+impl From<actix_web::error::BlockingError> for ApiError {
+    fn from(err: actix_web::error::BlockingError) -> Self {
+        ApiError::Internal(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::MessageBody;
+
+    use super::*;
+
+    //the status and JSON body a handler returning `err` answers with
+    fn response(err: ApiError) -> (StatusCode, Value) {
+        let res = err.error_response();
+        let status = res.status();
+        let body = res.into_body().try_into_bytes().unwrap_or_else(|_| panic!("the body is not in memory"));
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    //a constraint the database refused
+    fn constraint_violation() -> rusqlite::Error {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (id TEXT PRIMARY KEY); INSERT INTO t VALUES ('a');").unwrap();
+        conn.execute("INSERT INTO t VALUES ('a')", []).unwrap_err()
+    }
+
+    #[test]
+    fn answers_every_error_with_a_code_and_a_message() {
+        assert_eq!(response(ApiError::NotFound("booking")), (StatusCode::NOT_FOUND, json!({"error": "booking not found", "code": "not_found"})));
+        assert_eq!(
+            response(ApiError::conflict_with("booking_conflict", "room is taken", json!({"conflicts": []}))),
+            (StatusCode::CONFLICT, json!({"error": "room is taken", "code": "booking_conflict", "details": {"conflicts": []}})),
+        );
+        assert_eq!(
+            response(ApiError::invalid("stars", "must be between 1 and 5")),
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({"error": "1 field(s) failed validation", "code": "validation_failed",
+                       "details": [{"field": "stars", "message": "must be between 1 and 5"}]}),
+            ),
+        );
+        let declined = ApiError::PaymentDeclined { code: "card_declined", message: "the card was declined".into() };
+        assert_eq!(response(declined), (StatusCode::PAYMENT_REQUIRED, json!({"error": "the card was declined", "code": "card_declined"})));
+        assert_eq!(response(ApiError::BadRequest("bad cursor".into())).0, StatusCode::BAD_REQUEST);
+        assert_eq!(response(ApiError::Unavailable("try again".into())).1["code"], "service_unavailable");
+    }
+
+    #[test]
+    fn keeps_internal_details_out_of_the_body() {
+        let (status, body) = response(ApiError::Internal("pool poisoned at routes.rs:12".into()));
+        assert_eq!((status, body), (StatusCode::INTERNAL_SERVER_ERROR, json!({"error": "internal server error", "code": "internal_error"})));
+
+        let (status, body) = response(ApiError::Database(rusqlite::Error::InvalidColumnName("secret".into())));
+        assert_eq!((status, body), (StatusCode::INTERNAL_SERVER_ERROR, json!({"error": "database error", "code": "database_error"})));
+    }
+
+    #[test]
+    fn maps_database_errors_by_what_went_wrong() {
+        let (status, body) = response(constraint_violation().into());
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("constraint_violation")));
+        assert_eq!(body["error"], "UNIQUE constraint failed: t.id");
+
+        assert!(matches!(ApiError::from(rusqlite::Error::QueryReturnedNoRows), ApiError::NotFound(_)));
+        let busy = rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), None);
+        assert_eq!(response(busy.into()), (StatusCode::SERVICE_UNAVAILABLE, json!({"error": "database is busy, try again", "code": "service_unavailable"})));
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+use std::path::Path;
+
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::json;
+
+use crate::error::ApiError;
+use crate::money::{Currency, Rate};
+use crate::validation::is_iso_date;
+
+//rate turning `from` into `to` on `date`: the latest one published on or before that day,
+//read in either direction of the table
+pub fn rate_on(conn: &Connection, from: Currency, to: Currency, date: &str) -> Result<Rate, ApiError> {
+    if from == to {
+        return Ok(Rate::ONE);
+    }
+
+    let lookup = |base: Currency, quote: Currency| {
+        conn.query_row(
+            "SELECT rate FROM exchange_rates
+             WHERE base = ?1 AND quote = ?2 AND valid_on <= ?3
+             ORDER BY valid_on DESC LIMIT 1",
+            (base, quote, date),
+            |row| row.get::<_, Rate>(0),
+        ).optional()
+    };
+
+    if let Some(rate) = lookup(from, to)? {
+        return Ok(rate);
+    }
+    if let Some(rate) = lookup(to, from)? {
+        return Ok(rate.inverse());
+    }
+
+    Err(ApiError::conflict_with(
+        "exchange_rate_missing",
+        format!("no {from}/{to} exchange rate on or before {date}"),
+        json!({"from": from, "to": to, "date": date}),
+    ))
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Invalid(Vec<String>),
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "cannot read rates file: {err}"),
+            LoadError::Invalid(problems) => {
+                writeln!(f, "invalid rates file, nothing was loaded:")?;
+                for problem in problems {
+                    writeln!(f, "  - {problem}")?;
+                }
+                Ok(())
+            }
+            LoadError::Sqlite(err) => write!(f, "storing rates failed: {err}"),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for LoadError {
+    fn from(err: rusqlite::Error) -> Self {
+        LoadError::Sqlite(err)
+    }
+}
+
+//loads `valid_on,base,quote,rate` lines (e.g. `2025-01-01,EUR,USD,1.0834`) in one transaction,
+//replacing rates already stored for the same day and pair; returns how many lines were stored
+pub fn load_file(conn: &mut Connection, path: &Path) -> Result<usize, LoadError> {
+    let text = std::fs::read_to_string(path).map_err(LoadError::Io)?;
+
+    let mut problems = Vec::new();
+    let mut rates = Vec::new();
+    for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with("valid_on,") {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [valid_on, base, quote, rate] = fields[..] else {
+            problems.push(format!("line {number}: expected valid_on,base,quote,rate"));
+            continue;
+        };
+        if !is_iso_date(valid_on) {
+            problems.push(format!("line {number}: {valid_on:?} is not a YYYY-MM-DD date"));
+        }
+        match (base.parse::<Currency>(), quote.parse::<Currency>(), rate.parse::<Rate>()) {
+            (Ok(base), Ok(quote), Ok(rate)) if base != quote => {
+                rates.push((valid_on.to_string(), base, quote, rate));
+            }
+            (Ok(_), Ok(_), Ok(_)) => problems.push(format!("line {number}: base and quote are both {base}")),
+            (base, quote, rate) => {
+                for err in [base.err(), quote.err(), rate.err()].into_iter().flatten() {
+                    problems.push(format!("line {number}: {err}"));
+                }
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return Err(LoadError::Invalid(problems));
+    }
+
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO exchange_rates (valid_on, base, quote, rate) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(base, quote, valid_on) DO UPDATE SET rate = excluded.rate",
+        )?;
+        for rate in &rates {
+            stmt.execute((&rate.0, rate.1, rate.2, rate.3))?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(rates.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn currency(code: &str) -> Currency {
+        code.parse().unwrap()
+    }
+
+    fn rate(conn: &Connection, from: &str, to: &str, date: &str) -> Result<String, &'static str> {
+        rate_on(conn, currency(from), currency(to), date).map(|rate| rate.to_string()).map_err(|err| err.code())
+    }
+
+    //loads `text` as a rates file, the file is gone again afterwards
+    fn load(conn: &mut Connection, text: &str) -> Result<usize, LoadError> {
+        let path = std::env::temp_dir().join(format!("rates-{}.csv", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&path, text).unwrap();
+        let loaded = load_file(conn, &path);
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn uses_the_latest_rate_published_by_the_day_either_way_round() {
+        let mut conn = db::test_conn();
+        load(&mut conn, "valid_on,base,quote,rate\n2027-02-01,EUR,USD,1.25\n2027-02-20,EUR,USD,1.1\n").unwrap();
+
+        assert_eq!(rate(&conn, "EUR", "USD", "2027-02-01"), Ok("1.25".into()));
+        assert_eq!(rate(&conn, "EUR", "USD", "2027-02-19"), Ok("1.25".into()));
+        assert_eq!(rate(&conn, "EUR", "USD", "2027-03-01"), Ok("1.1".into()));
+        assert_eq!(rate(&conn, "USD", "EUR", "2027-02-15"), Ok("0.8".into()));
+        assert_eq!(rate(&conn, "EUR", "EUR", "2000-01-01"), Ok("1".into()));
+        assert_eq!(rate(&conn, "EUR", "USD", "2027-01-31"), Err("exchange_rate_missing"));
+        assert_eq!(rate(&conn, "EUR", "GBP", "2027-03-01"), Err("exchange_rate_missing"));
+    }
+
+    #[test]
+    fn replaces_the_rate_of_a_day_when_loaded_again() {
+        let mut conn = db::test_conn();
+        assert_eq!(load(&mut conn, "# published rates\n2027-02-01,EUR,USD,1.25\n\n2027-02-01,EUR,GBP,0.85\n").unwrap(), 2);
+        assert_eq!(load(&mut conn, "2027-02-01,EUR,USD,1.2").unwrap(), 1);
+        assert_eq!(rate(&conn, "EUR", "USD", "2027-02-01"), Ok("1.2".into()));
+        assert_eq!(rate(&conn, "EUR", "GBP", "2027-02-01"), Ok("0.85".into()));
+    }
+
+    #[test]
+    fn loads_nothing_from_a_file_with_a_bad_line() {
+        let mut conn = db::test_conn();
+        let text = "2027-02-01,EUR,USD,1.25\n2027-02-30,EUR,USD,1.2\n2027-02-01,EUR,EUR,1\n2027-02-01,EUR\n2027-02-01,EUR,GBP,abc\n";
+        let Err(LoadError::Invalid(problems)) = load(&mut conn, text) else {
+            panic!("the file was loaded");
+        };
+        let lines: Vec<_> = problems.iter().map(|problem| problem.split(':').next().unwrap()).collect();
+        assert_eq!(lines, ["line 2", "line 3", "line 4", "line 5"]);
+        assert_eq!(rate(&conn, "EUR", "USD", "2027-02-01"), Err("exchange_rate_missing"));
+    }
+}
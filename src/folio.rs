@@ -0,0 +1,141 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::json;
+
+use crate::error::ApiError;
+use crate::models::{FolioEntry, FolioEntryKind};
+use crate::money::{Currency, Money};
+
+pub const FOLIO_ENTRY_SELECT: &str = "
+    SELECT e.id, e.booking_id, e.kind, e.description, e.amount_minor, e.currency, e.posted_on, e.posted_at,
+           e.payment_id, e.reverses_id,
+           EXISTS (SELECT 1 FROM folio_entries r WHERE r.reverses_id = e.id) AS reversed
+    FROM folio_entries e
+";
+
+pub fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<FolioEntry> {
+    Ok(FolioEntry {
+        id: row.get(0)?,
+        booking_id: row.get(1)?,
+        kind: row.get(2)?,
+        description: row.get(3)?,
+        amount: Money::new(row.get(4)?, row.get(5)?),
+        posted_on: row.get(6)?,
+        posted_at: row.get(7)?,
+        payment_id: row.get(8)?,
+        reverses_id: row.get(9)?,
+        reversed: row.get(10)?,
+    })
+}
+
+//a folio is kept in the base currency of the booked hotel
+pub fn folio_currency(conn: &Connection, booking_id: &str) -> Result<Currency, ApiError> {
+    conn.query_row(
+        "SELECT h.currency FROM bookings b JOIN hotels h ON h.id = b.hotel_id WHERE b.id = ?1",
+        [booking_id],
+        |row| row.get(0),
+    ).optional()?.ok_or(ApiError::NotFound("booking"))
+}
+
+//appends an entry, the only way anything gets into a folio
+pub fn post(
+    conn: &Connection,
+    booking_id: &str,
+    kind: FolioEntryKind,
+    description: &str,
+    amount: Money,
+    payment_id: Option<&str>,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency, payment_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (booking_id, kind, description, amount.minor_units, amount.currency, payment_id),
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+//charges the stored price of a stay: every night, then promo codes, then taxes and fees
+pub fn post_stay(conn: &Connection, booking_id: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency)
+         SELECT booking_id, 'room', 'Room night ' || night, price_minor, currency
+         FROM booking_nights WHERE booking_id = ?1 ORDER BY night",
+        [booking_id],
+    )?;
+    conn.execute(
+        "INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency)
+         SELECT booking_id, 'discount', 'Promo code ' || code, -discount_minor, currency
+         FROM promotion_redemptions WHERE booking_id = ?1 ORDER BY rowid",
+        [booking_id],
+    )?;
+    conn.execute(
+        "INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency)
+         SELECT booking_id, category, name, amount_minor, currency
+         FROM booking_charges WHERE booking_id = ?1 ORDER BY line",
+        [booking_id],
+    )?;
+    Ok(())
+}
+
+//credits a settled payment to the folio of its booking
+pub fn post_payment(conn: &Connection, booking_id: &str, payment_id: &str, settled: Money, method: &str) -> rusqlite::Result<i64> {
+    let credit = Money::new(-settled.minor_units, settled.currency);
+    post(conn, booking_id, FolioEntryKind::Payment, &format!("Payment by {method}"), credit, Some(payment_id))
+}
+
+//charges back the settled part of a payment that went back to the guest
+pub fn post_refund(conn: &Connection, booking_id: &str, payment_id: &str, settled: Money, reason: &str) -> rusqlite::Result<i64> {
+    post(conn, booking_id, FolioEntryKind::Refund, &format!("Refund: {reason}"), settled, Some(payment_id))
+}
+
+//posts the opposite of an entry; each entry is reversed at most once and reversals are final
+pub fn reverse(conn: &Connection, booking_id: &str, entry_id: i64, reason: &str) -> Result<FolioEntry, ApiError> {
+    let entry = conn.query_row(
+        &format!("{FOLIO_ENTRY_SELECT} WHERE e.id = ?1 AND e.booking_id = ?2"),
+        (entry_id, booking_id),
+        entry_from_row,
+    ).optional()?.ok_or(ApiError::NotFound("folio entry"))?;
+
+    if entry.reversed {
+        return Err(ApiError::conflict("already_reversed", "the entry has already been reversed"));
+    }
+    if let Some(reverses_id) = entry.reverses_id {
+        return Err(ApiError::conflict_with(
+            "reversal_is_final",
+            "a reversal cannot be reversed, post the charge again instead",
+            json!({"reverses_id": reverses_id}),
+        ));
+    }
+
+    conn.execute(
+        "INSERT INTO folio_entries (booking_id, kind, description, amount_minor, currency, payment_id, reverses_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        (
+            booking_id, entry.kind, format!("Reversal of {}: {reason}", entry.description),
+            -entry.amount.minor_units, entry.amount.currency, &entry.payment_id, entry.id,
+        ),
+    )?;
+    let id = conn.last_insert_rowid();
+
+    Ok(conn.query_row(&format!("{FOLIO_ENTRY_SELECT} WHERE e.id = ?1"), [id], entry_from_row)?)
+}
+
+//reverses the live folio entry of a payment, if the payment was ever posted
+pub fn reverse_payment(conn: &Connection, payment_id: &str, reason: &str) -> Result<(), ApiError> {
+    let live: Option<(String, i64)> = conn.query_row(
+        "SELECT e.booking_id, e.id FROM folio_entries e
+         WHERE e.payment_id = ?1 AND e.kind = 'payment' AND e.reverses_id IS NULL
+           AND NOT EXISTS (SELECT 1 FROM folio_entries r WHERE r.reverses_id = e.id)",
+        [payment_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?;
+
+    if let Some((booking_id, entry_id)) = live {
+        reverse(conn, &booking_id, entry_id, reason)?;
+    }
+    Ok(())
+}
+
+//sum of every entry, what the guest still owes when positive
+pub fn balance(entries: &[FolioEntry], currency: Currency) -> Result<Money, ApiError> {
+    Ok(entries.iter().try_fold(Money::zero(currency), |sum, e| sum.checked_add(e.amount))?)
+}
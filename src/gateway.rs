@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusqlite::{Connection, OptionalExtension};
+use uuid::Uuid;
+
+use crate::config::{GatewayConfig, MockGatewayConfig};
+use crate::db::{self, DbPool};
+use crate::error::ApiError;
+use crate::models::{PaymentStatus, WebhookEvent, WebhookKind};
+use crate::money::Money;
+
+//why a gateway did not do what it was asked
+#[derive(Debug)]
+pub enum GatewayError {
+    //the card or the operation was refused, trying again will not help
+    Declined { code: &'static str, message: String },
+    //the provider failed or could not be reached, the operation can be retried
+    Unavailable(String),
+}
+
+impl From<GatewayError> for ApiError {
+    fn from(err: GatewayError) -> Self {
+        match err {
+            GatewayError::Declined { code, message } => ApiError::PaymentDeclined { code, message },
+            GatewayError::Unavailable(message) => ApiError::Unavailable(message),
+        }
+    }
+}
+
+//a card payment provider; calls block, so they are made from the blocking pool like database work
+pub trait PaymentGateway: Send + Sync {
+    //stored next to every payment so references of different providers never mix
+    fn name(&self) -> &'static str;
+
+    //holds `amount` on a card and returns the provider's reference for every later call
+    fn authorize(&self, card_number: &str, amount: Money) -> Result<String, GatewayError>;
+
+    //takes up to the held amount, the rest of the hold is released
+    fn capture(&self, reference: &str, amount: Money) -> Result<(), GatewayError>;
+
+    //hands back part or all of a captured amount and returns the provider's reference of the refund; asked again
+    //with the same `key` it answers with the refund it already made instead of making another
+    fn refund(&self, reference: &str, amount: Money, key: &str) -> Result<String, GatewayError>;
+
+    //releases a hold, or cancels a capture before it is settled
+    fn void(&self, reference: &str) -> Result<(), GatewayError>;
+
+    //webhook events the provider sent since the last call, for providers that live in-process
+    fn take_webhooks(&self) -> Vec<WebhookEvent> {
+        Vec::new()
+    }
+
+    //how long after a call its webhook events arrive
+    fn webhook_delay(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+//makes a gateway call on the blocking pool, outside any database transaction
+pub async fn call<F, T>(gateway: &Arc<dyn PaymentGateway>, f: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&dyn PaymentGateway) -> Result<T, GatewayError> + Send + 'static,
+    T: Send + 'static,
+{
+    let gateway = gateway.clone();
+    Ok(actix_web::web::block(move || f(gateway.as_ref())).await??)
+}
+
+//voids a reference whose authorization or capture could not be recorded, so the guest's card is not left
+//holding money the database does not know about
+pub async fn undo(gateway: &Arc<dyn PaymentGateway>, reference: String) {
+    let voided = reference.clone();
+    if let Err(err) = call(gateway, move |gateway| gateway.void(&voided)).await {
+        log::error!("could not void {reference} at {}: {err}", gateway.name());
+    }
+}
+
+//builds the gateway named in the config
+pub fn from_config(config: &GatewayConfig) -> Arc<dyn PaymentGateway> {
+    //mock is the only provider the config accepts so far
+    Arc::new(MockGateway::new(config.mock.clone()))
+}
+
+//---mock---
+
+//in-process gateway whose outcomes depend on the card number alone
+pub struct MockGateway {
+    config: MockGatewayConfig,
+    outbox: Mutex<Vec<WebhookEvent>>,
+    //references of the refunds made, by key
+    refunds: Mutex<HashMap<String, String>>,
+}
+
+impl MockGateway {
+    pub fn new(config: MockGatewayConfig) -> Self {
+        MockGateway { config, outbox: Mutex::new(Vec::new()), refunds: Mutex::new(HashMap::new()) }
+    }
+
+    fn call(&self) {
+        if self.config.latency_ms > 0 {
+            std::thread::sleep(Duration::from_millis(self.config.latency_ms));
+        }
+    }
+
+    fn emit(&self, kind: WebhookKind, reference: &str, amount: Option<Money>) {
+        if !self.config.webhooks {
+            return;
+        }
+        let event = WebhookEvent {
+            id: format!("evt_{}", Uuid::new_v4().simple()),
+            kind,
+            reference: reference.to_string(),
+            amount,
+        };
+        self.outbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(event);
+    }
+
+    fn known(reference: &str) -> Result<(), GatewayError> {
+        if reference.starts_with("mock_") {
+            Ok(())
+        } else {
+            Err(declined("unknown_reference", format!("the mock gateway did not issue {reference:?}")))
+        }
+    }
+}
+
+fn declined(code: &'static str, message: impl Into<String>) -> GatewayError {
+    GatewayError::Declined { code, message: message.into() }
+}
+
+//the check digit every real card number passes
+fn luhn_valid(card_number: &str) -> bool {
+    let mut sum = 0;
+    for (i, b) in card_number.bytes().rev().enumerate() {
+        let mut digit = u32::from(b - b'0');
+        if i % 2 == 1 {
+            digit *= 2;
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+        sum += digit;
+    }
+    sum % 10 == 0
+}
+
+impl PaymentGateway for MockGateway {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn authorize(&self, card_number: &str, amount: Money) -> Result<String, GatewayError> {
+        self.call();
+        let listed = |cards: &[String]| cards.iter().any(|card| card == card_number);
+
+        if !card_number.bytes().all(|b| b.is_ascii_digit()) || !luhn_valid(card_number) {
+            return Err(declined("incorrect_number", "the card number is incorrect"));
+        }
+        if listed(&self.config.error_cards) {
+            return Err(GatewayError::Unavailable("the payment provider failed, try again".to_string()));
+        }
+        if listed(&self.config.insufficient_funds_cards) {
+            return Err(declined("insufficient_funds", "the card has insufficient funds"));
+        }
+        if listed(&self.config.decline_cards) || (!self.config.approve_cards.is_empty() && !listed(&self.config.approve_cards)) {
+            return Err(declined("card_declined", "the card was declined"));
+        }
+
+        let reference = format!("mock_{}", Uuid::new_v4().simple());
+        self.emit(WebhookKind::PaymentAuthorized, &reference, Some(amount));
+        Ok(reference)
+    }
+
+    fn capture(&self, reference: &str, amount: Money) -> Result<(), GatewayError> {
+        self.call();
+        Self::known(reference)?;
+        self.emit(WebhookKind::PaymentCaptured, reference, Some(amount));
+        Ok(())
+    }
+
+    fn refund(&self, reference: &str, amount: Money, key: &str) -> Result<String, GatewayError> {
+        self.call();
+        Self::known(reference)?;
+        let mut refunds = self.refunds.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(refund) = refunds.get(key) {
+            return Ok(refund.clone());
+        }
+        let refund = format!("mock_re_{}", Uuid::new_v4().simple());
+        refunds.insert(key.to_string(), refund.clone());
+        self.emit(WebhookKind::PaymentRefunded, reference, Some(amount));
+        Ok(refund)
+    }
+
+    fn void(&self, reference: &str) -> Result<(), GatewayError> {
+        self.call();
+        Self::known(reference)?;
+        self.emit(WebhookKind::PaymentVoided, reference, None);
+        Ok(())
+    }
+
+    fn take_webhooks(&self) -> Vec<WebhookEvent> {
+        std::mem::take(&mut *self.outbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    fn webhook_delay(&self) -> Duration {
+        Duration::from_millis(self.config.webhook_delay_ms)
+    }
+}
+
+//---webhooks---
+
+//records a webhook event of `provider` once; false when the event was already received
+pub fn apply_event(conn: &mut Connection, provider: &str, event: &WebhookEvent) -> Result<bool, ApiError> {
+    let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+
+    let (payment_id, status): (String, PaymentStatus) = tx.query_row(
+        "SELECT id, status FROM payments WHERE gateway = ?1 AND gateway_reference = ?2",
+        (provider, &event.reference),
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.ok_or(ApiError::NotFound("payment"))?;
+
+    let inserted = tx.execute(
+        "INSERT OR IGNORE INTO gateway_events (provider, id, payment_id, kind, amount_minor, currency)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (
+            provider, &event.id, &payment_id, event.kind,
+            event.amount.map(|a| a.minor_units), event.amount.map(|a| a.currency),
+        ),
+    )?;
+    if inserted == 0 {
+        return Ok(false);
+    }
+
+    //the other events confirm what the payment routes already did when they called the gateway
+    if event.kind == WebhookKind::AuthorizationExpired && status == PaymentStatus::Authorized {
+        tx.execute(
+            "UPDATE payments SET status = ?1, void_reason = 'authorization expired', voided_at = datetime('now')
+             WHERE id = ?2",
+            (PaymentStatus::Voided, &payment_id),
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(true)
+}
+
+//hands the events an in-process gateway produced to `apply_event` once its webhook delay has passed
+pub fn deliver_webhooks(pool: &DbPool, gateway: &Arc<dyn PaymentGateway>) {
+    let events = gateway.take_webhooks();
+    if events.is_empty() {
+        return;
+    }
+    let pool = pool.clone();
+    let provider = gateway.name();
+    let delay = gateway.webhook_delay();
+
+    actix_web::rt::spawn(async move {
+        actix_web::rt::time::sleep(delay).await;
+        for event in events {
+            let id = event.id.clone();
+            if let Err(err) = db::run(&pool, move |conn| apply_event(conn, provider, &event)).await {
+                log::error!("webhook {id} from {provider}: {err}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARD: &str = "4242424242424242";
+
+    fn mock(config: MockGatewayConfig) -> MockGateway {
+        MockGateway::new(config)
+    }
+
+    fn cards(numbers: &[&str]) -> Vec<String> {
+        numbers.iter().map(|number| number.to_string()).collect()
+    }
+
+    //the decline code of a call, or what went wrong instead
+    fn declined_with<T>(result: Result<T, GatewayError>) -> &'static str {
+        match result {
+            Ok(_) => "approved",
+            Err(GatewayError::Declined { code, .. }) => code,
+            Err(GatewayError::Unavailable(_)) => "unavailable",
+        }
+    }
+
+    #[test]
+    fn takes_a_payment_through_every_call_and_reports_each() {
+        let gateway = mock(MockGatewayConfig::default());
+        let reference = gateway.authorize(CARD, db::eur(15000)).unwrap();
+        assert!(reference.starts_with("mock_"));
+        gateway.capture(&reference, db::eur(10000)).unwrap();
+        gateway.refund(&reference, db::eur(4000), "k1").unwrap();
+        gateway.void(&reference).unwrap();
+
+        let events = gateway.take_webhooks();
+        let kinds: Vec<_> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            [WebhookKind::PaymentAuthorized, WebhookKind::PaymentCaptured, WebhookKind::PaymentRefunded, WebhookKind::PaymentVoided],
+        );
+        assert!(events.iter().all(|event| event.reference == reference));
+        assert_eq!(events[1].amount, Some(db::eur(10000)));
+        assert!(gateway.take_webhooks().is_empty());
+    }
+
+    #[test]
+    fn sends_no_webhooks_unless_asked() {
+        let gateway = mock(MockGatewayConfig { webhooks: false, ..MockGatewayConfig::default() });
+        let reference = gateway.authorize(CARD, db::eur(15000)).unwrap();
+        gateway.capture(&reference, db::eur(15000)).unwrap();
+        assert!(gateway.take_webhooks().is_empty());
+    }
+
+    #[test]
+    fn refunds_once_per_key() {
+        let gateway = mock(MockGatewayConfig::default());
+        let reference = gateway.authorize(CARD, db::eur(15000)).unwrap();
+        gateway.take_webhooks();
+
+        let refund = gateway.refund(&reference, db::eur(5000), "k1").unwrap();
+        assert!(refund.starts_with("mock_re_"));
+        assert_eq!(gateway.refund(&reference, db::eur(5000), "k1").unwrap(), refund);
+        assert_ne!(gateway.refund(&reference, db::eur(5000), "k2").unwrap(), refund);
+        assert_eq!(gateway.take_webhooks().len(), 2);
+    }
+
+    #[test]
+    fn declines_the_cards_it_is_told_to() {
+        //the default config lists the usual test cards
+        let gateway = mock(MockGatewayConfig::default());
+        assert_eq!(declined_with(gateway.authorize("4000000000000002", db::eur(100))), "card_declined");
+        assert_eq!(declined_with(gateway.authorize("4000000000009995", db::eur(100))), "insufficient_funds");
+        assert_eq!(declined_with(gateway.authorize("4000000000000119", db::eur(100))), "unavailable");
+        assert_eq!(declined_with(gateway.authorize(CARD, db::eur(100))), "approved");
+
+        //once cards are approved by name, any other is declined
+        let gateway = mock(MockGatewayConfig { approve_cards: cards(&["5555555555554444"]), ..MockGatewayConfig::default() });
+        assert_eq!(declined_with(gateway.authorize("5555555555554444", db::eur(100))), "approved");
+        assert_eq!(declined_with(gateway.authorize(CARD, db::eur(100))), "card_declined");
+    }
+
+    #[test]
+    fn declines_numbers_failing_the_check_digit() {
+        let gateway = mock(MockGatewayConfig::default());
+        assert_eq!(declined_with(gateway.authorize("4242424242424241", db::eur(100))), "incorrect_number");
+        assert_eq!(declined_with(gateway.authorize("4242 4242 4242 4242", db::eur(100))), "incorrect_number");
+        assert!(luhn_valid("79927398713"));
+        assert!(!luhn_valid("79927398710"));
+    }
+
+    #[test]
+    fn declines_references_it_did_not_issue() {
+        let gateway = mock(MockGatewayConfig::default());
+        assert_eq!(declined_with(gateway.capture("ch_1", db::eur(100))), "unknown_reference");
+        assert_eq!(declined_with(gateway.refund("ch_1", db::eur(100), "k1")), "unknown_reference");
+        assert_eq!(declined_with(gateway.void("ch_1")), "unknown_reference");
+    }
+}
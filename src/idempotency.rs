@@ -0,0 +1,316 @@
+use actix_web::body::{self, BoxBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+use sha2::{Digest, Sha256};
+
+use crate::db::{self, DbPool};
+use crate::error::ApiError;
+
+pub const HEADER: &str = "Idempotency-Key";
+
+//sent on responses that come from the store instead of the handler
+const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+const MAX_KEY_LEN: usize = 255;
+
+//a response kept for replay
+struct Stored {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+enum Claim {
+    //first time the key is seen, the request goes on to its handler
+    New,
+    Replay(Stored),
+}
+
+//what identifies a request besides its key, so a key cannot be reused for something else
+fn fingerprint(method: &Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str());
+    hasher.update(b" ");
+    hasher.update(path);
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher.finalize().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+//a claim older than this belongs to a request that never finished, the next retry takes it over
+const CLAIM_LEASE: &str = "-5 minutes";
+
+//takes the key for this request in its scope, or finds the response it already got
+fn claim(conn: &mut Connection, scope: &str, key: &str, fingerprint: &str, retention_hours: u32) -> Result<Claim, ApiError> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute(
+        "DELETE FROM idempotency_keys WHERE created_at < datetime('now', ?1)",
+        [format!("-{retention_hours} hours")],
+    )?;
+
+    //responses kept before keys were scoped have an empty scope and still replay
+    let existing = tx.query_row(
+        "SELECT fingerprint, status, content_type, body, claimed_at > datetime('now', ?3) FROM idempotency_keys
+         WHERE scope IN (?1, '') AND key = ?2
+         ORDER BY scope = ?1 DESC
+         LIMIT 1",
+        (scope, key, CLAIM_LEASE),
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<u16>>(1)?,
+                row.get(2)?,
+                row.get::<_, Option<Vec<u8>>>(3)?,
+                row.get::<_, bool>(4)?,
+            ))
+        },
+    ).optional()?;
+
+    let claim = match existing {
+        None => {
+            tx.execute(
+                "INSERT INTO idempotency_keys (scope, key, fingerprint) VALUES (?1, ?2, ?3)",
+                (scope, key, fingerprint),
+            )?;
+            Claim::New
+        }
+        Some((stored, ..)) if stored != fingerprint => {
+            return Err(ApiError::conflict(
+                "idempotency_key_reused",
+                "the Idempotency-Key was already used for a different request",
+            ));
+        }
+        Some((_, None, _, _, true)) => {
+            return Err(ApiError::conflict(
+                "idempotency_key_in_progress",
+                "a request with this Idempotency-Key is still being handled, retry later",
+            ));
+        }
+        Some((_, None, _, _, false)) => {
+            tx.execute(
+                "UPDATE idempotency_keys SET claimed_at = datetime('now') WHERE scope = ?1 AND key = ?2",
+                (scope, key),
+            )?;
+            Claim::New
+        }
+        Some((_, Some(status), content_type, body, _)) => {
+            Claim::Replay(Stored { status, content_type, body: body.unwrap_or_default() })
+        }
+    };
+    tx.commit()?;
+    Ok(claim)
+}
+
+//server errors are not kept, the client is free to retry them with the same key
+fn release(conn: &Connection, scope: &str, key: &str) -> Result<(), ApiError> {
+    conn.execute("DELETE FROM idempotency_keys WHERE scope = ?1 AND key = ?2 AND status IS NULL", (scope, key))?;
+    Ok(())
+}
+
+fn store(conn: &Connection, scope: &str, key: &str, response: &Stored) -> Result<(), ApiError> {
+    conn.execute(
+        "UPDATE idempotency_keys SET status = ?1, content_type = ?2, body = ?3, completed_at = datetime('now')
+         WHERE scope = ?4 AND key = ?5",
+        (response.status, &response.content_type, &response.body, scope, key),
+    )?;
+    Ok(())
+}
+
+fn replay(stored: Stored) -> HttpResponse {
+    let status = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    let mut response = HttpResponse::build(status);
+    response.insert_header((REPLAYED_HEADER, "true"));
+    if let Some(content_type) = stored.content_type {
+        response.insert_header((header::CONTENT_TYPE, content_type));
+    }
+    response.body(stored.body)
+}
+
+//middleware for POST requests that carry an Idempotency-Key: the first response is kept for
+//`retention_hours` and sent again for every retry with the same key and the same request.
+//keys are scoped to the method and path, the same key sent to another endpoint is a different key
+pub async fn guard(mut req: ServiceRequest, next: Next<BoxBody>, retention_hours: u32) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if req.method() != Method::POST {
+        return next.call(req).await;
+    }
+    let key = match req.headers().get(HEADER).map(HeaderValue::to_str) {
+        None => return next.call(req).await,
+        Some(Ok(key)) if !key.trim().is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        Some(_) => {
+            let err = ApiError::BadRequest(format!("{HEADER} must be 1 to {MAX_KEY_LEN} visible characters"));
+            return Ok(req.error_response(err));
+        }
+    };
+    let Some(pool) = req.app_data::<web::Data<DbPool>>().cloned() else {
+        return next.call(req).await;
+    };
+
+    //the body is read here to fingerprint it and handed back to the handler untouched
+    let body = req.extract::<web::Bytes>().await?;
+    let fingerprint = fingerprint(req.method(), &req.uri().to_string(), &body);
+    req.set_payload(Payload::from(body));
+    let scope = format!("{} {}", req.method(), req.path());
+
+    let claimed = {
+        let (scope, key) = (scope.clone(), key.clone());
+        db::run(&pool, move |conn| claim(conn, &scope, &key, &fingerprint, retention_hours)).await
+    };
+    match claimed {
+        Ok(Claim::New) => {}
+        Ok(Claim::Replay(stored)) => return Ok(req.into_response(replay(stored))),
+        Err(err) => return Ok(req.error_response(err)),
+    }
+
+    let res = match next.call(req).await {
+        Ok(res) if !res.status().is_server_error() => res,
+        //the handler's own response or error goes back even if the key cannot be freed for a retry
+        outcome => {
+            let released_key = key.clone();
+            if let Err(err) = db::run(&pool, move |conn| release(conn, &scope, &released_key)).await {
+                log::error!("could not release {HEADER} {key} for a retry: {err}");
+            }
+            return outcome;
+        }
+    };
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = body::to_bytes(body).await.map_err(|err| ApiError::Internal(err.to_string()))?;
+    let stored = Stored {
+        status: res.status().as_u16(),
+        content_type: res.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::to_string),
+        body: bytes.to_vec(),
+    };
+    //the request has been handled by now, failing to keep its response must not turn it into an error
+    let stored_key = key.clone();
+    if let Err(err) = db::run(&pool, move |conn| store(conn, &scope, &stored_key, &stored)).await {
+        log::error!("could not keep the response for {HEADER} {key}: {err}");
+    }
+
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(bytes))))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{middleware, App};
+
+    use super::*;
+
+    const SCOPE: &str = "POST /payments";
+
+    fn stored(body: &str) -> Stored {
+        Stored { status: 200, content_type: Some("application/json".into()), body: body.as_bytes().to_vec() }
+    }
+
+    fn rejected(claim: Result<Claim, ApiError>) -> &'static str {
+        match claim {
+            Ok(_) => panic!("the key was claimed"),
+            Err(err) => err.code(),
+        }
+    }
+
+    #[test]
+    fn replays_the_stored_response() {
+        let mut conn = db::test_conn();
+        assert!(matches!(claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap(), Claim::New));
+        store(&conn, SCOPE, "k1", &stored(r#"{"id":"p1"}"#)).unwrap();
+
+        let Claim::Replay(replayed) = claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap() else {
+            panic!("the response was not replayed");
+        };
+        assert_eq!(replayed.status, 200);
+        assert_eq!(replayed.content_type.as_deref(), Some("application/json"));
+        assert_eq!(replayed.body, br#"{"id":"p1"}"#);
+    }
+
+    #[test]
+    fn rejects_a_key_reused_for_another_request() {
+        let mut conn = db::test_conn();
+        claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap();
+        assert_eq!(rejected(claim(&mut conn, SCOPE, "k1", "f2", 24)), "idempotency_key_reused");
+        store(&conn, SCOPE, "k1", &stored("{}")).unwrap();
+        assert_eq!(rejected(claim(&mut conn, SCOPE, "k1", "f2", 24)), "idempotency_key_reused");
+    }
+
+    #[test]
+    fn a_key_being_handled_is_in_progress_until_its_lease_runs_out() {
+        let mut conn = db::test_conn();
+        claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap();
+        assert_eq!(rejected(claim(&mut conn, SCOPE, "k1", "f1", 24)), "idempotency_key_in_progress");
+
+        //the request that claimed it crashed before storing a response
+        conn.execute("UPDATE idempotency_keys SET claimed_at = datetime('now', '-6 minutes')", []).unwrap();
+        assert!(matches!(claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap(), Claim::New));
+        assert_eq!(rejected(claim(&mut conn, SCOPE, "k1", "f1", 24)), "idempotency_key_in_progress");
+    }
+
+    #[test]
+    fn released_keys_can_be_retried() {
+        let mut conn = db::test_conn();
+        claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap();
+        release(&conn, SCOPE, "k1").unwrap();
+        assert!(matches!(claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap(), Claim::New));
+    }
+
+    #[test]
+    fn keys_are_scoped_to_method_and_path() {
+        let mut conn = db::test_conn();
+        claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap();
+        store(&conn, SCOPE, "k1", &stored("{}")).unwrap();
+        assert!(matches!(claim(&mut conn, "POST /bookings", "k1", "f2", 24).unwrap(), Claim::New));
+    }
+
+    #[test]
+    fn responses_kept_before_scoping_still_replay() {
+        let mut conn = db::test_conn();
+        conn.execute(
+            "INSERT INTO idempotency_keys (scope, key, fingerprint, status, body) VALUES ('', 'k1', 'f1', 201, x'7b7d')",
+            [],
+        ).unwrap();
+        assert!(matches!(claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap(), Claim::Replay(Stored { status: 201, .. })));
+        assert_eq!(rejected(claim(&mut conn, SCOPE, "k1", "f2", 24)), "idempotency_key_reused");
+    }
+
+    #[test]
+    fn expired_keys_are_forgotten() {
+        let mut conn = db::test_conn();
+        claim(&mut conn, SCOPE, "k1", "f1", 24).unwrap();
+        store(&conn, SCOPE, "k1", &stored("{}")).unwrap();
+        conn.execute("UPDATE idempotency_keys SET created_at = datetime('now', '-25 hours')", []).unwrap();
+        assert!(matches!(claim(&mut conn, SCOPE, "k1", "f2", 24).unwrap(), Claim::New));
+    }
+
+    #[test]
+    fn fingerprints_cover_method_path_and_body() {
+        let base = fingerprint(&Method::POST, "/payments", b"{}");
+        assert_eq!(base.len(), 64);
+        assert_eq!(base, fingerprint(&Method::POST, "/payments", b"{}"));
+        assert_ne!(base, fingerprint(&Method::PUT, "/payments", b"{}"));
+        assert_ne!(base, fingerprint(&Method::POST, "/payments?x=1", b"{}"));
+        assert_ne!(base, fingerprint(&Method::POST, "/payments", b"{ }"));
+    }
+
+    #[actix_web::test]
+    async fn answers_with_the_handler_response_when_the_key_cannot_be_released() {
+        let pool = db::test_pool();
+        let app = init_service(
+            App::new()
+                .wrap(middleware::from_fn(|req, next| guard(req, next, 24)))
+                .app_data(web::Data::new(pool.clone()))
+                .route("/fail", web::post().to(|pool: web::Data<DbPool>| async move {
+                    pool.get().unwrap().execute_batch("DROP TABLE idempotency_keys").unwrap();
+                    HttpResponse::ServiceUnavailable().body("down")
+                })),
+        ).await;
+
+        let req = TestRequest::post().uri("/fail").insert_header((HEADER, "k1")).to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(read_body(res).await, "down");
+    }
+}
@@ -0,0 +1,359 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use rusqlite::{params_from_iter, types::Value, Connection, Row};
+use serde::Serialize;
+
+use crate::error::{ApiError, FieldError};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 200;
+
+//how a filter parameter is compared against its column
+#[derive(Clone, Copy)]
+pub enum FilterOp {
+    Eq,
+    Contains,
+    Gte,
+    Lte,
+    Lt,
+}
+
+//the type a filter value must parse as before it is bound
+#[derive(Clone, Copy)]
+pub enum FilterKind {
+    Text,
+    Integer,
+}
+
+pub struct Filter {
+    pub param: &'static str,
+    pub expr: &'static str,
+    pub op: FilterOp,
+    pub kind: FilterKind,
+}
+
+//describes one listable resource: its base query, sortable fields and filters
+//expressions refer to the base query's columns through the alias `t`
+pub struct ListSpec {
+    pub select: &'static str,
+    pub sort_fields: &'static [(&'static str, &'static str)],
+    pub filters: &'static [Filter],
+}
+
+struct SortKey {
+    field: String,
+    descending: bool,
+}
+
+//`?limit=&after=&sort=field,-field` plus any resource filters, shared by every list endpoint
+pub struct ListQuery {
+    limit: usize,
+    after: Option<String>,
+    sort: Vec<SortKey>,
+    sort_param: String,
+    filters: Vec<(String, String)>,
+    //problems with the shared parameters, reported together with the resource-specific ones
+    errors: Vec<FieldError>,
+}
+
+#[derive(Serialize)]
+pub struct PageInfo {
+    pub limit: usize,
+    pub has_more: bool,
+    pub next_cursor: Option<String>,
+}
+
+//envelope returned by every list endpoint
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub page: PageInfo,
+}
+
+impl FromRequest for ListQuery {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::parse(req.query_string()))
+    }
+}
+
+impl ListQuery {
+    fn parse(query_string: &str) -> Result<Self, ApiError> {
+        let pairs = web::Query::<Vec<(String, String)>>::from_query(query_string)
+            .map_err(|err| ApiError::BadRequest(err.to_string()))?
+            .into_inner();
+
+        let mut query = ListQuery {
+            limit: DEFAULT_LIMIT,
+            after: None,
+            sort: Vec::new(),
+            sort_param: String::new(),
+            filters: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        for (name, value) in pairs {
+            match name.as_str() {
+                "limit" => match value.parse::<usize>() {
+                    Ok(limit) if (1..=MAX_LIMIT).contains(&limit) => query.limit = limit,
+                    _ => query.errors.push(field_error("limit", format!("must be a number between 1 and {MAX_LIMIT}"))),
+                },
+                "after" => query.after = Some(value),
+                "sort" => {
+                    query.sort = value
+                        .split(',')
+                        .filter(|field| !field.is_empty())
+                        .map(|field| match field.strip_prefix('-') {
+                            Some(field) => SortKey { field: field.to_string(), descending: true },
+                            None => SortKey { field: field.to_string(), descending: false },
+                        })
+                        .collect();
+                    query.sort_param = value;
+                }
+                _ => query.filters.push((name, value)),
+            }
+        }
+
+        Ok(query)
+    }
+}
+
+fn field_error(field: &str, message: impl Into<String>) -> FieldError {
+    FieldError { field: field.to_string(), message: message.into() }
+}
+
+fn parse_value(kind: FilterKind, raw: &str) -> Option<Value> {
+    match kind {
+        FilterKind::Text => Some(Value::Text(raw.to_string())),
+        FilterKind::Integer => raw.parse().ok().map(Value::Integer),
+    }
+}
+
+//cursors are the hex-encoded JSON of the sort parameter and the last row's sort values
+fn encode_cursor(sort_param: &str, values: &[Value]) -> String {
+    let values: Vec<serde_json::Value> = values
+        .iter()
+        .map(|value| match value {
+            Value::Null => serde_json::Value::Null,
+            Value::Integer(n) => serde_json::json!(n),
+            Value::Real(n) => serde_json::json!(n),
+            Value::Text(s) => serde_json::json!(s),
+            Value::Blob(_) => serde_json::Value::Null,
+        })
+        .collect();
+
+    let raw = serde_json::json!([sort_param, values]).to_string();
+    raw.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_cursor(cursor: &str, sort_param: &str, expected: usize) -> Option<Vec<Value>> {
+    if !cursor.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+        .collect();
+    let (sort, values): (String, Vec<serde_json::Value>) = serde_json::from_slice(&bytes?).ok()?;
+    if sort != sort_param || values.len() != expected {
+        return None;
+    }
+
+    values
+        .into_iter()
+        .map(|value| match value {
+            serde_json::Value::Null => Some(Value::Null),
+            serde_json::Value::String(s) => Some(Value::Text(s)),
+            serde_json::Value::Number(n) => n.as_i64().map(Value::Integer).or_else(|| n.as_f64().map(Value::Real)),
+            _ => None,
+        })
+        .collect()
+}
+
+//runs a filtered, sorted page of `spec` and builds the cursor for the next one
+pub fn fetch_page<T>(
+    conn: &Connection,
+    spec: &ListSpec,
+    query: &ListQuery,
+    map: impl Fn(&Row) -> rusqlite::Result<T>,
+) -> Result<Page<T>, ApiError> {
+    let mut errors = query.errors.clone();
+    let mut conditions = Vec::new();
+    let mut params: Vec<Value> = Vec::new();
+
+    for (name, raw) in &query.filters {
+        let Some(filter) = spec.filters.iter().find(|f| f.param == name) else {
+            errors.push(field_error(name, "unknown filter"));
+            continue;
+        };
+        let Some(value) = parse_value(filter.kind, raw) else {
+            errors.push(field_error(name, "has the wrong type"));
+            continue;
+        };
+
+        let condition = match filter.op {
+            FilterOp::Eq => format!("{} = ?", filter.expr),
+            FilterOp::Contains => format!("{} LIKE '%' || ? || '%'", filter.expr),
+            FilterOp::Gte => format!("{} >= ?", filter.expr),
+            FilterOp::Lte => format!("{} <= ?", filter.expr),
+            FilterOp::Lt => format!("{} < ?", filter.expr),
+        };
+        conditions.push(condition);
+        params.push(value);
+    }
+
+    //the id is always the last key so the order is total and cursors never skip rows
+    let mut keys: Vec<(&str, bool)> = Vec::new();
+    for key in &query.sort {
+        match spec.sort_fields.iter().find(|(field, _)| *field == key.field) {
+            Some((_, expr)) => keys.push((expr, key.descending)),
+            None => errors.push(field_error("sort", format!("cannot sort by {:?}", key.field))),
+        }
+    }
+    keys.push(("t.id", false));
+
+    if let Some(cursor) = &query.after {
+        match decode_cursor(cursor, &query.sort_param, keys.len()) {
+            Some(values) => {
+                //(k1 > v1) OR (k1 = v1 AND k2 > v2) OR ... with < for descending keys
+                let mut alternatives = Vec::new();
+                for (i, (expr, descending)) in keys.iter().enumerate() {
+                    let mut parts: Vec<String> = keys[..i].iter().map(|(e, _)| format!("{e} IS ?")).collect();
+                    parts.push(format!("{expr} {} ?", if *descending { "<" } else { ">" }));
+                    alternatives.push(format!("({})", parts.join(" AND ")));
+                    params.extend(values[..=i].iter().cloned());
+                }
+                conditions.push(format!("({})", alternatives.join(" OR ")));
+            }
+            None => errors.push(field_error("after", "invalid cursor for this sort")),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let key_columns: Vec<&str> = keys.iter().map(|(expr, _)| *expr).collect();
+    let order: Vec<String> = keys
+        .iter()
+        .map(|(expr, descending)| format!("{expr} {}", if *descending { "DESC" } else { "ASC" }))
+        .collect();
+    let sql = format!(
+        "SELECT t.*, {} FROM ({}) t {} ORDER BY {} LIMIT {}",
+        key_columns.join(", "),
+        spec.select,
+        if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) },
+        order.join(", "),
+        query.limit + 1,
+    );
+
+    let mut stmt = conn.prepare(&sql)?;
+    let first_key = stmt.column_count() - keys.len();
+    let mut rows = stmt.query(params_from_iter(params))?;
+
+    let mut data = Vec::new();
+    let mut last_keys = Vec::new();
+    let mut has_more = false;
+    while let Some(row) = rows.next()? {
+        if data.len() == query.limit {
+            has_more = true;
+            break;
+        }
+        data.push(map(row)?);
+        last_keys = (first_key..first_key + keys.len())
+            .map(|i| row.get::<_, Value>(i))
+            .collect::<rusqlite::Result<_>>()?;
+    }
+
+    Ok(Page {
+        data,
+        page: PageInfo {
+            limit: query.limit,
+            has_more,
+            next_cursor: has_more.then(|| encode_cursor(&query.sort_param, &last_keys)),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    const ROOMS: ListSpec = ListSpec {
+        select: "SELECT id, room_type, price_minor FROM rooms",
+        sort_fields: &[("price", "t.price_minor"), ("room_type", "t.room_type")],
+        filters: &[
+            Filter { param: "room_type", expr: "t.room_type", op: FilterOp::Eq, kind: FilterKind::Text },
+            Filter { param: "price_gte", expr: "t.price_minor", op: FilterOp::Gte, kind: FilterKind::Integer },
+        ],
+    };
+
+    fn page(conn: &Connection, query_string: &str) -> Result<Page<String>, ApiError> {
+        fetch_page(conn, &ROOMS, &ListQuery::parse(query_string)?, |row| row.get(0))
+    }
+
+    //every id the query lists, following the cursors one page of `limit` at a time
+    fn walk(conn: &Connection, query_string: &str, limit: usize) -> Vec<String> {
+        let mut ids = Vec::new();
+        let mut after = String::new();
+        loop {
+            let page = page(conn, &format!("{query_string}&limit={limit}{after}")).unwrap();
+            assert!(page.data.len() <= limit);
+            ids.extend(page.data);
+            match page.page.next_cursor {
+                Some(cursor) => after = format!("&after={cursor}"),
+                None => return ids,
+            }
+        }
+    }
+
+    fn fields(result: Result<Page<String>, ApiError>) -> Vec<String> {
+        match result {
+            Err(ApiError::Validation(errors)) => errors.into_iter().map(|error| error.field).collect(),
+            _ => panic!("the query was accepted"),
+        }
+    }
+
+    #[test]
+    fn walks_every_row_once_breaking_ties_by_id() {
+        let conn = db::test_hotel();
+        conn.execute("UPDATE rooms SET price_minor = 10000 WHERE id = 'r2'", []).unwrap();
+
+        assert_eq!(walk(&conn, "sort=-price", 1), ["s1", "r3", "r1", "r2"]);
+        assert_eq!(walk(&conn, "sort=price", 3), ["r1", "r2", "r3", "s1"]);
+        assert_eq!(walk(&conn, "sort=room_type,-price", 2), ["r3", "r1", "r2", "s1"]);
+        assert_eq!(walk(&conn, "", 50), ["r1", "r2", "r3", "s1"]);
+
+        let first = page(&conn, "sort=price&limit=2").unwrap();
+        assert_eq!((first.page.limit, first.page.has_more), (2, true));
+        let last = page(&conn, "sort=price&limit=4").unwrap();
+        assert_eq!((last.page.has_more, last.page.next_cursor), (false, None));
+    }
+
+    #[test]
+    fn filters_before_paging() {
+        let conn = db::test_hotel();
+        assert_eq!(walk(&conn, "room_type=double&price_gte=12000&sort=-price", 1), ["r3", "r2"]);
+    }
+
+    #[test]
+    fn reports_every_bad_parameter_at_once() {
+        let conn = db::test_hotel();
+        let errors = fields(page(&conn, "limit=0&sort=name&floor=2&price_gte=cheap"));
+        assert_eq!(errors, ["limit", "floor", "price_gte", "sort"]);
+        assert_eq!(fields(page(&conn, &format!("limit={}", MAX_LIMIT + 1))), ["limit"]);
+    }
+
+    #[test]
+    fn refuses_a_cursor_made_for_another_sort() {
+        let conn = db::test_hotel();
+        let cursor = page(&conn, "sort=price&limit=1").unwrap().page.next_cursor.unwrap();
+        assert_eq!(fields(page(&conn, &format!("sort=-price&after={cursor}"))), ["after"]);
+        assert_eq!(fields(page(&conn, "after=zz")), ["after"]);
+        assert_eq!(page(&conn, &format!("sort=price&after={cursor}")).unwrap().data, ["r2", "r3", "s1"]);
+    }
+}
@@ -1,18 +1,141 @@
-use actix_web::{App, HttpServer, web};
+use actix_web::{middleware::{self, Logger}, App, HttpServer, web};
+mod blocks;
+mod cancellation;
+mod config;
 mod db;
+mod error;
+mod exchange;
+mod folio;
+mod gateway;
+mod idempotency;
+mod listing;
+mod migrations;
 mod models;
+mod money;
+mod pricing;
+mod promotions;
+mod quotes;
 mod routes;
+mod taxes;
+mod validation;
+mod waitlist;
+
+//`hotel_project migrate status` lists migrations, `hotel_project migrate up` applies pending ones
+fn migrate(pool: &db::DbPool, action: Option<&str>) -> Result<(), migrations::MigrationError> {
+    let mut conn = pool.get().expect("could not get a database connection");
+
+    match action {
+        Some("status") => {
+            let version = migrations::current_version(&conn)?;
+            println!("database version {version}, binary version {}", migrations::latest_version());
+            for m in migrations::status(&conn)? {
+                match m.applied_at {
+                    Some(at) => println!("  {:04}_{:<24} applied {at}", m.version, m.name),
+                    None => println!("  {:04}_{:<24} pending", m.version, m.name),
+                }
+            }
+        }
+        Some("up") => {
+            let applied = migrations::run_pending(&mut conn)?;
+            if applied.is_empty() {
+                println!("✅ database already up to date");
+            }
+            for version in applied {
+                println!("⬆️  applied migration {version:04}");
+            }
+        }
+        _ => {
+            eprintln!("usage: hotel_project migrate <status|up>");
+            std::process::exit(2);
+        }
+    }
+
+    Ok(())
+}
+
+//`hotel_project rates load <file>` stores the exchange rates listed in a CSV file
+fn rates(pool: &db::DbPool, action: Option<&str>, file: Option<&str>) -> Result<(), exchange::LoadError> {
+    let mut conn = pool.get().expect("could not get a database connection");
+
+    match (action, file) {
+        (Some("load"), Some(file)) => {
+            let loaded = exchange::load_file(&mut conn, std::path::Path::new(file))?;
+            println!("💱 loaded {loaded} exchange rate(s) from {file}");
+        }
+        _ => {
+            eprintln!("usage: hotel_project rates load <file>");
+            std::process::exit(2);
+        }
+    }
+
+    Ok(())
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    db::init_db().expect("Database initialization failed");
-    println!("✅ Database ready");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cli = match config::Config::load(&args) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("❌ {err}");
+            eprintln!("{}", config::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if cli.help {
+        println!("{}", config::USAGE);
+        return Ok(());
+    }
+    let config = cli.config;
+
+    env_logger::Builder::new().parse_filters(&config.log_level).init();
+    for warning in &cli.warnings {
+        log::warn!("{warning}");
+    }
+
+    let pool = db::create_pool(&config.db_path).expect("Database pool creation failed");
+
+    if cli.command.first().map(String::as_str) == Some("migrate") {
+        if let Err(err) = migrate(&pool, cli.command.get(1).map(String::as_str)) {
+            eprintln!("❌ {err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Err(err) = db::init_db(&pool) {
+        eprintln!("❌ Database initialization failed: {err}");
+        std::process::exit(1);
+    }
+    println!("✅ Database ready at {}", config.db_path);
+
+    if cli.command.first().map(String::as_str) == Some("rates") {
+        let arg = |i: usize| cli.command.get(i).map(String::as_str);
+        if let Err(err) = rates(&pool, arg(1), arg(2)) {
+            eprintln!("❌ {err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    HttpServer::new(|| {
+    let features = config.features.clone();
+    let gateway = gateway::from_config(&config.gateway);
+    let retention_hours = config.idempotency_retention_hours;
+    let modification_rates = config.modification_rates;
+    let hold = waitlist::HoldWindow(config.waitlist_hold_minutes);
+    waitlist::start_sweeper(pool.clone(), hold);
+    HttpServer::new(move || {
         App::new()
-            .configure(routes::config)
+            .wrap(middleware::from_fn(move |req, next| idempotency::guard(req, next, retention_hours)))
+            .wrap(Logger::default())
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(gateway.clone()))
+            .app_data(web::Data::new(modification_rates))
+            .app_data(web::Data::new(hold))
+            .configure(|cfg| routes::config(cfg, &features))
     })
-    .bind(("127.0.0.1", 3000))?
+    .workers(config.workers)
+    .bind((config.bind.as_str(), config.port))?
     .run()
     .await
 }
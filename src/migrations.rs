@@ -0,0 +1,207 @@
+use std::fmt;
+
+use rusqlite::{Connection, OptionalExtension};
+
+//one schema change, applied in its own transaction and recorded in schema_migrations
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+//every migration this binary knows about, in order
+//new files in migrations/ must be appended here with the next version number
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "initial", sql: include_str!("../migrations/0001_initial.sql") },
+    Migration { version: 2, name: "booking_status", sql: include_str!("../migrations/0002_booking_status.sql") },
+    Migration { version: 3, name: "room_housekeeping", sql: include_str!("../migrations/0003_room_housekeeping.sql") },
+    Migration { version: 4, name: "money", sql: include_str!("../migrations/0004_money.sql") },
+    Migration { version: 5, name: "exchange_rates", sql: include_str!("../migrations/0005_exchange_rates.sql") },
+    Migration { version: 6, name: "rate_plans", sql: include_str!("../migrations/0006_rate_plans.sql") },
+    Migration { version: 7, name: "quotes", sql: include_str!("../migrations/0007_quotes.sql") },
+    Migration { version: 8, name: "taxes", sql: include_str!("../migrations/0008_taxes.sql") },
+    Migration { version: 9, name: "promotions", sql: include_str!("../migrations/0009_promotions.sql") },
+    Migration { version: 10, name: "folios", sql: include_str!("../migrations/0010_folios.sql") },
+    Migration { version: 11, name: "refunds", sql: include_str!("../migrations/0011_refunds.sql") },
+    Migration { version: 12, name: "payment_gateway", sql: include_str!("../migrations/0012_payment_gateway.sql") },
+    Migration { version: 13, name: "idempotency", sql: include_str!("../migrations/0013_idempotency.sql") },
+    Migration { version: 14, name: "cancellation_policies", sql: include_str!("../migrations/0014_cancellation_policies.sql") },
+    Migration { version: 15, name: "booking_amendments", sql: include_str!("../migrations/0015_booking_amendments.sql") },
+    Migration { version: 16, name: "reservations", sql: include_str!("../migrations/0016_reservations.sql") },
+    Migration { version: 17, name: "room_blocks", sql: include_str!("../migrations/0017_room_blocks.sql") },
+    Migration { version: 18, name: "waitlist", sql: include_str!("../migrations/0018_waitlist.sql") },
+    Migration { version: 19, name: "payment_gateway_calls", sql: include_str!("../migrations/0019_payment_gateway_calls.sql") },
+    Migration { version: 20, name: "idempotency_scope", sql: include_str!("../migrations/0020_idempotency_scope.sql") },
+    Migration { version: 21, name: "refund_reconciliation", sql: include_str!("../migrations/0021_refund_reconciliation.sql") },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    //the database was migrated by a newer binary, running against it could corrupt data
+    DatabaseTooNew { database: i64, binary: i64 },
+    Failed { version: i64, name: &'static str, source: rusqlite::Error },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(err) => write!(f, "migration bookkeeping failed: {err}"),
+            MigrationError::DatabaseTooNew { database, binary } => write!(
+                f,
+                "database schema is at version {database} but this binary only knows up to {binary}, refusing to start"
+            ),
+            MigrationError::Failed { version, name, source } => {
+                write!(f, "migration {version:04}_{name} failed and was rolled back: {source}")
+            }
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(err)
+    }
+}
+
+//status of one known migration as reported by `migrate status`
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: &'static str,
+    pub applied_at: Option<String>,
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+fn ensure_metadata_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at DATETIME NOT NULL DEFAULT (datetime('now'))
+        );"
+    )
+}
+
+//highest applied version, 0 for a fresh database
+pub fn current_version(conn: &Connection) -> Result<i64, MigrationError> {
+    ensure_metadata_table(conn)?;
+    let version: Option<i64> = conn.query_row("SELECT MAX(version) FROM schema_migrations", [], |row| row.get(0))?;
+    Ok(version.unwrap_or(0))
+}
+
+//fails when the database has been migrated past what this binary ships
+pub fn check_compatible(conn: &Connection) -> Result<i64, MigrationError> {
+    let database = current_version(conn)?;
+    let binary = latest_version();
+    if database > binary {
+        return Err(MigrationError::DatabaseTooNew { database, binary });
+    }
+    Ok(database)
+}
+
+//applies every pending migration in order and returns the versions that ran
+pub fn run_pending(conn: &mut Connection) -> Result<Vec<i64>, MigrationError> {
+    apply(conn, MIGRATIONS)
+}
+
+//applies the migrations of `migrations` the database has not seen yet, each in its own transaction
+fn apply(conn: &mut Connection, migrations: &[Migration]) -> Result<Vec<i64>, MigrationError> {
+    let current = check_compatible(conn)?;
+    let mut applied = Vec::new();
+
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        let failed = |source| MigrationError::Failed { version: migration.version, name: migration.name, source };
+
+        let tx = conn.transaction().map_err(failed)?;
+        tx.execute_batch(migration.sql).map_err(failed)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+            (migration.version, migration.name),
+        ).map_err(failed)?;
+        tx.commit().map_err(failed)?;
+
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+//every known migration with the time it was applied, if it was
+pub fn status(conn: &Connection) -> Result<Vec<MigrationStatus>, MigrationError> {
+    ensure_metadata_table(conn)?;
+    let mut stmt = conn.prepare("SELECT applied_at FROM schema_migrations WHERE version = ?1")?;
+
+    MIGRATIONS
+        .iter()
+        .map(|m| {
+            let applied_at = stmt.query_row([m.version], |row| row.get(0)).optional()?;
+            Ok(MigrationStatus { version: m.version, name: m.name, applied_at })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tables(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name").unwrap();
+        stmt.query_map([], |row| row.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn versions_follow_each_other_from_one() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i64 + 1, "{} is out of order", migration.name);
+        }
+    }
+
+    #[test]
+    fn runs_every_migration_once_in_order() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let applied = run_pending(&mut conn).unwrap();
+        assert_eq!(applied, (1..=latest_version()).collect::<Vec<_>>());
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+
+        assert!(run_pending(&mut conn).unwrap().is_empty());
+        assert!(status(&conn).unwrap().iter().all(|m| m.applied_at.is_some()));
+    }
+
+    #[test]
+    fn refuses_a_database_from_a_newer_binary() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_pending(&mut conn).unwrap();
+        conn.execute("INSERT INTO schema_migrations (version, name) VALUES (?1, 'future')", [latest_version() + 1]).unwrap();
+
+        match run_pending(&mut conn) {
+            Err(MigrationError::DatabaseTooNew { database, binary }) => {
+                assert_eq!(database, latest_version() + 1);
+                assert_eq!(binary, latest_version());
+            }
+            other => panic!("expected DatabaseTooNew, got {:?}", other.map_err(|err| err.to_string())),
+        }
+    }
+
+    #[test]
+    fn a_failing_migration_is_rolled_back_on_its_own() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let good = Migration { version: 1, name: "good", sql: "CREATE TABLE a (id INTEGER);" };
+        let broken = Migration { version: 2, name: "broken", sql: "CREATE TABLE b (id INTEGER); CREATE TABLE c (;" };
+
+        match apply(&mut conn, &[good, broken]) {
+            Err(MigrationError::Failed { version: 2, name: "broken", .. }) => {}
+            other => panic!("expected migration 2 to fail, got {:?}", other.map_err(|err| err.to_string())),
+        }
+        assert_eq!(current_version(&conn).unwrap(), 1);
+        assert_eq!(tables(&conn), ["a", "schema_migrations"]);
+
+        //once fixed only the migration that failed runs
+        let good = Migration { version: 1, name: "good", sql: "CREATE TABLE a (id INTEGER);" };
+        let fixed = Migration { version: 2, name: "fixed", sql: "CREATE TABLE b (id INTEGER);" };
+        assert_eq!(apply(&mut conn, &[good, fixed]).unwrap(), [2]);
+        assert_eq!(tables(&conn), ["a", "b", "schema_migrations"]);
+    }
+}
@@ -1,11 +1,31 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
 use serde::{Serialize, Deserialize};
 
+use crate::error::ApiError;
+use crate::money::{Currency, Money, Rate};
+use crate::validation::{Rules, Validate};
+
 #[derive(Serialize, Deserialize)]
 pub struct Hotel {
     pub id: Option<String>,
     pub name: String,
     pub location: String,
     pub stars: i32,
+    //base currency every room of the hotel is priced and settled in
+    pub currency: Currency,
+    //policy of bookings whose rate plan has none
+    #[serde(default)]
+    pub cancellation_policy_id: Option<String>,
+}
+
+impl Validate for Hotel {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("name", &self.name)
+            .not_blank("location", &self.location)
+            .range("stars", self.stars.into(), 1, 5)
+            .finish()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -13,8 +33,91 @@ pub struct Room {
     pub id: Option<String>,
     pub hotel_id: String,
     pub room_type: String,
-    pub price: f64,
-    pub status: String, // "available" / "occupied"
+    pub price: Money,
+    #[serde(default)]
+    pub housekeeping: HousekeepingStatus,
+    //derived from checked-in bookings and housekeeping, never set by clients
+    #[serde(default, skip_deserializing)]
+    pub status: RoomStatus,
+}
+
+impl Validate for Room {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("hotel_id", &self.hotel_id)
+            .not_blank("room_type", &self.room_type)
+            .positive("price", &self.price)
+            .finish()
+    }
+}
+
+
+//housekeeping state of a room, stored as snake_case text in rooms.housekeeping
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HousekeepingStatus {
+    #[default]
+    Clean,
+    Dirty,
+    Inspected,
+    OutOfOrder,
+}
+
+impl HousekeepingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HousekeepingStatus::Clean => "clean",
+            HousekeepingStatus::Dirty => "dirty",
+            HousekeepingStatus::Inspected => "inspected",
+            HousekeepingStatus::OutOfOrder => "out_of_order",
+        }
+    }
+}
+
+impl ToSql for HousekeepingStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for HousekeepingStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "clean" => Ok(HousekeepingStatus::Clean),
+            "dirty" => Ok(HousekeepingStatus::Dirty),
+            "inspected" => Ok(HousekeepingStatus::Inspected),
+            "out_of_order" => Ok(HousekeepingStatus::OutOfOrder),
+            other => Err(FromSqlError::Other(format!("unknown housekeeping status: {other}").into())),
+        }
+    }
+}
+
+
+//occupancy of a room as worked out by routes::ROOM_SELECT
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomStatus {
+    #[default]
+    Available,
+    Occupied,
+    OutOfOrder,
+}
+
+impl FromSql for RoomStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "available" => Ok(RoomStatus::Available),
+            "occupied" => Ok(RoomStatus::Occupied),
+            "out_of_order" => Ok(RoomStatus::OutOfOrder),
+            other => Err(FromSqlError::Other(format!("unknown room status: {other}").into())),
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct HousekeepingUpdate {
+    pub housekeeping: HousekeepingStatus,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -23,6 +126,59 @@ pub struct Guest {
     pub name: String,
     pub phone: String,
     pub email: String,
+    //decides which tax rules the guest is exempt from
+    #[serde(default)]
+    pub guest_type: GuestType,
+}
+
+impl Validate for Guest {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("name", &self.name)
+            .phone("phone", &self.phone)
+            .email("email", &self.email)
+            .finish()
+    }
+}
+
+//kind of guest for tax purposes, stored as snake_case text in guests.guest_type
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GuestType {
+    #[default]
+    Standard,
+    Resident,
+    Business,
+    Diplomat,
+}
+
+impl GuestType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GuestType::Standard => "standard",
+            GuestType::Resident => "resident",
+            GuestType::Business => "business",
+            GuestType::Diplomat => "diplomat",
+        }
+    }
+}
+
+impl ToSql for GuestType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for GuestType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "standard" => Ok(GuestType::Standard),
+            "resident" => Ok(GuestType::Resident),
+            "business" => Ok(GuestType::Business),
+            "diplomat" => Ok(GuestType::Diplomat),
+            other => Err(FromSqlError::Other(format!("unknown guest type: {other}").into())),
+        }
+    }
 }
 
 
@@ -34,13 +190,1409 @@ pub struct Booking {
     pub hotel_id: String,
     pub check_in: String,
     pub check_out: String,
+    //people staying, per-person taxes are charged for each of them
+    #[serde(default = "default_guests")]
+    pub guests: i64,
+    #[serde(default)]
+    pub status: BookingStatus,
+    //plan the stay is priced on, the room's flat price when left out
+    #[serde(default)]
+    pub rate_plan_id: Option<String>,
+    //stored nights plus taxes and fees, filled in by the server
+    #[serde(default, skip_deserializing)]
+    pub total_price: Option<Money>,
+    //quote whose prices the booking takes over instead of being priced again
+    #[serde(default)]
+    pub quote_id: Option<String>,
+    //codes redeemed by the booking, a quoted booking takes the quote's
+    #[serde(default)]
+    pub promo_codes: Vec<String>,
+    //policy the booking was sold with, filled in by the server
+    #[serde(default, skip_deserializing)]
+    pub cancellation_policy: Option<CancellationPolicy>,
+    //group reservation the booking is a room-stay of, set when the reservation is made
+    #[serde(default, skip_deserializing)]
+    pub reservation_id: Option<String>,
+    //room block the booking draws on, fixed when the booking is made
+    #[serde(default)]
+    pub block_code: Option<String>,
+    //why the stay was changed, kept in the amendment history when a booking is updated
+    #[serde(default, skip_serializing)]
+    pub reason: Option<String>,
+}
+
+impl Validate for Booking {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("guest_id", &self.guest_id)
+            .not_blank("room_id", &self.room_id)
+            .not_blank("hotel_id", &self.hotel_id)
+            .iso_date("check_in", &self.check_in)
+            .iso_date("check_out", &self.check_out)
+            .date_after("check_out", "check_in", &self.check_in, &self.check_out)
+            .range("guests", self.guests, 1, 20)
+            .finish()
+    }
+}
+
+fn default_guests() -> i64 {
+    1
+}
+
+//several room-stays booked together and billed to one guest; created and cancelled as a whole
+#[derive(Serialize, Deserialize)]
+pub struct Reservation {
+    pub id: Option<String>,
+    pub hotel_id: String,
+    pub billing_guest_id: String,
+    //group or party name shown to staff
+    #[serde(default)]
+    pub name: Option<String>,
+    //only read on create, the stays are booked as bookings of their own
+    #[serde(default, skip_serializing)]
+    pub stays: Vec<RoomStay>,
+    //room-stays in the reservation, cancelled ones included
+    #[serde(default, skip_deserializing)]
+    pub rooms: i64,
+    #[serde(default, skip_deserializing)]
+    pub created_at: String,
+}
+
+impl Validate for Reservation {
+    fn validate(&self) -> Result<(), ApiError> {
+        let mut rules = Rules::new()
+            .not_blank("hotel_id", &self.hotel_id)
+            .not_blank("billing_guest_id", &self.billing_guest_id)
+            .check("stays", (1..=MAX_ROOM_STAYS).contains(&self.stays.len()), "must list 1 to 50 room-stays");
+        for (i, stay) in self.stays.iter().enumerate() {
+            let field = |name: &str| format!("stays[{i}].{name}");
+            rules = rules
+                .not_blank(&field("room_id"), &stay.room_id)
+                .iso_date(&field("check_in"), &stay.check_in)
+                .iso_date(&field("check_out"), &stay.check_out)
+                .date_after(&field("check_out"), &field("check_in"), &stay.check_in, &stay.check_out)
+                .range(&field("guests"), stay.guests, 1, 20);
+            if let Some(guest_id) = &stay.guest_id {
+                rules = rules.not_blank(&field("guest_id"), guest_id);
+            }
+        }
+        rules.finish()
+    }
+}
+
+const MAX_ROOM_STAYS: usize = 50;
+
+//one room of a reservation, priced and kept like a single booking
+#[derive(Deserialize)]
+pub struct RoomStay {
+    pub room_id: String,
+    //who sleeps in the room, the billing guest when left out
+    #[serde(default)]
+    pub guest_id: Option<String>,
+    pub check_in: String,
+    pub check_out: String,
+    #[serde(default = "default_guests")]
+    pub guests: i64,
+    #[serde(default)]
+    pub rate_plan_id: Option<String>,
+    #[serde(default)]
+    pub promo_codes: Vec<String>,
+    #[serde(default)]
+    pub block_code: Option<String>,
+}
+
+//room-stays of a reservation to cancel, every one still open when empty
+#[derive(Deserialize, Default)]
+pub struct ReservationCancel {
+    #[serde(default)]
+    pub booking_ids: Vec<String>,
+}
+
+//rooms of one type held for an event or tour operator before the guests are known;
+//bookings quoting the code draw it down and the rest goes back on sale at the cutoff
+#[derive(Serialize, Deserialize)]
+pub struct RoomBlock {
+    #[serde(default, skip_deserializing)]
+    pub id: Option<String>,
+    pub hotel_id: String,
+    pub room_type: String,
+    pub code: String,
+    pub name: String,
+    //nights held, end_date excluded like a check-out
+    pub start_date: String,
+    pub end_date: String,
+    //rooms held each night
+    pub rooms: i64,
+    //unpicked rooms are released from this day on
+    pub cutoff_date: String,
+    //most rooms booked on any night of the block, filled in by the server
+    #[serde(default, skip_deserializing)]
+    pub picked_up: i64,
+    #[serde(default, skip_deserializing)]
+    pub status: RoomBlockStatus,
+    //when the rooms went back on sale, by hand or at the cutoff
+    #[serde(default, skip_deserializing)]
+    pub released_at: Option<String>,
+}
+
+impl Validate for RoomBlock {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("hotel_id", &self.hotel_id)
+            .not_blank("room_type", &self.room_type)
+            .not_blank("code", &self.code)
+            .check("code", !self.code.trim().contains(char::is_whitespace), "must not contain spaces")
+            .not_blank("name", &self.name)
+            .iso_date("start_date", &self.start_date)
+            .iso_date("end_date", &self.end_date)
+            .date_after("end_date", "start_date", &self.start_date, &self.end_date)
+            .range("rooms", self.rooms, 1, 500)
+            .iso_date("cutoff_date", &self.cutoff_date)
+            .check("cutoff_date", self.cutoff_date <= self.start_date, "must not be after start_date")
+            .finish()
+    }
+}
+
+//released once the cutoff has passed or staff let the rooms go
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomBlockStatus {
+    #[default]
+    Open,
+    Released,
+}
+
+impl FromSql for RoomBlockStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "open" => Ok(RoomBlockStatus::Open),
+            "released" => Ok(RoomBlockStatus::Released),
+            other => Err(FromSqlError::Other(format!("unknown room block status: {other}").into())),
+        }
+    }
+}
+
+//a guest waiting for a room type that is sold out for their dates
+#[derive(Serialize, Deserialize)]
+pub struct WaitlistEntry {
+    #[serde(default, skip_deserializing)]
+    pub id: Option<String>,
+    pub hotel_id: String,
+    pub guest_id: String,
+    pub room_type: String,
+    pub check_in: String,
+    pub check_out: String,
+    #[serde(default = "default_guests")]
+    pub guests: i64,
+    #[serde(default, skip_deserializing)]
+    pub status: WaitlistStatus,
+    #[serde(default, skip_deserializing)]
+    pub created_at: String,
+}
+
+impl Validate for WaitlistEntry {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("hotel_id", &self.hotel_id)
+            .not_blank("guest_id", &self.guest_id)
+            .not_blank("room_type", &self.room_type)
+            .iso_date("check_in", &self.check_in)
+            .iso_date("check_out", &self.check_out)
+            .date_after("check_out", "check_in", &self.check_in, &self.check_out)
+            .range("guests", self.guests, 1, 20)
+            .finish()
+    }
+}
+
+//where a waitlisted guest stands, stored as snake_case text in waitlist_entries.status
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitlistStatus {
+    #[default]
+    Waiting,
+    //a room is held for the guest until the offer expires
+    Offered,
+    Booked,
+    Declined,
+    //the offer ran out, or the stay began before a room freed up
+    Expired,
+    Withdrawn,
+}
+
+impl WaitlistStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WaitlistStatus::Waiting => "waiting",
+            WaitlistStatus::Offered => "offered",
+            WaitlistStatus::Booked => "booked",
+            WaitlistStatus::Declined => "declined",
+            WaitlistStatus::Expired => "expired",
+            WaitlistStatus::Withdrawn => "withdrawn",
+        }
+    }
+}
+
+impl ToSql for WaitlistStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for WaitlistStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "waiting" => Ok(WaitlistStatus::Waiting),
+            "offered" => Ok(WaitlistStatus::Offered),
+            "booked" => Ok(WaitlistStatus::Booked),
+            "declined" => Ok(WaitlistStatus::Declined),
+            "expired" => Ok(WaitlistStatus::Expired),
+            "withdrawn" => Ok(WaitlistStatus::Withdrawn),
+            other => Err(FromSqlError::Other(format!("unknown waitlist status: {other}").into())),
+        }
+    }
+}
+
+//a room held for a waitlisted guest until expires_at
+#[derive(Serialize)]
+pub struct WaitlistOffer {
+    pub id: String,
+    pub entry_id: String,
+    pub room_id: String,
+    pub offered_at: String,
+    pub expires_at: String,
+    pub status: OfferStatus,
+    pub closed_at: Option<String>,
+    pub booking_id: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OfferStatus {
+    Pending,
+    Accepted,
+    Declined,
+    Expired,
+    Withdrawn,
+}
+
+impl OfferStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OfferStatus::Pending => "pending",
+            OfferStatus::Accepted => "accepted",
+            OfferStatus::Declined => "declined",
+            OfferStatus::Expired => "expired",
+            OfferStatus::Withdrawn => "withdrawn",
+        }
+    }
+}
+
+impl ToSql for OfferStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for OfferStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "pending" => Ok(OfferStatus::Pending),
+            "accepted" => Ok(OfferStatus::Accepted),
+            "declined" => Ok(OfferStatus::Declined),
+            "expired" => Ok(OfferStatus::Expired),
+            "withdrawn" => Ok(OfferStatus::Withdrawn),
+            other => Err(FromSqlError::Other(format!("unknown offer status: {other}").into())),
+        }
+    }
+}
+
+//how an accepted offer is priced, the room's flat price when left out
+#[derive(Deserialize, Default)]
+pub struct OfferAcceptance {
+    #[serde(default)]
+    pub rate_plan_id: Option<String>,
+    #[serde(default)]
+    pub promo_codes: Vec<String>,
+}
+
+
+//lifecycle of a booking, stored as snake_case text in bookings.status
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BookingStatus {
+    #[default]
+    Tentative,
+    Confirmed,
+    CheckedIn,
+    CheckedOut,
+    Cancelled,
+    NoShow,
+}
+
+impl BookingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BookingStatus::Tentative => "tentative",
+            BookingStatus::Confirmed => "confirmed",
+            BookingStatus::CheckedIn => "checked_in",
+            BookingStatus::CheckedOut => "checked_out",
+            BookingStatus::Cancelled => "cancelled",
+            BookingStatus::NoShow => "no_show",
+        }
+    }
+
+    //tentative -> confirmed -> checked_in -> checked_out, with cancelled / no_show as dead ends
+    pub fn can_transition_to(&self, next: BookingStatus) -> bool {
+        use BookingStatus::*;
+        matches!(
+            (self, next),
+            (Tentative, Confirmed)
+                | (Tentative, Cancelled)
+                | (Confirmed, CheckedIn)
+                | (Confirmed, Cancelled)
+                | (Confirmed, NoShow)
+                | (CheckedIn, CheckedOut)
+        )
+    }
+}
+
+impl ToSql for BookingStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for BookingStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "tentative" => Ok(BookingStatus::Tentative),
+            "confirmed" => Ok(BookingStatus::Confirmed),
+            "checked_in" => Ok(BookingStatus::CheckedIn),
+            "checked_out" => Ok(BookingStatus::CheckedOut),
+            "cancelled" => Ok(BookingStatus::Cancelled),
+            "no_show" => Ok(BookingStatus::NoShow),
+            other => Err(FromSqlError::Other(format!("unknown booking status: {other}").into())),
+        }
+    }
+}
+
+
+//commercial flavour of a rate plan, stored as snake_case text in rate_plans.kind
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RatePlanKind {
+    Bar,
+    NonRefundable,
+    BreakfastIncluded,
+    Corporate,
+}
+
+impl RatePlanKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RatePlanKind::Bar => "bar",
+            RatePlanKind::NonRefundable => "non_refundable",
+            RatePlanKind::BreakfastIncluded => "breakfast_included",
+            RatePlanKind::Corporate => "corporate",
+        }
+    }
+}
+
+impl ToSql for RatePlanKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for RatePlanKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "bar" => Ok(RatePlanKind::Bar),
+            "non_refundable" => Ok(RatePlanKind::NonRefundable),
+            "breakfast_included" => Ok(RatePlanKind::BreakfastIncluded),
+            "corporate" => Ok(RatePlanKind::Corporate),
+            other => Err(FromSqlError::Other(format!("unknown rate plan kind: {other}").into())),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RatePlan {
+    pub id: Option<String>,
+    pub hotel_id: String,
+    pub code: String,
+    pub name: String,
+    pub kind: RatePlanKind,
+    //the hotel's policy applies when left out
+    #[serde(default)]
+    pub cancellation_policy_id: Option<String>,
+}
+
+impl Validate for RatePlan {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("hotel_id", &self.hotel_id)
+            .not_blank("code", &self.code)
+            .not_blank("name", &self.name)
+            .finish()
+    }
+}
+
+//prices of one room type on a rate plan for the nights in [start_date, end_date)
+//Friday and Saturday nights use weekend_price; the narrowest season covering a night wins
+#[derive(Serialize, Deserialize)]
+pub struct RateSeason {
+    pub id: Option<i64>,
+    pub room_type: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub weekday_price: Money,
+    pub weekend_price: Money,
+    //shortest stay allowed when arriving in this season
+    #[serde(default = "default_min_stay")]
+    pub min_stay: i64,
+    #[serde(default)]
+    pub closed_to_arrival: bool,
+    #[serde(default)]
+    pub closed_to_departure: bool,
+}
+
+fn default_min_stay() -> i64 {
+    1
+}
+
+impl Validate for RateSeason {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("room_type", &self.room_type)
+            .iso_date("start_date", &self.start_date)
+            .iso_date("end_date", &self.end_date)
+            .date_after("end_date", "start_date", &self.start_date, &self.end_date)
+            .positive("weekday_price", &self.weekday_price)
+            .positive("weekend_price", &self.weekend_price)
+            .range("min_stay", self.min_stay, 1, 365)
+            .finish()
+    }
+}
+
+//one night of a booking and what it costs
+#[derive(Serialize, Clone)]
+pub struct NightlyPrice {
+    pub night: String,
+    pub price: Money,
+    pub weekend: bool,
+    //season the price came from, none when priced from the room
+    pub rate_season_id: Option<i64>,
+}
+
+//whether a rule is a tax filed with the authorities or a fee the hotel keeps
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeCategory {
+    Tax,
+    Fee,
+}
+
+impl ChargeCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChargeCategory::Tax => "tax",
+            ChargeCategory::Fee => "fee",
+        }
+    }
+}
+
+impl ToSql for ChargeCategory {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for ChargeCategory {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "tax" => Ok(ChargeCategory::Tax),
+            "fee" => Ok(ChargeCategory::Fee),
+            other => Err(FromSqlError::Other(format!("unknown charge category: {other}").into())),
+        }
+    }
+}
+
+//what a tax rule is charged on
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TaxBasis {
+    //a share of the room subtotal, like VAT or a service charge
+    Percent,
+    //a flat amount for every night
+    PerNight,
+    //a flat amount for every guest and night, like most city taxes
+    PerPersonPerNight,
+}
+
+impl TaxBasis {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaxBasis::Percent => "percent",
+            TaxBasis::PerNight => "per_night",
+            TaxBasis::PerPersonPerNight => "per_person_per_night",
+        }
+    }
+}
+
+impl ToSql for TaxBasis {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for TaxBasis {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "percent" => Ok(TaxBasis::Percent),
+            "per_night" => Ok(TaxBasis::PerNight),
+            "per_person_per_night" => Ok(TaxBasis::PerPersonPerNight),
+            other => Err(FromSqlError::Other(format!("unknown tax basis: {other}").into())),
+        }
+    }
+}
+
+//a tax or fee a hotel charges on every stay
+#[derive(Serialize, Deserialize)]
+pub struct TaxRule {
+    pub id: Option<i64>,
+    pub name: String,
+    pub category: ChargeCategory,
+    pub basis: TaxBasis,
+    //for percent rules, "7.7" is 7.7% of the room subtotal
+    #[serde(default)]
+    pub percent: Option<Rate>,
+    //for per night and per person rules, in the hotel's currency
+    #[serde(default)]
+    pub amount: Option<Money>,
+    //nights after which a per night or per person rule stops being charged
+    #[serde(default)]
+    pub max_nights: Option<i64>,
+    #[serde(default)]
+    pub exempt_guest_types: Vec<GuestType>,
+}
+
+impl Validate for TaxRule {
+    fn validate(&self) -> Result<(), ApiError> {
+        let mut rules = Rules::new().not_blank("name", &self.name);
+        if self.basis == TaxBasis::Percent {
+            rules = rules
+                .check("percent", self.percent.is_some_and(|p| p <= Rate::HUNDRED), "must be between 0 and 100")
+                .check("amount", self.amount.is_none(), "must be left out for percent rules")
+                .check("max_nights", self.max_nights.is_none(), "must be left out for percent rules");
+        } else {
+            rules = rules
+                .check("amount", self.amount.is_some_and(|a| a.is_positive()), "must be greater than zero")
+                .check("percent", self.percent.is_none(), "must be left out unless basis is percent");
+            if let Some(max_nights) = self.max_nights {
+                rules = rules.range("max_nights", max_nights, 1, 365);
+            }
+        }
+        rules.finish()
+    }
+}
+
+//one tax or fee of a priced stay, with what it was worked out from
+#[derive(Serialize)]
+pub struct ChargeLine {
+    pub tax_rule_id: Option<i64>,
+    pub name: String,
+    pub category: ChargeCategory,
+    pub basis: TaxBasis,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<Rate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_amount: Option<Money>,
+    //nights or guest nights charged, after the max_nights cap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<i64>,
+    pub amount: Money,
+}
+
+//how a promotion takes money off a stay
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountKind {
+    //a share of the nights' subtotal
+    Percent,
+    //a fixed amount off, never more than the subtotal
+    Fixed,
+}
+
+impl DiscountKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiscountKind::Percent => "percent",
+            DiscountKind::Fixed => "fixed",
+        }
+    }
+}
+
+impl ToSql for DiscountKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for DiscountKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "percent" => Ok(DiscountKind::Percent),
+            "fixed" => Ok(DiscountKind::Fixed),
+            other => Err(FromSqlError::Other(format!("unknown discount kind: {other}").into())),
+        }
+    }
+}
+
+//a promo code and the stays it can be used for
+#[derive(Serialize, Deserialize)]
+pub struct Promotion {
+    pub id: Option<String>,
+    pub code: String,
+    pub name: String,
+    pub kind: DiscountKind,
+    #[serde(default)]
+    pub percent: Option<Rate>,
+    #[serde(default)]
+    pub amount: Option<Money>,
+    //days the code can be used on, valid_until excluded
+    pub valid_from: String,
+    pub valid_until: String,
+    //hotels and room types the code is limited to, all of them when empty
+    #[serde(default)]
+    pub hotel_ids: Vec<String>,
+    #[serde(default)]
+    pub room_types: Vec<String>,
+    #[serde(default = "default_min_stay")]
+    pub min_nights: i64,
+    //redemptions allowed in total and per guest, cancelled bookings give theirs back
+    #[serde(default)]
+    pub max_uses: Option<i64>,
+    #[serde(default)]
+    pub max_uses_per_guest: Option<i64>,
+    //whether the code can be combined with other codes
+    #[serde(default)]
+    pub stackable: bool,
+}
+
+impl Validate for Promotion {
+    fn validate(&self) -> Result<(), ApiError> {
+        let mut rules = Rules::new()
+            .not_blank("code", &self.code)
+            .check("code", !self.code.trim().contains(char::is_whitespace), "must not contain spaces")
+            .not_blank("name", &self.name)
+            .iso_date("valid_from", &self.valid_from)
+            .iso_date("valid_until", &self.valid_until)
+            .date_after("valid_until", "valid_from", &self.valid_from, &self.valid_until)
+            .range("min_nights", self.min_nights, 1, 365);
+        rules = match self.kind {
+            DiscountKind::Percent => rules
+                .check("percent", self.percent.is_some_and(|p| p <= Rate::HUNDRED), "must be between 0 and 100")
+                .check("amount", self.amount.is_none(), "must be left out for percent discounts"),
+            DiscountKind::Fixed => rules
+                .check("amount", self.amount.is_some_and(|a| a.is_positive()), "must be greater than zero")
+                .check("percent", self.percent.is_none(), "must be left out for fixed discounts"),
+        };
+        rules
+            .check("max_uses", self.max_uses.is_none_or(|max| max >= 1), "must be at least 1")
+            .check("max_uses_per_guest", self.max_uses_per_guest.is_none_or(|max| max >= 1), "must be at least 1")
+            .finish()
+    }
+}
+
+//what one promo code took off a stay
+#[derive(Serialize)]
+pub struct Discount {
+    pub promotion_id: String,
+    pub code: String,
+    pub amount: Money,
+}
+
+#[derive(Deserialize)]
+pub struct QuoteRequest {
+    pub hotel_id: String,
+    pub room_type: String,
+    pub check_in: String,
+    pub check_out: String,
+    pub guests: i64,
+    #[serde(default)]
+    pub guest_type: GuestType,
+    #[serde(default)]
+    pub rate_plan_id: Option<String>,
+    #[serde(default)]
+    pub promo_codes: Vec<String>,
 }
 
+impl Validate for QuoteRequest {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("hotel_id", &self.hotel_id)
+            .not_blank("room_type", &self.room_type)
+            .iso_date("check_in", &self.check_in)
+            .iso_date("check_out", &self.check_out)
+            .date_after("check_out", "check_in", &self.check_in, &self.check_out)
+            .range("guests", self.guests, 1, 20)
+            .finish()
+    }
+}
+
+//what cancelling late costs, stored as snake_case text in cancellation_policies.penalty
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CancellationPenalty {
+    //the price of the arrival night
+    FirstNight,
+    //a share of the booking total
+    Percent,
+    //the whole booking total
+    FullStay,
+}
+
+impl CancellationPenalty {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CancellationPenalty::FirstNight => "first_night",
+            CancellationPenalty::Percent => "percent",
+            CancellationPenalty::FullStay => "full_stay",
+        }
+    }
+}
+
+impl ToSql for CancellationPenalty {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for CancellationPenalty {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "first_night" => Ok(CancellationPenalty::FirstNight),
+            "percent" => Ok(CancellationPenalty::Percent),
+            "full_stay" => Ok(CancellationPenalty::FullStay),
+            other => Err(FromSqlError::Other(format!("unknown cancellation penalty: {other}").into())),
+        }
+    }
+}
+
+//terms a booking can be cancelled on, attached to hotels and rate plans;
+//bookings and quotes keep a copy of the policy they were sold with
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CancellationPolicy {
+    //none for the built-in policies of hotels and plans without one
+    pub id: Option<String>,
+    pub name: String,
+    //cancelling at least this many days before check-in is free, never when left out
+    #[serde(default)]
+    pub free_until_days: Option<i64>,
+    pub penalty: CancellationPenalty,
+    #[serde(default)]
+    pub percent: Option<Rate>,
+    //the terms in words, filled in by the server
+    #[serde(default, skip_deserializing)]
+    pub summary: String,
+}
+
+impl Validate for CancellationPolicy {
+    fn validate(&self) -> Result<(), ApiError> {
+        let mut rules = Rules::new().not_blank("name", &self.name);
+        if let Some(days) = self.free_until_days {
+            rules = rules.range("free_until_days", days, 0, 365);
+        }
+        rules = if self.penalty == CancellationPenalty::Percent {
+            rules.check("percent", self.percent.is_some_and(|p| p <= Rate::HUNDRED), "must be between 0 and 100")
+        } else {
+            rules.check("percent", self.percent.is_none(), "must be left out unless penalty is percent")
+        };
+        rules.finish()
+    }
+}
+
+//a priced stay that is not booked yet, create_booking honours it until expires_at
+#[derive(Serialize)]
+pub struct Quote {
+    pub id: String,
+    pub hotel_id: String,
+    pub room_type: String,
+    pub check_in: String,
+    pub check_out: String,
+    pub guests: i64,
+    pub guest_type: GuestType,
+    pub rate_plan_id: Option<String>,
+    pub promo_codes: Vec<String>,
+    pub nights: Vec<NightlyPrice>,
+    pub discounts: Vec<Discount>,
+    pub charges: Vec<ChargeLine>,
+    pub subtotal: Money,
+    pub discount: Money,
+    pub taxes: Money,
+    pub fees: Money,
+    pub total: Money,
+    pub cancellation_policy: CancellationPolicy,
+    pub expires_at: String,
+}
+
+//what a folio entry is for, stored as snake_case text in folio_entries.kind
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FolioEntryKind {
+    Room,
+    Discount,
+    Tax,
+    Fee,
+    Minibar,
+    Restaurant,
+    Adjustment,
+    Payment,
+    //money handed back to the guest, the opposite of part of a payment
+    Refund,
+    //penalty charged by the cancellation policy when a booking is cancelled or not shown up for
+    CancellationFee,
+}
+
+impl FolioEntryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FolioEntryKind::Room => "room",
+            FolioEntryKind::Discount => "discount",
+            FolioEntryKind::Tax => "tax",
+            FolioEntryKind::Fee => "fee",
+            FolioEntryKind::Minibar => "minibar",
+            FolioEntryKind::Restaurant => "restaurant",
+            FolioEntryKind::Adjustment => "adjustment",
+            FolioEntryKind::Payment => "payment",
+            FolioEntryKind::Refund => "refund",
+            FolioEntryKind::CancellationFee => "cancellation_fee",
+        }
+    }
+
+    //kinds staff post by hand, the others come from pricing and payments
+    pub fn is_manual(&self) -> bool {
+        matches!(self, FolioEntryKind::Minibar | FolioEntryKind::Restaurant | FolioEntryKind::Adjustment)
+    }
+
+    //kinds that move money rather than charge for something
+    pub fn is_payment(&self) -> bool {
+        matches!(self, FolioEntryKind::Payment | FolioEntryKind::Refund)
+    }
+}
+
+impl ToSql for FolioEntryKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for FolioEntryKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "room" => Ok(FolioEntryKind::Room),
+            "discount" => Ok(FolioEntryKind::Discount),
+            "tax" => Ok(FolioEntryKind::Tax),
+            "fee" => Ok(FolioEntryKind::Fee),
+            "minibar" => Ok(FolioEntryKind::Minibar),
+            "restaurant" => Ok(FolioEntryKind::Restaurant),
+            "adjustment" => Ok(FolioEntryKind::Adjustment),
+            "payment" => Ok(FolioEntryKind::Payment),
+            "refund" => Ok(FolioEntryKind::Refund),
+            "cancellation_fee" => Ok(FolioEntryKind::CancellationFee),
+            other => Err(FromSqlError::Other(format!("unknown folio entry kind: {other}").into())),
+        }
+    }
+}
+
+//one line of a guest folio, charges are positive and payments negative
+#[derive(Serialize)]
+pub struct FolioEntry {
+    pub id: i64,
+    pub booking_id: String,
+    pub kind: FolioEntryKind,
+    pub description: String,
+    pub amount: Money,
+    pub posted_on: String,
+    pub posted_at: String,
+    pub payment_id: Option<String>,
+    //entry this one cancels out
+    pub reverses_id: Option<i64>,
+    pub reversed: bool,
+}
+
+//a charge staff post to a folio; adjustments can be negative to credit the guest
+#[derive(Deserialize)]
+pub struct FolioCharge {
+    pub kind: FolioEntryKind,
+    pub description: String,
+    pub amount: Money,
+}
+
+impl Validate for FolioCharge {
+    fn validate(&self) -> Result<(), ApiError> {
+        let rules = Rules::new()
+            .check("kind", self.kind.is_manual(), "must be minibar, restaurant or adjustment")
+            .not_blank("description", &self.description);
+        if self.kind == FolioEntryKind::Adjustment {
+            rules.check("amount", self.amount.minor_units != 0, "must not be zero").finish()
+        } else {
+            rules.positive("amount", &self.amount).finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FolioReversal {
+    pub reason: String,
+}
+
+impl Validate for FolioReversal {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new().not_blank("reason", &self.reason).finish()
+    }
+}
+
+#[derive(Serialize)]
+pub struct BookingStatusChange {
+    pub from_status: Option<BookingStatus>,
+    pub to_status: BookingStatus,
+    pub changed_at: String,
+}
+
+//which prices a modified stay keeps, set for the whole property with `modification_rates`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ModificationRates {
+    //every night is priced again at today's rates
+    #[default]
+    Current,
+    //nights the booking already had keep what they were sold for, added nights get today's rates
+    Original,
+}
+
+impl ModificationRates {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModificationRates::Current => "current",
+            ModificationRates::Original => "original",
+        }
+    }
+}
+
+impl ToSql for ModificationRates {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for ModificationRates {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "current" => Ok(ModificationRates::Current),
+            "original" => Ok(ModificationRates::Original),
+            other => Err(FromSqlError::Other(format!("unknown modification rates: {other}").into())),
+        }
+    }
+}
+
+//changes to the stay of a booking, what is left out stays as it is
+#[derive(Deserialize)]
+pub struct BookingModification {
+    #[serde(default)]
+    pub room_id: Option<String>,
+    #[serde(default)]
+    pub check_in: Option<String>,
+    #[serde(default)]
+    pub check_out: Option<String>,
+    #[serde(default)]
+    pub guests: Option<i64>,
+    //why the guest asked for the change, kept in the amendment history
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl Validate for BookingModification {
+    fn validate(&self) -> Result<(), ApiError> {
+        let changes = self.room_id.is_some() || self.check_in.is_some() || self.check_out.is_some() || self.guests.is_some();
+        let mut rules = Rules::new()
+            .check("room_id", changes, "give at least one of room_id, check_in, check_out or guests")
+            .optional_iso_date("check_in", self.check_in.as_deref())
+            .optional_iso_date("check_out", self.check_out.as_deref());
+        if let Some(room_id) = &self.room_id {
+            rules = rules.not_blank("room_id", room_id);
+        }
+        if let Some(guests) = self.guests {
+            rules = rules.range("guests", guests, 1, 20);
+        }
+        rules.finish()
+    }
+}
+
+//one change of a booking with its stay before and after, oldest first
+#[derive(Serialize)]
+pub struct BookingAmendment {
+    pub id: i64,
+    pub booking_id: String,
+    //room, dates, guests, rate plan, nights and total
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+    pub rates: ModificationRates,
+    //what the change added to the total, negative when the stay got cheaper
+    pub difference: Option<Money>,
+    pub reason: Option<String>,
+    pub amended_at: String,
+}
+
+
+//where a payment stands; payments are never deleted, only refunded or voided
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    //sent to the gateway for authorization, nothing is held yet
+    Pending,
+    //held on the guest's card, nothing has been taken yet
+    Authorized,
+    Captured,
+    PartiallyRefunded,
+    Refunded,
+    //cancelled on the day it was taken, as if it never happened
+    Voided,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Authorized => "authorized",
+            PaymentStatus::Captured => "captured",
+            PaymentStatus::PartiallyRefunded => "partially_refunded",
+            PaymentStatus::Refunded => "refunded",
+            PaymentStatus::Voided => "voided",
+        }
+    }
+}
+
+impl ToSql for PaymentStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for PaymentStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "pending" => Ok(PaymentStatus::Pending),
+            "authorized" => Ok(PaymentStatus::Authorized),
+            "captured" => Ok(PaymentStatus::Captured),
+            "partially_refunded" => Ok(PaymentStatus::PartiallyRefunded),
+            "refunded" => Ok(PaymentStatus::Refunded),
+            "voided" => Ok(PaymentStatus::Voided),
+            other => Err(FromSqlError::Other(format!("unknown payment status: {other}").into())),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Payment {
     pub id: Option<String>,
     pub booking_id: String,
-    pub amount: f64,
+    //what the guest handed over, in any currency
+    pub amount: Money,
     pub method: String,
+    //day whose exchange rate applies, today when left out
+    #[serde(default)]
+    pub paid_on: Option<String>,
+    //amount converted into the booking currency and the rate used, both filled in by the server
+    #[serde(default, skip_deserializing)]
+    pub settled_amount: Option<Money>,
+    #[serde(default, skip_deserializing)]
+    pub exchange_rate: Option<Rate>,
+    //kept by the server as the payment is refunded or voided
+    #[serde(default, skip_deserializing)]
+    pub status: Option<PaymentStatus>,
+    //sum of the refunds, in the tendered currency
+    #[serde(default, skip_deserializing)]
+    pub refunded_amount: Option<Money>,
+    #[serde(default, skip_deserializing)]
+    pub void_reason: Option<String>,
+    //card payments go through the payment gateway, the number itself is never stored
+    #[serde(default, skip_serializing)]
+    pub card_number: Option<String>,
+    //only hold the amount on the card, it is captured later or at check-out
+    #[serde(default, skip_serializing)]
+    pub authorize_only: bool,
+    #[serde(default, skip_deserializing)]
+    pub gateway: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub gateway_reference: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub card_last4: Option<String>,
+}
+
+impl Validate for Payment {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("booking_id", &self.booking_id)
+            .positive("amount", &self.amount)
+            .not_blank("method", &self.method)
+            .optional_iso_date("paid_on", self.paid_on.as_deref())
+            .check(
+                "card_number",
+                self.card_number.as_deref().is_none_or(|n| (12..=19).contains(&n.len()) && n.bytes().all(|b| b.is_ascii_digit())),
+                "must be 12 to 19 digits",
+            )
+            .check(
+                "card_number",
+                !self.authorize_only || self.card_number.is_some(),
+                "is required to authorize a payment",
+            )
+            .check("paid_on", self.card_number.is_none() || self.paid_on.is_none(), "cannot be set, cards are charged today")
+            .finish()
+    }
+}
+
+//captures an authorized payment, the whole hold when no amount is given
+#[derive(Deserialize)]
+pub struct PaymentCapture {
+    #[serde(default)]
+    pub amount: Option<Money>,
+}
+
+impl Validate for PaymentCapture {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .check("amount", self.amount.is_none_or(|amount| amount.is_positive()), "must be greater than zero")
+            .finish()
+    }
+}
+
+//what a payment gateway tells us about a payment after the fact
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WebhookKind {
+    #[serde(rename = "payment.authorized")]
+    PaymentAuthorized,
+    #[serde(rename = "payment.captured")]
+    PaymentCaptured,
+    #[serde(rename = "payment.refunded")]
+    PaymentRefunded,
+    #[serde(rename = "payment.voided")]
+    PaymentVoided,
+    //the hold ran out before it was captured
+    #[serde(rename = "authorization.expired")]
+    AuthorizationExpired,
+}
+
+impl WebhookKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookKind::PaymentAuthorized => "payment.authorized",
+            WebhookKind::PaymentCaptured => "payment.captured",
+            WebhookKind::PaymentRefunded => "payment.refunded",
+            WebhookKind::PaymentVoided => "payment.voided",
+            WebhookKind::AuthorizationExpired => "authorization.expired",
+        }
+    }
+}
+
+impl ToSql for WebhookKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for WebhookKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "payment.authorized" => Ok(WebhookKind::PaymentAuthorized),
+            "payment.captured" => Ok(WebhookKind::PaymentCaptured),
+            "payment.refunded" => Ok(WebhookKind::PaymentRefunded),
+            "payment.voided" => Ok(WebhookKind::PaymentVoided),
+            "authorization.expired" => Ok(WebhookKind::AuthorizationExpired),
+            other => Err(FromSqlError::Other(format!("unknown webhook kind: {other}").into())),
+        }
+    }
+}
+
+//a webhook event, `reference` is the gateway's reference of the payment
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WebhookEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: WebhookKind,
+    pub reference: String,
+    #[serde(default)]
+    pub amount: Option<Money>,
+}
+
+impl Validate for WebhookEvent {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .not_blank("id", &self.id)
+            .not_blank("reference", &self.reference)
+            .finish()
+    }
+}
+
+//a refund through a gateway is pending from before the gateway is asked until it is booked
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundStatus {
+    Pending,
+    Completed,
+}
+
+impl RefundStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefundStatus::Pending => "pending",
+            RefundStatus::Completed => "completed",
+        }
+    }
+}
+
+impl ToSql for RefundStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for RefundStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "pending" => Ok(RefundStatus::Pending),
+            "completed" => Ok(RefundStatus::Completed),
+            other => Err(FromSqlError::Other(format!("unknown refund status: {other}").into())),
+        }
+    }
+}
+
+//money handed back from a payment, in the currency the guest paid in
+#[derive(Serialize, Deserialize)]
+pub struct Refund {
+    #[serde(default, skip_deserializing)]
+    pub id: Option<i64>,
+    #[serde(default, skip_deserializing)]
+    pub payment_id: Option<String>,
+    pub amount: Money,
+    pub reason: String,
+    //amount taken off the booking, at the exchange rate of the payment
+    #[serde(default, skip_deserializing)]
+    pub settled_amount: Option<Money>,
+    #[serde(default, skip_deserializing)]
+    pub refunded_at: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub status: Option<RefundStatus>,
+    //the provider's reference of the refund, for refunds of card payments
+    #[serde(default, skip_deserializing)]
+    pub gateway_reference: Option<String>,
+}
+
+impl Validate for Refund {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .positive("amount", &self.amount)
+            .not_blank("reason", &self.reason)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PaymentVoid {
+    pub reason: String,
+}
+
+impl Validate for PaymentVoid {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new().not_blank("reason", &self.reason).finish()
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct AvailabilityQuery {
+    pub check_in: String,
+    pub check_out: String,
+    pub hotel_id: Option<String>,
+    pub room_type: Option<String>,
+    pub min_stars: Option<i32>,
+    pub location: Option<String>,
+    //guest currency to show prices in as well
+    pub currency: Option<Currency>,
+}
+
+impl Validate for AvailabilityQuery {
+    fn validate(&self) -> Result<(), ApiError> {
+        Rules::new()
+            .iso_date("check_in", &self.check_in)
+            .iso_date("check_out", &self.check_out)
+            .date_after("check_out", "check_in", &self.check_in, &self.check_out)
+            .finish()
+    }
+}
+
+
+#[derive(Serialize)]
+pub struct AvailabilityGroup {
+    pub hotel_id: String,
+    pub hotel_name: String,
+    pub location: String,
+    pub stars: i32,
+    pub room_type: String,
+    pub available_rooms: i64,
+    pub lowest_price: Money,
+    //lowest_price at today's rate when the search asked for a currency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_price: Option<Money>,
+    pub room_ids: Vec<String>,
+}
+
+
+#[derive(Serialize)]
+pub struct ExchangeRate {
+    pub id: i64,
+    pub valid_on: String,
+    pub base: Currency,
+    pub quote: Currency,
+    pub rate: Rate,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUSES: [BookingStatus; 6] = [
+        BookingStatus::Tentative,
+        BookingStatus::Confirmed,
+        BookingStatus::CheckedIn,
+        BookingStatus::CheckedOut,
+        BookingStatus::Cancelled,
+        BookingStatus::NoShow,
+    ];
+
+    #[test]
+    fn moves_bookings_only_forward_through_their_lifecycle() {
+        use BookingStatus::*;
+        let allowed = [
+            (Tentative, Confirmed),
+            (Tentative, Cancelled),
+            (Confirmed, CheckedIn),
+            (Confirmed, Cancelled),
+            (Confirmed, NoShow),
+            (CheckedIn, CheckedOut),
+        ];
+        for from in STATUSES {
+            for to in STATUSES {
+                assert_eq!(from.can_transition_to(to), allowed.contains(&(from, to)), "{} -> {}", from.as_str(), to.as_str());
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_no_way_out_of_the_final_statuses() {
+        for from in [BookingStatus::CheckedOut, BookingStatus::Cancelled, BookingStatus::NoShow] {
+            assert!(STATUSES.iter().all(|to| !from.can_transition_to(*to)), "{}", from.as_str());
+        }
+    }
 }
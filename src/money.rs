@@ -0,0 +1,422 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::ApiError;
+
+//currencies whose minor unit is not a hundredth, everything else has two decimals
+const EXPONENTS: &[(&str, u32)] = &[
+    ("BIF", 0), ("CLP", 0), ("DJF", 0), ("GNF", 0), ("ISK", 0), ("JPY", 0), ("KMF", 0), ("KRW", 0),
+    ("PYG", 0), ("RWF", 0), ("UGX", 0), ("VND", 0), ("VUV", 0), ("XAF", 0), ("XOF", 0), ("XPF", 0),
+    ("BHD", 3), ("IQD", 3), ("JOD", 3), ("KWD", 3), ("LYD", 3), ("OMR", 3), ("TND", 3),
+];
+
+//ISO-4217 alphabetic code, stored as text next to every amount
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+
+    //number of decimals in one major unit, 2 for EUR and 0 for JPY
+    pub fn exponent(&self) -> u32 {
+        EXPONENTS.iter().find(|(code, _)| *code == self.as_str()).map_or(2, |(_, exponent)| *exponent)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        match code.as_bytes() {
+            [a, b, c] if code.bytes().all(|b| b.is_ascii_uppercase()) => Ok(Currency([*a, *b, *c])),
+            _ => Err(MoneyError::Parse(format!("{code:?} is not an ISO-4217 currency code"))),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ToSql for Currency {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Currency {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: MoneyError| FromSqlError::Other(err.to_string().into()))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+//exact amount in integer minor units, serialized as "120.50 EUR"
+//stored as two columns: <name>_minor INTEGER and currency TEXT
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Money {
+    pub minor_units: i64,
+    pub currency: Currency,
+}
+
+#[derive(Debug)]
+pub enum MoneyError {
+    Parse(String),
+    CurrencyMismatch { left: Currency, right: Currency },
+    Overflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Parse(message) => write!(f, "{message}"),
+            MoneyError::CurrencyMismatch { left, right } => write!(f, "cannot combine amounts in {left} and {right}"),
+            MoneyError::Overflow => write!(f, "amount is too large"),
+        }
+    }
+}
+
+impl From<MoneyError> for ApiError {
+    fn from(err: MoneyError) -> Self {
+        match err {
+            MoneyError::CurrencyMismatch { left, right } => ApiError::conflict_with(
+                "currency_mismatch",
+                err.to_string(),
+                serde_json::json!({"currencies": [left, right]}),
+            ),
+            err => ApiError::BadRequest(err.to_string()),
+        }
+    }
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Self {
+        Money { minor_units, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::new(0, currency)
+    }
+
+    //fails unless the amount is in `currency`
+    pub fn ensure_currency(&self, currency: Currency) -> Result<(), MoneyError> {
+        if self.currency == currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch { left: currency, right: self.currency })
+        }
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.ensure_currency(other.currency)?;
+        let minor_units = self.minor_units.checked_add(other.minor_units).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.minor_units > 0
+    }
+
+    //converts into `to` at `rate` (units of `to` per unit of self), rounding half away from zero
+    pub fn convert(self, to: Currency, rate: Rate) -> Result<Money, MoneyError> {
+        let numerator = i128::from(self.minor_units)
+            .checked_mul(rate.0)
+            .and_then(|n| n.checked_mul(10i128.pow(to.exponent())))
+            .ok_or(MoneyError::Overflow)?;
+        let denominator = RATE_SCALE * 10i128.pow(self.currency.exponent());
+        Ok(Money::new(round_div(numerator, denominator)?, to))
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.ensure_currency(other.currency)?;
+        let minor_units = self.minor_units.checked_sub(other.minor_units).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    pub fn checked_mul(self, factor: i64) -> Result<Money, MoneyError> {
+        let minor_units = self.minor_units.checked_mul(factor).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    //`percent` hundredths of the amount, rounding half away from zero
+    pub fn percent(self, percent: Rate) -> Result<Money, MoneyError> {
+        let numerator = i128::from(self.minor_units).checked_mul(percent.0).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(round_div(numerator, RATE_SCALE * 100)?, self.currency))
+    }
+}
+
+//numerator / denominator in minor units, rounding half away from zero
+fn round_div(numerator: i128, denominator: i128) -> Result<i64, MoneyError> {
+    let rounded = numerator.checked_add(numerator.signum() * (denominator / 2)).ok_or(MoneyError::Overflow)? / denominator;
+    i64::try_from(rounded).map_err(|_| MoneyError::Overflow)
+}
+
+const RATE_DECIMALS: u32 = 10;
+const RATE_SCALE: i128 = 10i128.pow(RATE_DECIMALS);
+//no exchange rate or percentage gets near this, it keeps every product with an amount well inside i128
+const RATE_MAX: i128 = 1_000_000 * RATE_SCALE;
+
+//exchange rate or percentage as an exact decimal with up to ten places, stored as text like "1.0834"
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Rate(i128);
+
+impl Rate {
+    pub const ONE: Rate = Rate(RATE_SCALE);
+    pub const HUNDRED: Rate = Rate(100 * RATE_SCALE);
+
+    //rate for the opposite direction, rounded to the last decimal
+    pub fn inverse(self) -> Rate {
+        Rate((RATE_SCALE * RATE_SCALE + self.0 / 2) / self.0)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = MoneyError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || MoneyError::Parse(format!("{text:?} is not a positive decimal number"));
+
+        let (whole, fraction) = text.trim().split_once('.').unwrap_or((text.trim(), ""));
+        if whole.is_empty()
+            || fraction.len() > RATE_DECIMALS as usize
+            || !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let whole: i128 = whole.parse().map_err(|_| invalid())?;
+        let fraction: i128 = if fraction.is_empty() {
+            0
+        } else {
+            fraction.parse::<i128>().map_err(|_| invalid())? * 10i128.pow(RATE_DECIMALS - fraction.len() as u32)
+        };
+        let scaled = whole.checked_mul(RATE_SCALE).and_then(|w| w.checked_add(fraction)).ok_or_else(invalid)?;
+        if scaled == 0 {
+            return Err(invalid());
+        }
+        if scaled > RATE_MAX {
+            return Err(MoneyError::Parse(format!("{text:?} is larger than {}", Rate(RATE_MAX))));
+        }
+        Ok(Rate(scaled))
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fraction = format!("{:0width$}", self.0 % RATE_SCALE, width = RATE_DECIMALS as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            write!(f, "{}", self.0 / RATE_SCALE)
+        } else {
+            write!(f, "{}.{fraction}", self.0 / RATE_SCALE)
+        }
+    }
+}
+
+impl ToSql for Rate {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for Rate {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: MoneyError| FromSqlError::Other(err.to_string().into()))
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exponent = self.currency.exponent();
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let units = self.minor_units.unsigned_abs();
+        if exponent == 0 {
+            return write!(f, "{sign}{units} {}", self.currency);
+        }
+        let scale = 10u64.pow(exponent);
+        write!(f, "{sign}{}.{:0width$} {}", units / scale, units % scale, self.currency, width = exponent as usize)
+    }
+}
+
+//accepts "120.5 EUR" or "-3 JPY", never more decimals than the currency has
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || MoneyError::Parse(format!("{text:?} is not an amount like \"120.50 EUR\""));
+
+        let (amount, code) = text.trim().split_once(' ').ok_or_else(invalid)?;
+        let currency: Currency = code.trim().parse()?;
+        let exponent = currency.exponent();
+
+        let (negative, amount) = match amount.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, amount),
+        };
+        let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+        if whole.is_empty()
+            || !whole.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+            || (amount.contains('.') && fraction.is_empty())
+        {
+            return Err(invalid());
+        }
+        if fraction.len() > exponent as usize {
+            return Err(MoneyError::Parse(format!("{currency} amounts have at most {exponent} decimals")));
+        }
+
+        let scale = 10i64.pow(exponent);
+        let whole: i64 = whole.parse().map_err(|_| MoneyError::Overflow)?;
+        let fraction: i64 = if fraction.is_empty() {
+            0
+        } else {
+            fraction.parse::<i64>().map_err(|_| invalid())? * 10i64.pow(exponent - fraction.len() as u32)
+        };
+        let minor_units = whole
+            .checked_mul(scale)
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or(MoneyError::Overflow)?;
+
+        Ok(Money::new(if negative { -minor_units } else { minor_units }, currency))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(text: &str) -> Money {
+        text.parse().unwrap()
+    }
+
+    fn rate(text: &str) -> Rate {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_formats_amounts_by_currency_exponent() {
+        assert_eq!(money("120.5 EUR"), Money::new(12050, "EUR".parse().unwrap()));
+        assert_eq!(money("-3 JPY").minor_units, -3);
+        assert_eq!(money("1.005 KWD").minor_units, 1005);
+        assert_eq!(money("120.5 EUR").to_string(), "120.50 EUR");
+        assert_eq!(money("-0.05 EUR").to_string(), "-0.05 EUR");
+        assert_eq!(money("7 JPY").to_string(), "7 JPY");
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        for text in ["120.50", "1.234 EUR", "1.5 JPY", "1. EUR", ".5 EUR", "1,5 EUR", "12 eur", "12 EURO", "x EUR"] {
+            assert!(text.parse::<Money>().is_err(), "{text} parsed");
+        }
+        assert!(matches!("99999999999999999999 EUR".parse::<Money>(), Err(MoneyError::Overflow)));
+    }
+
+    #[test]
+    fn adding_amounts_needs_one_currency() {
+        assert_eq!(money("1.50 EUR").checked_add(money("2.25 EUR")).unwrap(), money("3.75 EUR"));
+        assert!(matches!(money("1 EUR").checked_add(money("1 USD")), Err(MoneyError::CurrencyMismatch { .. })));
+        assert!(matches!(Money::new(i64::MAX, money("1 EUR").currency).checked_add(money("0.01 EUR")), Err(MoneyError::Overflow)));
+    }
+
+    #[test]
+    fn parses_and_formats_rates() {
+        assert_eq!(rate("1.0834").to_string(), "1.0834");
+        assert_eq!(rate("2.50").to_string(), "2.5");
+        assert_eq!(rate("100").to_string(), "100");
+        assert_eq!(rate("0.0000000001").to_string(), "0.0000000001");
+        for text in ["0", "0.0", "-1", "1.00000000001", "abc", "", ".5", "1e3"] {
+            assert!(text.parse::<Rate>().is_err(), "{text} parsed");
+        }
+    }
+
+    #[test]
+    fn rates_are_bounded() {
+        assert_eq!(rate("1000000").to_string(), "1000000");
+        assert!("1000000.0000000001".parse::<Rate>().is_err());
+        assert!("99999999999999999999999999999".parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn percent_rounds_half_away_from_zero() {
+        assert_eq!(money("10.00 EUR").percent(rate("7")).unwrap(), money("0.70 EUR"));
+        //12.5 cents
+        assert_eq!(money("2.50 EUR").percent(rate("5")).unwrap(), money("0.13 EUR"));
+        assert_eq!(money("-2.50 EUR").percent(rate("5")).unwrap(), money("-0.13 EUR"));
+        //12.4 cents
+        assert_eq!(money("2.48 EUR").percent(rate("5")).unwrap(), money("0.12 EUR"));
+        assert_eq!(money("-2.48 EUR").percent(rate("5")).unwrap(), money("-0.12 EUR"));
+    }
+
+    #[test]
+    fn convert_scales_between_exponents_and_rounds_half_away_from_zero() {
+        let eur = "EUR".parse().unwrap();
+        let jpy = "JPY".parse().unwrap();
+        let kwd = "KWD".parse().unwrap();
+        assert_eq!(money("100.00 USD").convert(eur, rate("0.9231")).unwrap(), money("92.31 EUR"));
+        assert_eq!(money("1.00 EUR").convert(jpy, rate("161.5")).unwrap(), money("162 JPY"));
+        assert_eq!(money("-1.00 EUR").convert(jpy, rate("161.5")).unwrap(), money("-162 JPY"));
+        assert_eq!(money("1000 JPY").convert(eur, rate("0.0061919505")).unwrap(), money("6.19 EUR"));
+        assert_eq!(money("10.00 EUR").convert(kwd, rate("0.3312")).unwrap(), money("3.312 KWD"));
+        assert_eq!(money("0.01 EUR").convert(eur, rate("0.5")).unwrap(), money("0.01 EUR"));
+        assert_eq!(money("-0.01 EUR").convert(eur, rate("0.5")).unwrap(), money("-0.01 EUR"));
+    }
+
+    #[test]
+    fn convert_reports_overflow() {
+        let huge = Money::new(i64::MAX, "EUR".parse().unwrap());
+        assert!(matches!(huge.convert("USD".parse().unwrap(), rate("2")), Err(MoneyError::Overflow)));
+        assert!(matches!(huge.convert("KWD".parse().unwrap(), rate("1000000")), Err(MoneyError::Overflow)));
+        assert!(matches!(huge.percent(rate("1000000")), Err(MoneyError::Overflow)));
+    }
+
+    #[test]
+    fn inverse_rounds_to_the_last_decimal() {
+        assert_eq!(Rate::ONE.inverse(), Rate::ONE);
+        assert_eq!(rate("4").inverse(), rate("0.25"));
+        assert_eq!(rate("3").inverse(), rate("0.3333333333"));
+        assert_eq!(rate("1.5").inverse(), rate("0.6666666667"));
+        assert_eq!(rate("0.25").inverse(), rate("4"));
+    }
+}
@@ -0,0 +1,416 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::cancellation;
+use crate::error::ApiError;
+use crate::models::{CancellationPolicy, ChargeLine, Discount, NightlyPrice};
+use crate::money::{Currency, Money};
+use crate::promotions::{self, PromoStay};
+use crate::taxes::{self, Occupancy};
+
+//every night in [check_in, check_out) with the narrowest season of the plan covering it
+const NIGHTS_SQL: &str = "
+    WITH RECURSIVE nights(night) AS (
+        SELECT ?2
+        UNION ALL
+        SELECT date(night, '+1 day') FROM nights WHERE date(night, '+1 day') < ?3
+    )
+    SELECT n.night, strftime('%w', n.night) IN ('5', '6') AS weekend,
+           s.id, s.weekday_minor, s.weekend_minor, s.currency, s.min_stay, s.closed_to_arrival
+    FROM nights n
+    LEFT JOIN rate_seasons s ON s.id = (
+        SELECT id FROM rate_seasons
+        WHERE rate_plan_id = ?1 AND room_type = ?4 AND start_date <= n.night AND end_date > n.night
+        ORDER BY julianday(end_date) - julianday(start_date), id DESC
+        LIMIT 1
+    )
+    ORDER BY n.night
+";
+
+//priced stay, what create_booking stores and the quote endpoint returns
+#[derive(Serialize)]
+pub struct StayPrice {
+    pub rate_plan_id: Option<String>,
+    pub nights: Vec<NightlyPrice>,
+    //sum of the nights
+    pub subtotal: Money,
+    //promo codes taken off the subtotal before taxes
+    pub discounts: Vec<Discount>,
+    //the hotel's taxes and fees on the discounted nights
+    pub charges: Vec<ChargeLine>,
+    pub total: Money,
+    //terms the stay is sold on, the rate plan's or else the hotel's
+    pub cancellation_policy: CancellationPolicy,
+}
+
+//what to price: a room for some nights, for whom, on which plan and with which promo codes
+pub struct StayRequest<'a> {
+    pub room_id: &'a str,
+    pub rate_plan_id: Option<&'a str>,
+    pub check_in: &'a str,
+    pub check_out: &'a str,
+    pub occupancy: Occupancy,
+    pub promo_codes: &'a [String],
+    //booking being priced and its guest, none for quotes
+    pub guest_id: Option<&'a str>,
+    pub booking_id: Option<&'a str>,
+}
+
+struct SeasonNight {
+    night: String,
+    weekend: bool,
+    season: Option<(i64, Money, Money, i64, bool)>,
+}
+
+//prices every night of a stay in the room, on the rate plan or at the room's flat price,
+//takes the promo codes off and adds the hotel's taxes and fees for the occupancy;
+//refuses stays the plan's length-of-stay or arrival/departure rules do not allow
+pub fn price_stay(conn: &Connection, req: &StayRequest) -> Result<StayPrice, ApiError> {
+    let StayRequest { room_id, rate_plan_id, check_in, check_out, .. } = *req;
+    let (hotel_id, room_type, room_price): (String, String, Money) = conn.query_row(
+        "SELECT hotel_id, room_type, price_minor, currency FROM rooms WHERE id = ?1",
+        [room_id],
+        |row| Ok((row.get(0)?, row.get(1)?, Money::new(row.get(2)?, row.get(3)?))),
+    ).optional()?.ok_or(ApiError::NotFound("room"))?;
+
+    let Some(rate_plan_id) = rate_plan_id else {
+        let nights = season_nights(conn, "", "", check_in, check_out)?
+            .into_iter()
+            .map(|n| NightlyPrice { night: n.night, price: room_price, weekend: n.weekend, rate_season_id: None })
+            .collect();
+        return total(conn, req, &hotel_id, &room_type, nights, room_price.currency);
+    };
+
+    let plan_hotel: String = conn.query_row(
+        "SELECT hotel_id FROM rate_plans WHERE id = ?1",
+        [rate_plan_id],
+        |row| row.get(0),
+    ).optional()?.ok_or(ApiError::NotFound("rate plan"))?;
+    if plan_hotel != hotel_id {
+        return Err(ApiError::invalid("rate_plan_id", "belongs to another hotel than the room"));
+    }
+
+    let nights = season_nights(conn, rate_plan_id, &room_type, check_in, check_out)?;
+    let mut violations: Vec<Value> = Vec::new();
+
+    let unpriced: Vec<&str> = nights.iter().filter(|n| n.season.is_none()).map(|n| n.night.as_str()).collect();
+    if !unpriced.is_empty() {
+        violations.push(json!({"rule": "no_rate", "nights": unpriced}));
+    }
+
+    //length of stay and closed-to-arrival are decided by the season of the arrival night
+    if let Some((_, _, _, min_stay, closed_to_arrival)) = nights.first().and_then(|n| n.season) {
+        if (nights.len() as i64) < min_stay {
+            violations.push(json!({"rule": "min_stay", "min_stay": min_stay}));
+        }
+        if closed_to_arrival {
+            violations.push(json!({"rule": "closed_to_arrival", "date": check_in}));
+        }
+    }
+
+    //closed-to-departure belongs to the season covering the check-out day itself
+    let closed_to_departure: bool = conn.query_row(
+        "SELECT closed_to_departure FROM rate_seasons
+         WHERE rate_plan_id = ?1 AND room_type = ?2 AND start_date <= ?3 AND end_date > ?3
+         ORDER BY julianday(end_date) - julianday(start_date), id DESC
+         LIMIT 1",
+        (rate_plan_id, &room_type, check_out),
+        |row| row.get(0),
+    ).optional()?.unwrap_or(false);
+    if closed_to_departure {
+        violations.push(json!({"rule": "closed_to_departure", "date": check_out}));
+    }
+
+    if !violations.is_empty() {
+        return Err(ApiError::conflict_with(
+            "rate_restrictions",
+            "the rate plan does not allow this stay",
+            json!({"violations": violations}),
+        ));
+    }
+
+    let nights = nights
+        .into_iter()
+        .filter_map(|n| {
+            let (season_id, weekday, weekend, _, _) = n.season?;
+            let price = if n.weekend { weekend } else { weekday };
+            Some(NightlyPrice { night: n.night, price, weekend: n.weekend, rate_season_id: Some(season_id) })
+        })
+        .collect();
+    total(conn, req, &hotel_id, &room_type, nights, room_price.currency)
+}
+
+//prices a stay like `price_stay`, except that the nights in `kept` keep the price they were sold for;
+//discounts and taxes are worked out again on the resulting nights
+pub fn price_stay_keeping(conn: &Connection, req: &StayRequest, kept: &[NightlyPrice]) -> Result<StayPrice, ApiError> {
+    let stay = price_stay(conn, req)?;
+    if kept.is_empty() {
+        return Ok(stay);
+    }
+    let (hotel_id, room_type): (String, String) = conn.query_row(
+        "SELECT hotel_id, room_type FROM rooms WHERE id = ?1",
+        [req.room_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    let nights = stay
+        .nights
+        .into_iter()
+        .map(|n| kept.iter().find(|k| k.night == n.night).cloned().unwrap_or(n))
+        .collect();
+    total(conn, req, &hotel_id, &room_type, nights, stay.total.currency)
+}
+
+fn season_nights(
+    conn: &Connection,
+    rate_plan_id: &str,
+    room_type: &str,
+    check_in: &str,
+    check_out: &str,
+) -> rusqlite::Result<Vec<SeasonNight>> {
+    let mut stmt = conn.prepare(NIGHTS_SQL)?;
+    let nights = stmt.query_map((rate_plan_id, check_in, check_out, room_type), |row| {
+        let season = match row.get::<_, Option<i64>>(2)? {
+            Some(id) => {
+                let currency: Currency = row.get(5)?;
+                Some((
+                    id,
+                    Money::new(row.get(3)?, currency),
+                    Money::new(row.get(4)?, currency),
+                    row.get(6)?,
+                    row.get(7)?,
+                ))
+            }
+            None => None,
+        };
+        Ok(SeasonNight { night: row.get(0)?, weekend: row.get(1)?, season })
+    })?;
+
+    nights.collect()
+}
+
+fn total(
+    conn: &Connection,
+    req: &StayRequest,
+    hotel_id: &str,
+    room_type: &str,
+    nights: Vec<NightlyPrice>,
+    currency: Currency,
+) -> Result<StayPrice, ApiError> {
+    let subtotal = nights.iter().try_fold(Money::zero(currency), |sum, n| sum.checked_add(n.price))?;
+
+    let promo_stay = PromoStay {
+        hotel_id,
+        room_type,
+        nights: nights.len() as i64,
+        guest_id: req.guest_id,
+        booking_id: req.booking_id,
+    };
+    let discounts = promotions::discount_stay(conn, req.promo_codes, &promo_stay, subtotal)?;
+    let discounted = discounts.iter().try_fold(subtotal, |sum, d| sum.checked_sub(d.amount))?;
+
+    let charges = taxes::charge_stay(conn, hotel_id, discounted, nights.len() as i64, req.occupancy)?;
+    let total = charges.iter().try_fold(discounted, |sum, line| sum.checked_add(line.amount))?;
+    let cancellation_policy = cancellation::policy_for(conn, hotel_id, req.rate_plan_id)?;
+    Ok(StayPrice {
+        rate_plan_id: req.rate_plan_id.map(str::to_string),
+        nights,
+        subtotal,
+        discounts,
+        charges,
+        total,
+        cancellation_policy,
+    })
+}
+
+//replaces the stored breakdown, redemptions, charges, total and cancellation policy of a booking
+pub fn store_stay_price(conn: &Connection, booking_id: &str, stay: &StayPrice) -> Result<(), ApiError> {
+    conn.execute("DELETE FROM booking_nights WHERE booking_id = ?1", [booking_id])?;
+    conn.execute("DELETE FROM booking_charges WHERE booking_id = ?1", [booking_id])?;
+    conn.execute("DELETE FROM promotion_redemptions WHERE booking_id = ?1", [booking_id])?;
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO booking_nights (booking_id, night, price_minor, currency, weekend, rate_season_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for n in &stay.nights {
+        stmt.execute((booking_id, &n.night, n.price.minor_units, n.price.currency, n.weekend, n.rate_season_id))?;
+    }
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO promotion_redemptions (promotion_id, booking_id, code, discount_minor, currency)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for d in &stay.discounts {
+        stmt.execute((&d.promotion_id, booking_id, &d.code, d.amount.minor_units, d.amount.currency))?;
+    }
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO booking_charges (booking_id, line, tax_rule_id, name, category, basis, percent, unit_minor,
+                                      quantity, amount_minor, currency)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    )?;
+    for (line, c) in stay.charges.iter().enumerate() {
+        stmt.execute((
+            booking_id, line as i64 + 1, c.tax_rule_id, &c.name, c.category, c.basis, c.percent,
+            c.unit_amount.map(|unit| unit.minor_units), c.quantity, c.amount.minor_units, c.amount.currency,
+        ))?;
+    }
+
+    conn.execute(
+        "UPDATE bookings SET rate_plan_id = ?1, total_minor = ?2, currency = ?3, cancellation_policy = ?4 WHERE id = ?5",
+        (
+            &stay.rate_plan_id, stay.total.minor_units, stay.total.currency,
+            cancellation::to_snapshot(&stay.cancellation_policy)?, booking_id,
+        ),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, eur};
+    use crate::models::GuestType;
+
+    //the test hotel, r1 at a flat 100.00 EUR, and an empty rate plan
+    fn hotel() -> Connection {
+        let conn = db::test_hotel();
+        conn.execute_batch(
+            "INSERT INTO rate_plans (id, hotel_id, code, name, kind) VALUES ('p1', 'h1', 'BAR', 'Best available', 'bar');",
+        ).unwrap();
+        conn
+    }
+
+    fn season(conn: &Connection, start: &str, end: &str, weekday: i64, weekend: i64) -> i64 {
+        conn.query_row(
+            "INSERT INTO rate_seasons (rate_plan_id, room_type, start_date, end_date, weekday_minor, weekend_minor, currency)
+             VALUES ('p1', 'double', ?1, ?2, ?3, ?4, 'EUR') RETURNING id",
+            (start, end, weekday, weekend),
+            |row| row.get(0),
+        ).unwrap()
+    }
+
+    fn price(conn: &Connection, rate_plan_id: Option<&str>, check_in: &str, check_out: &str) -> Result<StayPrice, ApiError> {
+        price_stay(conn, &StayRequest {
+            room_id: "r1",
+            rate_plan_id,
+            check_in,
+            check_out,
+            occupancy: Occupancy { guests: 2, guest_type: GuestType::Standard },
+            promo_codes: &[],
+            guest_id: None,
+            booking_id: None,
+        })
+    }
+
+    fn prices(stay: &StayPrice) -> Vec<i64> {
+        stay.nights.iter().map(|n| n.price.minor_units).collect()
+    }
+
+    //rules of the restrictions a stay broke
+    fn violations(result: Result<StayPrice, ApiError>) -> Vec<String> {
+        match result {
+            Err(ApiError::Conflict { code: "rate_restrictions", details: Some(details), .. }) => details["violations"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v["rule"].as_str().unwrap().to_string())
+                .collect(),
+            Err(err) => panic!("unexpected error {}", err.code()),
+            Ok(_) => panic!("the stay was priced"),
+        }
+    }
+
+    #[test]
+    fn prices_every_night_at_the_room_price_without_a_plan() {
+        let conn = hotel();
+        let stay = price(&conn, None, "2027-03-01", "2027-03-04").unwrap();
+        assert_eq!(prices(&stay), [10000, 10000, 10000]);
+        assert!(stay.nights.iter().all(|n| n.rate_season_id.is_none()));
+        assert_eq!(stay.subtotal, eur(30000));
+        assert_eq!(stay.total, stay.subtotal);
+        assert_eq!(stay.rate_plan_id, None);
+    }
+
+    #[test]
+    fn prices_friday_and_saturday_at_the_weekend_rate() {
+        let conn = hotel();
+        let march = season(&conn, "2027-03-01", "2027-04-01", 12000, 15000);
+        //thursday, friday, saturday and sunday nights
+        let stay = price(&conn, Some("p1"), "2027-03-04", "2027-03-08").unwrap();
+        assert_eq!(prices(&stay), [12000, 15000, 15000, 12000]);
+        assert_eq!(stay.nights.iter().map(|n| n.weekend).collect::<Vec<_>>(), [false, true, true, false]);
+        assert!(stay.nights.iter().all(|n| n.rate_season_id == Some(march)));
+        assert_eq!(stay.total, eur(54000));
+    }
+
+    #[test]
+    fn prices_each_night_from_the_narrowest_season_covering_it() {
+        let conn = hotel();
+        let march = season(&conn, "2027-03-01", "2027-04-01", 12000, 15000);
+        let fair = season(&conn, "2027-03-02", "2027-03-04", 30000, 30000);
+        let stay = price(&conn, Some("p1"), "2027-03-01", "2027-03-05").unwrap();
+        assert_eq!(prices(&stay), [12000, 30000, 30000, 12000]);
+        let seasons: Vec<_> = stay.nights.iter().map(|n| n.rate_season_id.unwrap()).collect();
+        assert_eq!(seasons, [march, fair, fair, march]);
+    }
+
+    #[test]
+    fn refuses_nights_no_season_prices() {
+        let conn = hotel();
+        season(&conn, "2027-03-01", "2027-03-03", 12000, 15000);
+        assert_eq!(violations(price(&conn, Some("p1"), "2027-03-02", "2027-03-05")), ["no_rate"]);
+    }
+
+    #[test]
+    fn applies_the_restrictions_of_the_arrival_and_departure_seasons() {
+        let conn = hotel();
+        let march = season(&conn, "2027-03-01", "2027-03-10", 12000, 15000);
+        conn.execute("UPDATE rate_seasons SET min_stay = 3, closed_to_arrival = 1 WHERE id = ?1", [march]).unwrap();
+        let late = season(&conn, "2027-03-10", "2027-04-01", 12000, 15000);
+        conn.execute("UPDATE rate_seasons SET closed_to_departure = 1 WHERE id = ?1", [late]).unwrap();
+
+        assert_eq!(violations(price(&conn, Some("p1"), "2027-03-07", "2027-03-09")), ["min_stay", "closed_to_arrival"]);
+        assert_eq!(violations(price(&conn, Some("p1"), "2027-03-10", "2027-03-12")), ["closed_to_departure"]);
+        //arriving in the late season is open and leaving inside the early one is allowed
+        conn.execute("UPDATE rate_seasons SET closed_to_arrival = 0 WHERE id = ?1", [march]).unwrap();
+        assert_eq!(prices(&price(&conn, Some("p1"), "2027-03-01", "2027-03-04").unwrap()), [12000, 12000, 12000]);
+    }
+
+    #[test]
+    fn charges_taxes_on_the_nights_after_promo_codes() {
+        let conn = hotel();
+        conn.execute_batch(
+            "INSERT INTO tax_rules (hotel_id, name, category, basis, percent) VALUES ('h1', 'VAT', 'tax', 'percent', '10');
+             INSERT INTO promotions (id, code, name, kind, percent, valid_from, valid_until)
+             VALUES ('save20', 'SAVE20', 'Twenty off', 'percent', '20', '2000-01-01', '2999-01-01');",
+        ).unwrap();
+        let stay = price_stay(&conn, &StayRequest {
+            room_id: "r1",
+            rate_plan_id: None,
+            check_in: "2027-03-01",
+            check_out: "2027-03-04",
+            occupancy: Occupancy { guests: 2, guest_type: GuestType::Standard },
+            promo_codes: &["save20".to_string()],
+            guest_id: None,
+            booking_id: None,
+        }).unwrap();
+        assert_eq!(stay.subtotal, eur(30000));
+        assert_eq!(stay.discounts.iter().map(|d| d.amount).collect::<Vec<_>>(), [eur(6000)]);
+        assert_eq!(stay.charges.iter().map(|c| c.amount).collect::<Vec<_>>(), [eur(2400)]);
+        assert_eq!(stay.total, eur(26400));
+    }
+
+    #[test]
+    fn refuses_a_plan_of_another_hotel() {
+        let conn = hotel();
+        conn.execute_batch(
+            "INSERT INTO hotels (id, name, currency) VALUES ('h2', 'Other', 'EUR');
+             INSERT INTO rate_plans (id, hotel_id, code, name, kind) VALUES ('p2', 'h2', 'BAR', 'Best available', 'bar');",
+        ).unwrap();
+        let err = price(&conn, Some("p2"), "2027-03-01", "2027-03-02").err().unwrap();
+        assert_eq!(err.code(), "validation_failed");
+    }
+}
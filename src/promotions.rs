@@ -0,0 +1,311 @@
+use rusqlite::types::Type;
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::{json, Value};
+
+use crate::error::ApiError;
+use crate::models::{Discount, DiscountKind, Promotion};
+use crate::money::{Currency, Money};
+
+pub const PROMOTION_SELECT: &str = "
+    SELECT id, code, name, kind, percent, amount_minor, currency, valid_from, valid_until, hotel_ids, room_types,
+           min_nights, max_uses, max_uses_per_guest, stackable
+    FROM promotions
+";
+
+fn json_column(row: &rusqlite::Row, index: usize) -> rusqlite::Result<Vec<String>> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text).map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(err)))
+}
+
+pub fn promotion_from_row(row: &rusqlite::Row) -> rusqlite::Result<Promotion> {
+    let amount = match (row.get::<_, Option<i64>>(5)?, row.get::<_, Option<Currency>>(6)?) {
+        (Some(minor_units), Some(currency)) => Some(Money::new(minor_units, currency)),
+        _ => None,
+    };
+
+    Ok(Promotion {
+        id: Some(row.get(0)?),
+        code: row.get(1)?,
+        name: row.get(2)?,
+        kind: row.get(3)?,
+        percent: row.get(4)?,
+        amount,
+        valid_from: row.get(7)?,
+        valid_until: row.get(8)?,
+        hotel_ids: json_column(row, 9)?,
+        room_types: json_column(row, 10)?,
+        min_nights: row.get(11)?,
+        max_uses: row.get(12)?,
+        max_uses_per_guest: row.get(13)?,
+        stackable: row.get(14)?,
+    })
+}
+
+//codes are matched case-insensitively and stored upper case
+pub fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+//the stay a set of promo codes is checked against
+pub struct PromoStay<'a> {
+    pub hotel_id: &'a str,
+    pub room_type: &'a str,
+    pub nights: i64,
+    //booking being priced and its guest, caps per guest are only known for bookings
+    pub guest_id: Option<&'a str>,
+    pub booking_id: Option<&'a str>,
+}
+
+//redemptions counting against a promotion's caps: every booking but cancelled ones and the one being priced
+fn uses(conn: &Connection, promotion_id: &str, guest_id: Option<&str>, booking_id: Option<&str>) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM promotion_redemptions r
+         JOIN bookings b ON b.id = r.booking_id
+         WHERE r.promotion_id = ?1 AND b.status != 'cancelled' AND b.id IS NOT ?2
+           AND (?3 IS NULL OR b.guest_id = ?3)",
+        (promotion_id, booking_id, guest_id),
+        |row| row.get(0),
+    )
+}
+
+fn cap_violations(conn: &Connection, promotion: &Promotion, stay: &PromoStay) -> rusqlite::Result<Vec<Value>> {
+    let id = promotion.id.as_deref().unwrap_or_default();
+    let mut violations = Vec::new();
+    if let Some(max_uses) = promotion.max_uses
+        && uses(conn, id, None, stay.booking_id)? >= max_uses
+    {
+        violations.push(json!({"code": promotion.code, "rule": "max_uses", "max_uses": max_uses}));
+    }
+    if let (Some(max_uses), Some(guest_id)) = (promotion.max_uses_per_guest, stay.guest_id)
+        && uses(conn, id, Some(guest_id), stay.booking_id)? >= max_uses
+    {
+        violations.push(json!({"code": promotion.code, "rule": "max_uses_per_guest", "max_uses_per_guest": max_uses}));
+    }
+    Ok(violations)
+}
+
+fn not_applicable(violations: Vec<Value>) -> ApiError {
+    ApiError::conflict_with(
+        "promotion_not_applicable",
+        "the promo codes cannot be used for this stay",
+        json!({"violations": violations}),
+    )
+}
+
+//what `codes` take off a stay whose nights cost `subtotal`; unknown codes are a 422,
+//codes the stay does not qualify for a 409 listing every broken rule
+pub fn discount_stay(conn: &Connection, codes: &[String], stay: &PromoStay, subtotal: Money) -> Result<Vec<Discount>, ApiError> {
+    let mut normalized: Vec<String> = Vec::new();
+    for code in codes.iter().map(|code| normalize_code(code)) {
+        if !code.is_empty() && !normalized.contains(&code) {
+            normalized.push(code);
+        }
+    }
+    if normalized.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut promotions = Vec::new();
+    let mut unknown = Vec::new();
+    for code in normalized {
+        let promotion = conn.query_row(&format!("{PROMOTION_SELECT} WHERE code = ?1"), [&code], promotion_from_row).optional()?;
+        match promotion {
+            Some(promotion) => promotions.push(promotion),
+            None => unknown.push(code),
+        }
+    }
+    if !unknown.is_empty() {
+        return Err(ApiError::invalid("promo_codes", format!("unknown promo code {}", unknown.join(", "))));
+    }
+
+    let today: String = conn.query_row("SELECT date('now')", [], |row| row.get(0))?;
+    let mut violations = Vec::new();
+    for p in &promotions {
+        if today < p.valid_from {
+            violations.push(json!({"code": p.code, "rule": "not_started", "valid_from": p.valid_from}));
+        }
+        if today >= p.valid_until {
+            violations.push(json!({"code": p.code, "rule": "expired", "valid_until": p.valid_until}));
+        }
+        if !p.hotel_ids.is_empty() && !p.hotel_ids.iter().any(|id| id == stay.hotel_id) {
+            violations.push(json!({"code": p.code, "rule": "hotel"}));
+        }
+        if !p.room_types.is_empty() && !p.room_types.iter().any(|room_type| room_type == stay.room_type) {
+            violations.push(json!({"code": p.code, "rule": "room_type", "room_types": p.room_types}));
+        }
+        if stay.nights < p.min_nights {
+            violations.push(json!({"code": p.code, "rule": "min_nights", "min_nights": p.min_nights}));
+        }
+        if let Some(amount) = p.amount
+            && amount.currency != subtotal.currency
+        {
+            violations.push(json!({"code": p.code, "rule": "currency", "currency": amount.currency}));
+        }
+        if promotions.len() > 1 && !p.stackable {
+            violations.push(json!({"code": p.code, "rule": "not_stackable"}));
+        }
+        violations.extend(cap_violations(conn, p, stay)?);
+    }
+    if !violations.is_empty() {
+        return Err(not_applicable(violations));
+    }
+
+    //discounts come off in the order the codes were given and never take the stay below zero
+    let mut remaining = subtotal;
+    let mut discounts = Vec::new();
+    for p in promotions {
+        let amount = match (p.kind, p.percent, p.amount) {
+            (DiscountKind::Percent, Some(percent), _) => subtotal.percent(percent)?,
+            (DiscountKind::Fixed, _, Some(amount)) => amount,
+            _ => Money::zero(subtotal.currency),
+        };
+        let amount = if amount.minor_units > remaining.minor_units { remaining } else { amount };
+        remaining = remaining.checked_sub(amount)?;
+        discounts.push(Discount { promotion_id: p.id.unwrap_or_default(), code: p.code, amount });
+    }
+    Ok(discounts)
+}
+
+//checks the caps of a quote's discounts again when it is booked, the guest is only known by then
+pub fn recheck_caps(conn: &Connection, discounts: &[Discount], stay: &PromoStay) -> Result<(), ApiError> {
+    let mut violations = Vec::new();
+    for discount in discounts {
+        let promotion = conn.query_row(
+            &format!("{PROMOTION_SELECT} WHERE id = ?1"),
+            [&discount.promotion_id],
+            promotion_from_row,
+        )?;
+        violations.extend(cap_violations(conn, &promotion, stay)?);
+    }
+    if violations.is_empty() { Ok(()) } else { Err(not_applicable(violations)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, eur};
+
+    //the test hotel, SAVE10 takes 10% off and FIFTY 50.00 EUR off any stay
+    fn hotel() -> Connection {
+        let conn = db::test_hotel();
+        conn.execute_batch(
+            "INSERT INTO promotions (id, code, name, kind, percent, valid_from, valid_until, stackable)
+             VALUES ('save10', 'SAVE10', 'Ten off', 'percent', '10', '2000-01-01', '2999-01-01', 1);
+             INSERT INTO promotions (id, code, name, kind, amount_minor, currency, valid_from, valid_until, stackable)
+             VALUES ('fifty', 'FIFTY', 'Fifty off', 'fixed', 5000, 'EUR', '2000-01-01', '2999-01-01', 1);",
+        ).unwrap();
+        conn
+    }
+
+    fn stay(nights: i64, guest_id: Option<&str>) -> PromoStay<'_> {
+        PromoStay { hotel_id: "h1", room_type: "double", nights, guest_id, booking_id: None }
+    }
+
+    fn discount(conn: &Connection, codes: &[&str], stay: &PromoStay, subtotal: i64) -> Result<Vec<(String, i64)>, ApiError> {
+        let codes: Vec<String> = codes.iter().map(|code| code.to_string()).collect();
+        let discounts = discount_stay(conn, &codes, stay, eur(subtotal))?;
+        Ok(discounts.into_iter().map(|d| (d.code, d.amount.minor_units)).collect())
+    }
+
+    //rules the codes broke, one per violation
+    fn violations(result: Result<Vec<(String, i64)>, ApiError>) -> Vec<String> {
+        match result {
+            Err(ApiError::Conflict { code: "promotion_not_applicable", details: Some(details), .. }) => details["violations"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| format!("{} {}", v["code"].as_str().unwrap(), v["rule"].as_str().unwrap()))
+                .collect(),
+            Err(err) => panic!("unexpected error {}", err.code()),
+            Ok(_) => panic!("the codes were applied"),
+        }
+    }
+
+    //a booking of `guest_id` that redeemed `promotion_id`
+    fn redeemed(conn: &Connection, booking_id: &str, guest_id: &str, status: &str, promotion_id: &str) {
+        conn.execute(
+            "INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, status)
+             VALUES (?1, ?2, 'r1', 'h1', '2027-03-01', '2027-03-03', ?3)",
+            (booking_id, guest_id, status),
+        ).unwrap();
+        conn.execute(
+            "INSERT INTO promotion_redemptions (promotion_id, booking_id, code, discount_minor, currency)
+             VALUES (?1, ?2, 'CODE', 1000, 'EUR')",
+            (promotion_id, booking_id),
+        ).unwrap();
+    }
+
+    #[test]
+    fn takes_stacked_codes_off_in_the_order_given() {
+        let conn = hotel();
+        //the percentage is of the whole subtotal whatever comes off before it
+        let discounts = discount(&conn, &[" fifty", "save10 ", "FIFTY"], &stay(2, None), 20000).unwrap();
+        assert_eq!(discounts, [("FIFTY".to_string(), 5000), ("SAVE10".to_string(), 2000)]);
+    }
+
+    #[test]
+    fn never_takes_a_stay_below_zero() {
+        let conn = hotel();
+        let discounts = discount(&conn, &["SAVE10", "FIFTY"], &stay(1, None), 4000).unwrap();
+        assert_eq!(discounts, [("SAVE10".to_string(), 400), ("FIFTY".to_string(), 3600)]);
+    }
+
+    #[test]
+    fn rejects_unknown_codes() {
+        let conn = hotel();
+        let err = discount(&conn, &["SAVE10", "NOPE"], &stay(1, None), 10000).err().unwrap();
+        assert_eq!(err.code(), "validation_failed");
+        assert!(discount(&conn, &["", "  "], &stay(1, None), 10000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn lists_every_rule_a_stay_breaks() {
+        let conn = hotel();
+        conn.execute(
+            "UPDATE promotions SET min_nights = 3, room_types = '[\"suite\"]', hotel_ids = '[\"h2\"]' WHERE id = 'save10'",
+            [],
+        ).unwrap();
+        conn.execute("UPDATE promotions SET valid_until = '2001-01-01' WHERE id = 'fifty'", []).unwrap();
+        assert_eq!(
+            violations(discount(&conn, &["SAVE10", "FIFTY"], &stay(2, None), 10000)),
+            ["SAVE10 hotel", "SAVE10 room_type", "SAVE10 min_nights", "FIFTY expired"],
+        );
+    }
+
+    #[test]
+    fn refuses_to_stack_a_code_that_does_not_stack() {
+        let conn = hotel();
+        conn.execute("UPDATE promotions SET stackable = 0 WHERE id = 'fifty'", []).unwrap();
+        assert_eq!(violations(discount(&conn, &["SAVE10", "FIFTY"], &stay(1, None), 10000)), ["FIFTY not_stackable"]);
+        assert_eq!(discount(&conn, &["FIFTY"], &stay(1, None), 10000).unwrap(), [("FIFTY".to_string(), 5000)]);
+    }
+
+    #[test]
+    fn refuses_fixed_discounts_in_another_currency() {
+        let conn = hotel();
+        let usd = Money::new(10000, "USD".parse().unwrap());
+        let err = discount_stay(&conn, &["FIFTY".to_string()], &stay(1, None), usd).err().unwrap();
+        assert_eq!(violations(Err(err)), ["FIFTY currency"]);
+    }
+
+    #[test]
+    fn counts_uses_of_bookings_not_cancelled_against_the_caps() {
+        let conn = hotel();
+        conn.execute("UPDATE promotions SET max_uses = 2, max_uses_per_guest = 1 WHERE id = 'save10'", []).unwrap();
+        redeemed(&conn, "b1", "g1", "confirmed", "save10");
+        redeemed(&conn, "b2", "g2", "cancelled", "save10");
+
+        assert_eq!(violations(discount(&conn, &["SAVE10"], &stay(1, Some("g1")), 10000)), ["SAVE10 max_uses_per_guest"]);
+        assert!(discount(&conn, &["SAVE10"], &stay(1, Some("g2")), 10000).is_ok());
+        //the booking being repriced does not count against itself
+        let repriced = PromoStay { booking_id: Some("b1"), ..stay(1, Some("g1")) };
+        assert!(discount(&conn, &["SAVE10"], &repriced, 10000).is_ok());
+
+        redeemed(&conn, "b3", "g2", "tentative", "save10");
+        assert_eq!(violations(discount(&conn, &["SAVE10"], &stay(1, None), 10000)), ["SAVE10 max_uses"]);
+        //quotes do not know their guest, the cap per guest is checked again when they are booked
+        let discounts = vec![Discount { promotion_id: "save10".into(), code: "SAVE10".into(), amount: eur(1000) }];
+        let err = recheck_caps(&conn, &discounts, &stay(1, Some("g2"))).err().unwrap();
+        assert_eq!(violations(Err(err)), ["SAVE10 max_uses", "SAVE10 max_uses_per_guest"]);
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::BTreeSet;
+
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::json;
+
+use crate::blocks;
+use crate::cancellation;
+use crate::error::ApiError;
+use crate::models::{Booking, ChargeCategory, Discount, NightlyPrice, Quote, QuoteRequest};
+use crate::money::{Currency, Money};
+use crate::pricing::{self, StayPrice, StayRequest};
+use crate::promotions::{self, PromoStay};
+use crate::taxes::{self, Occupancy};
+
+//how long a guest can take to book what they were shown, as an SQLite date modifier
+const QUOTE_VALIDITY: &str = "+30 minutes";
+
+//cheapest room of the type that is in service and free for the whole stay, unless room blocks hold the rest
+fn free_room(conn: &Connection, req: &QuoteRequest) -> rusqlite::Result<Option<String>> {
+    if blocks::public_rooms(conn, &req.hotel_id, &req.room_type, &req.check_in, &req.check_out)? < 1 {
+        return Ok(None);
+    }
+    conn.query_row(
+        "SELECT r.id FROM rooms r
+         WHERE r.hotel_id = ?1 AND r.room_type = ?2 AND r.housekeeping != 'out_of_order'
+           AND NOT EXISTS (
+                SELECT 1 FROM bookings b
+                WHERE b.room_id = r.id AND b.check_in < ?4 AND b.check_out > ?3
+                  AND b.status NOT IN ('cancelled', 'no_show')
+           )
+         ORDER BY r.price_minor, r.id
+         LIMIT 1",
+        (&req.hotel_id, &req.room_type, &req.check_in, &req.check_out),
+        |row| row.get(0),
+    ).optional()
+}
+
+//prices a stay for a room type and stores the offer, nothing is reserved
+pub fn create_quote(conn: &Connection, id: &str, req: &QuoteRequest) -> Result<Quote, ApiError> {
+    conn.query_row("SELECT 1 FROM hotels WHERE id = ?1", [&req.hotel_id], |_| Ok(()))
+        .optional()?
+        .ok_or(ApiError::NotFound("hotel"))?;
+
+    let room_id = free_room(conn, req)?.ok_or_else(|| {
+        ApiError::conflict_with(
+            "no_availability",
+            "no room of this type is free for these dates",
+            json!({"room_type": req.room_type, "check_in": req.check_in, "check_out": req.check_out}),
+        )
+    })?;
+    let stay = pricing::price_stay(conn, &StayRequest {
+        room_id: &room_id,
+        rate_plan_id: req.rate_plan_id.as_deref(),
+        check_in: &req.check_in,
+        check_out: &req.check_out,
+        occupancy: Occupancy { guests: req.guests, guest_type: req.guest_type },
+        promo_codes: &req.promo_codes,
+        guest_id: None,
+        booking_id: None,
+    })?;
+
+    let discount = stay.discounts.iter().try_fold(Money::zero(stay.total.currency), |sum, d| sum.checked_add(d.amount))?;
+    let taxes = taxes::category_total(&stay.charges, ChargeCategory::Tax, stay.total.currency)?;
+    let fees = taxes::category_total(&stay.charges, ChargeCategory::Fee, stay.total.currency)?;
+
+    let expires_at: String = conn.query_row(
+        "INSERT INTO quotes (id, hotel_id, room_type, check_in, check_out, guests, guest_type, rate_plan_id,
+                             subtotal_minor, discount_minor, taxes_minor, fees_minor, total_minor, currency,
+                             cancellation_policy, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, datetime('now', ?16))
+         RETURNING expires_at",
+        (
+            id, &req.hotel_id, &req.room_type, &req.check_in, &req.check_out, req.guests, req.guest_type,
+            &stay.rate_plan_id, stay.subtotal.minor_units, discount.minor_units, taxes.minor_units, fees.minor_units,
+            stay.total.minor_units, stay.total.currency, cancellation::to_snapshot(&stay.cancellation_policy)?,
+            QUOTE_VALIDITY,
+        ),
+        |row| row.get(0),
+    )?;
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO quote_nights (quote_id, night, price_minor, currency, weekend, rate_season_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for n in &stay.nights {
+        stmt.execute((id, &n.night, n.price.minor_units, n.price.currency, n.weekend, n.rate_season_id))?;
+    }
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO quote_promotions (quote_id, promotion_id, code, discount_minor, currency)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for d in &stay.discounts {
+        stmt.execute((id, &d.promotion_id, &d.code, d.amount.minor_units, d.amount.currency))?;
+    }
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO quote_charges (quote_id, line, tax_rule_id, name, category, basis, percent, unit_minor,
+                                    quantity, amount_minor, currency)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    )?;
+    for (line, c) in stay.charges.iter().enumerate() {
+        stmt.execute((
+            id, line as i64 + 1, c.tax_rule_id, &c.name, c.category, c.basis, c.percent,
+            c.unit_amount.map(|unit| unit.minor_units), c.quantity, c.amount.minor_units, c.amount.currency,
+        ))?;
+    }
+
+    Ok(Quote {
+        id: id.to_string(),
+        hotel_id: req.hotel_id.clone(),
+        room_type: req.room_type.clone(),
+        check_in: req.check_in.clone(),
+        check_out: req.check_out.clone(),
+        guests: req.guests,
+        guest_type: req.guest_type,
+        rate_plan_id: stay.rate_plan_id,
+        promo_codes: stay.discounts.iter().map(|d| d.code.clone()).collect(),
+        nights: stay.nights,
+        discounts: stay.discounts,
+        charges: stay.charges,
+        subtotal: stay.subtotal,
+        discount,
+        taxes,
+        fees,
+        total: stay.total,
+        cancellation_policy: stay.cancellation_policy,
+        expires_at,
+    })
+}
+
+//a stored quote with its nights, discounts and charges, and whether it has expired
+pub fn load_quote(conn: &Connection, id: &str) -> Result<(Quote, bool), ApiError> {
+    let (mut quote, expired) = conn.query_row(
+        "SELECT id, hotel_id, room_type, check_in, check_out, guests, guest_type, rate_plan_id, discount_minor,
+                subtotal_minor, taxes_minor, fees_minor, total_minor, currency, cancellation_policy, expires_at,
+                expires_at <= datetime('now')
+         FROM quotes WHERE id = ?1",
+        [id],
+        |row| {
+            let currency: Currency = row.get(13)?;
+            let quote = Quote {
+                id: row.get(0)?,
+                hotel_id: row.get(1)?,
+                room_type: row.get(2)?,
+                check_in: row.get(3)?,
+                check_out: row.get(4)?,
+                guests: row.get(5)?,
+                guest_type: row.get(6)?,
+                rate_plan_id: row.get(7)?,
+                promo_codes: Vec::new(),
+                nights: Vec::new(),
+                discounts: Vec::new(),
+                charges: Vec::new(),
+                subtotal: Money::new(row.get(9)?, currency),
+                discount: Money::new(row.get(8)?, currency),
+                taxes: Money::new(row.get(10)?, currency),
+                fees: Money::new(row.get(11)?, currency),
+                total: Money::new(row.get(12)?, currency),
+                cancellation_policy: cancellation::snapshot_column(row, 14)?,
+                expires_at: row.get(15)?,
+            };
+            Ok((quote, row.get::<_, bool>(16)?))
+        },
+    ).optional()?.ok_or(ApiError::NotFound("quote"))?;
+
+    let mut stmt = conn.prepare(
+        "SELECT night, price_minor, currency, weekend, rate_season_id FROM quote_nights
+         WHERE quote_id = ?1 ORDER BY night",
+    )?;
+    let nights = stmt.query_map([id], |row| {
+        Ok(NightlyPrice {
+            night: row.get(0)?,
+            price: Money::new(row.get(1)?, row.get(2)?),
+            weekend: row.get(3)?,
+            rate_season_id: row.get(4)?,
+        })
+    })?;
+    quote.nights = nights.collect::<rusqlite::Result<_>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT promotion_id, code, discount_minor, currency FROM quote_promotions
+         WHERE quote_id = ?1 ORDER BY rowid",
+    )?;
+    let discounts = stmt.query_map([id], |row| {
+        Ok(Discount { promotion_id: row.get(0)?, code: row.get(1)?, amount: Money::new(row.get(2)?, row.get(3)?) })
+    })?;
+    quote.discounts = discounts.collect::<rusqlite::Result<_>>()?;
+    quote.promo_codes = quote.discounts.iter().map(|d| d.code.clone()).collect();
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM quote_charges WHERE quote_id = ?1 ORDER BY line",
+        taxes::CHARGE_COLUMNS
+    ))?;
+    quote.charges = stmt.query_map([id], taxes::charge_from_row)?.collect::<rusqlite::Result<_>>()?;
+
+    Ok((quote, expired))
+}
+
+//the distinct non-empty codes of a list, normalized
+fn code_set(codes: &[String]) -> BTreeSet<String> {
+    codes.iter().map(|code| promotions::normalize_code(code)).filter(|code| !code.is_empty()).collect()
+}
+
+//the prices of a quote for a booking that matches it; a quote is only honoured once and before it expires
+pub fn redeem_quote(conn: &Connection, quote_id: &str, booking: &Booking) -> Result<StayPrice, ApiError> {
+    let (quote, expired) = load_quote(conn, quote_id)?;
+    if expired {
+        return Err(ApiError::conflict_with(
+            "quote_expired",
+            "the quote has expired, ask for a new one",
+            json!({"expires_at": quote.expires_at}),
+        ));
+    }
+    let used: Option<String> = conn.query_row(
+        "SELECT id FROM bookings WHERE quote_id = ?1",
+        [quote_id],
+        |row| row.get(0),
+    ).optional()?;
+    if let Some(booking_id) = used {
+        return Err(ApiError::conflict_with(
+            "quote_used",
+            "the quote has already been booked",
+            json!({"booking_id": booking_id}),
+        ));
+    }
+
+    let room: Option<(String, String)> = conn.query_row(
+        "SELECT hotel_id, room_type FROM rooms WHERE id = ?1",
+        [&booking.room_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?;
+    let (room_hotel, room_type) = room.ok_or(ApiError::NotFound("room"))?;
+
+    let mut mismatches = Vec::new();
+    if booking.hotel_id != quote.hotel_id || room_hotel != quote.hotel_id {
+        mismatches.push("hotel_id");
+    }
+    if room_type != quote.room_type {
+        mismatches.push("room_id");
+    }
+    if booking.check_in != quote.check_in {
+        mismatches.push("check_in");
+    }
+    if booking.check_out != quote.check_out {
+        mismatches.push("check_out");
+    }
+    if booking.guests != quote.guests {
+        mismatches.push("guests");
+    }
+    //the quote's taxes were worked out for its guest type, an exempt quote must not go to a taxed guest
+    if taxes::guest_type(conn, &booking.guest_id)? != quote.guest_type {
+        mismatches.push("guest_id");
+    }
+    if booking.rate_plan_id.is_some() && booking.rate_plan_id != quote.rate_plan_id {
+        mismatches.push("rate_plan_id");
+    }
+    //codes are matched as a set, their order and spelling do not matter
+    let codes = code_set(&booking.promo_codes);
+    if !codes.is_empty() && codes != code_set(&quote.promo_codes) {
+        mismatches.push("promo_codes");
+    }
+    if !mismatches.is_empty() {
+        return Err(ApiError::conflict_with(
+            "quote_mismatch",
+            "the booking does not match the quote",
+            json!({"fields": mismatches}),
+        ));
+    }
+
+    //the guest is only known now, so per guest caps are checked on booking
+    let promo_stay = PromoStay {
+        hotel_id: &quote.hotel_id,
+        room_type: &quote.room_type,
+        nights: quote.nights.len() as i64,
+        guest_id: Some(&booking.guest_id),
+        booking_id: None,
+    };
+    promotions::recheck_caps(conn, &quote.discounts, &promo_stay)?;
+
+    Ok(StayPrice {
+        rate_plan_id: quote.rate_plan_id,
+        nights: quote.nights,
+        subtotal: quote.subtotal,
+        discounts: quote.discounts,
+        charges: quote.charges,
+        total: quote.total,
+        cancellation_policy: quote.cancellation_policy,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, eur};
+
+    //the test hotel, SAVE10 takes 10% off and FIFTY 50.00 EUR off any stay
+    fn hotel() -> Connection {
+        let conn = db::test_hotel();
+        conn.execute_batch(
+            "INSERT INTO promotions (id, code, name, kind, percent, valid_from, valid_until, stackable)
+             VALUES ('save10', 'SAVE10', 'Ten off', 'percent', '10', '2000-01-01', '2999-01-01', 1);
+             INSERT INTO promotions (id, code, name, kind, amount_minor, currency, valid_from, valid_until, stackable)
+             VALUES ('fifty', 'FIFTY', 'Fifty off', 'fixed', 5000, 'EUR', '2000-01-01', '2999-01-01', 1);",
+        ).unwrap();
+        conn
+    }
+
+    //a quote for two nights in a double from Monday 2027-03-01
+    fn quote(conn: &Connection, codes: &[&str]) -> Quote {
+        create_quote(conn, "q1", &QuoteRequest {
+            hotel_id: "h1".to_string(),
+            room_type: "double".to_string(),
+            check_in: "2027-03-01".to_string(),
+            check_out: "2027-03-03".to_string(),
+            guests: 2,
+            guest_type: Default::default(),
+            rate_plan_id: None,
+            promo_codes: codes.iter().map(|code| code.to_string()).collect(),
+        }).unwrap()
+    }
+
+    fn booking(room_id: &str, check_out: &str, codes: &[&str]) -> Booking {
+        serde_json::from_value(json!({
+            "guest_id": "g1",
+            "room_id": room_id,
+            "hotel_id": "h1",
+            "check_in": "2027-03-01",
+            "check_out": check_out,
+            "guests": 2,
+            "quote_id": "q1",
+            "promo_codes": codes,
+        })).unwrap()
+    }
+
+    //the total a booking is charged on its quote
+    fn redeem(conn: &Connection, booking: &Booking) -> Result<Money, String> {
+        redeem_quote(conn, "q1", booking).map(|stay| stay.total).map_err(|err| err.code().to_string())
+    }
+
+    //fields a booking did not match its quote on
+    fn mismatches(conn: &Connection, booking: &Booking) -> serde_json::Value {
+        match redeem_quote(conn, "q1", booking) {
+            Err(ApiError::Conflict { code: "quote_mismatch", details: Some(details), .. }) => details["fields"].clone(),
+            Err(err) => panic!("unexpected error {}", err.code()),
+            Ok(_) => panic!("the quote was redeemed"),
+        }
+    }
+
+    #[test]
+    fn quotes_the_cheapest_free_room_and_keeps_its_prices() {
+        let conn = hotel();
+        conn.execute(
+            "INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, status)
+             VALUES ('b1', 'g2', 'r1', 'h1', '2027-03-02', '2027-03-04', 'confirmed')",
+            [],
+        ).unwrap();
+        let quote = quote(&conn, &[]);
+        //r1 is taken on the second night, r2 is the cheapest double left
+        assert_eq!(quote.subtotal, eur(24000));
+        conn.execute("UPDATE rooms SET price_minor = 99900 WHERE id = 'r2'", []).unwrap();
+
+        //any double of the hotel takes the quoted prices
+        assert_eq!(redeem(&conn, &booking("r3", "2027-03-03", &[])).unwrap(), quote.total);
+    }
+
+    #[test]
+    fn honours_a_quote_once_and_before_it_expires() {
+        let conn = hotel();
+        quote(&conn, &[]);
+        conn.execute(
+            "INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, status, quote_id)
+             VALUES ('b1', 'g1', 'r1', 'h1', '2027-03-01', '2027-03-03', 'confirmed', 'q1')",
+            [],
+        ).unwrap();
+        assert_eq!(redeem(&conn, &booking("r2", "2027-03-03", &[])).unwrap_err(), "quote_used");
+
+        conn.execute("DELETE FROM bookings", []).unwrap();
+        conn.execute("UPDATE quotes SET expires_at = datetime('now', '-1 minute')", []).unwrap();
+        assert_eq!(redeem(&conn, &booking("r2", "2027-03-03", &[])).unwrap_err(), "quote_expired");
+        assert!(matches!(redeem_quote(&conn, "q2", &booking("r2", "2027-03-03", &[])), Err(ApiError::NotFound("quote"))));
+    }
+
+    #[test]
+    fn refuses_a_booking_that_differs_from_its_quote() {
+        let conn = hotel();
+        quote(&conn, &[]);
+        assert_eq!(mismatches(&conn, &booking("s1", "2027-03-04", &[])), json!(["room_id", "check_out"]));
+
+        //a resident would not pay the taxes a standard guest was quoted
+        conn.execute("UPDATE guests SET guest_type = 'resident' WHERE id = 'g1'", []).unwrap();
+        assert_eq!(mismatches(&conn, &booking("r1", "2027-03-03", &[])), json!(["guest_id"]));
+    }
+
+    #[test]
+    fn matches_promo_codes_as_a_set() {
+        let conn = hotel();
+        let quote = quote(&conn, &["SAVE10", "FIFTY"]);
+        assert_eq!(quote.discount, eur(7000));
+
+        assert_eq!(redeem(&conn, &booking("r1", "2027-03-03", &[" fifty", "save10", "FIFTY", ""])).unwrap(), quote.total);
+        //leaving the codes out takes the quote's
+        assert!(redeem(&conn, &booking("r1", "2027-03-03", &[])).is_ok());
+        assert_eq!(mismatches(&conn, &booking("r1", "2027-03-03", &["SAVE10"])), json!(["promo_codes"]));
+    }
+}
@@ -1,217 +1,425 @@
-use actix_web::{get, post, put, delete, web, HttpResponse, Responder};
-use rusqlite::Connection;
+use std::sync::Arc;
+
+use actix_web::{get, post, put, delete, web, HttpResponse};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+use crate::config::Features;
+use crate::blocks;
+use crate::cancellation;
+use crate::db::{self, DbPool};
+use crate::error::{ApiError, FieldError};
+use crate::validation::{Rules, Valid, Validate};
+use crate::exchange;
+use crate::folio;
+use crate::gateway::{self, PaymentGateway};
+use crate::money::{Currency, Money, Rate};
+use crate::pricing::{self, StayPrice, StayRequest};
+use crate::promotions;
+use crate::quotes;
+use crate::taxes::{self, Occupancy};
+use crate::waitlist::{self, HoldWindow};
+use crate::listing::{self, Filter, FilterKind, FilterOp, ListQuery, ListSpec};
 use serde_json::json;
 use uuid::Uuid;
-use crate::models::{Hotel, Room, Guest, Booking, Payment};
+use crate::models::{CancellationPolicy, Hotel, Room, HousekeepingStatus, HousekeepingUpdate, Guest, Booking, BookingStatus, BookingStatusChange, BookingAmendment, BookingModification, Reservation, ReservationCancel, RoomBlock, RoomBlockStatus, WaitlistEntry, WaitlistOffer, WaitlistStatus, OfferStatus, OfferAcceptance, ModificationRates, Payment, PaymentCapture, PaymentStatus, PaymentVoid, Refund, RefundStatus, WebhookEvent, WebhookKind, AvailabilityQuery, AvailabilityGroup, ExchangeRate, RatePlan, RateSeason, NightlyPrice, QuoteRequest, TaxRule, ChargeCategory, Promotion, Discount, FolioCharge, FolioEntryKind, FolioReversal};
 
 //---Hotels---
 
-//creates an hotel 
+//creates an hotel
 #[post("/hotels")]
-async fn create_hotel(data: web::Json<Hotel>) -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
+async fn create_hotel(pool: web::Data<DbPool>, data: Valid<Hotel>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
     let id = Uuid::new_v4().to_string();
+    let hotel_id = id.clone();
 
-    conn.execute(
-        "INSERT INTO hotels (id, name, location, stars) VALUES (?1, ?2, ?3, ?4)",
-        (&id, &data.name, &data.location, &data.stars),
-    ).unwrap();
+    db::run(&pool, move |conn| {
+        cancellation::check_exists(conn, data.cancellation_policy_id.as_deref())?;
+        conn.execute(
+            "INSERT INTO hotels (id, name, location, stars, currency, cancellation_policy_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (&hotel_id, &data.name, &data.location, &data.stars, data.currency, &data.cancellation_policy_id),
+        )?;
+        Ok(())
+    }).await?;
 
-    HttpResponse::Ok().json(json!({
+    Ok(HttpResponse::Ok().json(json!({
         "status": "hotel added",
         "id": id
-    }))
+    })))
 }
 
-//returns all hotels in DB
+const HOTEL_LIST: ListSpec = ListSpec {
+    select: "SELECT id, name, location, stars, currency, cancellation_policy_id FROM hotels",
+    sort_fields: &[("name", "t.name"), ("location", "COALESCE(t.location, '')"), ("stars", "COALESCE(t.stars, 0)")],
+    filters: &[
+        Filter { param: "location", expr: "t.location", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "name", expr: "t.name", op: FilterOp::Contains, kind: FilterKind::Text },
+        Filter { param: "stars_gte", expr: "t.stars", op: FilterOp::Gte, kind: FilterKind::Integer },
+        Filter { param: "stars_lte", expr: "t.stars", op: FilterOp::Lte, kind: FilterKind::Integer },
+        Filter { param: "currency", expr: "t.currency", op: FilterOp::Eq, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of hotels, see `listing` for the query parameters
 #[get("/hotels")]
-async fn get_hotels() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare("SELECT id, name, location, stars FROM hotels").unwrap();
-    let hotels_iter = stmt
-        .query_map([], |row| {
+async fn get_hotels(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| {
+        listing::fetch_page(conn, &HOTEL_LIST, &query, |row| {
             Ok(Hotel {
                 id: Some(row.get(0)?),
                 name: row.get(1)?,
                 location: row.get(2)?,
                 stars: row.get(3)?,
+                currency: row.get(4)?,
+                cancellation_policy_id: row.get(5)?,
             })
-        }).unwrap();
-
-    let hotels: Vec<Hotel> = hotels_iter.map(|h| h.unwrap()).collect();
-    HttpResponse::Ok().json(hotels)
+        })
+    }).await?;
+    Ok(HttpResponse::Ok().json(page))
 }
 
 //return hotel by ID
 #[get("/hotels/{id}")]
-async fn get_hotel_by_id(path: web::Path<String>) -> impl Responder {
-    let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare("SELECT id, name, location, stars FROM hotels WHERE id = ?1").unwrap();
-    let hotel = stmt.query_row([id], |row| {
-        Ok(Hotel {
-            id: Some(row.get(0)?),
-            name: row.get(1)?,
-            location: row.get(2)?,
-            stars: row.get(3)?,
-        })
-    });
+async fn get_hotel_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let hotel = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare("SELECT id, name, location, stars, currency, cancellation_policy_id FROM hotels WHERE id = ?1")?;
+        stmt.query_row([id], |row| {
+            Ok(Hotel {
+                id: Some(row.get(0)?),
+                name: row.get(1)?,
+                location: row.get(2)?,
+                stars: row.get(3)?,
+                currency: row.get(4)?,
+                cancellation_policy_id: row.get(5)?,
+            })
+        }).optional()?.ok_or(ApiError::NotFound("hotel"))
+    }).await?;
 
-    match hotel {
-        Ok(h) => HttpResponse::Ok().json(h),
-        Err(_) => HttpResponse::NotFound().json(json!({"error": "hotel not found"})),
-    }
+    Ok(HttpResponse::Ok().json(hotel))
 }
 
 //returns highest rated hotel in DB
 #[get("/hotels/highest-rated")]
-async fn get_highest_rated_hotel() -> impl Responder {
+async fn get_highest_rated_hotel(pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
     println!("🔥 get_highest_rated_hotel called!");
 
-    let conn = Connection::open("hotel.db").unwrap();
-
-    let mut stmt = conn
-        .prepare("SELECT id, name, location, stars FROM hotels ORDER BY stars DESC LIMIT 1")
-        .unwrap();
+    let hotels: Vec<Hotel> = db::run(&pool, |conn| {
+        let mut stmt = conn
+            .prepare("SELECT id, name, location, stars, currency, cancellation_policy_id FROM hotels ORDER BY stars DESC LIMIT 1")?;
 
-    let result = stmt.query_map([], |row| {
-        Ok(Hotel {
-            id: Some(row.get(0)?),
-            name: row.get(1)?,
-            location: row.get(2)?,
-            stars: row.get(3)?,
-        })
-    }).unwrap();
+        let result = stmt.query_map([], |row| {
+            Ok(Hotel {
+                id: Some(row.get(0)?),
+                name: row.get(1)?,
+                location: row.get(2)?,
+                stars: row.get(3)?,
+                currency: row.get(4)?,
+                cancellation_policy_id: row.get(5)?,
+            })
+        })?;
 
-    let hotels: Vec<Hotel> = result.map(|h| h.unwrap()).collect();
+        Ok(result.collect::<rusqlite::Result<_>>()?)
+    }).await?;
 
     if let Some(top_hotel) = hotels.first() {
-        HttpResponse::Ok().json(top_hotel)
+        Ok(HttpResponse::Ok().json(top_hotel))
     } else {
-        HttpResponse::Ok().json(json!({"message": "No hotels found"}))
+        Ok(HttpResponse::Ok().json(json!({"message": "No hotels found"})))
     }
 }
 
 
 //updetes an hotel by a certain ID
 #[put("/hotels/{id}")]
-async fn update_hotel(path: web::Path<String>, data: web::Json<Hotel>) -> impl Responder {
+async fn update_hotel(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<Hotel>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
-    conn.execute(
-        "UPDATE hotels SET name = ?1, location = ?2, stars = ?3 WHERE id = ?4",
-        (&data.name, &data.location, &data.stars, id),
-    ).unwrap();
-    HttpResponse::Ok().json(json!({"status": "hotel updated"}))
+    let data = data.into_inner();
+    db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        //switching the base currency would leave rooms and flat taxes priced in the old one
+        let other: Option<Currency> = tx.query_row(
+            "SELECT currency FROM rooms WHERE hotel_id = ?1 AND currency != ?2
+             UNION ALL
+             SELECT currency FROM tax_rules WHERE hotel_id = ?1 AND currency != ?2
+             LIMIT 1",
+            (&id, data.currency),
+            |row| row.get(0),
+        ).optional()?;
+        if let Some(other) = other {
+            Money::zero(other).ensure_currency(data.currency)?;
+        }
+
+        cancellation::check_exists(&tx, data.cancellation_policy_id.as_deref())?;
+        let updated = tx.execute(
+            "UPDATE hotels SET name = ?1, location = ?2, stars = ?3, currency = ?4, cancellation_policy_id = ?5
+             WHERE id = ?6",
+            (&data.name, &data.location, &data.stars, data.currency, &data.cancellation_policy_id, &id),
+        )?;
+        if updated == 0 {
+            return Err(ApiError::NotFound("hotel"));
+        }
+        tx.commit()?;
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "hotel updated"})))
 }
 
 //deletes an hotel by ID from DB
 #[delete("/hotels/{id}")]
-async fn delete_hotel(path: web::Path<String>) -> impl Responder {
+async fn delete_hotel(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
-    conn.execute("DELETE FROM hotels WHERE id = ?1", [&id]).unwrap();
-    HttpResponse::Ok().json(json!({"status": "hotel deleted"}))
+    db::run(&pool, move |conn| {
+        if conn.execute("DELETE FROM hotels WHERE id = ?1", [&id])? == 0 {
+            return Err(ApiError::NotFound("hotel"));
+        }
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "hotel deleted"})))
 }
 
 //---rooms---
 
+//room columns plus the status derived from checked-in bookings and housekeeping
+const ROOM_SELECT: &str = "
+    SELECT r.id, r.hotel_id, r.room_type, r.price_minor, r.currency, r.housekeeping,
+           CASE
+               WHEN EXISTS (
+                   SELECT 1 FROM bookings b WHERE b.room_id = r.id AND b.status = 'checked_in'
+               ) THEN 'occupied'
+               WHEN r.housekeeping = 'out_of_order' THEN 'out_of_order'
+               ELSE 'available'
+           END AS status
+    FROM rooms r
+";
+
+fn room_from_row(row: &rusqlite::Row) -> rusqlite::Result<Room> {
+    Ok(Room {
+        id: Some(row.get(0)?),
+        hotel_id: row.get(1)?,
+        room_type: row.get(2)?,
+        price: Money::new(row.get(3)?, row.get(4)?),
+        housekeeping: row.get(5)?,
+        status: row.get(6)?,
+    })
+}
+
+//rooms and rate plans are priced in the base currency of their hotel
+fn check_hotel_currency(conn: &Connection, hotel_id: &str, price: &Money) -> Result<(), ApiError> {
+    let currency: Currency = conn.query_row(
+        "SELECT currency FROM hotels WHERE id = ?1",
+        [hotel_id],
+        |row| row.get(0),
+    ).optional()?.ok_or(ApiError::NotFound("hotel"))?;
+
+    Ok(price.ensure_currency(currency)?)
+}
+
 //creates a room in DB
 #[post("/rooms")]
-async fn create_room(data: web::Json<Room>) -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
+async fn create_room(pool: web::Data<DbPool>, data: Valid<Room>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
     let id = Uuid::new_v4().to_string();
+    let room_id = id.clone();
 
-    conn.execute(
-        "INSERT INTO rooms (id, hotel_id, room_type, price, status)
-         VALUES (?1, ?2, ?3, ?4, ?5)",
-        (&id, &data.hotel_id, &data.room_type, &data.price, &data.status),
-    ).unwrap();
+    db::run(&pool, move |conn| {
+        check_hotel_currency(conn, &data.hotel_id, &data.price)?;
+        conn.execute(
+            "INSERT INTO rooms (id, hotel_id, room_type, price_minor, currency, housekeeping)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (&room_id, &data.hotel_id, &data.room_type, data.price.minor_units, data.price.currency, &data.housekeeping),
+        )?;
+        Ok(())
+    }).await?;
 
-    HttpResponse::Ok().json(json!({"status": "room added", "id": id}))
+    Ok(HttpResponse::Ok().json(json!({"status": "room added", "id": id})))
 }
 
 
-//returns all rooms in DB
-#[get("/rooms")]
-async fn get_rooms() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare(
-        "SELECT id, hotel_id, room_type, price, status FROM rooms"
-    ).unwrap();
-
-    let rooms_iter = stmt.query_map([], |row| {
-        Ok(Room {
-            id: Some(row.get(0)?),
-            hotel_id: row.get(1)?,
-            room_type: row.get(2)?,
-            price: row.get(3)?,
-            status: row.get(4)?,
-        })
-    }).unwrap();
+//filters run against ROOM_SELECT so the derived status can be filtered on too
+const ROOM_LIST: ListSpec = ListSpec {
+    select: ROOM_SELECT,
+    sort_fields: &[("hotel_id", "t.hotel_id"), ("room_type", "COALESCE(t.room_type, '')"), ("price", "t.price_minor"), ("status", "t.status")],
+    filters: &[
+        Filter { param: "hotel_id", expr: "t.hotel_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "room_type", expr: "t.room_type", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "status", expr: "t.status", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "housekeeping", expr: "t.housekeeping", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "currency", expr: "t.currency", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "price_minor_gte", expr: "t.price_minor", op: FilterOp::Gte, kind: FilterKind::Integer },
+        Filter { param: "price_minor_lte", expr: "t.price_minor", op: FilterOp::Lte, kind: FilterKind::Integer },
+    ],
+};
 
-    let rooms: Vec<Room> = rooms_iter.map(|r| r.unwrap()).collect();
-    HttpResponse::Ok().json(rooms)
+//returns a page of rooms, see `listing` for the query parameters
+#[get("/rooms")]
+async fn get_rooms(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| listing::fetch_page(conn, &ROOM_LIST, &query, room_from_row)).await?;
+    Ok(HttpResponse::Ok().json(page))
 }
 
 
 //returns a room by ID
 #[get("/rooms/{id}")]
-async fn get_room_by_id(path: web::Path<String>) -> impl Responder {
+async fn get_room_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare(
-        "SELECT id, hotel_id, room_type, price, status FROM rooms WHERE id = ?1"
-    ).unwrap();
-
-    let room = stmt.query_row([id], |row| {
-        Ok(Room {
-            id: Some(row.get(0)?),
-            hotel_id: row.get(1)?,
-            room_type: row.get(2)?,
-            price: row.get(3)?,
-            status: row.get(4)?,
-        })
-    });
+    let room = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare(&format!("{ROOM_SELECT} WHERE r.id = ?1"))?;
 
-    match room {
-        Ok(r) => HttpResponse::Ok().json(r),
-        Err(_) => HttpResponse::NotFound().json(json!({"error": "room not found"})),
-    }
+        stmt.query_row([id], room_from_row).optional()?.ok_or(ApiError::NotFound("room"))
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(room))
 }
 
-//updates a certain room by ID
+//updates a certain room by ID, housekeeping has its own endpoint
 #[put("/rooms/{id}")]
-async fn update_room(path: web::Path<String>, data: web::Json<Room>) -> impl Responder {
+async fn update_room(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<Room>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
+    let data = data.into_inner();
 
-    conn.execute(
-        "UPDATE rooms SET hotel_id = ?1, room_type = ?2, price = ?3, status = ?4 WHERE id = ?5",
-        (&data.hotel_id, &data.room_type, &data.price, &data.status, &id),
-    ).unwrap();
+    db::run(&pool, move |conn| {
+        check_hotel_currency(conn, &data.hotel_id, &data.price)?;
+        let updated = conn.execute(
+            "UPDATE rooms SET hotel_id = ?1, room_type = ?2, price_minor = ?3, currency = ?4 WHERE id = ?5",
+            (&data.hotel_id, &data.room_type, data.price.minor_units, data.price.currency, &id),
+        )?;
+        if updated == 0 {
+            return Err(ApiError::NotFound("room"));
+        }
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "room updated"})))
+}
+
+//sets the housekeeping state of a room
+#[put("/rooms/{id}/housekeeping")]
+async fn update_room_housekeeping(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    data: web::Json<HousekeepingUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let housekeeping = data.housekeeping;
+
+    db::run(&pool, move |conn| {
+        let updated = conn.execute(
+            "UPDATE rooms SET housekeeping = ?1 WHERE id = ?2",
+            (housekeeping, &id),
+        )?;
+        if updated == 0 {
+            return Err(ApiError::NotFound("room"));
+        }
+        Ok(())
+    }).await?;
 
-    HttpResponse::Ok().json(json!({"status": "room updated"}))
+    Ok(HttpResponse::Ok().json(json!({"status": "housekeeping updated", "housekeeping": housekeeping})))
 }
 
 //deletes a room by ID
 #[delete("/rooms/{id}")]
-async fn delete_room(path: web::Path<String>) -> impl Responder {
+async fn delete_room(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
 
-    conn.execute("DELETE FROM rooms WHERE id = ?1", [&id]).unwrap();
-    HttpResponse::Ok().json(json!({"status": "room deleted"}))
+    db::run(&pool, move |conn| {
+        if conn.execute("DELETE FROM rooms WHERE id = ?1", [&id])? == 0 {
+            return Err(ApiError::NotFound("room"));
+        }
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "room deleted"})))
 }
 
 
-//returns number of available room
+//returns number of available rooms, and how many of those are ready to hand out right now
 #[get("/rooms/available/count")]
-async fn count_available_rooms() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM rooms WHERE status = 'available'").unwrap();
-    let count: i64 = stmt.query_row([], |row| row.get(0)).unwrap();
-    HttpResponse::Ok().json(json!({ "available_rooms": count }))
+async fn count_available_rooms(pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let (count, ready): (i64, i64) = db::run(&pool, |conn| {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT COUNT(*), COALESCE(SUM(housekeeping IN ('clean', 'inspected')), 0)
+             FROM ({ROOM_SELECT}) WHERE status = 'available'"
+        ))?;
+        Ok(stmt.query_row([], |row| Ok((row.get(0)?, row.get(1)?)))?)
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({ "available_rooms": count, "ready_rooms": ready })))
+}
+
+//---availability---
+
+//returns rooms free for the whole stay, grouped by hotel and room type; rooms held for room blocks are not offered
+#[get("/availability")]
+async fn search_availability(pool: web::Data<DbPool>, query: web::Query<AvailabilityQuery>) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    query.validate()?;
+    let (check_in, check_out) = (query.check_in.clone(), query.check_out.clone());
+
+    //same half-open overlap rule as find_conflicting_bookings
+    let sql = "
+        SELECT h.id, h.name, h.location, h.stars, r.room_type,
+               COUNT(r.id), MIN(r.price_minor), r.currency, GROUP_CONCAT(r.id ORDER BY r.price_minor, r.id)
+        FROM rooms r
+        JOIN hotels h ON h.id = r.hotel_id
+        WHERE r.housekeeping != 'out_of_order'
+          AND NOT EXISTS (
+                SELECT 1 FROM bookings b
+                WHERE b.room_id = r.id AND b.check_in < ?2 AND b.check_out > ?1
+                  AND b.status NOT IN ('cancelled', 'no_show')
+              )
+          AND (?3 IS NULL OR r.hotel_id = ?3)
+          AND (?4 IS NULL OR r.room_type = ?4)
+          AND (?5 IS NULL OR h.stars >= ?5)
+          AND (?6 IS NULL OR h.location LIKE '%' || ?6 || '%')
+        GROUP BY h.id, r.room_type, r.currency
+        ORDER BY h.name, r.room_type
+    ";
+
+    let groups: Vec<AvailabilityGroup> = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare(sql)?;
+        let groups_iter = stmt.query_map(
+            (&query.check_in, &query.check_out, &query.hotel_id, &query.room_type, &query.min_stars, &query.location),
+            |row| {
+                let room_ids: String = row.get(8)?;
+                Ok(AvailabilityGroup {
+                    hotel_id: row.get(0)?,
+                    hotel_name: row.get(1)?,
+                    location: row.get(2)?,
+                    stars: row.get(3)?,
+                    room_type: row.get(4)?,
+                    available_rooms: row.get(5)?,
+                    lowest_price: Money::new(row.get(6)?, row.get(7)?),
+                    display_price: None,
+                    room_ids: room_ids.split(',').map(String::from).collect(),
+                })
+            },
+        )?;
+        let mut groups = groups_iter.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        //blocks hold a number of rooms rather than particular ones, so only that many of the free rooms are offered;
+        //they are the cheapest ones, which keeps the lowest price that of a room offered
+        for group in &mut groups {
+            let public = blocks::public_rooms(conn, &group.hotel_id, &group.room_type, &query.check_in, &query.check_out)?;
+            group.available_rooms = group.available_rooms.min(public);
+            group.room_ids.truncate(group.available_rooms as usize);
+        }
+        groups.retain(|group| group.available_rooms > 0);
+
+        if let Some(currency) = query.currency {
+            let today: String = conn.query_row("SELECT date('now')", [], |row| row.get(0))?;
+            for group in &mut groups {
+                let rate = exchange::rate_on(conn, group.lowest_price.currency, currency, &today)?;
+                group.display_price = Some(group.lowest_price.convert(currency, rate)?);
+            }
+        }
+
+        Ok(groups)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "check_in": check_in,
+        "check_out": check_out,
+        "results": groups
+    })))
 }
 
 //---guests---
@@ -219,237 +427,1053 @@ async fn count_available_rooms() -> impl Responder {
 
 //creates a guest in DB
 #[post("/guests")]
-async fn create_guest(data: web::Json<Guest>) -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
+async fn create_guest(pool: web::Data<DbPool>, data: Valid<Guest>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
     let id = Uuid::new_v4().to_string();
+    let guest_id = id.clone();
 
-    conn.execute(
-        "INSERT INTO guests (id, name, phone, email)
-         VALUES (?1, ?2, ?3, ?4)",
-        (&id, &data.name, &data.phone, &data.email),
-    ).unwrap();
+    db::run(&pool, move |conn| {
+        conn.execute(
+            "INSERT INTO guests (id, name, phone, email, guest_type)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            (&guest_id, &data.name, &data.phone, &data.email, data.guest_type),
+        )?;
+        Ok(())
+    }).await?;
 
-    HttpResponse::Ok().json(json!({"status": "guest added", "id": id}))
+    Ok(HttpResponse::Ok().json(json!({"status": "guest added", "id": id})))
 }
 
-//returns guests in DB
+const GUEST_LIST: ListSpec = ListSpec {
+    select: "SELECT id, name, phone, email, guest_type FROM guests",
+    sort_fields: &[("name", "COALESCE(t.name, '')"), ("email", "COALESCE(t.email, '')")],
+    filters: &[
+        Filter { param: "name", expr: "t.name", op: FilterOp::Contains, kind: FilterKind::Text },
+        Filter { param: "email", expr: "t.email", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "phone", expr: "t.phone", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "guest_type", expr: "t.guest_type", op: FilterOp::Eq, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of guests, see `listing` for the query parameters
 #[get("/guests")]
-async fn get_guests() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare("SELECT id, name, phone, email FROM guests").unwrap();
-
-    let guests_iter = stmt.query_map([], |row| {
-        Ok(Guest {
-            id: Some(row.get(0)?),
-            name: row.get(1)?,
-            phone: row.get(2)?,
-            email: row.get(3)?,
+async fn get_guests(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| {
+        listing::fetch_page(conn, &GUEST_LIST, &query, |row| {
+            Ok(Guest {
+                id: Some(row.get(0)?),
+                name: row.get(1)?,
+                phone: row.get(2)?,
+                email: row.get(3)?,
+                guest_type: row.get(4)?,
+            })
         })
-    }).unwrap();
-
-    let guests: Vec<Guest> = guests_iter.map(|g| g.unwrap()).collect();
-    HttpResponse::Ok().json(guests)
+    }).await?;
+    Ok(HttpResponse::Ok().json(page))
 }
 
 //returns a guest by ID
 #[get("/guests/{id}")]
-async fn get_guest_by_id(path: web::Path<String>) -> impl Responder {
+async fn get_guest_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare(
-        "SELECT id, name, phone, email FROM guests WHERE id = ?1"
-    ).unwrap();
-
-    let guest = stmt.query_row([id], |row| {
-        Ok(Guest {
-            id: Some(row.get(0)?),
-            name: row.get(1)?,
-            phone: row.get(2)?,
-            email: row.get(3)?,
-        })
-    });
+    let guest = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, name, phone, email, guest_type FROM guests WHERE id = ?1"
+        )?;
 
-    match guest {
-        Ok(g) => HttpResponse::Ok().json(g),
-        Err(_) => HttpResponse::NotFound().json(json!({"error": "guest not found"})),
-    }
+        stmt.query_row([id], |row| {
+            Ok(Guest {
+                id: Some(row.get(0)?),
+                name: row.get(1)?,
+                phone: row.get(2)?,
+                email: row.get(3)?,
+                guest_type: row.get(4)?,
+            })
+        }).optional()?.ok_or(ApiError::NotFound("guest"))
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(guest))
 }
 
 //updates a guest by ID
 #[put("/guests/{id}")]
-async fn update_guest(path: web::Path<String>, data: web::Json<Guest>) -> impl Responder {
+async fn update_guest(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<Guest>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
+    let data = data.into_inner();
 
-    conn.execute(
-        "UPDATE guests SET name = ?1, phone = ?2, email = ?3 WHERE id = ?4",
-        (&data.name, &data.phone, &data.email, &id),
-    ).unwrap();
+    db::run(&pool, move |conn| {
+        let updated = conn.execute(
+            "UPDATE guests SET name = ?1, phone = ?2, email = ?3, guest_type = ?4 WHERE id = ?5",
+            (&data.name, &data.phone, &data.email, data.guest_type, &id),
+        )?;
+        if updated == 0 {
+            return Err(ApiError::NotFound("guest"));
+        }
+        Ok(())
+    }).await?;
 
-    HttpResponse::Ok().json(json!({"status": "guest updated"}))
+    Ok(HttpResponse::Ok().json(json!({"status": "guest updated"})))
 }
 
 //deletes a guest by ID
 #[delete("/guests/{id}")]
-async fn delete_guest(path: web::Path<String>) -> impl Responder {
+async fn delete_guest(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
 
-    conn.execute("DELETE FROM guests WHERE id = ?1", [&id]).unwrap();
-    HttpResponse::Ok().json(json!({"status": "guest deleted"}))
+    db::run(&pool, move |conn| {
+        if conn.execute("DELETE FROM guests WHERE id = ?1", [&id])? == 0 {
+            return Err(ApiError::NotFound("guest"));
+        }
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "guest deleted"})))
 }
 
 
 //return guest with most bookings
 #[get("/guests/top")]
-async fn get_guest_with_most_bookings() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-
+async fn get_guest_with_most_bookings(pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
     let sql = "
         SELECT g.id, g.name, COUNT(b.id) AS total_bookings
         FROM guests g
-        LEFT JOIN bookings b ON g.id = b.guest_id
+        LEFT JOIN bookings b ON g.id = b.guest_id AND b.status NOT IN ('cancelled', 'no_show')
         GROUP BY g.id
         ORDER BY total_bookings DESC
         LIMIT 1
     ";
 
-    let mut stmt = conn.prepare(sql).unwrap();
+    let result = db::run(&pool, |conn| {
+        let mut stmt = conn.prepare(sql)?;
 
-    let result = stmt.query_row([], |row| {
-        Ok(json!({
-            "id": row.get::<_, String>(0)?,
-            "name": row.get::<_, String>(1)?,
-            "total_bookings": row.get::<_, i64>(2)?
-        }))
-    });
+        Ok(stmt.query_row([], |row| {
+            Ok(json!({
+                "id": row.get::<_, String>(0)?,
+                "name": row.get::<_, String>(1)?,
+                "total_bookings": row.get::<_, i64>(2)?
+            }))
+        }).optional()?)
+    }).await?;
 
     match result {
-        Ok(guest) => HttpResponse::Ok().json(guest),
-        Err(_) => HttpResponse::Ok().json(json!({"message": "no guests found"})),
+        Some(guest) => Ok(HttpResponse::Ok().json(guest)),
+        None => Ok(HttpResponse::Ok().json(json!({"message": "no guests found"}))),
     }
 }
 
 
 //---bookings---
 
+const BOOKING_SELECT: &str = "
+    SELECT id, guest_id, room_id, hotel_id, check_in, check_out, status, rate_plan_id, total_minor, currency,
+           quote_id, guests,
+           (SELECT json_group_array(code) FROM promotion_redemptions WHERE booking_id = bookings.id) AS promo_codes,
+           cancellation_policy, reservation_id,
+           (SELECT code FROM room_blocks WHERE id = bookings.block_id) AS block_code
+    FROM bookings
+";
+
+fn booking_from_row(row: &rusqlite::Row) -> rusqlite::Result<Booking> {
+    //bookings made before rate plans have no stored total
+    let total_price = match (row.get::<_, Option<i64>>(8)?, row.get::<_, Option<Currency>>(9)?) {
+        (Some(minor_units), Some(currency)) => Some(Money::new(minor_units, currency)),
+        _ => None,
+    };
+
+    Ok(Booking {
+        id: Some(row.get(0)?),
+        guest_id: row.get(1)?,
+        room_id: row.get(2)?,
+        hotel_id: row.get(3)?,
+        check_in: row.get(4)?,
+        check_out: row.get(5)?,
+        guests: row.get(11)?,
+        status: row.get(6)?,
+        rate_plan_id: row.get(7)?,
+        total_price,
+        quote_id: row.get(10)?,
+        promo_codes: serde_json::from_str(&row.get::<_, String>(12)?)
+            .map_err(|err| rusqlite::Error::FromSqlConversionFailure(12, rusqlite::types::Type::Text, Box::new(err)))?,
+        cancellation_policy: Some(cancellation::snapshot_column(row, 13)?),
+        reservation_id: row.get(14)?,
+        block_code: row.get(15)?,
+        reason: None,
+    })
+}
+
+//returns bookings of a room whose stay overlaps [check_in, check_out)
+//half-open ranges, so a check-out day can be the next check-in day
+fn find_conflicting_bookings(
+    conn: &Connection,
+    room_id: &str,
+    check_in: &str,
+    check_out: &str,
+    exclude_id: Option<&str>,
+) -> rusqlite::Result<Vec<Booking>> {
+    let mut stmt = conn.prepare(&format!(
+        "{BOOKING_SELECT}
+         WHERE room_id = ?1 AND check_in < ?3 AND check_out > ?2
+           AND status NOT IN ('cancelled', 'no_show')
+           AND (?4 IS NULL OR id != ?4)
+         ORDER BY check_in"
+    ))?;
+
+    let conflicts = stmt.query_map((room_id, check_in, check_out, exclude_id), booking_from_row)?;
+
+    conflicts.collect()
+}
+
+//409 listing the bookings a stay clashes with
+fn booking_conflict(conflicts: Vec<Booking>) -> ApiError {
+    ApiError::conflict_with(
+        "booking_conflict",
+        "room is already booked for these dates",
+        json!({ "conflicts": conflicts }),
+    )
+}
+
+//folios, taxes, policies and blocks all go by bookings.hotel_id, so it has to be the room's hotel
+fn check_room_hotel(conn: &Connection, room_id: &str, hotel_id: &str) -> Result<(), ApiError> {
+    let room_hotel: String = conn.query_row("SELECT hotel_id FROM rooms WHERE id = ?1", [room_id], |row| row.get(0))
+        .optional()?
+        .ok_or(ApiError::NotFound("room"))?;
+    if room_hotel != hotel_id {
+        return Err(ApiError::invalid("hotel_id", "must be the hotel of the room"));
+    }
+    Ok(())
+}
+
+//checks the room is free and not held for a block the stay does not draw on,
+//prices the stay and stores it as a tentative booking
+fn book_stay(conn: &Connection, booking_id: &str, data: &Booking, reservation_id: Option<&str>) -> Result<StayPrice, ApiError> {
+    check_room_hotel(conn, &data.room_id, &data.hotel_id)?;
+    let conflicts = find_conflicting_bookings(conn, &data.room_id, &data.check_in, &data.check_out, None)?;
+    if !conflicts.is_empty() {
+        return Err(booking_conflict(conflicts));
+    }
+    let block_id = match &data.block_code {
+        Some(code) => Some(blocks::block_for_code(conn, &data.hotel_id, code)?),
+        None => None,
+    };
+    blocks::check_inventory(conn, &data.room_id, &data.check_in, &data.check_out, block_id.as_deref(), None)?;
+    //a quoted stay is charged what the guest was shown, anything else is priced now
+    let stay = match &data.quote_id {
+        Some(quote_id) => quotes::redeem_quote(conn, quote_id, data)?,
+        None => pricing::price_stay(conn, &StayRequest {
+            room_id: &data.room_id,
+            rate_plan_id: data.rate_plan_id.as_deref(),
+            check_in: &data.check_in,
+            check_out: &data.check_out,
+            occupancy: Occupancy { guests: data.guests, guest_type: taxes::guest_type(conn, &data.guest_id)? },
+            promo_codes: &data.promo_codes,
+            guest_id: Some(&data.guest_id),
+            booking_id: Some(booking_id),
+        })?,
+    };
+
+    conn.execute(
+        "INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, guests, status, quote_id,
+                               reservation_id, block_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        (
+            booking_id, &data.guest_id, &data.room_id, &data.hotel_id, &data.check_in, &data.check_out,
+            data.guests, BookingStatus::Tentative, &data.quote_id, reservation_id, &block_id,
+        ),
+    )?;
+    conn.execute(
+        "INSERT INTO booking_status_history (booking_id, from_status, to_status) VALUES (?1, NULL, ?2)",
+        (booking_id, BookingStatus::Tentative),
+    )?;
+    pricing::store_stay_price(conn, booking_id, &stay)?;
+    Ok(stay)
+}
+
 //creates a booking in DB
 #[post("/bookings")]
-async fn create_booking(data: web::Json<Booking>) -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
+async fn create_booking(pool: web::Data<DbPool>, data: Valid<Booking>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
     let id = Uuid::new_v4().to_string();
+    let booking_id = id.clone();
 
-    conn.execute(
-        "INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        (&id, &data.guest_id, &data.room_id, &data.hotel_id, &data.check_in, &data.check_out),
-    ).unwrap();
+    let stay = db::run(&pool, move |conn| {
+        //IMMEDIATE takes the write lock up front so no other booking can slip in between check and insert
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let stay = book_stay(&tx, &booking_id, &data, None)?;
+        tx.commit()?;
 
-    HttpResponse::Ok().json(json!({"status": "booking added", "id": id}))
+        Ok(stay)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "booking added",
+        "id": id,
+        "subtotal": stay.subtotal,
+        "discounts": stay.discounts,
+        "charges": stay.charges,
+        "total_price": stay.total,
+        "nights": stay.nights,
+        "cancellation_policy": stay.cancellation_policy
+    })))
 }
 
-//returns all bookings in DB
-#[get("/bookings")]
-async fn get_bookings() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare(
-        "SELECT id, guest_id, room_id, hotel_id, check_in, check_out FROM bookings"
-    ).unwrap();
-
-    let bookings_iter = stmt.query_map([], |row| {
-        Ok(Booking {
-            id: Some(row.get(0)?),
-            guest_id: row.get(1)?,
-            room_id: row.get(2)?,
-            hotel_id: row.get(3)?,
-            check_in: row.get(4)?,
-            check_out: row.get(5)?,
-        })
-    }).unwrap();
+const BOOKING_LIST: ListSpec = ListSpec {
+    select: BOOKING_SELECT,
+    sort_fields: &[("check_in", "COALESCE(t.check_in, '')"), ("check_out", "COALESCE(t.check_out, '')"), ("status", "t.status")],
+    filters: &[
+        Filter { param: "hotel_id", expr: "t.hotel_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "room_id", expr: "t.room_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "guest_id", expr: "t.guest_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "status", expr: "t.status", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "check_in_from", expr: "t.check_in", op: FilterOp::Gte, kind: FilterKind::Text },
+        Filter { param: "check_in_to", expr: "t.check_in", op: FilterOp::Lt, kind: FilterKind::Text },
+        Filter { param: "rate_plan_id", expr: "t.rate_plan_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "quote_id", expr: "t.quote_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "reservation_id", expr: "t.reservation_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "block_code", expr: "t.block_code", op: FilterOp::Eq, kind: FilterKind::Text },
+    ],
+};
 
-    let bookings: Vec<Booking> = bookings_iter.map(|b| b.unwrap()).collect();
-    HttpResponse::Ok().json(bookings)
+//returns a page of bookings, see `listing` for the query parameters
+#[get("/bookings")]
+async fn get_bookings(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| listing::fetch_page(conn, &BOOKING_LIST, &query, booking_from_row)).await?;
+    Ok(HttpResponse::Ok().json(page))
 }
 
 //returns a booking by ID
 #[get("/bookings/{id}")]
-async fn get_booking_by_id(path: web::Path<String>) -> impl Responder {
+async fn get_booking_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare(
-        "SELECT id, guest_id, room_id, hotel_id, check_in, check_out FROM bookings WHERE id = ?1"
-    ).unwrap();
-
-    let booking = stmt.query_row([id], |row| {
-        Ok(Booking {
-            id: Some(row.get(0)?),
-            guest_id: row.get(1)?,
-            room_id: row.get(2)?,
-            hotel_id: row.get(3)?,
-            check_in: row.get(4)?,
-            check_out: row.get(5)?,
-        })
+    let booking = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare(&format!("{BOOKING_SELECT} WHERE id = ?1"))?;
+
+        stmt.query_row([id], booking_from_row).optional()?.ok_or(ApiError::NotFound("booking"))
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(booking))
+}
+
+//replaces the stay of a booking by ID; it goes through the same checks, repricing and amendment history
+//as `/bookings/{id}/modify`, and may also change the guest, rate plan and promo codes
+#[put("/bookings/{id}")]
+async fn update_booking(
+    pool: web::Data<DbPool>,
+    rates: web::Data<ModificationRates>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+    data: Valid<Booking>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+    let (rates, hold) = (**rates, **hold);
+
+    let offers = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        check_room_hotel(&tx, &data.room_id, &data.hotel_id)?;
+        let before = stay_snapshot(&tx, &id)?;
+        if data.hotel_id != before.0.hotel_id {
+            return Err(ApiError::invalid("hotel_id", "a booking cannot move to another hotel"));
+        }
+
+        let change = StayChange {
+            guest_id: data.guest_id,
+            room_id: data.room_id,
+            check_in: data.check_in,
+            check_out: data.check_out,
+            guests: data.guests,
+            rate_plan_id: data.rate_plan_id,
+            promo_codes: data.promo_codes,
+        };
+        let (_, _, offers) = modify_stay(&tx, &id, before, &change, rates, data.reason.as_deref(), hold)?;
+        tx.commit()?;
+
+        Ok(offers)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "booking updated", "waitlist_offers": offers})))
+}
+
+//what an amendment keeps of a booking: enough to see what the stay looked like and cost
+fn stay_snapshot(conn: &Connection, booking_id: &str) -> Result<(Booking, serde_json::Value), ApiError> {
+    let booking = conn.query_row(&format!("{BOOKING_SELECT} WHERE id = ?1"), [booking_id], booking_from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("booking"))?;
+    let snapshot = json!({
+        "room_id": booking.room_id,
+        "check_in": booking.check_in,
+        "check_out": booking.check_out,
+        "guests": booking.guests,
+        "rate_plan_id": booking.rate_plan_id,
+        "promo_codes": booking.promo_codes,
+        "nights": booking_nights(conn, booking_id)?,
+        "total_price": booking.total_price,
     });
+    Ok((booking, snapshot))
+}
+
+const AMENDMENT_SELECT: &str = "
+    SELECT id, booking_id, before, after, rates, difference_minor, currency, reason, amended_at
+    FROM booking_amendments
+";
+
+fn amendment_from_row(row: &rusqlite::Row) -> rusqlite::Result<BookingAmendment> {
+    let json_column = |index: usize| -> rusqlite::Result<serde_json::Value> {
+        serde_json::from_str(&row.get::<_, String>(index)?)
+            .map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(err)))
+    };
+    let difference = match (row.get::<_, Option<i64>>(5)?, row.get::<_, Option<Currency>>(6)?) {
+        (Some(minor_units), Some(currency)) => Some(Money::new(minor_units, currency)),
+        _ => None,
+    };
+
+    Ok(BookingAmendment {
+        id: row.get(0)?,
+        booking_id: row.get(1)?,
+        before: json_column(2)?,
+        after: json_column(3)?,
+        rates: row.get(4)?,
+        difference,
+        reason: row.get(7)?,
+        amended_at: row.get(8)?,
+    })
+}
+
+//appends the change from `before` to the stay the booking has now to its amendment history
+fn record_amendment(
+    conn: &Connection,
+    booking_id: &str,
+    before: (Booking, serde_json::Value),
+    rates: ModificationRates,
+    reason: Option<&str>,
+) -> Result<BookingAmendment, ApiError> {
+    let (old, before) = before;
+    let (new, after) = stay_snapshot(conn, booking_id)?;
+    let difference = match (old.total_price, new.total_price) {
+        (Some(old), Some(new)) => Some(new.checked_sub(old)?),
+        _ => None,
+    };
+
+    conn.execute(
+        "INSERT INTO booking_amendments (booking_id, before, after, rates, difference_minor, currency, reason)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        (
+            booking_id, before.to_string(), after.to_string(), rates,
+            difference.map(|d| d.minor_units), difference.map(|d| d.currency), reason,
+        ),
+    )?;
+    let id = conn.last_insert_rowid();
+    Ok(conn.query_row(&format!("{AMENDMENT_SELECT} WHERE id = ?1"), [id], amendment_from_row)?)
+}
 
-    match booking {
-        Ok(b) => HttpResponse::Ok().json(b),
-        Err(_) => HttpResponse::NotFound().json(json!({"error": "booking not found"})),
+//the stay a booking is changed to
+struct StayChange {
+    guest_id: String,
+    room_id: String,
+    check_in: String,
+    check_out: String,
+    guests: i64,
+    rate_plan_id: Option<String>,
+    promo_codes: Vec<String>,
+}
+
+//changes the stay of a tentative or confirmed booking inside the caller's transaction: availability is checked
+//again, the stay is repriced by `rates` and the change goes into the amendment history. nights keep their sold
+//price under original rates while the room type and rate plan stay the same, and the booking keeps the
+//cancellation policy it was sold with unless it moves to another rate plan
+fn modify_stay(
+    conn: &Connection,
+    id: &str,
+    before: (Booking, serde_json::Value),
+    change: &StayChange,
+    rates: ModificationRates,
+    reason: Option<&str>,
+    hold: HoldWindow,
+) -> Result<(BookingAmendment, StayPrice, Vec<WaitlistOffer>), ApiError> {
+    let booking = &before.0;
+    if !matches!(booking.status, BookingStatus::Tentative | BookingStatus::Confirmed) {
+        return Err(ApiError::conflict_with(
+            "booking_not_modifiable",
+            "only tentative and confirmed bookings can be modified",
+            json!({"status": booking.status}),
+        ));
+    }
+    Rules::new().date_after("check_out", "check_in", &change.check_in, &change.check_out).finish()?;
+
+    let (room_hotel, room_type): (String, String) = conn.query_row(
+        "SELECT hotel_id, room_type FROM rooms WHERE id = ?1",
+        [&change.room_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.ok_or(ApiError::NotFound("room"))?;
+    if room_hotel != booking.hotel_id {
+        return Err(ApiError::invalid("room_id", "belongs to another hotel than the booking"));
+    }
+
+    let conflicts = find_conflicting_bookings(conn, &change.room_id, &change.check_in, &change.check_out, Some(id))?;
+    if !conflicts.is_empty() {
+        return Err(booking_conflict(conflicts));
+    }
+    //the block a booking draws on is fixed when it is made
+    let block_id: Option<String> = conn.query_row("SELECT block_id FROM bookings WHERE id = ?1", [id], |row| row.get(0))?;
+    blocks::check_inventory(conn, &change.room_id, &change.check_in, &change.check_out, block_id.as_deref(), Some(id))?;
+
+    let old_room_type: String = conn.query_row("SELECT room_type FROM rooms WHERE id = ?1", [&booking.room_id], |row| row.get(0))?;
+    let guest_type = taxes::guest_type(conn, &change.guest_id)?;
+
+    //a quoted stay that only moves to another room of its type keeps the price the guest was shown
+    let quoted = booking.quote_id.is_some()
+        && old_room_type == room_type
+        && change.check_in == booking.check_in
+        && change.check_out == booking.check_out
+        && change.guests == booking.guests
+        && change.rate_plan_id == booking.rate_plan_id
+        && change.promo_codes == booking.promo_codes
+        && guest_type == taxes::guest_type(conn, &booking.guest_id)?;
+    let stay = if quoted {
+        stored_stay_price(conn, id, booking)?
+    } else {
+        //sold prices only carry over while the guest stays in the same kind of room on the same plan
+        let kept = if rates == ModificationRates::Original && old_room_type == room_type && change.rate_plan_id == booking.rate_plan_id {
+            booking_nights(conn, id)?
+        } else {
+            Vec::new()
+        };
+        //codes the booking already redeemed do not count against their own caps
+        let mut stay = pricing::price_stay_keeping(conn, &StayRequest {
+            room_id: &change.room_id,
+            rate_plan_id: change.rate_plan_id.as_deref(),
+            check_in: &change.check_in,
+            check_out: &change.check_out,
+            occupancy: Occupancy { guests: change.guests, guest_type },
+            promo_codes: &change.promo_codes,
+            guest_id: Some(&change.guest_id),
+            booking_id: Some(id),
+        }, &kept)?;
+        if stay.rate_plan_id == booking.rate_plan_id
+            && let Some(policy) = &booking.cancellation_policy
+        {
+            stay.cancellation_policy = policy.clone();
+        }
+        stay
+    };
+
+    conn.execute(
+        "UPDATE bookings SET guest_id = ?1, room_id = ?2, check_in = ?3, check_out = ?4, guests = ?5 WHERE id = ?6",
+        (&change.guest_id, &change.room_id, &change.check_in, &change.check_out, change.guests, id),
+    )?;
+    if !quoted {
+        pricing::store_stay_price(conn, id, &stay)?;
     }
+    //nights the booking no longer takes in its old room can go to the waitlist
+    let moved = change.room_id != booking.room_id || change.check_in != booking.check_in || change.check_out != booking.check_out;
+    let offers = if moved { waitlist::offer_freed_room(conn, &booking.room_id, hold)? } else { Vec::new() };
+    let amendment = record_amendment(conn, id, before, rates, reason)?;
+    Ok((amendment, stay, offers))
 }
 
-//updates a booking by ID
-#[put("/bookings/{id}")]
-async fn update_booking(path: web::Path<String>, data: web::Json<Booking>) -> impl Responder {
+//changes the room, dates or guest count of a booking that has not started, repriced by the property's
+//`modification_rates`; the booking keeps its rate plan, promo codes and the cancellation policy it was sold with
+#[post("/bookings/{id}/modify")]
+async fn modify_booking(
+    pool: web::Data<DbPool>,
+    rates: web::Data<ModificationRates>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+    data: Valid<BookingModification>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+    let (rates, hold) = (**rates, **hold);
+
+    let (amendment, stay, offers) = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let before = stay_snapshot(&tx, &id)?;
+        let booking = &before.0;
+
+        let change = StayChange {
+            guest_id: booking.guest_id.clone(),
+            room_id: data.room_id.unwrap_or_else(|| booking.room_id.clone()),
+            check_in: data.check_in.unwrap_or_else(|| booking.check_in.clone()),
+            check_out: data.check_out.unwrap_or_else(|| booking.check_out.clone()),
+            guests: data.guests.unwrap_or(booking.guests),
+            rate_plan_id: booking.rate_plan_id.clone(),
+            promo_codes: booking.promo_codes.clone(),
+        };
+        let changed = modify_stay(&tx, &id, before, &change, rates, data.reason.as_deref(), hold)?;
+        tx.commit()?;
+
+        Ok(changed)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "booking modified",
+        "amendment_id": amendment.id,
+        "rates": amendment.rates,
+        "previous_total": amendment.before["total_price"],
+        "total_price": stay.total,
+        "difference": amendment.difference,
+        "nights": stay.nights,
+        "charges": stay.charges,
+        "discounts": stay.discounts,
+        "waitlist_offers": offers
+    })))
+}
+
+//returns the amendment history of a booking, oldest first
+#[get("/bookings/{id}/amendments")]
+async fn get_booking_amendments(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
+    let amendments = db::run(&pool, move |conn| {
+        conn.query_row("SELECT 1 FROM bookings WHERE id = ?1", [&id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("booking"))?;
+
+        let mut stmt = conn.prepare(&format!("{AMENDMENT_SELECT} WHERE booking_id = ?1 ORDER BY id"))?;
+        let amendments = stmt.query_map([&id], amendment_from_row)?;
+        Ok(amendments.collect::<rusqlite::Result<Vec<_>>>()?)
+    }).await?;
+    Ok(HttpResponse::Ok().json(amendments))
+}
+
+
+//moves a booking to the next lifecycle status inside the caller's transaction, rejecting illegal transitions;
+//returns the status it had, for cancellations and no-shows what its cancellation policy charged, and the card
+//holds to close once the transaction committed. the room of a cancelled or no-show booking is offered to the
+//waitlist straight away
+fn transition(
+    conn: &Connection,
+    hold: HoldWindow,
+    id: &str,
+    next: BookingStatus,
+) -> Result<(BookingStatus, Option<serde_json::Value>, Vec<HoldClosing>), ApiError> {
+    let (current, room_id): (BookingStatus, String) = conn.query_row(
+        "SELECT status, room_id FROM bookings WHERE id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.ok_or(ApiError::NotFound("booking"))?;
 
+    if !current.can_transition_to(next) {
+        return Err(ApiError::conflict_with(
+            "illegal_transition",
+            format!("cannot move booking from {} to {}", current.as_str(), next.as_str()),
+            json!({ "status": current }),
+        ));
+    }
+
+    //occupancy is derived from checked-in bookings, so the room must be free and usable first
+    if next == BookingStatus::CheckedIn {
+        let (housekeeping, occupied): (HousekeepingStatus, bool) = conn.query_row(
+            "SELECT housekeeping,
+                    EXISTS (SELECT 1 FROM bookings WHERE room_id = ?1 AND status = 'checked_in')
+             FROM rooms WHERE id = ?1",
+            [&room_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        if housekeeping == HousekeepingStatus::OutOfOrder {
+            return Err(ApiError::conflict("room_out_of_order", "room is out of order"));
+        }
+        if occupied {
+            return Err(ApiError::conflict("room_occupied", "room is still occupied"));
+        }
+    }
+
+    conn.execute("UPDATE bookings SET status = ?1 WHERE id = ?2", (next, id))?;
+
+    //the stay is charged to the folio once the guest is in the room
+    if next == BookingStatus::CheckedIn {
+        folio::post_stay(conn, id)?;
+    }
+    if next == BookingStatus::CheckedOut {
+        conn.execute(
+            "UPDATE rooms SET housekeeping = ?1 WHERE id = ?2",
+            (HousekeepingStatus::Dirty, &room_id),
+        )?;
+    }
+    //a stay that never happens costs what its cancellation policy says, card holds pay for it first
+    let charge = match next {
+        BookingStatus::Cancelled | BookingStatus::NoShow => Some(cancellation::charge(conn, id)?),
+        _ => None,
+    };
+    let closings = match next {
+        BookingStatus::CheckedOut | BookingStatus::Cancelled | BookingStatus::NoShow => claim_authorizations(conn, id, next)?,
+        _ => Vec::new(),
+    };
+    let cancellation = match charge {
+        Some(charge) => {
+            let paid = cancellation::paid(conn, id)?;
+            let refundable = cancellation::refundable(paid, charge.penalty)?;
+            let mut body = json!(charge);
+            body["paid"] = json!(paid);
+            body["refundable"] = json!(refundable);
+            Some(body)
+        }
+        None => None,
+    };
+    let cancellation = match cancellation {
+        Some(mut body) => {
+            body["waitlist_offers"] = json!(waitlist::offer_freed_room(conn, &room_id, hold)?);
+            Some(body)
+        }
+        None => None,
+    };
     conn.execute(
-        "UPDATE bookings SET guest_id = ?1, room_id = ?2, hotel_id = ?3, check_in = ?4, check_out = ?5 WHERE id = ?6",
-        (&data.guest_id, &data.room_id, &data.hotel_id, &data.check_in, &data.check_out, &id),
-    ).unwrap();
+        "INSERT INTO booking_status_history (booking_id, from_status, to_status) VALUES (?1, ?2, ?3)",
+        (id, current, next),
+    )?;
+    Ok((current, cancellation, closings))
+}
+
+//moves a booking to the next lifecycle status, closes its card holds and hands the gateway's webhooks on
+async fn transition_booking(
+    pool: &DbPool,
+    gateway: web::Data<dyn PaymentGateway>,
+    hold: HoldWindow,
+    id: String,
+    next: BookingStatus,
+) -> Result<HttpResponse, ApiError> {
+    let gateway = gateway.into_inner();
+    let (current, cancellation, closings) = db::run(pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let transitioned = transition(&tx, hold, &id, next)?;
+        tx.commit()?;
+
+        Ok(transitioned)
+    }).await?;
+    let card_holds = close_authorizations(pool, &gateway, closings).await;
+    gateway::deliver_webhooks(pool, &gateway);
+
+    let mut body = json!({"status": next, "previous_status": current});
+    if let Some(cancellation) = cancellation {
+        body["cancellation"] = cancellation;
+    }
+    if !card_holds.is_empty() {
+        body["card_holds"] = json!(card_holds);
+    }
+    Ok(HttpResponse::Ok().json(body))
+}
+
+//confirms a tentative booking
+#[post("/bookings/{id}/confirm")]
+async fn confirm_booking(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    transition_booking(&pool, gateway, **hold, path.into_inner(), BookingStatus::Confirmed).await
+}
+
+//checks a guest in on a confirmed booking
+#[post("/bookings/{id}/check-in")]
+async fn check_in_booking(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    transition_booking(&pool, gateway, **hold, path.into_inner(), BookingStatus::CheckedIn).await
+}
+
+//checks a guest out
+#[post("/bookings/{id}/check-out")]
+async fn check_out_booking(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    transition_booking(&pool, gateway, **hold, path.into_inner(), BookingStatus::CheckedOut).await
+}
+
+//cancels a booking that has not started yet
+#[post("/bookings/{id}/cancel")]
+async fn cancel_booking(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    transition_booking(&pool, gateway, **hold, path.into_inner(), BookingStatus::Cancelled).await
+}
+
+//marks a confirmed booking as a no-show
+#[post("/bookings/{id}/no-show")]
+async fn no_show_booking(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    transition_booking(&pool, gateway, **hold, path.into_inner(), BookingStatus::NoShow).await
+}
+
+//returns the timestamped status changes of a booking
+#[get("/bookings/{id}/status-history")]
+async fn get_booking_status_history(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let history: Vec<BookingStatusChange> = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT from_status, to_status, changed_at FROM booking_status_history
+             WHERE booking_id = ?1 ORDER BY id"
+        )?;
+
+        let history_iter = stmt.query_map([id], |row| {
+            Ok(BookingStatusChange {
+                from_status: row.get(0)?,
+                to_status: row.get(1)?,
+                changed_at: row.get(2)?,
+            })
+        })?;
+
+        Ok(history_iter.collect::<rusqlite::Result<_>>()?)
+    }).await?;
+    Ok(HttpResponse::Ok().json(history))
+}
+
+fn booking_nights(conn: &Connection, booking_id: &str) -> rusqlite::Result<Vec<NightlyPrice>> {
+    let mut stmt = conn.prepare(
+        "SELECT night, price_minor, currency, weekend, rate_season_id FROM booking_nights
+         WHERE booking_id = ?1 ORDER BY night"
+    )?;
+    let nights_iter = stmt.query_map([booking_id], |row| {
+        Ok(NightlyPrice {
+            night: row.get(0)?,
+            price: Money::new(row.get(1)?, row.get(2)?),
+            weekend: row.get(3)?,
+            rate_season_id: row.get(4)?,
+        })
+    })?;
+
+    nights_iter.collect()
+}
+
+//returns the stored nightly price breakdown of a booking
+#[get("/bookings/{id}/nights")]
+async fn get_booking_nights(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let (total_price, nights) = db::run(&pool, move |conn| {
+        let booking = conn.query_row(&format!("{BOOKING_SELECT} WHERE id = ?1"), [&id], booking_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("booking"))?;
+
+        Ok((booking.total_price, booking_nights(conn, &id)?))
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"total_price": total_price, "nights": nights})))
+}
+
+//the price a booking was stored with, as `pricing::price_stay` would have returned it
+fn stored_stay_price(conn: &Connection, booking_id: &str, booking: &Booking) -> Result<StayPrice, ApiError> {
+    //bookings made before rate plans were never priced
+    let total = booking.total_price.ok_or_else(|| {
+        ApiError::conflict("booking_unpriced", "the booking has no stored prices, update it to price it")
+    })?;
+
+    let nights = booking_nights(conn, booking_id)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM booking_charges WHERE booking_id = ?1 ORDER BY line",
+        taxes::CHARGE_COLUMNS
+    ))?;
+    let charges = stmt.query_map([booking_id], taxes::charge_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT promotion_id, code, discount_minor, currency FROM promotion_redemptions
+         WHERE booking_id = ?1 ORDER BY rowid"
+    )?;
+    let discounts = stmt.query_map([booking_id], |row| {
+        Ok(Discount { promotion_id: row.get(0)?, code: row.get(1)?, amount: Money::new(row.get(2)?, row.get(3)?) })
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let subtotal = nights.iter().try_fold(Money::zero(total.currency), |sum, n| sum.checked_add(n.price))?;
+    let cancellation_policy = match &booking.cancellation_policy {
+        Some(policy) => policy.clone(),
+        None => cancellation::policy_for(conn, &booking.hotel_id, booking.rate_plan_id.as_deref())?,
+    };
+    Ok(StayPrice {
+        rate_plan_id: booking.rate_plan_id.clone(),
+        nights,
+        subtotal,
+        discounts,
+        charges,
+        total,
+        cancellation_policy,
+    })
+}
+
+//returns what a booking is charged: the nights, one line per tax and fee, and what is still owed
+#[get("/bookings/{id}/invoice")]
+async fn get_booking_invoice(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let invoice = db::run(&pool, move |conn| {
+        let booking = conn.query_row(&format!("{BOOKING_SELECT} WHERE id = ?1"), [&id], booking_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("booking"))?;
+        let StayPrice { nights, subtotal, discounts, charges, total, .. } = stored_stay_price(conn, &id, &booking)?;
+        let discount = discounts.iter().try_fold(Money::zero(total.currency), |sum, d| sum.checked_add(d.amount))?;
+        let tax_total = taxes::category_total(&charges, ChargeCategory::Tax, total.currency)?;
+        let fee_total = taxes::category_total(&charges, ChargeCategory::Fee, total.currency)?;
+
+        //what reached the folio, card holds that were not captured have not paid anything yet
+        let paid = cancellation::paid(conn, &id)?;
+
+        Ok(json!({
+            "booking_id": id,
+            "hotel_id": booking.hotel_id,
+            "guest_id": booking.guest_id,
+            "check_in": booking.check_in,
+            "check_out": booking.check_out,
+            "guests": booking.guests,
+            "nights": nights,
+            "subtotal": subtotal,
+            "discounts": discounts,
+            "discount": discount,
+            "charges": charges,
+            "taxes": tax_total,
+            "fees": fee_total,
+            "total": total,
+            "paid": paid,
+            "balance_due": total.checked_sub(paid)?
+        }))
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(invoice))
+}
+
+//returns every entry of a booking's folio and its balance
+#[get("/bookings/{id}/folio")]
+async fn get_booking_folio(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let folio = db::run(&pool, move |conn| {
+        let currency = folio::folio_currency(conn, &id)?;
+        let mut stmt = conn.prepare(&format!("{} WHERE e.booking_id = ?1 ORDER BY e.id", folio::FOLIO_ENTRY_SELECT))?;
+        let entries = stmt.query_map([&id], folio::entry_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let (payments, charges): (Vec<_>, Vec<_>) = entries.iter().partition(|e| e.kind.is_payment());
+        let charged = charges.iter().try_fold(Money::zero(currency), |sum, e| sum.checked_add(e.amount))?;
+        let paid = payments.iter().try_fold(Money::zero(currency), |sum, e| sum.checked_sub(e.amount))?;
+
+        Ok(json!({
+            "booking_id": id,
+            "currency": currency,
+            "entries": entries,
+            "charges": charged,
+            "payments": paid,
+            "balance": folio::balance(&entries, currency)?
+        }))
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(folio))
+}
+
+//posts a minibar, restaurant or adjustment charge to a booking's folio
+#[post("/bookings/{id}/folio/charges")]
+async fn post_folio_charge(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<FolioCharge>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+
+    let entry_id = db::run(&pool, move |conn| {
+        data.amount.ensure_currency(folio::folio_currency(conn, &id)?)?;
+        Ok(folio::post(conn, &id, data.kind, &data.description, data.amount, None)?)
+    }).await?;
 
-    HttpResponse::Ok().json(json!({"status": "booking updated"}))
+    Ok(HttpResponse::Ok().json(json!({"status": "charge posted", "id": entry_id})))
 }
 
+//cancels a folio entry by posting its opposite, payments are reversed through their payment
+#[post("/bookings/{id}/folio/entries/{entry_id}/reverse")]
+async fn reverse_folio_entry(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i64)>,
+    data: Valid<FolioReversal>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, entry_id) = path.into_inner();
+    let data = data.into_inner();
+
+    let reversal = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let kind: Option<FolioEntryKind> = tx.query_row(
+            "SELECT kind FROM folio_entries WHERE id = ?1 AND booking_id = ?2",
+            (entry_id, &id),
+            |row| row.get(0),
+        ).optional()?;
+        if kind.is_some_and(|kind| kind.is_payment()) {
+            return Err(ApiError::conflict("payment_entry", "payments are reversed by refunding or voiding the payment"));
+        }
+        let reversal = folio::reverse(&tx, &id, entry_id, &data.reason)?;
+        tx.commit()?;
+        Ok(reversal)
+    }).await?;
 
-//deletes a booking by ID
+    Ok(HttpResponse::Ok().json(json!({"status": "entry reversed", "reversal": reversal})))
+}
+
+//deletes a booking by ID, only tentative bookings or ones that already ended without a charge;
+//a booking the guest holds is cancelled instead so its cancellation policy applies
 #[delete("/bookings/{id}")]
-async fn delete_booking(path: web::Path<String>) -> impl Responder {
+async fn delete_booking(pool: web::Data<DbPool>, hold: web::Data<HoldWindow>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
+    let hold = **hold;
 
-    conn.execute("DELETE FROM bookings WHERE id = ?1", [&id]).unwrap();
-    HttpResponse::Ok().json(json!({"status": "booking deleted"}))
+    let offers = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let (status, room_id): (BookingStatus, String) = tx.query_row(
+            "SELECT status, room_id FROM bookings WHERE id = ?1",
+            [&id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()?.ok_or(ApiError::NotFound("booking"))?;
+        if matches!(status, BookingStatus::Confirmed | BookingStatus::CheckedIn | BookingStatus::CheckedOut) {
+            return Err(ApiError::conflict_with(
+                "booking_active",
+                "a confirmed or started booking cannot be deleted, cancel it so its cancellation policy applies",
+                json!({"status": status}),
+            ));
+        }
+        let posted: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM folio_entries WHERE booking_id = ?1)",
+            [&id],
+            |row| row.get(0),
+        )?;
+        if posted {
+            return Err(ApiError::conflict("folio_not_empty", "the booking has folio entries, cancel it instead"));
+        }
+        tx.execute("DELETE FROM bookings WHERE id = ?1", [&id])?;
+        //a tentative booking held its room until now
+        let offers = if status == BookingStatus::Tentative { waitlist::offer_freed_room(&tx, &room_id, hold)? } else { Vec::new() };
+        tx.commit()?;
+        Ok(offers)
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "booking deleted", "waitlist_offers": offers})))
 }
 
 
 //returns average stay duration (in days)
 #[get("/analytics/bookings/average_stay")]
-async fn get_average_stay_duration() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare(
-        "SELECT AVG(julianday(check_out) - julianday(check_in)) AS avg_stay FROM bookings"
-    ).unwrap();
+async fn get_average_stay_duration(pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let avg_stay: Option<f64> = db::run(&pool, |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT AVG(julianday(check_out) - julianday(check_in)) AS avg_stay FROM bookings
+             WHERE status NOT IN ('cancelled', 'no_show')"
+        )?;
 
-    let avg_stay: Option<f64> = stmt.query_row([], |row| row.get(0)).ok();
+        Ok(stmt.query_row([], |row| row.get(0))?)
+    }).await?;
 
-    HttpResponse::Ok().json(json!({
+    Ok(HttpResponse::Ok().json(json!({
         "average_stay_days": avg_stay.unwrap_or(0.0)
-    }))
+    })))
 }
 
 
 //returns the last hotel (or current) a guest stayed at
 #[get("/analytics/bookings/guest/{guest_id}/current_or_last_hotel")]
-async fn get_current_or_last_hotel_by_guest(path: web::Path<String>) -> impl Responder {
+async fn get_current_or_last_hotel_by_guest(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let guest_id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
 
     let sql = "
         SELECT h.id, h.name, h.location, h.stars
         FROM bookings b
         JOIN hotels h ON b.hotel_id = h.id
-        WHERE b.guest_id = ?1
+        WHERE b.guest_id = ?1 AND b.status NOT IN ('cancelled', 'no_show')
         ORDER BY
             CASE
                 WHEN DATE('now') BETWEEN b.check_in AND b.check_out THEN 0
@@ -459,131 +1483,2339 @@ async fn get_current_or_last_hotel_by_guest(path: web::Path<String>) -> impl Res
         LIMIT 1
     ";
 
-    let mut stmt = conn.prepare(sql).unwrap();
-    let hotel = stmt.query_row([guest_id], |row| {
-        Ok(json!({
-            "id": row.get::<_, String>(0)?,
-            "name": row.get::<_, String>(1)?,
-            "location": row.get::<_, String>(2)?,
-            "stars": row.get::<_, i32>(3)?
-        }))
-    });
+    let hotel = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare(sql)?;
+        Ok(stmt.query_row([guest_id], |row| {
+            Ok(json!({
+                "id": row.get::<_, String>(0)?,
+                "name": row.get::<_, String>(1)?,
+                "location": row.get::<_, String>(2)?,
+                "stars": row.get::<_, i32>(3)?
+            }))
+        }).optional()?)
+    }).await?;
 
     match hotel {
-        Ok(h) => HttpResponse::Ok().json(h),
-        Err(_) => HttpResponse::Ok().json(json!({"message": "no current or previous hotel found"})),
+        Some(h) => Ok(HttpResponse::Ok().json(h)),
+        None => Ok(HttpResponse::Ok().json(json!({"message": "no current or previous hotel found"}))),
     }
 }
 
-//---payments---
-//creates a payment in DB
-#[post("/payments")]
-async fn create_payment(data: web::Json<Payment>) -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-    let id = Uuid::new_v4().to_string();
+//---reservations---
 
-    conn.execute(
-        "INSERT INTO payments (id, booking_id, amount, method)
-         VALUES (?1, ?2, ?3, ?4)",
-        (&id, &data.booking_id, &data.amount, &data.method),
-    ).unwrap();
+const RESERVATION_SELECT: &str = "
+    SELECT id, hotel_id, billing_guest_id, name, created_at,
+           (SELECT COUNT(*) FROM bookings b WHERE b.reservation_id = reservations.id) AS rooms
+    FROM reservations
+";
 
-    HttpResponse::Ok().json(json!({"status": "payment added", "id": id}))
+fn reservation_from_row(row: &rusqlite::Row) -> rusqlite::Result<Reservation> {
+    Ok(Reservation {
+        id: Some(row.get(0)?),
+        hotel_id: row.get(1)?,
+        billing_guest_id: row.get(2)?,
+        name: row.get(3)?,
+        stays: Vec::new(),
+        rooms: row.get(5)?,
+        created_at: row.get(4)?,
+    })
 }
 
-//returns all payments in DB
-#[get("/payments")]
-async fn get_payments() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
-    let mut stmt = conn.prepare("SELECT id, booking_id, amount, method FROM payments").unwrap();
-
-    let payments_iter = stmt.query_map([], |row| {
-        Ok(Payment {
-            id: Some(row.get(0)?),
-            booking_id: row.get(1)?,
-            amount: row.get(2)?,
-            method: row.get(3)?,
-        })
-    }).unwrap();
-
-    let payments: Vec<Payment> = payments_iter.map(|p| p.unwrap()).collect();
-    HttpResponse::Ok().json(payments)
+//points an error at the room-stay of a reservation it is about
+fn stay_error(err: ApiError, stay: serde_json::Value) -> ApiError {
+    match err {
+        ApiError::Conflict { code, message, details } => {
+            let mut details = details.unwrap_or_else(|| json!({}));
+            if let Some(fields) = details.as_object_mut() {
+                fields.insert("stay".to_string(), stay);
+            }
+            ApiError::Conflict { code, message, details: Some(details) }
+        }
+        ApiError::Validation(errors) => ApiError::Validation(
+            errors
+                .into_iter()
+                .map(|e| FieldError { field: format!("stays[{stay}].{}", e.field), message: e.message })
+                .collect(),
+        ),
+        other => other,
+    }
 }
 
-//returns a payment by ID
-#[get("/payments/{id}")]
-async fn get_payment_by_id(path: web::Path<String>) -> impl Responder {
-    let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
-
-    let mut stmt = conn.prepare("SELECT id, booking_id, amount, method FROM payments WHERE id = ?1").unwrap();
+//what the room-stays of a reservation cost together and what was paid towards them, in the hotel currency;
+//charges are what the folios hold plus the price of stays that are not in their folio yet
+fn reservation_totals(conn: &Connection, id: &str) -> Result<serde_json::Value, ApiError> {
+    let currency: Currency = conn.query_row(
+        "SELECT h.currency FROM reservations r JOIN hotels h ON h.id = r.hotel_id WHERE r.id = ?1",
+        [id],
+        |row| row.get(0),
+    ).optional()?.ok_or(ApiError::NotFound("reservation"))?;
 
-    let payment = stmt.query_row([id], |row| {
-        Ok(Payment {
-            id: Some(row.get(0)?),
-            booking_id: row.get(1)?,
-            amount: row.get(2)?,
-            method: row.get(3)?,
-        })
-    });
+    let (total, fees, charges, paid): (i64, i64, i64, i64) = conn.query_row(
+        "SELECT
+             COALESCE((SELECT SUM(total_minor) FROM bookings
+                       WHERE reservation_id = ?1 AND status NOT IN ('cancelled', 'no_show')), 0),
+             COALESCE((SELECT SUM(e.amount_minor) FROM folio_entries e JOIN bookings b ON b.id = e.booking_id
+                       WHERE b.reservation_id = ?1 AND e.kind = 'cancellation_fee'), 0),
+             COALESCE((SELECT SUM(e.amount_minor) FROM folio_entries e JOIN bookings b ON b.id = e.booking_id
+                       WHERE b.reservation_id = ?1 AND e.kind NOT IN ('payment', 'refund')), 0)
+               + COALESCE((SELECT SUM(total_minor) FROM bookings
+                           WHERE reservation_id = ?1 AND status IN ('tentative', 'confirmed')), 0),
+             -COALESCE((SELECT SUM(e.amount_minor) FROM folio_entries e JOIN bookings b ON b.id = e.booking_id
+                        WHERE b.reservation_id = ?1 AND e.kind IN ('payment', 'refund')), 0)",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
 
-    match payment {
-        Ok(p) => HttpResponse::Ok().json(p),
-        Err(_) => HttpResponse::NotFound().json(json!({"error": "payment not found"})),
+    //what could go back to the guests: what each stay was paid beyond its folio charges and, while it can still be
+    //cancelled, beyond the penalty its policy would take now
+    let mut stmt = conn.prepare("SELECT id, status FROM bookings WHERE reservation_id = ?1 ORDER BY rowid")?;
+    let stays = stmt
+        .query_map([id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, BookingStatus>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut refundable = Money::zero(currency);
+    for (booking_id, status) in stays {
+        let charged: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(amount_minor), 0) FROM folio_entries WHERE booking_id = ?1 AND kind NOT IN ('payment', 'refund')",
+            [&booking_id],
+            |row| row.get(0),
+        )?;
+        let mut cost = Money::new(charged, currency);
+        if status.can_transition_to(BookingStatus::Cancelled) {
+            cost = cost.checked_add(cancellation::quote_cancellation(conn, &booking_id)?.penalty)?;
+        }
+        refundable = refundable.checked_add(cancellation::refundable(cancellation::paid(conn, &booking_id)?, cost)?)?;
     }
+
+    let balance = Money::new(charges, currency).checked_sub(Money::new(paid, currency))?;
+    Ok(json!({
+        "total_price": Money::new(total, currency),
+        "cancellation_fees": Money::new(fees, currency),
+        "charges": Money::new(charges, currency),
+        "paid": Money::new(paid, currency),
+        "balance": balance,
+        "refundable": refundable,
+    }))
 }
 
-//updates a payment by ID
-#[put("/payments/{id}")]
-async fn update_payment(path: web::Path<String>, data: web::Json<Payment>) -> impl Responder {
-    let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
+//books every room-stay of a group under one reservation; if any stay cannot be booked none is
+#[post("/reservations")]
+async fn create_reservation(pool: web::Data<DbPool>, data: Valid<Reservation>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
+    let id = Uuid::new_v4().to_string();
+    let reservation_id = id.clone();
 
-    conn.execute(
-        "UPDATE payments SET booking_id = ?1, amount = ?2, method = ?3 WHERE id = ?4",
-        (&data.booking_id, &data.amount, &data.method, &id),
-    ).unwrap();
+    let (stays, totals) = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.query_row("SELECT 1 FROM hotels WHERE id = ?1", [&data.hotel_id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("hotel"))?;
+        tx.query_row("SELECT 1 FROM guests WHERE id = ?1", [&data.billing_guest_id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("guest"))?;
+        tx.execute(
+            "INSERT INTO reservations (id, hotel_id, billing_guest_id, name) VALUES (?1, ?2, ?3, ?4)",
+            (&reservation_id, &data.hotel_id, &data.billing_guest_id, &data.name),
+        )?;
 
-    HttpResponse::Ok().json(json!({"status": "payment updated"}))
-}
+        let mut stays = Vec::new();
+        for (i, stay) in data.stays.into_iter().enumerate() {
+            let room_hotel: String = tx.query_row("SELECT hotel_id FROM rooms WHERE id = ?1", [&stay.room_id], |row| row.get(0))
+                .optional()?
+                .ok_or(ApiError::NotFound("room"))?;
+            if room_hotel != data.hotel_id {
+                return Err(ApiError::invalid(&format!("stays[{i}].room_id"), "belongs to another hotel than the reservation"));
+            }
 
-//deletes a payment by id
-#[delete("/payments/{id}")]
-async fn delete_payment(path: web::Path<String>) -> impl Responder {
-    let id = path.into_inner();
-    let conn = Connection::open("hotel.db").unwrap();
+            let booking = Booking {
+                id: None,
+                guest_id: stay.guest_id.unwrap_or_else(|| data.billing_guest_id.clone()),
+                room_id: stay.room_id,
+                hotel_id: data.hotel_id.clone(),
+                check_in: stay.check_in,
+                check_out: stay.check_out,
+                guests: stay.guests,
+                status: BookingStatus::Tentative,
+                rate_plan_id: stay.rate_plan_id,
+                total_price: None,
+                quote_id: None,
+                promo_codes: stay.promo_codes,
+                cancellation_policy: None,
+                reservation_id: None,
+                block_code: stay.block_code,
+                reason: None,
+            };
+            let booking_id = Uuid::new_v4().to_string();
+            let price: StayPrice = book_stay(&tx, &booking_id, &booking, Some(&reservation_id))
+                .map_err(|err| stay_error(err, json!(i)))?;
+            stays.push(json!({
+                "booking_id": booking_id,
+                "room_id": booking.room_id,
+                "guest_id": booking.guest_id,
+                "check_in": booking.check_in,
+                "check_out": booking.check_out,
+                "total_price": price.total,
+                "cancellation_policy": price.cancellation_policy,
+            }));
+        }
+
+        let totals = reservation_totals(&tx, &reservation_id)?;
+        tx.commit()?;
+        Ok((stays, totals))
+    }).await?;
 
-    conn.execute("DELETE FROM payments WHERE id = ?1", [&id]).unwrap();
-    HttpResponse::Ok().json(json!({"status": "payment deleted"}))
+    Ok(HttpResponse::Ok().json(json!({"status": "reservation added", "id": id, "stays": stays, "totals": totals})))
 }
 
-//returns total payments per booking
-#[get("/analytics/payments/total_per_booking")]
-async fn get_total_paid_per_booking() -> impl Responder {
-    let conn = Connection::open("hotel.db").unwrap();
+const RESERVATION_LIST: ListSpec = ListSpec {
+    select: RESERVATION_SELECT,
+    sort_fields: &[("created_at", "t.created_at"), ("name", "COALESCE(t.name, '')")],
+    filters: &[
+        Filter { param: "hotel_id", expr: "t.hotel_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "billing_guest_id", expr: "t.billing_guest_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "name", expr: "t.name", op: FilterOp::Contains, kind: FilterKind::Text },
+    ],
+};
 
-    let mut stmt = conn.prepare(
-        "SELECT booking_id, SUM(amount) AS total_paid FROM payments GROUP BY booking_id"
-    ).unwrap();
+//returns a page of reservations, see `listing` for the query parameters
+#[get("/reservations")]
+async fn get_reservations(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| listing::fetch_page(conn, &RESERVATION_LIST, &query, reservation_from_row)).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
 
-    let mut rows = stmt.query([]).unwrap();
-    let mut result = Vec::new();
+//returns a reservation with its room-stays and group totals
+#[get("/reservations/{id}")]
+async fn get_reservation_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let body = db::run(&pool, move |conn| {
+        let reservation = conn.query_row(&format!("{RESERVATION_SELECT} WHERE id = ?1"), [&id], reservation_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("reservation"))?;
 
-    while let Some(row) = rows.next().unwrap() {
-        result.push(json!({
-            "booking_id": row.get::<_, String>(0).unwrap(),
-            "total_paid": row.get::<_, f64>(1).unwrap(),
-        }));
-    }
+        let mut stmt = conn.prepare(&format!("{BOOKING_SELECT} WHERE reservation_id = ?1 ORDER BY check_in, rowid"))?;
+        let stays = stmt.query_map([&id], booking_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
 
-    HttpResponse::Ok().json(result)
+        let mut body = json!(reservation);
+        body["stays"] = json!(stays);
+        body["totals"] = reservation_totals(conn, &id)?;
+        Ok(body)
+    }).await?;
+    Ok(HttpResponse::Ok().json(body))
 }
 
+//cancels the listed room-stays of a reservation, or every one still open when none are listed;
+//each pays what its cancellation policy says and either all of them are cancelled or none
+#[post("/reservations/{id}/cancel")]
+async fn cancel_reservation(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+    data: Option<web::Json<ReservationCancel>>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let hold = **hold;
+    let data = data.map(web::Json::into_inner).unwrap_or_default();
+    let gateway = gateway.into_inner();
 
+    let (cancelled, remaining, totals, closings) = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.query_row("SELECT 1 FROM reservations WHERE id = ?1", [&id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("reservation"))?;
 
+        let mut stmt = tx.prepare("SELECT id, status FROM bookings WHERE reservation_id = ?1 ORDER BY check_in, rowid")?;
+        let stays = stmt
+            .query_map([&id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, BookingStatus>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        drop(stmt);
 
+        let targets: Vec<String> = if data.booking_ids.is_empty() {
+            stays
+                .iter()
+                .filter(|(_, status)| status.can_transition_to(BookingStatus::Cancelled))
+                .map(|(booking_id, _)| booking_id.clone())
+                .collect()
+        } else {
+            let unknown: Vec<&str> = data.booking_ids
+                .iter()
+                .filter(|booking_id| !stays.iter().any(|(stay, _)| stay == *booking_id))
+                .map(String::as_str)
+                .collect();
+            if !unknown.is_empty() {
+                return Err(ApiError::invalid("booking_ids", format!("not room-stays of the reservation: {}", unknown.join(", "))));
+            }
+            //a stay listed twice is cancelled once, in the order it was first listed
+            let mut listed = std::collections::HashSet::new();
+            data.booking_ids.iter().filter(|booking_id| listed.insert(*booking_id)).cloned().collect()
+        };
+        if targets.is_empty() {
+            return Err(ApiError::conflict("nothing_to_cancel", "no room-stay of the reservation can still be cancelled"));
+        }
+
+        let mut cancelled = Vec::new();
+        let mut closings = Vec::new();
+        for booking_id in targets {
+            let (_, cancellation, stay_closings) = transition(&tx, hold, &booking_id, BookingStatus::Cancelled)
+                .map_err(|err| stay_error(err, json!(booking_id)))?;
+            cancelled.push(json!({"booking_id": booking_id, "cancellation": cancellation}));
+            closings.extend(stay_closings);
+        }
+
+        let remaining: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM bookings WHERE reservation_id = ?1 AND status NOT IN ('cancelled', 'no_show')",
+            [&id],
+            |row| row.get(0),
+        )?;
+        let totals = reservation_totals(&tx, &id)?;
+        tx.commit()?;
+        Ok((cancelled, remaining, totals, closings))
+    }).await?;
+    let card_holds = close_authorizations(&pool, &gateway, closings).await;
+    gateway::deliver_webhooks(&pool, &gateway);
+
+    let status = if remaining == 0 { "reservation cancelled" } else { "room-stays cancelled" };
+    let mut body = json!({"status": status, "cancelled": cancelled, "remaining": remaining, "totals": totals});
+    if !card_holds.is_empty() {
+        body["card_holds"] = json!(card_holds);
+    }
+    Ok(HttpResponse::Ok().json(body))
+}
+
+//returns the payments made for every room-stay of a reservation with the group totals
+#[get("/reservations/{id}/payments")]
+async fn get_reservation_payments(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let body = db::run(&pool, move |conn| {
+        let totals = reservation_totals(conn, &id)?;
+        let mut stmt = conn.prepare(&format!(
+            "{PAYMENT_SELECT} WHERE booking_id IN (SELECT id FROM bookings WHERE reservation_id = ?1) ORDER BY paid_on, rowid"
+        ))?;
+        let payments = stmt.query_map([&id], payment_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(json!({"reservation_id": id, "payments": payments, "totals": totals}))
+    }).await?;
+    Ok(HttpResponse::Ok().json(body))
+}
+
+//---room blocks---
+
+//holds rooms of a type for an event or tour operator, if the hotel has that many left on every night
+#[post("/room-blocks")]
+async fn create_room_block(pool: web::Data<DbPool>, data: Valid<RoomBlock>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
+    let id = Uuid::new_v4().to_string();
+    let block_id = id.clone();
+
+    db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.query_row("SELECT 1 FROM hotels WHERE id = ?1", [&data.hotel_id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("hotel"))?;
+        blocks::check_capacity(&tx, &block_id, &data)?;
+        tx.execute(
+            "INSERT INTO room_blocks (id, hotel_id, room_type, code, name, start_date, end_date, rooms, cutoff_date)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            (
+                &block_id, &data.hotel_id, &data.room_type, promotions::normalize_code(&data.code), &data.name,
+                &data.start_date, &data.end_date, data.rooms, &data.cutoff_date,
+            ),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "room block added", "id": id})))
+}
+
+const ROOM_BLOCK_LIST: ListSpec = ListSpec {
+    select: blocks::BLOCK_SELECT,
+    sort_fields: &[("start_date", "t.start_date"), ("cutoff_date", "t.cutoff_date"), ("code", "t.code")],
+    filters: &[
+        Filter { param: "hotel_id", expr: "t.hotel_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "room_type", expr: "t.room_type", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "code", expr: "t.code", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "status", expr: "t.status", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "start_date_from", expr: "t.start_date", op: FilterOp::Gte, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of room blocks, see `listing` for the query parameters
+#[get("/room-blocks")]
+async fn get_room_blocks(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| listing::fetch_page(conn, &ROOM_BLOCK_LIST, &query, blocks::block_from_row)).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+//returns a room block with its pickup on each night
+#[get("/room-blocks/{id}")]
+async fn get_room_block_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let body = db::run(&pool, move |conn| {
+        let block = conn.query_row(&format!("{} WHERE id = ?1", blocks::BLOCK_SELECT), [&id], blocks::block_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("room block"))?;
+        let nights = blocks::nights(conn, &block.hotel_id, &block.room_type, &block.start_date, &block.end_date, None, Some(&id))?;
+
+        let mut body = json!(block);
+        body["nights"] = nights
+            .iter()
+            .map(|n| json!({"night": n.night, "picked_up": n.picked, "left": (block.rooms - n.picked).max(0)}))
+            .collect();
+        Ok(body)
+    }).await?;
+    Ok(HttpResponse::Ok().json(body))
+}
+
+//changes an open room block; the hotel and room type are fixed once bookings draw on it
+#[put("/room-blocks/{id}")]
+async fn update_room_block(
+    pool: web::Data<DbPool>,
+    hold: web::Data<HoldWindow>,
+    path: web::Path<String>,
+    data: Valid<RoomBlock>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+    let hold = **hold;
+
+    let offers = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let block = tx.query_row(&format!("{} WHERE id = ?1", blocks::BLOCK_SELECT), [&id], blocks::block_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("room block"))?;
+        if block.status == RoomBlockStatus::Released {
+            return Err(ApiError::conflict("block_released", "the room block has been released"));
+        }
+        if block.picked_up > 0 && (block.hotel_id != data.hotel_id || block.room_type != data.room_type) {
+            return Err(ApiError::invalid("room_type", "cannot change once bookings draw on the block"));
+        }
+        //stays booked on the block must still fit in it
+        let outside: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM bookings WHERE block_id = ?1 AND status NOT IN ('cancelled', 'no_show')
+                              AND (check_in < ?2 OR check_out > ?3))",
+            (&id, &data.start_date, &data.end_date),
+            |row| row.get(0),
+        )?;
+        if outside {
+            return Err(ApiError::invalid("start_date", "bookings on the block fall outside the new dates"));
+        }
+        blocks::check_capacity(&tx, &id, &data)?;
+
+        tx.execute(
+            "UPDATE room_blocks
+             SET hotel_id = ?1, room_type = ?2, code = ?3, name = ?4, start_date = ?5, end_date = ?6, rooms = ?7,
+                 cutoff_date = ?8
+             WHERE id = ?9",
+            (
+                &data.hotel_id, &data.room_type, promotions::normalize_code(&data.code), &data.name,
+                &data.start_date, &data.end_date, data.rooms, &data.cutoff_date, &id,
+            ),
+        )?;
+        //a block that shrank, moved or changed type gives rooms of its old type back
+        let offers = waitlist::offer_rooms(&tx, &block.hotel_id, &block.room_type, hold, None)?;
+        tx.commit()?;
+        Ok(offers)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "room block updated", "waitlist_offers": offers})))
+}
+
+//puts the rooms of a block nobody picked up back on sale before its cutoff; bookings already made stay
+#[post("/room-blocks/{id}/release")]
+async fn release_room_block(pool: web::Data<DbPool>, hold: web::Data<HoldWindow>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let hold = **hold;
+    let (block, offers) = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let released = tx.execute(
+            "UPDATE room_blocks SET released_at = datetime('now')
+             WHERE id = ?1 AND released_at IS NULL AND cutoff_date > date('now')",
+            [&id],
+        )?;
+        let block = tx.query_row(&format!("{} WHERE id = ?1", blocks::BLOCK_SELECT), [&id], blocks::block_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("room block"))?;
+        if released == 0 {
+            return Err(ApiError::conflict_with(
+                "block_released",
+                "the room block has been released",
+                json!({"released_at": block.released_at}),
+            ));
+        }
+        let offers = waitlist::offer_rooms(&tx, &block.hotel_id, &block.room_type, hold, None)?;
+        tx.commit()?;
+        Ok((block, offers))
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "room block released", "block": block, "waitlist_offers": offers})))
+}
+
+//deletes a room block no booking ever drew on, release it otherwise
+#[delete("/room-blocks/{id}")]
+async fn delete_room_block(pool: web::Data<DbPool>, hold: web::Data<HoldWindow>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let hold = **hold;
+    let offers = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let used: bool = tx.query_row("SELECT EXISTS (SELECT 1 FROM bookings WHERE block_id = ?1)", [&id], |row| row.get(0))?;
+        if used {
+            return Err(ApiError::conflict("block_in_use", "bookings were made on the room block, release it instead"));
+        }
+        let (hotel_id, room_type): (String, String) = tx.query_row(
+            "DELETE FROM room_blocks WHERE id = ?1 RETURNING hotel_id, room_type",
+            [&id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()?.ok_or(ApiError::NotFound("room block"))?;
+        let offers = waitlist::offer_rooms(&tx, &hotel_id, &room_type, hold, None)?;
+        tx.commit()?;
+        Ok(offers)
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "room block deleted", "waitlist_offers": offers})))
+}
+
+//---waitlist---
+
+//puts a guest on the waitlist of a room type that has no room left for their dates
+#[post("/waitlist")]
+async fn join_waitlist(pool: web::Data<DbPool>, hold: web::Data<HoldWindow>, data: Valid<WaitlistEntry>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
+    let hold = **hold;
+    let id = Uuid::new_v4().to_string();
+    let entry_id = id.clone();
+
+    let position = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.query_row("SELECT 1 FROM hotels WHERE id = ?1", [&data.hotel_id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("hotel"))?;
+        tx.query_row("SELECT 1 FROM guests WHERE id = ?1", [&data.guest_id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("guest"))?;
+        let (has_type, past): (bool, bool) = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM rooms WHERE hotel_id = ?1 AND room_type = ?2), ?3 < date('now')",
+            (&data.hotel_id, &data.room_type, &data.check_in),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Rules::new()
+            .check("room_type", has_type, "the hotel has no rooms of this type")
+            .check("check_in", !past, "must not be in the past")
+            .finish()?;
+
+        //guests already waiting get rooms that came free before this one joins the queue
+        waitlist::offer_rooms(&tx, &data.hotel_id, &data.room_type, hold, None)?;
+        let available = blocks::public_rooms(&tx, &data.hotel_id, &data.room_type, &data.check_in, &data.check_out)?;
+        if available > 0 {
+            return Err(ApiError::conflict_with(
+                "rooms_available",
+                "rooms of this type are free for these dates, book one instead",
+                json!({"available_rooms": available}),
+            ));
+        }
+        let waiting: Option<String> = tx.query_row(
+            "SELECT id FROM waitlist_entries
+             WHERE guest_id = ?1 AND hotel_id = ?2 AND room_type = ?3 AND check_in = ?4 AND check_out = ?5
+               AND status IN ('waiting', 'offered')",
+            (&data.guest_id, &data.hotel_id, &data.room_type, &data.check_in, &data.check_out),
+            |row| row.get(0),
+        ).optional()?;
+        if let Some(waiting) = waiting {
+            return Err(ApiError::conflict_with(
+                "already_waitlisted",
+                "the guest is already on the waitlist for this stay",
+                json!({"entry_id": waiting}),
+            ));
+        }
+
+        tx.execute(
+            "INSERT INTO waitlist_entries (id, hotel_id, guest_id, room_type, check_in, check_out, guests, status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                &entry_id, &data.hotel_id, &data.guest_id, &data.room_type, &data.check_in, &data.check_out,
+                data.guests, WaitlistStatus::Waiting,
+            ),
+        )?;
+        let position = waitlist::position(&tx, &entry_id)?;
+        tx.commit()?;
+        Ok(position)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "added to waitlist", "id": id, "position": position})))
+}
+
+const WAITLIST_LIST: ListSpec = ListSpec {
+    select: waitlist::ENTRY_SELECT,
+    sort_fields: &[("created_at", "t.created_at"), ("check_in", "t.check_in")],
+    filters: &[
+        Filter { param: "hotel_id", expr: "t.hotel_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "guest_id", expr: "t.guest_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "room_type", expr: "t.room_type", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "status", expr: "t.status", op: FilterOp::Eq, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of waitlist entries, see `listing` for the query parameters
+#[get("/waitlist")]
+async fn get_waitlist(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| listing::fetch_page(conn, &WAITLIST_LIST, &query, waitlist::entry_from_row)).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+const WAITLIST_OFFER_LIST: ListSpec = ListSpec {
+    select: waitlist::OFFER_SELECT,
+    sort_fields: &[("offered_at", "t.offered_at"), ("expires_at", "t.expires_at")],
+    filters: &[
+        Filter { param: "entry_id", expr: "t.entry_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "room_id", expr: "t.room_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "status", expr: "t.status", op: FilterOp::Eq, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of every offer made to waitlisted guests, see `listing` for the query parameters
+#[get("/waitlist/offers")]
+async fn get_waitlist_offers(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| {
+        listing::fetch_page(conn, &WAITLIST_OFFER_LIST, &query, waitlist::offer_from_row)
+    }).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+//returns a waitlist entry with its place in the queue and the offers it was made
+#[get("/waitlist/{id}")]
+async fn get_waitlist_entry_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let body = db::run(&pool, move |conn| {
+        let entry = conn.query_row(&format!("{} WHERE id = ?1", waitlist::ENTRY_SELECT), [&id], waitlist::entry_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("waitlist entry"))?;
+        let mut stmt = conn.prepare(&format!("{} WHERE entry_id = ?1 ORDER BY offered_at, rowid", waitlist::OFFER_SELECT))?;
+        let offers = stmt.query_map([&id], waitlist::offer_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut body = json!(entry);
+        if entry.status == WaitlistStatus::Waiting {
+            body["position"] = json!(waitlist::position(conn, &id)?);
+        }
+        body["offers"] = json!(offers);
+        Ok(body)
+    }).await?;
+    Ok(HttpResponse::Ok().json(body))
+}
+
+//takes a guest off the waitlist; a room held for them goes to the next guest
+#[post("/waitlist/{id}/withdraw")]
+async fn withdraw_from_waitlist(pool: web::Data<DbPool>, hold: web::Data<HoldWindow>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let hold = **hold;
+    let passed_to = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let entry = tx.query_row(&format!("{} WHERE id = ?1", waitlist::ENTRY_SELECT), [&id], waitlist::entry_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("waitlist entry"))?;
+        if !matches!(entry.status, WaitlistStatus::Waiting | WaitlistStatus::Offered) {
+            return Err(ApiError::conflict_with(
+                "waitlist_closed",
+                "the guest is no longer waiting",
+                json!({"status": entry.status}),
+            ));
+        }
+
+        waitlist::close_offer(&tx, &id, OfferStatus::Withdrawn)?;
+        tx.execute("UPDATE waitlist_entries SET status = ?1 WHERE id = ?2", (WaitlistStatus::Withdrawn, &id))?;
+        let passed_to = waitlist::offer_rooms(&tx, &entry.hotel_id, &entry.room_type, hold, None)?;
+        tx.commit()?;
+        Ok(passed_to)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "withdrawn from waitlist", "waitlist_offers": passed_to})))
+}
+
+//loads an offer that can still be answered along with its entry
+fn open_offer(conn: &Connection, id: &str) -> Result<(WaitlistOffer, WaitlistEntry), ApiError> {
+    let offer = conn.query_row(&format!("{} WHERE id = ?1", waitlist::OFFER_SELECT), [id], waitlist::offer_from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("waitlist offer"))?;
+    if offer.status != OfferStatus::Pending {
+        return Err(ApiError::conflict_with(
+            "offer_closed",
+            "the offer can no longer be answered",
+            json!({"status": offer.status, "closed_at": offer.closed_at}),
+        ));
+    }
+    let entry = conn.query_row(&format!("{} WHERE id = ?1", waitlist::ENTRY_SELECT), [&offer.entry_id], waitlist::entry_from_row)?;
+    Ok((offer, entry))
+}
+
+//books the room a waitlisted guest was offered, another free room of the type if the offered one is gone
+#[post("/waitlist/offers/{id}/accept")]
+async fn accept_waitlist_offer(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    data: Option<web::Json<OfferAcceptance>>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.map(web::Json::into_inner).unwrap_or_default();
+    let booking_id = Uuid::new_v4().to_string();
+    let new_booking_id = booking_id.clone();
+
+    let stay = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let (offer, entry) = open_offer(&tx, &id)?;
+
+        //the offer stops holding its room before the booking takes one
+        waitlist::close_offer(&tx, &offer.entry_id, OfferStatus::Accepted)?;
+        let room_id = if find_conflicting_bookings(&tx, &offer.room_id, &entry.check_in, &entry.check_out, None)?.is_empty() {
+            offer.room_id.clone()
+        } else {
+            waitlist::free_room(&tx, &entry, None)?.ok_or_else(|| {
+                ApiError::conflict("no_availability", "no room of this type is free for these dates any more")
+            })?
+        };
+
+        let booking = Booking {
+            id: None,
+            guest_id: entry.guest_id.clone(),
+            room_id,
+            hotel_id: entry.hotel_id.clone(),
+            check_in: entry.check_in.clone(),
+            check_out: entry.check_out.clone(),
+            guests: entry.guests,
+            status: BookingStatus::Tentative,
+            rate_plan_id: data.rate_plan_id,
+            total_price: None,
+            quote_id: None,
+            promo_codes: data.promo_codes,
+            cancellation_policy: None,
+            reservation_id: None,
+            block_code: None,
+            reason: None,
+        };
+        let stay = book_stay(&tx, &new_booking_id, &booking, None)?;
+        tx.execute("UPDATE waitlist_offers SET booking_id = ?1 WHERE id = ?2", (&new_booking_id, &id))?;
+        tx.execute("UPDATE waitlist_entries SET status = ?1 WHERE id = ?2", (WaitlistStatus::Booked, &offer.entry_id))?;
+        tx.commit()?;
+        Ok(stay)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "booking added",
+        "id": booking_id,
+        "total_price": stay.total,
+        "nights": stay.nights,
+        "cancellation_policy": stay.cancellation_policy
+    })))
+}
+
+//turns an offer down, the room goes to the next guest waiting
+#[post("/waitlist/offers/{id}/decline")]
+async fn decline_waitlist_offer(pool: web::Data<DbPool>, hold: web::Data<HoldWindow>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let hold = **hold;
+    let passed_to = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let (offer, entry) = open_offer(&tx, &id)?;
+
+        waitlist::close_offer(&tx, &offer.entry_id, OfferStatus::Declined)?;
+        tx.execute("UPDATE waitlist_entries SET status = ?1 WHERE id = ?2", (WaitlistStatus::Declined, &offer.entry_id))?;
+        let passed_to = waitlist::offer_rooms(&tx, &entry.hotel_id, &entry.room_type, hold, Some(&offer.room_id))?;
+        tx.commit()?;
+        Ok(passed_to)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "offer declined", "waitlist_offers": passed_to})))
+}
+
+//---quotes---
+
+//prices a stay for a room type without booking it, the returned id can be passed to create_booking
+#[post("/quotes")]
+async fn create_quote(pool: web::Data<DbPool>, data: Valid<QuoteRequest>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
+    let id = Uuid::new_v4().to_string();
+
+    let quote = db::run(&pool, move |conn| {
+        let tx = conn.transaction()?;
+        let quote = quotes::create_quote(&tx, &id, &data)?;
+        tx.commit()?;
+        Ok(quote)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(quote))
+}
+
+//returns a quote by ID with whether it can still be booked
+#[get("/quotes/{id}")]
+async fn get_quote_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let (quote, expired) = db::run(&pool, move |conn| quotes::load_quote(conn, &id)).await?;
+
+    let mut body = json!(quote);
+    body["expired"] = json!(expired);
+    Ok(HttpResponse::Ok().json(body))
+}
+
+//---rate plans---
+
+//creates a rate plan for a hotel
+#[post("/rate-plans")]
+async fn create_rate_plan(pool: web::Data<DbPool>, data: Valid<RatePlan>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
+    let id = Uuid::new_v4().to_string();
+    let plan_id = id.clone();
+
+    db::run(&pool, move |conn| {
+        cancellation::check_exists(conn, data.cancellation_policy_id.as_deref())?;
+        conn.execute(
+            "INSERT INTO rate_plans (id, hotel_id, code, name, kind, cancellation_policy_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (&plan_id, &data.hotel_id, &data.code, &data.name, data.kind, &data.cancellation_policy_id),
+        )?;
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "rate plan added", "id": id})))
+}
+
+fn rate_plan_from_row(row: &rusqlite::Row) -> rusqlite::Result<RatePlan> {
+    Ok(RatePlan {
+        id: Some(row.get(0)?),
+        hotel_id: row.get(1)?,
+        code: row.get(2)?,
+        name: row.get(3)?,
+        kind: row.get(4)?,
+        cancellation_policy_id: row.get(5)?,
+    })
+}
+
+const RATE_PLAN_LIST: ListSpec = ListSpec {
+    select: "SELECT id, hotel_id, code, name, kind, cancellation_policy_id FROM rate_plans",
+    sort_fields: &[("code", "t.code"), ("name", "t.name"), ("kind", "t.kind")],
+    filters: &[
+        Filter { param: "hotel_id", expr: "t.hotel_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "code", expr: "t.code", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "kind", expr: "t.kind", op: FilterOp::Eq, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of rate plans, see `listing` for the query parameters
+#[get("/rate-plans")]
+async fn get_rate_plans(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| listing::fetch_page(conn, &RATE_PLAN_LIST, &query, rate_plan_from_row)).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+//returns a rate plan by ID
+#[get("/rate-plans/{id}")]
+async fn get_rate_plan_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let plan = db::run(&pool, move |conn| {
+        conn.query_row("SELECT id, hotel_id, code, name, kind, cancellation_policy_id FROM rate_plans WHERE id = ?1", [id], rate_plan_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("rate plan"))
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(plan))
+}
+
+//updates a rate plan, its seasons are priced in the hotel currency so it cannot change hotel
+#[put("/rate-plans/{id}")]
+async fn update_rate_plan(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<RatePlan>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+
+    db::run(&pool, move |conn| {
+        let hotel_id: String = conn.query_row("SELECT hotel_id FROM rate_plans WHERE id = ?1", [&id], |row| row.get(0))
+            .optional()?
+            .ok_or(ApiError::NotFound("rate plan"))?;
+        if hotel_id != data.hotel_id {
+            return Err(ApiError::invalid("hotel_id", "a rate plan cannot move to another hotel"));
+        }
+
+        cancellation::check_exists(conn, data.cancellation_policy_id.as_deref())?;
+        conn.execute(
+            "UPDATE rate_plans SET code = ?1, name = ?2, kind = ?3, cancellation_policy_id = ?4 WHERE id = ?5",
+            (&data.code, &data.name, data.kind, &data.cancellation_policy_id, &id),
+        )?;
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "rate plan updated"})))
+}
+
+//deletes a rate plan and its seasons, refused while bookings are priced on it
+#[delete("/rate-plans/{id}")]
+async fn delete_rate_plan(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    db::run(&pool, move |conn| {
+        if conn.execute("DELETE FROM rate_plans WHERE id = ?1", [&id])? == 0 {
+            return Err(ApiError::NotFound("rate plan"));
+        }
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "rate plan deleted"})))
+}
+
+//checks the plan exists and the season is priced in its hotel's currency
+fn check_season(conn: &Connection, plan_id: &str, season: &RateSeason) -> Result<(), ApiError> {
+    let hotel_id: String = conn.query_row("SELECT hotel_id FROM rate_plans WHERE id = ?1", [plan_id], |row| row.get(0))
+        .optional()?
+        .ok_or(ApiError::NotFound("rate plan"))?;
+
+    check_hotel_currency(conn, &hotel_id, &season.weekday_price)?;
+    check_hotel_currency(conn, &hotel_id, &season.weekend_price)
+}
+
+//adds a seasonal price range to a rate plan
+#[post("/rate-plans/{id}/seasons")]
+async fn create_rate_season(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<RateSeason>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+
+    let season_id = db::run(&pool, move |conn| {
+        check_season(conn, &id, &data)?;
+        conn.execute(
+            "INSERT INTO rate_seasons (rate_plan_id, room_type, start_date, end_date, weekday_minor, weekend_minor,
+                                       currency, min_stay, closed_to_arrival, closed_to_departure)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            (
+                &id, &data.room_type, &data.start_date, &data.end_date,
+                data.weekday_price.minor_units, data.weekend_price.minor_units, data.weekday_price.currency,
+                data.min_stay, data.closed_to_arrival, data.closed_to_departure,
+            ),
+        )?;
+        Ok(conn.last_insert_rowid())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "rate season added", "id": season_id})))
+}
+
+//returns the seasons of a rate plan ordered by room type and start date
+#[get("/rate-plans/{id}/seasons")]
+async fn get_rate_seasons(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let seasons: Vec<RateSeason> = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, room_type, start_date, end_date, weekday_minor, weekend_minor, currency,
+                    min_stay, closed_to_arrival, closed_to_departure
+             FROM rate_seasons WHERE rate_plan_id = ?1 ORDER BY room_type, start_date"
+        )?;
+
+        let seasons_iter = stmt.query_map([id], |row| {
+            let currency: Currency = row.get(6)?;
+            Ok(RateSeason {
+                id: Some(row.get(0)?),
+                room_type: row.get(1)?,
+                start_date: row.get(2)?,
+                end_date: row.get(3)?,
+                weekday_price: Money::new(row.get(4)?, currency),
+                weekend_price: Money::new(row.get(5)?, currency),
+                min_stay: row.get(7)?,
+                closed_to_arrival: row.get(8)?,
+                closed_to_departure: row.get(9)?,
+            })
+        })?;
+
+        Ok(seasons_iter.collect::<rusqlite::Result<_>>()?)
+    }).await?;
+    Ok(HttpResponse::Ok().json(seasons))
+}
+
+//replaces a season of a rate plan
+#[put("/rate-plans/{id}/seasons/{season_id}")]
+async fn update_rate_season(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i64)>,
+    data: Valid<RateSeason>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, season_id) = path.into_inner();
+    let data = data.into_inner();
+
+    db::run(&pool, move |conn| {
+        check_season(conn, &id, &data)?;
+        let updated = conn.execute(
+            "UPDATE rate_seasons
+             SET room_type = ?1, start_date = ?2, end_date = ?3, weekday_minor = ?4, weekend_minor = ?5,
+                 currency = ?6, min_stay = ?7, closed_to_arrival = ?8, closed_to_departure = ?9
+             WHERE id = ?10 AND rate_plan_id = ?11",
+            (
+                &data.room_type, &data.start_date, &data.end_date,
+                data.weekday_price.minor_units, data.weekend_price.minor_units, data.weekday_price.currency,
+                data.min_stay, data.closed_to_arrival, data.closed_to_departure, season_id, &id,
+            ),
+        )?;
+        if updated == 0 {
+            return Err(ApiError::NotFound("rate season"));
+        }
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "rate season updated"})))
+}
+
+//deletes a season of a rate plan, bookings keep the prices they were given
+#[delete("/rate-plans/{id}/seasons/{season_id}")]
+async fn delete_rate_season(pool: web::Data<DbPool>, path: web::Path<(String, i64)>) -> Result<HttpResponse, ApiError> {
+    let (id, season_id) = path.into_inner();
+    db::run(&pool, move |conn| {
+        if conn.execute("DELETE FROM rate_seasons WHERE id = ?1 AND rate_plan_id = ?2", (season_id, &id))? == 0 {
+            return Err(ApiError::NotFound("rate season"));
+        }
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "rate season deleted"})))
+}
+
+//---cancellation policies---
+
+//creates a cancellation policy, hotels and rate plans pick it with cancellation_policy_id
+#[post("/cancellation-policies")]
+async fn create_cancellation_policy(pool: web::Data<DbPool>, data: Valid<CancellationPolicy>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
+    let id = Uuid::new_v4().to_string();
+    let policy_id = id.clone();
+
+    db::run(&pool, move |conn| {
+        conn.execute(
+            "INSERT INTO cancellation_policies (id, name, free_until_days, penalty, percent) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (&policy_id, &data.name, data.free_until_days, data.penalty, data.percent),
+        )?;
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "cancellation policy added", "id": id})))
+}
+
+const CANCELLATION_POLICY_LIST: ListSpec = ListSpec {
+    select: cancellation::POLICY_SELECT,
+    sort_fields: &[("name", "t.name"), ("free_until_days", "COALESCE(t.free_until_days, -1)")],
+    filters: &[
+        Filter { param: "name", expr: "t.name", op: FilterOp::Contains, kind: FilterKind::Text },
+        Filter { param: "penalty", expr: "t.penalty", op: FilterOp::Eq, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of cancellation policies, see `listing` for the query parameters
+#[get("/cancellation-policies")]
+async fn get_cancellation_policies(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| {
+        listing::fetch_page(conn, &CANCELLATION_POLICY_LIST, &query, cancellation::policy_from_row)
+    }).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+//returns a cancellation policy by ID
+#[get("/cancellation-policies/{id}")]
+async fn get_cancellation_policy_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let policy = db::run(&pool, move |conn| {
+        conn.query_row(&format!("{} WHERE id = ?1", cancellation::POLICY_SELECT), [id], cancellation::policy_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("cancellation policy"))
+    }).await?;
+    Ok(HttpResponse::Ok().json(policy))
+}
+
+//updates a cancellation policy, bookings and quotes keep the terms they were sold with
+#[put("/cancellation-policies/{id}")]
+async fn update_cancellation_policy(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    data: Valid<CancellationPolicy>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+
+    db::run(&pool, move |conn| {
+        let updated = conn.execute(
+            "UPDATE cancellation_policies SET name = ?1, free_until_days = ?2, penalty = ?3, percent = ?4 WHERE id = ?5",
+            (&data.name, data.free_until_days, data.penalty, data.percent, &id),
+        )?;
+        if updated == 0 {
+            return Err(ApiError::NotFound("cancellation policy"));
+        }
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "cancellation policy updated"})))
+}
+
+//deletes a cancellation policy no hotel or rate plan uses
+#[delete("/cancellation-policies/{id}")]
+async fn delete_cancellation_policy(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    db::run(&pool, move |conn| {
+        let used: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM hotels WHERE cancellation_policy_id = ?1)
+                 OR EXISTS (SELECT 1 FROM rate_plans WHERE cancellation_policy_id = ?1)",
+            [&id],
+            |row| row.get(0),
+        )?;
+        if used {
+            return Err(ApiError::conflict("policy_in_use", "hotels or rate plans still use the policy"));
+        }
+        if conn.execute("DELETE FROM cancellation_policies WHERE id = ?1", [&id])? == 0 {
+            return Err(ApiError::NotFound("cancellation policy"));
+        }
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "cancellation policy deleted"})))
+}
+
+//---tax rules---
+
+//checks the hotel exists and flat amounts are in its currency
+fn check_tax_rule(conn: &Connection, hotel_id: &str, rule: &TaxRule) -> Result<(), ApiError> {
+    match &rule.amount {
+        Some(amount) => check_hotel_currency(conn, hotel_id, amount),
+        None => conn.query_row("SELECT 1 FROM hotels WHERE id = ?1", [hotel_id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("hotel")),
+    }
+}
+
+fn exempt_guest_types(rule: &TaxRule) -> Result<String, ApiError> {
+    serde_json::to_string(&rule.exempt_guest_types).map_err(|err| ApiError::Internal(err.to_string()))
+}
+
+//adds a tax or fee to every stay priced for a hotel from now on
+#[post("/hotels/{id}/tax-rules")]
+async fn create_tax_rule(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<TaxRule>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+
+    let rule_id = db::run(&pool, move |conn| {
+        check_tax_rule(conn, &id, &data)?;
+        conn.execute(
+            "INSERT INTO tax_rules (hotel_id, name, category, basis, percent, amount_minor, currency, max_nights,
+                                    exempt_guest_types)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            (
+                &id, &data.name, data.category, data.basis, data.percent,
+                data.amount.map(|amount| amount.minor_units), data.amount.map(|amount| amount.currency),
+                data.max_nights, exempt_guest_types(&data)?,
+            ),
+        )?;
+        Ok(conn.last_insert_rowid())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "tax rule added", "id": rule_id})))
+}
+
+//returns the tax rules of a hotel in the order they are charged
+#[get("/hotels/{id}/tax-rules")]
+async fn get_tax_rules(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let rules: Vec<TaxRule> = db::run(&pool, move |conn| {
+        let mut stmt = conn.prepare(&format!("{} WHERE hotel_id = ?1 ORDER BY id", taxes::TAX_RULE_SELECT))?;
+        let rules_iter = stmt.query_map([id], taxes::tax_rule_from_row)?;
+
+        Ok(rules_iter.collect::<rusqlite::Result<_>>()?)
+    }).await?;
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+//replaces a tax rule of a hotel, stays priced before keep the charges they were given
+#[put("/hotels/{id}/tax-rules/{rule_id}")]
+async fn update_tax_rule(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, i64)>,
+    data: Valid<TaxRule>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, rule_id) = path.into_inner();
+    let data = data.into_inner();
+
+    db::run(&pool, move |conn| {
+        check_tax_rule(conn, &id, &data)?;
+        let updated = conn.execute(
+            "UPDATE tax_rules
+             SET name = ?1, category = ?2, basis = ?3, percent = ?4, amount_minor = ?5, currency = ?6,
+                 max_nights = ?7, exempt_guest_types = ?8
+             WHERE id = ?9 AND hotel_id = ?10",
+            (
+                &data.name, data.category, data.basis, data.percent,
+                data.amount.map(|amount| amount.minor_units), data.amount.map(|amount| amount.currency),
+                data.max_nights, exempt_guest_types(&data)?, rule_id, &id,
+            ),
+        )?;
+        if updated == 0 {
+            return Err(ApiError::NotFound("tax rule"));
+        }
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "tax rule updated"})))
+}
+
+//deletes a tax rule of a hotel
+#[delete("/hotels/{id}/tax-rules/{rule_id}")]
+async fn delete_tax_rule(pool: web::Data<DbPool>, path: web::Path<(String, i64)>) -> Result<HttpResponse, ApiError> {
+    let (id, rule_id) = path.into_inner();
+    db::run(&pool, move |conn| {
+        if conn.execute("DELETE FROM tax_rules WHERE id = ?1 AND hotel_id = ?2", (rule_id, &id))? == 0 {
+            return Err(ApiError::NotFound("tax rule"));
+        }
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "tax rule deleted"})))
+}
+
+//---promotions---
+
+//checks every hotel the promotion is limited to exists
+fn check_promotion(conn: &Connection, promotion: &Promotion) -> Result<(), ApiError> {
+    let mut missing = Vec::new();
+    for hotel_id in &promotion.hotel_ids {
+        if conn.query_row("SELECT 1 FROM hotels WHERE id = ?1", [hotel_id], |_| Ok(())).optional()?.is_none() {
+            missing.push(hotel_id.as_str());
+        }
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::invalid("hotel_ids", format!("unknown hotel {}", missing.join(", "))))
+    }
+}
+
+fn json_list(values: &[String]) -> Result<String, ApiError> {
+    serde_json::to_string(values).map_err(|err| ApiError::Internal(err.to_string()))
+}
+
+//creates a promo code, codes are unique whatever their case
+#[post("/promotions")]
+async fn create_promotion(pool: web::Data<DbPool>, data: Valid<Promotion>) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
+    let id = Uuid::new_v4().to_string();
+    let promotion_id = id.clone();
+
+    db::run(&pool, move |conn| {
+        check_promotion(conn, &data)?;
+        conn.execute(
+            "INSERT INTO promotions (id, code, name, kind, percent, amount_minor, currency, valid_from, valid_until,
+                                     hotel_ids, room_types, min_nights, max_uses, max_uses_per_guest, stackable)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            (
+                &promotion_id, promotions::normalize_code(&data.code), &data.name, data.kind, data.percent,
+                data.amount.map(|amount| amount.minor_units), data.amount.map(|amount| amount.currency),
+                &data.valid_from, &data.valid_until, json_list(&data.hotel_ids)?, json_list(&data.room_types)?,
+                data.min_nights, data.max_uses, data.max_uses_per_guest, data.stackable,
+            ),
+        )?;
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "promotion added", "id": id})))
+}
+
+const PROMOTION_LIST: ListSpec = ListSpec {
+    select: promotions::PROMOTION_SELECT,
+    sort_fields: &[("code", "t.code"), ("valid_from", "t.valid_from"), ("valid_until", "t.valid_until")],
+    filters: &[
+        Filter { param: "code", expr: "t.code", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "kind", expr: "t.kind", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "valid_until_gte", expr: "t.valid_until", op: FilterOp::Gte, kind: FilterKind::Text },
+        Filter { param: "stackable", expr: "t.stackable", op: FilterOp::Eq, kind: FilterKind::Integer },
+    ],
+};
+
+//returns a page of promotions, see `listing` for the query parameters
+#[get("/promotions")]
+async fn get_promotions(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| {
+        listing::fetch_page(conn, &PROMOTION_LIST, &query, promotions::promotion_from_row)
+    }).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+//returns a promotion by ID
+#[get("/promotions/{id}")]
+async fn get_promotion_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let promotion = db::run(&pool, move |conn| {
+        conn.query_row(&format!("{} WHERE id = ?1", promotions::PROMOTION_SELECT), [id], promotions::promotion_from_row)
+            .optional()?
+            .ok_or(ApiError::NotFound("promotion"))
+    }).await?;
+    Ok(HttpResponse::Ok().json(promotion))
+}
+
+//updates a promotion by ID, bookings keep the discounts they were given
+#[put("/promotions/{id}")]
+async fn update_promotion(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<Promotion>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+
+    db::run(&pool, move |conn| {
+        check_promotion(conn, &data)?;
+        let updated = conn.execute(
+            "UPDATE promotions
+             SET code = ?1, name = ?2, kind = ?3, percent = ?4, amount_minor = ?5, currency = ?6, valid_from = ?7,
+                 valid_until = ?8, hotel_ids = ?9, room_types = ?10, min_nights = ?11, max_uses = ?12,
+                 max_uses_per_guest = ?13, stackable = ?14
+             WHERE id = ?15",
+            (
+                promotions::normalize_code(&data.code), &data.name, data.kind, data.percent,
+                data.amount.map(|amount| amount.minor_units), data.amount.map(|amount| amount.currency),
+                &data.valid_from, &data.valid_until, json_list(&data.hotel_ids)?, json_list(&data.room_types)?,
+                data.min_nights, data.max_uses, data.max_uses_per_guest, data.stackable, &id,
+            ),
+        )?;
+        if updated == 0 {
+            return Err(ApiError::NotFound("promotion"));
+        }
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "promotion updated"})))
+}
+
+//deletes a promotion that was never quoted or redeemed, end it with valid_until otherwise
+#[delete("/promotions/{id}")]
+async fn delete_promotion(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    db::run(&pool, move |conn| {
+        let used: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM promotion_redemptions WHERE promotion_id = ?1)
+                 OR EXISTS (SELECT 1 FROM quote_promotions WHERE promotion_id = ?1)",
+            [&id],
+            |row| row.get(0),
+        )?;
+        if used {
+            return Err(ApiError::conflict("promotion_in_use", "the promotion was already quoted or redeemed, end it with valid_until instead"));
+        }
+        if conn.execute("DELETE FROM promotions WHERE id = ?1", [&id])? == 0 {
+            return Err(ApiError::NotFound("promotion"));
+        }
+        Ok(())
+    }).await?;
+    Ok(HttpResponse::Ok().json(json!({"status": "promotion deleted"})))
+}
+
+//returns the bookings a promotion was redeemed on, oldest first
+#[get("/promotions/{id}/redemptions")]
+async fn get_promotion_redemptions(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let redemptions = db::run(&pool, move |conn| {
+        conn.query_row("SELECT 1 FROM promotions WHERE id = ?1", [&id], |_| Ok(()))
+            .optional()?
+            .ok_or(ApiError::NotFound("promotion"))?;
+
+        let mut stmt = conn.prepare(
+            "SELECT r.booking_id, b.guest_id, b.status, r.code, r.discount_minor, r.currency, r.redeemed_at
+             FROM promotion_redemptions r
+             JOIN bookings b ON b.id = r.booking_id
+             WHERE r.promotion_id = ?1
+             ORDER BY r.redeemed_at, r.booking_id"
+        )?;
+        let rows = stmt.query_map([&id], |row| {
+            Ok(json!({
+                "booking_id": row.get::<_, String>(0)?,
+                "guest_id": row.get::<_, String>(1)?,
+                "booking_status": row.get::<_, BookingStatus>(2)?,
+                "code": row.get::<_, String>(3)?,
+                "discount": Money::new(row.get(4)?, row.get(5)?),
+                "redeemed_at": row.get::<_, String>(6)?,
+            }))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }).await?;
+    Ok(HttpResponse::Ok().json(redemptions))
+}
+
+//---exchange rates---
+
+const EXCHANGE_RATE_LIST: ListSpec = ListSpec {
+    select: "SELECT id, valid_on, base, quote, rate FROM exchange_rates",
+    sort_fields: &[("valid_on", "t.valid_on"), ("base", "t.base"), ("quote", "t.quote")],
+    filters: &[
+        Filter { param: "base", expr: "t.base", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "quote", expr: "t.quote", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "valid_on_from", expr: "t.valid_on", op: FilterOp::Gte, kind: FilterKind::Text },
+        Filter { param: "valid_on_to", expr: "t.valid_on", op: FilterOp::Lte, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of stored exchange rates, loaded with `hotel_project rates load <file>`
+#[get("/exchange-rates")]
+async fn get_exchange_rates(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| {
+        listing::fetch_page(conn, &EXCHANGE_RATE_LIST, &query, |row| {
+            Ok(ExchangeRate {
+                id: row.get(0)?,
+                valid_on: row.get(1)?,
+                base: row.get(2)?,
+                quote: row.get(3)?,
+                rate: row.get(4)?,
+            })
+        })
+    }).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+//---payments---
+
+//payment columns, amount_minor/currency hold the settled amount in the booking currency
+const PAYMENT_SELECT: &str = "
+    SELECT id, booking_id, tendered_minor, tendered_currency, method, paid_on,
+           amount_minor, currency, exchange_rate, status, void_reason,
+           (SELECT COALESCE(SUM(r.amount_minor), 0) FROM payment_refunds r
+            WHERE r.payment_id = payments.id AND r.status = 'completed') AS refunded_minor,
+           gateway, gateway_reference, card_last4
+    FROM payments
+";
+
+fn payment_from_row(row: &rusqlite::Row) -> rusqlite::Result<Payment> {
+    Ok(Payment {
+        id: Some(row.get(0)?),
+        booking_id: row.get(1)?,
+        amount: Money::new(row.get(2)?, row.get(3)?),
+        method: row.get(4)?,
+        paid_on: row.get(5)?,
+        settled_amount: Some(Money::new(row.get(6)?, row.get(7)?)),
+        exchange_rate: Some(row.get(8)?),
+        status: Some(row.get(9)?),
+        void_reason: row.get(10)?,
+        refunded_amount: Some(Money::new(row.get(11)?, row.get(3)?)),
+        card_number: None,
+        authorize_only: false,
+        gateway: row.get(12)?,
+        gateway_reference: row.get(13)?,
+        card_last4: row.get(14)?,
+    })
+}
+
+fn load_payment(conn: &Connection, id: &str) -> Result<Payment, ApiError> {
+    conn.query_row(&format!("{PAYMENT_SELECT} WHERE id = ?1"), [id], payment_from_row)
+        .optional()?
+        .ok_or(ApiError::NotFound("payment"))
+}
+
+//converts a tendered amount into the currency of the booked hotel at the rate of the payment day, today when not given
+fn settle_payment(conn: &Connection, booking_id: &str, amount: Money, paid_on: Option<&str>) -> Result<(String, Money, Rate), ApiError> {
+    let currency: Currency = conn.query_row(
+        "SELECT h.currency FROM bookings b
+         JOIN rooms r ON r.id = b.room_id
+         JOIN hotels h ON h.id = r.hotel_id
+         WHERE b.id = ?1",
+        [booking_id],
+        |row| row.get(0),
+    ).optional()?.ok_or(ApiError::NotFound("booking"))?;
+
+    let paid_on = match paid_on {
+        Some(paid_on) => paid_on.to_string(),
+        None => conn.query_row("SELECT date('now')", [], |row| row.get(0))?,
+    };
+    let rate = exchange::rate_on(conn, amount.currency, currency, &paid_on)?;
+    let settled = amount.convert(currency, rate)?;
+
+    Ok((paid_on, settled, rate))
+}
+
+//checks `amount` can be taken of a card hold
+fn check_capture(payment: &Payment, amount: Money) -> Result<(), ApiError> {
+    if payment.status != Some(PaymentStatus::Authorized) {
+        return Err(ApiError::conflict_with(
+            "payment_not_authorized",
+            "only authorized payments can be captured",
+            json!({"status": payment.status}),
+        ));
+    }
+    amount.ensure_currency(payment.amount.currency)?;
+    if amount.minor_units > payment.amount.minor_units {
+        return Err(ApiError::conflict_with(
+            "capture_exceeds_authorization",
+            "cannot capture more than was authorized",
+            json!({"authorized": payment.amount}),
+        ));
+    }
+    Ok(())
+}
+
+//records that the gateway took `amount` of a card hold, settled at today's rate and credited to the folio
+fn record_capture(conn: &Connection, payment: &Payment, amount: Money) -> Result<Money, ApiError> {
+    let id = payment.id.as_deref().unwrap_or_default();
+    let (paid_on, settled, rate) = settle_payment(conn, &payment.booking_id, amount, None)?;
+    conn.execute(
+        "UPDATE payments
+         SET status = ?1, tendered_minor = ?2, amount_minor = ?3, currency = ?4, exchange_rate = ?5, paid_on = ?6,
+             gateway_call = NULL, gateway_call_at = NULL
+         WHERE id = ?7",
+        (PaymentStatus::Captured, amount.minor_units, settled.minor_units, settled.currency, rate, &paid_on, id),
+    )?;
+    folio::post_payment(conn, &payment.booking_id, id, settled, &payment.method)?;
+    Ok(settled)
+}
+
+//records that a payment was voided, a captured one is taken back off the folio
+fn record_void(conn: &Connection, payment: &Payment, reason: &str) -> Result<(), ApiError> {
+    let id = payment.id.as_deref().unwrap_or_default();
+    if payment.status == Some(PaymentStatus::Captured) {
+        folio::reverse_payment(conn, id, reason)?;
+    }
+    conn.execute(
+        "UPDATE payments
+         SET status = ?1, void_reason = ?2, voided_at = datetime('now'), gateway_call = NULL, gateway_call_at = NULL,
+             gateway_call_done = 0
+         WHERE id = ?3",
+        (PaymentStatus::Voided, reason, id),
+    )?;
+    Ok(())
+}
+
+//a gateway call a crash left behind stops blocking its payment after this long
+const GATEWAY_CALL_LEASE: &str = "-5 minutes";
+
+//what the first transaction of a payment operation left to do
+enum GatewayStep<T, C = String> {
+    //nothing goes through the gateway, the operation is done
+    Done(T),
+    //the gateway has to be called with this, usually the payment reference, before the outcome is recorded
+    Call(C),
+}
+
+//marks a gateway call on a payment as in flight, no other request acts on the payment until it is recorded
+fn begin_gateway_call(conn: &Connection, id: &str, call: &str) -> Result<(), ApiError> {
+    let (done, done_call): (bool, Option<String>) = conn.query_row(
+        "SELECT gateway_call_done, gateway_call FROM payments WHERE id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    if done {
+        return Err(ApiError::conflict_with(
+            "payment_unsettled",
+            "the gateway made a call on the payment that is not recorded yet, repeat it first",
+            json!({"gateway_call": done_call}),
+        ));
+    }
+    let claimed = conn.execute(
+        "UPDATE payments SET gateway_call = ?1, gateway_call_at = datetime('now')
+         WHERE id = ?2 AND (gateway_call IS NULL OR gateway_call_at <= datetime('now', ?3))",
+        (call, id, GATEWAY_CALL_LEASE),
+    )?;
+    if claimed == 0 {
+        let (call, since): (Option<String>, Option<String>) = conn.query_row(
+            "SELECT gateway_call, gateway_call_at FROM payments WHERE id = ?1",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        return Err(ApiError::conflict_with(
+            "payment_busy",
+            "a gateway call on the payment is still in flight, try again shortly",
+            json!({"gateway_call": call, "since": since}),
+        ));
+    }
+    Ok(())
+}
+
+//clears a gateway call that failed, the payment stays as it was
+async fn abort_gateway_call(pool: &DbPool, id: &str) {
+    let payment_id = id.to_string();
+    let cleared = db::run(pool, move |conn| {
+        conn.execute("UPDATE payments SET gateway_call = NULL, gateway_call_at = NULL WHERE id = ?1", [&payment_id])?;
+        Ok(())
+    }).await;
+    if let Err(err) = cleared {
+        log::error!("payment {id}: could not clear its gateway call: {err}");
+    }
+}
+
+//captures a payment claimed for a capture through the gateway and records it; a capture that cannot be
+//recorded is voided again so the card is not left charged for money the folio does not show
+async fn capture_claimed(pool: &DbPool, gateway: &Arc<dyn PaymentGateway>, payment: Payment, amount: Money) -> Result<Money, ApiError> {
+    let id = payment.id.clone().unwrap_or_default();
+    let reference = payment.gateway_reference.clone().unwrap_or_default();
+    let captured = reference.clone();
+    if let Err(err) = gateway::call(gateway, move |gateway| gateway.capture(&captured, amount)).await {
+        abort_gateway_call(pool, &id).await;
+        return Err(err);
+    }
+    let recorded = db::run(pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let settled = record_capture(&tx, &payment, amount)?;
+        tx.commit()?;
+        Ok(settled)
+    }).await;
+    if recorded.is_err() {
+        gateway::undo(gateway, reference).await;
+        abort_gateway_call(pool, &id).await;
+    }
+    recorded
+}
+
+//voids a payment claimed for a void through the gateway and records it
+async fn void_claimed(
+    pool: &DbPool,
+    gateway: &Arc<dyn PaymentGateway>,
+    id: String,
+    reference: String,
+    reason: String,
+) -> Result<(), ApiError> {
+    if let Err(err) = gateway::call(gateway, move |gateway| gateway.void(&reference)).await {
+        abort_gateway_call(pool, &id).await;
+        return Err(err);
+    }
+    let payment_id = id.clone();
+    let recorded = db::run(pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        record_void(&tx, &load_payment(&tx, &payment_id)?, &reason)?;
+        tx.commit()?;
+        Ok(())
+    }).await;
+    if let Err(err) = &recorded {
+        //the void stays claimed and marked done so the next void request records it without the gateway
+        let payment_id = id.clone();
+        let kept = db::run(pool, move |conn| {
+            conn.execute("UPDATE payments SET gateway_call_done = 1 WHERE id = ?1 AND gateway_call = 'void'", [&payment_id])?;
+            Ok(())
+        }).await;
+        match kept {
+            Ok(()) => log::error!("payment {id}: voided at the gateway but not recorded yet: {err}"),
+            Err(kept) => log::error!("payment {id}: voided at the gateway but not recorded: {err}; {kept}"),
+        }
+    }
+    recorded
+}
+
+//what check-out, cancellation or no-show does with a card hold once the status change committed
+struct HoldClosing {
+    payment: Payment,
+    //taken of the hold for what the guest still owes, the hold is released when nothing is
+    capture: Option<Money>,
+    reason: &'static str,
+}
+
+//check-out, cancellation and no-show capture what the guest still owes from their card holds, whatever is left
+//of a hold is released; the holds are claimed for their gateway calls inside the caller's transaction
+fn claim_authorizations(conn: &Connection, booking_id: &str, next: BookingStatus) -> Result<Vec<HoldClosing>, ApiError> {
+    let mut stmt = conn.prepare(&format!("{PAYMENT_SELECT} WHERE booking_id = ?1 AND status = 'authorized' ORDER BY rowid"))?;
+    let holds = stmt.query_map([booking_id], payment_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+    if holds.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let currency = folio::folio_currency(conn, booking_id)?;
+    let today: String = conn.query_row("SELECT date('now')", [], |row| row.get(0))?;
+    let balance: i64 = conn.query_row(
+        "SELECT COALESCE(SUM(amount_minor), 0) FROM folio_entries WHERE booking_id = ?1",
+        [booking_id],
+        |row| row.get(0),
+    )?;
+    let reason = match next {
+        BookingStatus::CheckedOut => "nothing owed at check-out",
+        BookingStatus::NoShow => "guest did not show up",
+        _ => "booking cancelled",
+    };
+
+    //later holds only take what the earlier ones leave owing
+    let mut owed = Money::new(balance, currency);
+    let mut closings = Vec::new();
+    for hold in holds {
+        let due = if owed.is_positive() {
+            let rate = exchange::rate_on(conn, currency, hold.amount.currency, &today)?;
+            owed.convert(hold.amount.currency, rate)?
+        } else {
+            Money::zero(hold.amount.currency)
+        };
+        let capture = if due.is_positive() {
+            let amount = if due.minor_units < hold.amount.minor_units { due } else { hold.amount };
+            let (_, settled, _) = settle_payment(conn, booking_id, amount, None)?;
+            owed = owed.checked_sub(settled)?;
+            Some(amount)
+        } else {
+            None
+        };
+        let call = if capture.is_some() { "capture" } else { "void" };
+        begin_gateway_call(conn, hold.id.as_deref().unwrap_or_default(), call)?;
+        closings.push(HoldClosing { payment: hold, capture, reason });
+    }
+    Ok(closings)
+}
+
+//makes the gateway calls `claim_authorizations` claimed and records them; the status change stands whatever the
+//gateway answers, a hold it failed on stays authorized for staff to capture or void
+async fn close_authorizations(pool: &DbPool, gateway: &Arc<dyn PaymentGateway>, closings: Vec<HoldClosing>) -> Vec<serde_json::Value> {
+    let mut outcomes = Vec::new();
+    for closing in closings {
+        let id = closing.payment.id.clone().unwrap_or_default();
+        let outcome = match closing.capture {
+            Some(amount) => capture_claimed(pool, gateway, closing.payment, amount)
+                .await
+                .map(|settled| json!({"payment_id": id, "captured": settled})),
+            None => {
+                let reference = closing.payment.gateway_reference.clone().unwrap_or_default();
+                void_claimed(pool, gateway, id.clone(), reference, closing.reason.to_string())
+                    .await
+                    .map(|()| json!({"payment_id": id, "released": true}))
+            }
+        };
+        outcomes.push(outcome.unwrap_or_else(|err| {
+            log::error!("payment {id}: card hold not closed: {err}");
+            json!({"payment_id": id, "error": err.code()})
+        }));
+    }
+    outcomes
+}
+
+//creates a payment in DB; card payments are authorized through the gateway and captured unless `authorize_only` is set.
+//a card payment is stored as pending before the gateway is called and settled once it answered, a declined card
+//leaves no payment behind
+#[post("/payments")]
+async fn create_payment(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    data: Valid<Payment>,
+) -> Result<HttpResponse, ApiError> {
+    let data = data.into_inner();
+    let gateway = gateway.into_inner();
+    let id = Uuid::new_v4().to_string();
+    let payment_id = id.clone();
+    let card_number = data.card_number.clone();
+    let (amount, authorize_only) = (data.amount, data.authorize_only);
+    let gateway_name = gateway.name();
+
+    let payment = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let (paid_on, settled, rate) = settle_payment(&tx, &data.booking_id, data.amount, data.paid_on.as_deref())?;
+
+        let card = data.card_number.is_some();
+        let card_last4 = data.card_number.as_deref().map(|n| n[n.len() - 4..].to_string());
+        let status = if card { PaymentStatus::Pending } else { PaymentStatus::Captured };
+        tx.execute(
+            "INSERT INTO payments (id, booking_id, amount_minor, currency, method,
+                                   tendered_minor, tendered_currency, exchange_rate, paid_on,
+                                   status, gateway, card_last4, gateway_call, gateway_call_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, CASE WHEN ?13 IS NULL THEN NULL ELSE datetime('now') END)",
+            (
+                &payment_id, &data.booking_id, settled.minor_units, settled.currency, &data.method,
+                data.amount.minor_units, data.amount.currency, rate, &paid_on,
+                status, card.then_some(gateway_name), &card_last4, card.then_some("authorize"),
+            ),
+        )?;
+        if !card {
+            folio::post_payment(&tx, &data.booking_id, &payment_id, settled, &data.method)?;
+        }
+        let payment = load_payment(&tx, &payment_id)?;
+        tx.commit()?;
+        Ok(payment)
+    }).await?;
+
+    let payment = match card_number {
+        None => payment,
+        Some(card_number) => {
+            //a hold that cannot be captured straight away is let go again
+            let authorized = gateway::call(&gateway, move |gateway| {
+                let reference = gateway.authorize(&card_number, amount)?;
+                if !authorize_only && let Err(err) = gateway.capture(&reference, amount) {
+                    if let Err(void_err) = gateway.void(&reference) {
+                        log::error!("could not void {reference} at {}: {void_err:?}", gateway.name());
+                    }
+                    return Err(err);
+                }
+                Ok(reference)
+            }).await;
+            let reference = match authorized {
+                Ok(reference) => reference,
+                Err(err) => {
+                    drop_pending_payment(&pool, &id).await;
+                    return Err(err);
+                }
+            };
+
+            let payment_id = id.clone();
+            let gateway_reference = reference.clone();
+            let recorded = db::run(&pool, move |conn| {
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                tx.execute(
+                    "UPDATE payments SET status = ?1, gateway_reference = ?2, gateway_call = NULL, gateway_call_at = NULL
+                     WHERE id = ?3",
+                    (PaymentStatus::Authorized, &gateway_reference, &payment_id),
+                )?;
+                if !authorize_only {
+                    record_capture(&tx, &load_payment(&tx, &payment_id)?, amount)?;
+                }
+                let payment = load_payment(&tx, &payment_id)?;
+                tx.commit()?;
+                Ok(payment)
+            }).await;
+            match recorded {
+                Ok(payment) => payment,
+                Err(err) => {
+                    gateway::undo(&gateway, reference).await;
+                    drop_pending_payment(&pool, &id).await;
+                    return Err(err);
+                }
+            }
+        }
+    };
+    gateway::deliver_webhooks(&pool, &gateway);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "payment added",
+        "id": id,
+        "payment_status": payment.status,
+        "settled_amount": payment.settled_amount,
+        "exchange_rate": payment.exchange_rate
+    })))
+}
+
+//removes a card payment the gateway never authorized, it held nothing
+async fn drop_pending_payment(pool: &DbPool, id: &str) {
+    let payment_id = id.to_string();
+    let dropped = db::run(pool, move |conn| {
+        conn.execute("DELETE FROM payments WHERE id = ?1 AND status = ?2", (&payment_id, PaymentStatus::Pending))?;
+        Ok(())
+    }).await;
+    if let Err(err) = dropped {
+        log::error!("payment {id}: could not drop it after its authorization failed: {err}");
+    }
+}
+
+const PAYMENT_LIST: ListSpec = ListSpec {
+    select: PAYMENT_SELECT,
+    sort_fields: &[("amount", "t.amount_minor"), ("method", "COALESCE(t.method, '')")],
+    filters: &[
+        Filter { param: "booking_id", expr: "t.booking_id", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter {
+            param: "reservation_id",
+            expr: "(SELECT reservation_id FROM bookings WHERE id = t.booking_id)",
+            op: FilterOp::Eq,
+            kind: FilterKind::Text,
+        },
+        Filter { param: "method", expr: "t.method", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "currency", expr: "t.currency", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "amount_minor_gte", expr: "t.amount_minor", op: FilterOp::Gte, kind: FilterKind::Integer },
+        Filter { param: "amount_minor_lte", expr: "t.amount_minor", op: FilterOp::Lte, kind: FilterKind::Integer },
+        Filter { param: "tendered_currency", expr: "t.tendered_currency", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "paid_on_from", expr: "t.paid_on", op: FilterOp::Gte, kind: FilterKind::Text },
+        Filter { param: "status", expr: "t.status", op: FilterOp::Eq, kind: FilterKind::Text },
+        Filter { param: "gateway_reference", expr: "t.gateway_reference", op: FilterOp::Eq, kind: FilterKind::Text },
+    ],
+};
+
+//returns a page of payments, see `listing` for the query parameters
+#[get("/payments")]
+async fn get_payments(pool: web::Data<DbPool>, query: ListQuery) -> Result<HttpResponse, ApiError> {
+    let page = db::run(&pool, move |conn| listing::fetch_page(conn, &PAYMENT_LIST, &query, payment_from_row)).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+//returns a payment by ID
+#[get("/payments/{id}")]
+async fn get_payment_by_id(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let payment = db::run(&pool, move |conn| load_payment(conn, &id)).await?;
+    Ok(HttpResponse::Ok().json(payment))
+}
+
+//updates a payment by ID, its folio entry is reversed and posted again;
+//card payments, refunded and voided payments are final
+#[put("/payments/{id}")]
+async fn update_payment(pool: web::Data<DbPool>, path: web::Path<String>, data: Valid<Payment>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+
+    db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let payment = load_payment(&tx, &id)?;
+        if payment.status != Some(PaymentStatus::Captured) || payment.gateway_reference.is_some() {
+            return Err(ApiError::conflict_with(
+                "payment_not_editable",
+                "card, refunded and voided payments cannot be changed",
+                json!({"status": payment.status}),
+            ));
+        }
+        if data.card_number.is_some() {
+            return Err(ApiError::invalid("card_number", "cannot be added to an existing payment"));
+        }
+        let (paid_on, settled, rate) = settle_payment(&tx, &data.booking_id, data.amount, data.paid_on.as_deref())?;
+        tx.execute(
+            "UPDATE payments
+             SET booking_id = ?1, amount_minor = ?2, currency = ?3, method = ?4,
+                 tendered_minor = ?5, tendered_currency = ?6, exchange_rate = ?7, paid_on = ?8
+             WHERE id = ?9",
+            (
+                &data.booking_id, settled.minor_units, settled.currency, &data.method,
+                data.amount.minor_units, data.amount.currency, rate, &paid_on, &id,
+            ),
+        )?;
+        folio::reverse_payment(&tx, &id, "payment updated")?;
+        folio::post_payment(&tx, &data.booking_id, &id, settled, &data.method)?;
+        tx.commit()?;
+        Ok(())
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(json!({"status": "payment updated"})))
+}
+
+//captures an authorized card payment, all of it unless a smaller amount is given
+#[post("/payments/{id}/capture")]
+async fn capture_payment(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    path: web::Path<String>,
+    data: Valid<PaymentCapture>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+    let gateway = gateway.into_inner();
+    let payment_id = id.clone();
+
+    let (payment, amount) = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let payment = load_payment(&tx, &payment_id)?;
+        let amount = data.amount.unwrap_or(payment.amount);
+        check_capture(&payment, amount)?;
+        //fails before the gateway is called when there is no rate to settle at
+        settle_payment(&tx, &payment.booking_id, amount, None)?;
+        begin_gateway_call(&tx, &payment_id, "capture")?;
+        tx.commit()?;
+        Ok((payment, amount))
+    }).await?;
+
+    let settled = capture_claimed(&pool, &gateway, payment, amount).await?;
+    gateway::deliver_webhooks(&pool, &gateway);
+
+    Ok(HttpResponse::Ok().json(json!({"status": "payment captured", "settled_amount": settled})))
+}
+
+//checks `amount` can be refunded of a payment; returns what it takes off the booking
+fn plan_refund(conn: &Connection, payment: &Payment, amount: Money) -> Result<Money, ApiError> {
+    let (Some(settled), Some(rate), Some(refunded)) = (payment.settled_amount, payment.exchange_rate, payment.refunded_amount)
+    else {
+        return Err(ApiError::Internal("payment row is missing its settled amount".into()));
+    };
+
+    match payment.status {
+        Some(PaymentStatus::Voided) => {
+            return Err(ApiError::conflict("payment_voided", "a voided payment cannot be refunded"));
+        }
+        Some(PaymentStatus::Pending | PaymentStatus::Authorized) => {
+            return Err(ApiError::conflict("payment_not_captured", "nothing was taken yet, void the authorization instead"));
+        }
+        _ => {}
+    }
+    amount.ensure_currency(payment.amount.currency)?;
+    let refundable = payment.amount.checked_sub(refunded)?;
+    if amount.minor_units > refundable.minor_units {
+        return Err(ApiError::conflict_with(
+            "refund_exceeds_payment",
+            "refunds cannot add up to more than the payment",
+            json!({"payment": payment.amount, "refunded": refunded, "refundable": refundable}),
+        ));
+    }
+
+    //the last refund takes whatever is left of the settled amount so rounding never leaves a remainder
+    if amount == refundable {
+        let settled_refunded: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(settled_minor), 0) FROM payment_refunds WHERE payment_id = ?1 AND status = 'completed'",
+            [&payment.id],
+            |row| row.get(0),
+        )?;
+        Ok(settled.checked_sub(Money::new(settled_refunded, settled.currency))?)
+    } else {
+        Ok(amount.convert(settled.currency, rate)?)
+    }
+}
+
+//stores a refund of a payment as pending, `key` is what the gateway will know it by; returns its id
+fn insert_refund(conn: &Connection, payment: &Payment, amount: Money, reason: &str, key: Option<&str>) -> Result<i64, ApiError> {
+    let settled_refund = plan_refund(conn, payment, amount)?;
+    conn.execute(
+        "INSERT INTO payment_refunds (payment_id, amount_minor, currency, settled_minor, settled_currency, reason, status, gateway_key)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        (
+            &payment.id, amount.minor_units, amount.currency, settled_refund.minor_units, settled_refund.currency, reason,
+            RefundStatus::Pending, key,
+        ),
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+//books a pending refund the gateway made, or one that needed no gateway, and takes it off the folio
+fn complete_refund(conn: &Connection, refund_id: i64, gateway_reference: Option<&str>) -> Result<serde_json::Value, ApiError> {
+    conn.execute(
+        "UPDATE payment_refunds SET status = ?1, gateway_reference = COALESCE(?2, gateway_reference), refunded_at = datetime('now')
+         WHERE id = ?3",
+        (RefundStatus::Completed, gateway_reference, refund_id),
+    )?;
+    let refund = conn.query_row(&format!("{REFUND_SELECT} WHERE id = ?1"), [refund_id], refund_from_row)?;
+    let payment_id = refund.payment_id.clone().unwrap_or_default();
+    let payment = load_payment(conn, &payment_id)?;
+    let refundable = payment.amount.checked_sub(payment.refunded_amount.unwrap_or(Money::zero(payment.amount.currency)))?;
+
+    let status = if refundable.is_positive() { PaymentStatus::PartiallyRefunded } else { PaymentStatus::Refunded };
+    conn.execute(
+        "UPDATE payments SET status = ?1, gateway_call = NULL, gateway_call_at = NULL WHERE id = ?2",
+        (status, &payment_id),
+    )?;
+    let settled_refund = refund.settled_amount.unwrap_or(Money::zero(payment.amount.currency));
+    folio::post_refund(conn, &payment.booking_id, &payment_id, settled_refund, &refund.reason)?;
+    Ok(json!({"status": status, "refund": refund, "refundable": refundable}))
+}
+
+//the refund of a payment that was stored before its gateway call and never booked
+fn pending_refund(conn: &Connection, payment_id: &str) -> Result<Option<Refund>, ApiError> {
+    Ok(conn.query_row(
+        &format!("{REFUND_SELECT} WHERE payment_id = ?1 AND status = 'pending' ORDER BY id LIMIT 1"),
+        [payment_id],
+        refund_from_row,
+    ).optional()?)
+}
+
+//what the first transaction of a refund left to do through the gateway
+struct RefundCall {
+    refund_id: i64,
+    payment_reference: String,
+    amount: Money,
+    key: String,
+    //set when the gateway already made the refund and only booking it failed
+    refund_reference: Option<String>,
+}
+
+//hands part or all of a payment back to the guest; refunds together never exceed the payment.
+//a card refund is stored before the gateway is asked, so one whose outcome was lost is finished by the next
+//refund request of the payment, which answers with that refund instead of making a new one
+#[post("/payments/{id}/refund")]
+async fn refund_payment(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    path: web::Path<String>,
+    data: Valid<Refund>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+    let gateway = gateway.into_inner();
+    let (payment_id, amount, reason) = (id.clone(), data.amount, data.reason.clone());
+
+    let step = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let payment = load_payment(&tx, &payment_id)?;
+        let step = match (&payment.gateway_reference, pending_refund(&tx, &payment_id)?) {
+            (Some(reference), Some(pending)) => {
+                begin_gateway_call(&tx, &payment_id, "refund")?;
+                let (refund_id, key, refund_reference): (i64, String, Option<String>) = tx.query_row(
+                    "SELECT id, gateway_key, gateway_reference FROM payment_refunds WHERE id = ?1",
+                    [pending.id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )?;
+                GatewayStep::Call(RefundCall {
+                    refund_id, payment_reference: reference.clone(), amount: pending.amount, key, refund_reference,
+                })
+            }
+            (Some(reference), None) => {
+                let key = Uuid::new_v4().to_string();
+                let refund_id = insert_refund(&tx, &payment, amount, &reason, Some(&key))?;
+                begin_gateway_call(&tx, &payment_id, "refund")?;
+                GatewayStep::Call(RefundCall { refund_id, payment_reference: reference.clone(), amount, key, refund_reference: None })
+            }
+            (None, _) => {
+                let refund_id = insert_refund(&tx, &payment, amount, &reason, None)?;
+                GatewayStep::Done(complete_refund(&tx, refund_id, None)?)
+            }
+        };
+        tx.commit()?;
+        Ok(step)
+    }).await?;
+
+    let refund = match step {
+        GatewayStep::Done(refund) => refund,
+        GatewayStep::Call(call) => {
+            let RefundCall { refund_id, payment_reference, amount, key, refund_reference } = call;
+            let refund_reference = match refund_reference {
+                Some(refund_reference) => refund_reference,
+                None => match gateway::call(&gateway, move |gateway| gateway.refund(&payment_reference, amount, &key)).await {
+                    Ok(refund_reference) => refund_reference,
+                    Err(err) => {
+                        //a declined refund was never made; one that went unanswered stays pending to be asked again
+                        if matches!(err, ApiError::PaymentDeclined { .. }) {
+                            drop_pending_refund(&pool, refund_id).await;
+                        }
+                        abort_gateway_call(&pool, &id).await;
+                        return Err(err);
+                    }
+                },
+            };
+            let reference = refund_reference.clone();
+            let booked = db::run(&pool, move |conn| {
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                let refund = complete_refund(&tx, refund_id, Some(&reference))?;
+                tx.commit()?;
+                Ok(refund)
+            }).await;
+            match booked {
+                Ok(refund) => refund,
+                Err(err) => {
+                    keep_refund_reference(&pool, refund_id, refund_reference).await;
+                    abort_gateway_call(&pool, &id).await;
+                    return Err(err);
+                }
+            }
+        }
+    };
+    gateway::deliver_webhooks(&pool, &gateway);
+
+    Ok(HttpResponse::Ok().json(refund))
+}
+
+//removes a pending refund the gateway declined, nothing went back to the guest
+async fn drop_pending_refund(pool: &DbPool, refund_id: i64) {
+    let dropped = db::run(pool, move |conn| {
+        conn.execute("DELETE FROM payment_refunds WHERE id = ?1 AND status = ?2", (refund_id, RefundStatus::Pending))?;
+        Ok(())
+    }).await;
+    if let Err(err) = dropped {
+        log::error!("refund {refund_id}: could not drop it after the gateway declined it: {err}");
+    }
+}
+
+//keeps what the gateway answered for a refund that could not be booked, the next refund request books it
+async fn keep_refund_reference(pool: &DbPool, refund_id: i64, reference: String) {
+    let kept_reference = reference.clone();
+    let kept = db::run(pool, move |conn| {
+        conn.execute("UPDATE payment_refunds SET gateway_reference = ?1 WHERE id = ?2", (&kept_reference, refund_id))?;
+        Ok(())
+    }).await;
+    match kept {
+        Ok(()) => log::error!("refund {refund_id}: made at the gateway as {reference} but not booked yet"),
+        Err(err) => log::error!("refund {refund_id}: made at the gateway as {reference} but not recorded: {err}"),
+    }
+}
+
+const REFUND_SELECT: &str = "
+    SELECT id, payment_id, amount_minor, currency, reason, settled_minor, settled_currency, refunded_at, status,
+           gateway_reference
+    FROM payment_refunds
+";
+
+fn refund_from_row(row: &rusqlite::Row) -> rusqlite::Result<Refund> {
+    Ok(Refund {
+        id: Some(row.get(0)?),
+        payment_id: Some(row.get(1)?),
+        amount: Money::new(row.get(2)?, row.get(3)?),
+        reason: row.get(4)?,
+        settled_amount: Some(Money::new(row.get(5)?, row.get(6)?)),
+        refunded_at: Some(row.get(7)?),
+        status: Some(row.get(8)?),
+        gateway_reference: row.get(9)?,
+    })
+}
+
+//returns the refunds of a payment, oldest first
+#[get("/payments/{id}/refunds")]
+async fn get_payment_refunds(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+
+    let refunds = db::run(&pool, move |conn| {
+        load_payment(conn, &id)?;
+        let mut stmt = conn.prepare(&format!("{REFUND_SELECT} WHERE payment_id = ?1 ORDER BY id"))?;
+        Ok(stmt.query_map([&id], refund_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(refunds))
+}
+
+//releases a card hold at any time, or cancels a payment taken today that has not been refunded;
+//the folio keeps the payment and its reversal. a card payment whose authorization never came back can be
+//voided once its gateway call is stale
+#[post("/payments/{id}/void")]
+async fn void_payment(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    path: web::Path<String>,
+    data: Valid<PaymentVoid>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let data = data.into_inner();
+    let gateway = gateway.into_inner();
+    let (payment_id, reason) = (id.clone(), data.reason.clone());
+
+    let step = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let payment = load_payment(&tx, &payment_id)?;
+        let today: String = tx.query_row("SELECT date('now')", [], |row| row.get(0))?;
+
+        //a void the gateway already made is only recorded
+        let voided: bool = tx.query_row(
+            "SELECT gateway_call = 'void' AND gateway_call_done = 1 FROM payments WHERE id = ?1",
+            [&payment_id],
+            |row| Ok(row.get::<_, Option<bool>>(0)?.unwrap_or(false)),
+        )?;
+        if voided {
+            record_void(&tx, &payment, &reason)?;
+            tx.commit()?;
+            return Ok(GatewayStep::Done(()));
+        }
+        if pending_refund(&tx, &payment_id)?.is_some() {
+            return Err(ApiError::conflict(
+                "refund_pending",
+                "a refund of the payment is not settled yet, repeat the refund first",
+            ));
+        }
+
+        match payment.status {
+            Some(PaymentStatus::Pending | PaymentStatus::Authorized) => {}
+            Some(PaymentStatus::Captured) => {
+                if payment.paid_on.as_deref() != Some(today.as_str()) {
+                    return Err(ApiError::conflict_with(
+                        "void_window_closed",
+                        "payments can only be voided on the day they were made, refund it instead",
+                        json!({"paid_on": payment.paid_on}),
+                    ));
+                }
+            }
+            status => {
+                return Err(ApiError::conflict_with(
+                    "payment_not_voidable",
+                    "only payments that were not refunded or voided can be voided",
+                    json!({"status": status}),
+                ));
+            }
+        }
+        let step = match &payment.gateway_reference {
+            Some(reference) => {
+                begin_gateway_call(&tx, &payment_id, "void")?;
+                GatewayStep::Call(reference.clone())
+            }
+            None => {
+                if payment.status == Some(PaymentStatus::Pending) {
+                    begin_gateway_call(&tx, &payment_id, "void")?;
+                }
+                record_void(&tx, &payment, &reason)?;
+                GatewayStep::Done(())
+            }
+        };
+        tx.commit()?;
+        Ok(step)
+    }).await?;
+
+    if let GatewayStep::Call(reference) = step {
+        void_claimed(&pool, &gateway, id, reference, data.reason).await?;
+    }
+    gateway::deliver_webhooks(&pool, &gateway);
+
+    Ok(HttpResponse::Ok().json(json!({"status": "payment voided"})))
+}
+
+//returns the webhook events received for a payment, oldest first
+#[get("/payments/{id}/events")]
+async fn get_payment_events(pool: web::Data<DbPool>, path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+
+    let events = db::run(&pool, move |conn| {
+        load_payment(conn, &id)?;
+        let mut stmt = conn.prepare(
+            "SELECT provider, id, kind, amount_minor, currency, received_at FROM gateway_events
+             WHERE payment_id = ?1 ORDER BY received_at, rowid"
+        )?;
+        let rows = stmt.query_map([&id], |row| {
+            let amount = match (row.get::<_, Option<i64>>(3)?, row.get::<_, Option<Currency>>(4)?) {
+                (Some(minor_units), Some(currency)) => Some(Money::new(minor_units, currency)),
+                _ => None,
+            };
+            Ok(json!({
+                "provider": row.get::<_, String>(0)?,
+                "id": row.get::<_, String>(1)?,
+                "type": row.get::<_, WebhookKind>(2)?,
+                "amount": amount,
+                "received_at": row.get::<_, String>(5)?,
+            }))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(events))
+}
+
+//receives a webhook event from the configured payment gateway, redelivered events are acknowledged again
+#[post("/payments/webhooks")]
+async fn receive_payment_webhook(
+    pool: web::Data<DbPool>,
+    gateway: web::Data<dyn PaymentGateway>,
+    data: Valid<WebhookEvent>,
+) -> Result<HttpResponse, ApiError> {
+    let event = data.into_inner();
+    let provider = gateway.name();
+
+    let new = db::run(&pool, move |conn| gateway::apply_event(conn, provider, &event)).await?;
+
+    let status = if new { "event received" } else { "event already received" };
+    Ok(HttpResponse::Ok().json(json!({"status": status})))
+}
+
+//returns charges, payments and balance of every booking with a folio, largest balance first
+#[get("/analytics/folios/balances")]
+async fn get_folio_balances(pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let result = db::run(&pool, |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT e.booking_id, b.guest_id, b.status, e.currency,
+                    SUM(CASE WHEN e.kind NOT IN ('payment', 'refund') THEN e.amount_minor ELSE 0 END),
+                    -SUM(CASE WHEN e.kind IN ('payment', 'refund') THEN e.amount_minor ELSE 0 END),
+                    SUM(e.amount_minor) AS balance, b.reservation_id
+             FROM folio_entries e
+             JOIN bookings b ON b.id = e.booking_id
+             GROUP BY e.booking_id, e.currency
+             ORDER BY balance DESC, e.booking_id"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let currency: Currency = row.get(3)?;
+            Ok(json!({
+                "booking_id": row.get::<_, String>(0)?,
+                "guest_id": row.get::<_, String>(1)?,
+                "status": row.get::<_, BookingStatus>(2)?,
+                "reservation_id": row.get::<_, Option<String>>(7)?,
+                "charges": Money::new(row.get(4)?, currency),
+                "payments": Money::new(row.get(5)?, currency),
+                "balance": Money::new(row.get(6)?, currency),
+            }))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+//returns the folio totals of every group reservation across its room-stays, largest balance first
+#[get("/analytics/reservations/balances")]
+async fn get_reservation_balances(pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let result = db::run(&pool, |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT r.id, r.name, r.billing_guest_id, e.currency, COUNT(DISTINCT e.booking_id),
+                    SUM(CASE WHEN e.kind NOT IN ('payment', 'refund') THEN e.amount_minor ELSE 0 END),
+                    -SUM(CASE WHEN e.kind IN ('payment', 'refund') THEN e.amount_minor ELSE 0 END),
+                    SUM(e.amount_minor) AS balance
+             FROM folio_entries e
+             JOIN bookings b ON b.id = e.booking_id
+             JOIN reservations r ON r.id = b.reservation_id
+             GROUP BY r.id, e.currency
+             ORDER BY balance DESC, r.id"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let currency: Currency = row.get(3)?;
+            Ok(json!({
+                "reservation_id": row.get::<_, String>(0)?,
+                "name": row.get::<_, Option<String>>(1)?,
+                "billing_guest_id": row.get::<_, String>(2)?,
+                "rooms": row.get::<_, i64>(4)?,
+                "charges": Money::new(row.get(5)?, currency),
+                "payments": Money::new(row.get(6)?, currency),
+                "balance": Money::new(row.get(7)?, currency),
+            }))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+
+
+
+
+pub fn config(cfg: &mut web::ServiceConfig, features: &Features) {
+    //malformed bodies, queries and paths get the same JSON error shape as the handlers
+    cfg.app_data(web::JsonConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()))
+       .app_data(web::QueryConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()))
+       .app_data(web::PathConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()));
 
-pub fn config(cfg: &mut web::ServiceConfig) {
     //hotels
     cfg.service(create_hotel)
        .service(get_hotels)
@@ -591,12 +3823,13 @@ pub fn config(cfg: &mut web::ServiceConfig) {
        .service(get_hotel_by_id)
        .service(update_hotel)
        .service(delete_hotel)
-       
+
        // Rooms
         .service(create_room)
         .service(get_rooms)
         .service(get_room_by_id)
         .service(update_room)
+        .service(update_room_housekeeping)
         .service(delete_room)
         .service(count_available_rooms)
 
@@ -616,21 +3849,853 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         .service(get_bookings)
         .service(get_booking_by_id)
         .service(update_booking)
-        .service(get_average_stay_duration)
-        .service(get_current_or_last_hotel_by_guest)
+        .service(modify_booking)
+        .service(get_booking_amendments)
+        .service(confirm_booking)
+        .service(check_in_booking)
+        .service(check_out_booking)
+        .service(cancel_booking)
+        .service(no_show_booking)
+        .service(get_booking_status_history)
+        .service(get_booking_nights)
+        .service(get_booking_invoice)
+        .service(get_booking_folio)
+        .service(post_folio_charge)
+        .service(reverse_folio_entry)
         .service(delete_booking)
 
 
+        //reservations
+        .service(create_reservation)
+        .service(get_reservations)
+        .service(get_reservation_by_id)
+        .service(cancel_reservation)
+        .service(get_reservation_payments)
+
+
+        //room blocks
+        .service(create_room_block)
+        .service(get_room_blocks)
+        .service(get_room_block_by_id)
+        .service(update_room_block)
+        .service(release_room_block)
+        .service(delete_room_block)
+
+
+        //waitlist, offers before {id} so the path is not taken for an entry id
+        .service(join_waitlist)
+        .service(get_waitlist)
+        .service(get_waitlist_offers)
+        .service(get_waitlist_entry_by_id)
+        .service(withdraw_from_waitlist)
+        .service(accept_waitlist_offer)
+        .service(decline_waitlist_offer)
+
+
+        //quotes
+        .service(create_quote)
+        .service(get_quote_by_id)
+
+
+        //rate plans
+        .service(create_rate_plan)
+        .service(get_rate_plans)
+        .service(get_rate_plan_by_id)
+        .service(update_rate_plan)
+        .service(delete_rate_plan)
+        .service(create_rate_season)
+        .service(get_rate_seasons)
+        .service(update_rate_season)
+        .service(delete_rate_season)
+
+
+        //cancellation policies
+        .service(create_cancellation_policy)
+        .service(get_cancellation_policies)
+        .service(get_cancellation_policy_by_id)
+        .service(update_cancellation_policy)
+        .service(delete_cancellation_policy)
+
+
+        //tax rules
+        .service(create_tax_rule)
+        .service(get_tax_rules)
+        .service(update_tax_rule)
+        .service(delete_tax_rule)
+
+
+        //promotions
+        .service(create_promotion)
+        .service(get_promotions)
+        .service(get_promotion_by_id)
+        .service(update_promotion)
+        .service(delete_promotion)
+        .service(get_promotion_redemptions)
+
+
+        //exchange rates
+        .service(get_exchange_rates)
+
+
         //payments
         .service(create_payment)
         .service(get_payments)
         .service(get_payment_by_id)
+        .service(receive_payment_webhook)
         .service(update_payment)
-        .service(delete_payment)
-        .service(get_total_paid_per_booking);
-       
-       
+        .service(capture_payment)
+        .service(refund_payment)
+        .service(get_payment_refunds)
+        .service(void_payment)
+        .service(get_payment_events);
+
+    // Availability
+    if features.availability_search {
+        cfg.service(search_availability);
+    }
+
+    // Analytics
+    if features.analytics {
+        cfg.service(get_average_stay_duration)
+           .service(get_current_or_last_hotel_by_guest)
+           .service(get_folio_balances)
+           .service(get_reservation_balances);
+    }
+
+
 }
 
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::config::MockGatewayConfig;
+    use crate::gateway::MockGateway;
+
+    //a pool over the test hotel
+    fn hotel() -> DbPool {
+        let pool = db::test_pool();
+        db::seed_hotel(&pool.get().unwrap());
+        pool
+    }
+
+    //sends `req` through the app as main.rs builds it; returns the status and the JSON body, null when there is none
+    async fn send(pool: &DbPool, req: TestRequest) -> (StatusCode, Value) {
+        let gateway: Arc<dyn PaymentGateway> = Arc::new(MockGateway::new(MockGatewayConfig::default()));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::from(gateway))
+                .app_data(web::Data::new(ModificationRates::Current))
+                .app_data(web::Data::new(HoldWindow(60)))
+                .configure(|cfg| config(cfg, &Features::default())),
+        ).await;
+        let res = call_service(&app, req.to_request()).await;
+        let status = res.status();
+        let body = read_body(res).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    async fn get(pool: &DbPool, uri: &str) -> (StatusCode, Value) {
+        send(pool, TestRequest::get().uri(uri)).await
+    }
+
+    async fn post(pool: &DbPool, uri: &str, body: Value) -> (StatusCode, Value) {
+        send(pool, TestRequest::post().uri(uri).set_json(body)).await
+    }
+
+    fn exec(pool: &DbPool, sql: &str) {
+        pool.get().unwrap().execute_batch(sql).unwrap();
+    }
+
+    //rooms offered per room type, with the lowest price
+    fn offered(body: &Value) -> Vec<(String, Vec<String>, String)> {
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|group| {
+                let rooms = group["room_ids"].as_array().unwrap().iter().map(|id| id.as_str().unwrap().to_string()).collect();
+                (group["room_type"].as_str().unwrap().to_string(), rooms, group["lowest_price"].as_str().unwrap().to_string())
+            })
+            .collect()
+    }
+
+    fn rooms(type_rooms: &[(&str, &[&str], &str)]) -> Vec<(String, Vec<String>, String)> {
+        type_rooms
+            .iter()
+            .map(|(room_type, ids, price)| (room_type.to_string(), ids.iter().map(|id| id.to_string()).collect(), price.to_string()))
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn offers_the_cheapest_free_rooms_blocks_leave() {
+        let pool = hotel();
+        //r1 is booked and a block holds one of the doubles still free, r3 is the cheapest left
+        exec(&pool, "
+            UPDATE rooms SET price_minor = 16000 WHERE id = 'r2';
+            INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, status)
+            VALUES ('b1', 'g1', 'r1', 'h1', '2027-03-01', '2027-03-03', 'confirmed');
+            INSERT INTO room_blocks (id, hotel_id, room_type, code, name, start_date, end_date, rooms, cutoff_date)
+            VALUES ('k1', 'h1', 'double', 'FAIR', 'Fair', '2027-03-01', '2027-03-03', 1, '2027-02-01');
+        ");
+        let (status, body) = get(&pool, "/availability?check_in=2027-03-01&check_out=2027-03-03").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(offered(&body), rooms(&[("double", &["r3"], "150.00 EUR"), ("suite", &["s1"], "250.00 EUR")]));
+
+        //with r1 free again the block takes one of three, the two cheapest are offered
+        exec(&pool, "UPDATE bookings SET status = 'cancelled'");
+        let (_, body) = get(&pool, "/availability?check_in=2027-03-01&check_out=2027-03-03&room_type=double").await;
+        assert_eq!(offered(&body), rooms(&[("double", &["r1", "r3"], "100.00 EUR")]));
+    }
+
+    //books `room_id` for g1 from `check_in` to `check_out`; returns the booking id
+    async fn book(pool: &DbPool, room_id: &str, check_in: &str, check_out: &str) -> String {
+        let booking = json!({"guest_id": "g1", "room_id": room_id, "hotel_id": "h1", "check_in": check_in, "check_out": check_out});
+        let (status, body) = post(pool, "/bookings", booking).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    //pays `amount` towards a booking, by card when a number is given; returns the payment id
+    async fn pay(pool: &DbPool, booking_id: &str, amount: &str, card: Option<&str>, authorize_only: bool) -> String {
+        let payment = json!({
+            "booking_id": booking_id, "amount": amount, "method": if card.is_some() { "card" } else { "cash" },
+            "card_number": card, "authorize_only": authorize_only,
+        });
+        let (status, body) = post(pool, "/payments", payment).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    const CARD: &str = "4242424242424242";
+
+    fn count(pool: &DbPool, sql: &str) -> i64 {
+        pool.get().unwrap().query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    //a reservation of g1 for `rooms` from Monday 2027-03-01 to 2027-03-03; returns the body of the response
+    async fn reserve(pool: &DbPool, rooms: &[&str]) -> (StatusCode, Value) {
+        let stays: Vec<Value> = rooms
+            .iter()
+            .map(|room_id| json!({"room_id": room_id, "check_in": "2027-03-01", "check_out": "2027-03-03"}))
+            .collect();
+        post(pool, "/reservations", json!({"hotel_id": "h1", "billing_guest_id": "g1", "name": "Party", "stays": stays})).await
+    }
+
+    #[actix_web::test]
+    async fn books_every_stay_of_a_reservation_or_none() {
+        let pool = hotel();
+        let booking = json!({"guest_id": "g2", "room_id": "r2", "hotel_id": "h1", "check_in": "2027-03-02", "check_out": "2027-03-04"});
+        assert_eq!(post(&pool, "/bookings", booking).await.0, StatusCode::OK);
+        let (status, body) = reserve(&pool, &["r1", "r2"]).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["details"]["stay"], 1);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM reservations"), 0);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM bookings"), 1);
+
+        let (status, body) = reserve(&pool, &["r1", "r3"]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["totals"]["total_price"], "500.00 EUR");
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM bookings WHERE reservation_id IS NOT NULL AND status = 'tentative'"), 2);
+    }
+
+    #[actix_web::test]
+    async fn cancels_a_stay_listed_twice_once() {
+        let pool = hotel();
+        let (_, body) = reserve(&pool, &["r1", "r2", "r3"]).await;
+        let id = body["id"].as_str().unwrap();
+        let stays: Vec<&str> = body["stays"].as_array().unwrap().iter().map(|s| s["booking_id"].as_str().unwrap()).collect();
+
+        let (status, body) = post(&pool, &format!("/reservations/{id}/cancel"), json!({"booking_ids": [stays[2], stays[0], stays[2]]})).await;
+        assert_eq!(status, StatusCode::OK);
+        let cancelled: Vec<&str> = body["cancelled"].as_array().unwrap().iter().map(|c| c["booking_id"].as_str().unwrap()).collect();
+        assert_eq!(cancelled, [stays[2], stays[0]]);
+        assert_eq!((body["status"].as_str(), body["remaining"].as_i64()), (Some("room-stays cancelled"), Some(1)));
+
+        let (status, body) = post(&pool, &format!("/reservations/{id}/cancel"), json!({"booking_ids": ["b9"]})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::UNPROCESSABLE_ENTITY, Some("validation_failed")));
+    }
+
+    #[actix_web::test]
+    async fn refunds_what_each_stay_was_paid_beyond_its_penalty() {
+        let pool = hotel();
+        //cancelling never is free and costs the first night
+        exec(&pool, "
+            INSERT INTO cancellation_policies (id, name, free_until_days, penalty) VALUES ('strict', 'Strict', NULL, 'first_night');
+            UPDATE hotels SET cancellation_policy_id = 'strict';
+        ");
+        let (_, body) = reserve(&pool, &["r1", "r2"]).await;
+        let id = body["id"].as_str().unwrap();
+        let first = body["stays"][0]["booking_id"].as_str().unwrap();
+        let payment = json!({"booking_id": first, "amount": "300.00 EUR", "method": "cash"});
+        assert_eq!(post(&pool, "/payments", payment).await.0, StatusCode::OK);
+
+        //r1 was paid 300.00 and keeps 100.00 of it, nothing was paid for r2
+        let (_, body) = get(&pool, &format!("/reservations/{id}")).await;
+        assert_eq!((body["totals"]["balance"].as_str(), body["totals"]["refundable"].as_str()), (Some("140.00 EUR"), Some("200.00 EUR")));
+
+        let (_, body) = post(&pool, &format!("/reservations/{id}/cancel"), json!({})).await;
+        assert_eq!(body["status"], "reservation cancelled");
+        assert_eq!(body["totals"]["cancellation_fees"], "220.00 EUR");
+        assert_eq!(body["totals"]["refundable"], "200.00 EUR");
+    }
+
+    //paid and balance due on the invoice of a booking
+    async fn invoice(pool: &DbPool, booking_id: &str) -> (String, String) {
+        let (_, body) = get(pool, &format!("/bookings/{booking_id}/invoice")).await;
+        (body["paid"].as_str().unwrap().to_string(), body["balance_due"].as_str().unwrap().to_string())
+    }
+
+    #[actix_web::test]
+    async fn invoices_what_reached_the_folio_as_paid() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let hold = pay(&pool, &booking, "150.00 EUR", Some(CARD), true).await;
+        pay(&pool, &booking, "50.00 EUR", None, false).await;
+
+        //a card hold has not paid anything yet
+        assert_eq!(invoice(&pool, &booking).await, ("50.00 EUR".into(), "150.00 EUR".into()));
+
+        assert_eq!(post(&pool, &format!("/payments/{hold}/capture"), json!({})).await.0, StatusCode::OK);
+        assert_eq!(invoice(&pool, &booking).await, ("200.00 EUR".into(), "0.00 EUR".into()));
+
+        let refund = json!({"amount": "30.00 EUR", "reason": "late breakfast"});
+        assert_eq!(post(&pool, &format!("/payments/{hold}/refund"), refund).await.0, StatusCode::OK);
+        assert_eq!(invoice(&pool, &booking).await, ("170.00 EUR".into(), "30.00 EUR".into()));
+    }
+
+    //status and captured amount of a payment
+    fn payment_state(pool: &DbPool, id: &str) -> (String, i64, Option<String>) {
+        pool.get()
+            .unwrap()
+            .query_row("SELECT status, tendered_minor, gateway_call FROM payments WHERE id = ?1", [id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+    }
+
+    //cancelling never is free and costs the first night
+    const STRICT: &str = "
+        INSERT INTO cancellation_policies (id, name, free_until_days, penalty) VALUES ('strict', 'Strict', NULL, 'first_night');
+        UPDATE hotels SET cancellation_policy_id = 'strict';
+    ";
+
+    #[actix_web::test]
+    async fn closes_card_holds_after_the_status_change() {
+        let pool = hotel();
+        exec(&pool, STRICT);
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let first = pay(&pool, &booking, "60.00 EUR", Some(CARD), true).await;
+        let second = pay(&pool, &booking, "80.00 EUR", Some(CARD), true).await;
+        let third = pay(&pool, &booking, "30.00 EUR", Some(CARD), true).await;
+
+        //the 100.00 fee takes all of the first hold and the rest from the second, the third is let go
+        let (status, body) = post(&pool, &format!("/bookings/{booking}/cancel"), json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["card_holds"], json!([
+            {"payment_id": first, "captured": "60.00 EUR"},
+            {"payment_id": second, "captured": "40.00 EUR"},
+            {"payment_id": third, "released": true},
+        ]));
+        assert_eq!(payment_state(&pool, &first), ("captured".into(), 6000, None));
+        assert_eq!(payment_state(&pool, &second), ("captured".into(), 4000, None));
+        assert_eq!(payment_state(&pool, &third), ("voided".into(), 3000, None));
+        assert_eq!(invoice(&pool, &booking).await.0, "100.00 EUR");
+    }
+
+    #[actix_web::test]
+    async fn keeps_the_status_change_when_the_gateway_fails_on_a_hold() {
+        let pool = hotel();
+        exec(&pool, STRICT);
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let hold = pay(&pool, &booking, "150.00 EUR", Some(CARD), true).await;
+        exec(&pool, "UPDATE payments SET gateway_reference = 'lost'");
+
+        let (status, body) = post(&pool, &format!("/bookings/{booking}/cancel"), json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["card_holds"], json!([{"payment_id": hold, "error": "unknown_reference"}]));
+        assert_eq!(body["status"], "cancelled");
+        assert_eq!(payment_state(&pool, &hold), ("authorized".into(), 15000, None));
+    }
+
+    #[actix_web::test]
+    async fn leaves_a_booking_alone_while_a_hold_is_busy() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let hold = pay(&pool, &booking, "150.00 EUR", Some(CARD), true).await;
+        exec(&pool, "UPDATE payments SET gateway_call = 'capture', gateway_call_at = datetime('now')");
+
+        let (status, body) = post(&pool, &format!("/bookings/{booking}/cancel"), json!({})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("payment_busy")));
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM bookings WHERE status = 'cancelled'"), 0);
+        assert_eq!(payment_state(&pool, &hold).0, "authorized");
+    }
+
+    #[actix_web::test]
+    async fn books_a_refund_the_gateway_made_instead_of_refunding_again() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let payment = pay(&pool, &booking, "100.00 EUR", Some(CARD), false).await;
+        //the gateway refunded 30.00 but booking it failed
+        exec(&pool, &format!("
+            INSERT INTO payment_refunds (payment_id, amount_minor, currency, settled_minor, settled_currency, reason, status,
+                                         gateway_key, gateway_reference)
+            VALUES ('{payment}', 3000, 'EUR', 3000, 'EUR', 'Minibar', 'pending', 'k1', 'mock_re_1');
+        "));
+        assert_eq!(invoice(&pool, &booking).await.0, "100.00 EUR");
+
+        let (status, body) = post(&pool, &format!("/payments/{payment}/void"), json!({"reason": "Mistake"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("refund_pending")));
+
+        let (status, body) = post(&pool, &format!("/payments/{payment}/refund"), json!({"amount": "50.00 EUR", "reason": "Noise"})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["refund"]["amount"], "30.00 EUR");
+        assert_eq!(body["refund"]["gateway_reference"], "mock_re_1");
+        assert_eq!(body["refundable"], "70.00 EUR");
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM payment_refunds WHERE status = 'completed'"), 1);
+        assert_eq!(payment_state(&pool, &payment), ("partially_refunded".into(), 10000, None));
+        assert_eq!(invoice(&pool, &booking).await.0, "70.00 EUR");
+    }
+
+    #[actix_web::test]
+    async fn keeps_no_refund_the_gateway_declined() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let payment = pay(&pool, &booking, "100.00 EUR", Some(CARD), false).await;
+        exec(&pool, "UPDATE payments SET gateway_reference = 'other_1'");
+
+        let (status, body) = post(&pool, &format!("/payments/{payment}/refund"), json!({"amount": "50.00 EUR", "reason": "Noise"})).await;
+        assert_eq!(body["code"], "unknown_reference", "{status}");
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM payment_refunds"), 0);
+        assert_eq!(payment_state(&pool, &payment), ("captured".into(), 10000, None));
+    }
+
+    #[actix_web::test]
+    async fn records_a_void_the_gateway_made_before_anything_else() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let payment = pay(&pool, &booking, "100.00 EUR", Some(CARD), false).await;
+        //the gateway voided the payment but recording it failed
+        exec(&pool, "UPDATE payments SET gateway_call = 'void', gateway_call_at = datetime('now', '-1 hour'), gateway_call_done = 1");
+
+        let (status, body) = post(&pool, &format!("/payments/{payment}/refund"), json!({"amount": "50.00 EUR", "reason": "Noise"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("payment_unsettled")));
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM payment_refunds"), 0);
+
+        let (status, _) = post(&pool, &format!("/payments/{payment}/void"), json!({"reason": "Mistake"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(payment_state(&pool, &payment), ("voided".into(), 10000, None));
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM payments WHERE gateway_call_done = 1"), 0);
+        assert_eq!(invoice(&pool, &booking).await.0, "0.00 EUR");
+    }
+
+    #[actix_web::test]
+    async fn captures_part_of_a_hold_but_never_more() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let hold = pay(&pool, &booking, "150.00 EUR", Some(CARD), true).await;
+
+        let (status, body) = post(&pool, &format!("/payments/{hold}/capture"), json!({"amount": "150.01 EUR"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("capture_exceeds_authorization")));
+        assert_eq!(payment_state(&pool, &hold), ("authorized".into(), 15000, None));
+
+        let (status, body) = post(&pool, &format!("/payments/{hold}/capture"), json!({"amount": "100.00 EUR"})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["settled_amount"], "100.00 EUR");
+        assert_eq!(payment_state(&pool, &hold), ("captured".into(), 10000, None));
+        assert_eq!(invoice(&pool, &booking).await, ("100.00 EUR".into(), "100.00 EUR".into()));
+
+        let (status, body) = post(&pool, &format!("/payments/{hold}/capture"), json!({})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("payment_not_authorized")));
+    }
+
+    #[actix_web::test]
+    async fn refunds_a_payment_in_parts_up_to_what_was_paid() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let payment = pay(&pool, &booking, "100.00 EUR", Some(CARD), false).await;
+        let refund = |amount: &str| json!({"amount": amount, "reason": "Noise"});
 
+        let (status, body) = post(&pool, &format!("/payments/{payment}/refund"), refund("40.00 EUR")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!((body["status"].as_str(), body["refundable"].as_str()), (Some("partially_refunded"), Some("60.00 EUR")));
+        assert!(body["refund"]["gateway_reference"].as_str().unwrap().starts_with("mock_re_"));
 
+        let (status, body) = post(&pool, &format!("/payments/{payment}/refund"), refund("60.01 EUR")).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("refund_exceeds_payment")));
+
+        let (status, body) = post(&pool, &format!("/payments/{payment}/refund"), refund("60.00 EUR")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!((body["status"].as_str(), body["refundable"].as_str()), (Some("refunded"), Some("0.00 EUR")));
+        assert_eq!(get(&pool, &format!("/payments/{payment}/refunds")).await.1.as_array().unwrap().len(), 2);
+        assert_eq!(invoice(&pool, &booking).await.0, "0.00 EUR");
+    }
+
+    #[actix_web::test]
+    async fn voids_a_hold_that_cannot_be_refunded() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let hold = pay(&pool, &booking, "150.00 EUR", Some(CARD), true).await;
+        let refund = json!({"amount": "50.00 EUR", "reason": "Noise"});
+
+        let (status, body) = post(&pool, &format!("/payments/{hold}/refund"), refund.clone()).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("payment_not_captured")));
+
+        let (status, _) = post(&pool, &format!("/payments/{hold}/void"), json!({"reason": "Mistake"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(payment_state(&pool, &hold), ("voided".into(), 15000, None));
+
+        let (status, body) = post(&pool, &format!("/payments/{hold}/void"), json!({"reason": "Mistake"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("payment_not_voidable")));
+        let (status, body) = post(&pool, &format!("/payments/{hold}/refund"), refund).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("payment_voided")));
+    }
+
+    #[actix_web::test]
+    async fn rejects_overlapping_stays_of_a_room_but_not_back_to_back_ones() {
+        let pool = hotel();
+        let first = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let stay = |room_id: &str, check_in: &str, check_out: &str| {
+            json!({"guest_id": "g2", "room_id": room_id, "hotel_id": "h1", "check_in": check_in, "check_out": check_out})
+        };
+
+        let (status, body) = post(&pool, "/bookings", stay("r1", "2027-03-02", "2027-03-04")).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("booking_conflict")));
+        let clashes: Vec<_> = body["details"]["conflicts"].as_array().unwrap().iter().map(|b| b["id"].as_str().unwrap()).collect();
+        assert_eq!(clashes, [first.as_str()]);
+
+        //the check-out day of one stay is the check-in day of the next
+        let after = book(&pool, "r1", "2027-03-03", "2027-03-05").await;
+        book(&pool, "r1", "2027-02-27", "2027-03-01").await;
+        assert_eq!(post(&pool, "/bookings", stay("r2", "2027-03-02", "2027-03-04")).await.0, StatusCode::OK);
+
+        //moving a booking onto another is refused, moving it within its own dates is not
+        let (status, body) = send(&pool, TestRequest::put().uri(&format!("/bookings/{after}")).set_json(stay("r1", "2027-03-02", "2027-03-05"))).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("booking_conflict")));
+        let (status, body) = send(&pool, TestRequest::put().uri(&format!("/bookings/{after}")).set_json(stay("r1", "2027-03-03", "2027-03-04"))).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        //a cancelled stay frees its nights
+        assert_eq!(post(&pool, &format!("/bookings/{first}/cancel"), json!({})).await.0, StatusCode::OK);
+        book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+    }
+
+    #[actix_web::test]
+    async fn finds_the_rooms_free_for_the_whole_stay_in_every_hotel() {
+        let pool = hotel();
+        //r1 is left the day the stay starts, r2 is taken, r3's booking was cancelled and s1 is out of order
+        exec(&pool, "
+            INSERT INTO hotels (id, name, location, stars, currency) VALUES ('h2', 'Inn', 'Porto', 3, 'EUR');
+            INSERT INTO rooms (id, hotel_id, room_type, price_minor, currency) VALUES ('p1', 'h2', 'double', 8000, 'EUR');
+            INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, status) VALUES
+                ('b1', 'g1', 'r1', 'h1', '2027-02-27', '2027-03-01', 'checked_out'),
+                ('b2', 'g2', 'r2', 'h1', '2027-03-02', '2027-03-05', 'confirmed'),
+                ('b3', 'g3', 'r3', 'h1', '2027-03-01', '2027-03-03', 'cancelled');
+            UPDATE rooms SET housekeeping = 'out_of_order' WHERE id = 's1';
+        ");
+        let search = |filters: &str| format!("/availability?check_in=2027-03-01&check_out=2027-03-03{filters}");
+        let hotels = |body: &Value| -> Vec<(String, i64)> {
+            body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|group| (group["hotel_id"].as_str().unwrap().to_string(), group["available_rooms"].as_i64().unwrap()))
+                .collect()
+        };
+
+        let (status, body) = get(&pool, &search("")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hotels(&body), [("h1".to_string(), 2), ("h2".to_string(), 1)]);
+        assert_eq!(offered(&body), rooms(&[("double", &["r1", "r3"], "100.00 EUR"), ("double", &["p1"], "80.00 EUR")]));
+
+        assert_eq!(hotels(&get(&pool, &search("&min_stars=4")).await.1), [("h1".to_string(), 2)]);
+        assert_eq!(hotels(&get(&pool, &search("&location=Port")).await.1), [("h2".to_string(), 1)]);
+        assert_eq!(hotels(&get(&pool, &search("&hotel_id=h2&room_type=double")).await.1), [("h2".to_string(), 1)]);
+        assert!(hotels(&get(&pool, &search("&room_type=suite")).await.1).is_empty());
+
+        let (status, body) = get(&pool, "/availability?check_in=2027-03-03&check_out=2027-03-03").await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::UNPROCESSABLE_ENTITY, Some("validation_failed")));
+    }
+
+    #[actix_web::test]
+    async fn takes_a_booking_through_its_lifecycle_and_records_each_step() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+
+        let (status, body) = post(&pool, &format!("/bookings/{booking}/check-in"), json!({})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("illegal_transition")));
+        assert_eq!(body["details"]["status"], "tentative");
+
+        for step in ["confirm", "check-in", "check-out"] {
+            let (status, body) = post(&pool, &format!("/bookings/{booking}/{step}"), json!({})).await;
+            assert_eq!(status, StatusCode::OK, "{step}: {body}");
+        }
+        let (status, body) = post(&pool, &format!("/bookings/{booking}/cancel"), json!({})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("illegal_transition")));
+
+        let (_, history) = get(&pool, &format!("/bookings/{booking}/status-history")).await;
+        let steps: Vec<_> = history
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|change| (change["from_status"].as_str(), change["to_status"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            steps,
+            [(None, "tentative"), (Some("tentative"), "confirmed"), (Some("confirmed"), "checked_in"), (Some("checked_in"), "checked_out")],
+        );
+        assert!(history.as_array().unwrap().iter().all(|change| change["changed_at"].is_string()));
+    }
+
+    #[actix_web::test]
+    async fn derives_room_status_from_stays_and_housekeeping() {
+        let pool = hotel();
+        async fn room(pool: &DbPool) -> (String, String) {
+            let (_, body) = get(pool, "/rooms/r1").await;
+            (body["status"].as_str().unwrap().to_string(), body["housekeeping"].as_str().unwrap().to_string())
+        }
+        async fn available(pool: &DbPool) -> (i64, i64) {
+            let (_, body) = get(pool, "/rooms/available/count").await;
+            (body["available_rooms"].as_i64().unwrap(), body["ready_rooms"].as_i64().unwrap())
+        }
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let other = book(&pool, "r1", "2027-03-03", "2027-03-05").await;
+        assert_eq!(room(&pool).await, ("available".into(), "clean".into()));
+
+        post(&pool, &format!("/bookings/{booking}/confirm"), json!({})).await;
+        assert_eq!(post(&pool, &format!("/bookings/{booking}/check-in"), json!({})).await.0, StatusCode::OK);
+        assert_eq!(room(&pool).await, ("occupied".into(), "clean".into()));
+        assert_eq!(available(&pool).await, (3, 3));
+
+        //nobody moves into a room that is still occupied
+        post(&pool, &format!("/bookings/{other}/confirm"), json!({})).await;
+        let (status, body) = post(&pool, &format!("/bookings/{other}/check-in"), json!({})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("room_occupied")));
+
+        //a room the guest left is free again but has to be cleaned first
+        assert_eq!(post(&pool, &format!("/bookings/{booking}/check-out"), json!({})).await.0, StatusCode::OK);
+        assert_eq!(room(&pool).await, ("available".into(), "dirty".into()));
+        assert_eq!(available(&pool).await, (4, 3));
+
+        let out_of_order = json!({"housekeeping": "out_of_order"});
+        assert_eq!(send(&pool, TestRequest::put().uri("/rooms/r1/housekeeping").set_json(out_of_order)).await.0, StatusCode::OK);
+        assert_eq!(room(&pool).await, ("out_of_order".into(), "out_of_order".into()));
+        assert_eq!(available(&pool).await, (3, 3));
+        let (status, body) = post(&pool, &format!("/bookings/{other}/check-in"), json!({})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("room_out_of_order")));
+    }
+
+    #[actix_web::test]
+    async fn rejects_a_bad_payload_with_every_field_error_before_touching_the_database() {
+        let pool = hotel();
+        let hotel = json!({"name": "", "location": "Porto", "stars": 7, "currency": "EUR"});
+        let (status, body) = post(&pool, "/hotels", hotel.clone()).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::UNPROCESSABLE_ENTITY, Some("validation_failed")));
+        assert_eq!(
+            body["details"],
+            json!([{"field": "name", "message": "must not be blank"}, {"field": "stars", "message": "must be between 1 and 5"}]),
+        );
+        let (status, body) = send(&pool, TestRequest::put().uri("/hotels/h1").set_json(hotel)).await;
+        assert_eq!((status, body["details"].as_array().map(Vec::len)), (StatusCode::UNPROCESSABLE_ENTITY, Some(2)));
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM hotels WHERE name = 'Hotel' AND stars = 4"), 1);
+
+        let booking = json!({"guest_id": "g1", "room_id": "r1", "hotel_id": "h1", "check_in": "2027-03-03", "check_out": "2027-03-01", "guests": 0});
+        let (status, body) = post(&pool, "/bookings", booking).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let fields: Vec<_> = body["details"].as_array().unwrap().iter().map(|error| error["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, ["check_out", "guests"]);
+
+        let payment = json!({"booking_id": "b1", "amount": "-5.00 EUR", "method": "cash"});
+        let (status, body) = post(&pool, "/payments", payment).await;
+        assert_eq!((status, body["details"][0]["field"].as_str()), (StatusCode::UNPROCESSABLE_ENTITY, Some("amount")));
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM bookings") + count(&pool, "SELECT COUNT(*) FROM payments"), 0);
+    }
+
+    #[actix_web::test]
+    async fn settles_foreign_payments_at_the_rate_of_their_day() {
+        let pool = hotel();
+        exec(&pool, "
+            INSERT INTO exchange_rates (valid_on, base, quote, rate) VALUES
+                ('2026-01-01', 'EUR', 'USD', '1.1'), ('2027-02-01', 'EUR', 'USD', '1.25'), ('2027-02-20', 'EUR', 'USD', '1.2');
+        ");
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+
+        let payment = json!({"booking_id": booking, "amount": "100.00 USD", "method": "cash", "paid_on": "2027-02-15"});
+        let (status, body) = post(&pool, "/payments", payment).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (_, payment) = get(&pool, &format!("/payments/{}", body["id"].as_str().unwrap())).await;
+        assert_eq!((payment["amount"].as_str(), payment["settled_amount"].as_str()), (Some("100.00 USD"), Some("80.00 EUR")));
+        assert_eq!(payment["exchange_rate"], "0.8");
+        assert_eq!(invoice(&pool, &booking).await, ("80.00 EUR".into(), "120.00 EUR".into()));
+
+        let payment = json!({"booking_id": booking, "amount": "100.00 GBP", "method": "cash"});
+        let (status, body) = post(&pool, "/payments", payment).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("exchange_rate_missing")));
+
+        //guests see prices in their own currency at today's rate as well
+        let (_, body) = get(&pool, "/availability?check_in=2027-03-01&check_out=2027-03-03&room_type=suite&currency=USD").await;
+        assert_eq!((body["results"][0]["lowest_price"].as_str(), body["results"][0]["display_price"].as_str()), (Some("250.00 EUR"), Some("275.00 USD")));
+    }
+
+    #[actix_web::test]
+    async fn keeps_the_folio_balance_the_sum_of_its_entries() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        async fn folio(pool: &DbPool, booking_id: &str) -> Value {
+            get(pool, &format!("/bookings/{booking_id}/folio")).await.1
+        }
+        let totals = |folio: &Value| {
+            [&folio["charges"], &folio["payments"], &folio["balance"]].map(|total| total.as_str().unwrap().to_string())
+        };
+
+        post(&pool, &format!("/bookings/{booking}/confirm"), json!({})).await;
+        post(&pool, &format!("/bookings/{booking}/check-in"), json!({})).await;
+        let charge = json!({"kind": "minibar", "description": "Water", "amount": "12.50 EUR"});
+        let (status, body) = post(&pool, &format!("/bookings/{booking}/folio/charges"), charge).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let minibar = body["id"].as_i64().unwrap();
+        pay(&pool, &booking, "100.00 EUR", None, false).await;
+
+        let entries = folio(&pool, &booking).await;
+        let kinds: Vec<_> = entries["entries"].as_array().unwrap().iter().map(|entry| entry["kind"].as_str().unwrap()).collect();
+        assert_eq!(kinds, ["room", "room", "minibar", "payment"]);
+        assert_eq!(totals(&entries), ["212.50 EUR", "100.00 EUR", "112.50 EUR"]);
+
+        //entries are never changed, a reversal posts their opposite once
+        let reverse = |entry_id: i64| format!("/bookings/{booking}/folio/entries/{entry_id}/reverse");
+        let (status, body) = post(&pool, &reverse(minibar), json!({"reason": "Not taken"})).await;
+        let reversal = &body["reversal"];
+        assert_eq!((status, reversal["amount"].as_str()), (StatusCode::OK, Some("-12.50 EUR")));
+        assert_eq!(totals(&folio(&pool, &booking).await), ["200.00 EUR", "100.00 EUR", "100.00 EUR"]);
+
+        let (status, body) = post(&pool, &reverse(minibar), json!({"reason": "Again"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("already_reversed")));
+        let (status, body) = post(&pool, &reverse(reversal["id"].as_i64().unwrap()), json!({"reason": "Taken after all"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("reversal_is_final")));
+        let payment_entry = folio(&pool, &booking).await["entries"][3]["id"].as_i64().unwrap();
+        let (status, body) = post(&pool, &reverse(payment_entry), json!({"reason": "Bounced"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("payment_entry")));
+
+        let (_, balances) = get(&pool, "/analytics/folios/balances").await;
+        assert_eq!(balances.as_array().unwrap().len(), 1);
+        assert_eq!(totals(&balances[0]), ["200.00 EUR", "100.00 EUR", "100.00 EUR"]);
+    }
+
+    #[actix_web::test]
+    async fn refunds_a_foreign_payment_down_to_the_last_cent_it_settled() {
+        let pool = hotel();
+        exec(&pool, "INSERT INTO exchange_rates (valid_on, base, quote, rate) VALUES ('2027-02-01', 'USD', 'EUR', '0.3333')");
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let payment = json!({"booking_id": booking, "amount": "100.00 USD", "method": "cash", "paid_on": "2027-02-15"});
+        let payment = post(&pool, "/payments", payment).await.1["id"].as_str().unwrap().to_string();
+        let refund = |reason: &str| json!({"amount": "50.00 USD", "reason": reason});
+
+        let (_, first) = post(&pool, &format!("/payments/{payment}/refund"), refund("Noise")).await;
+        assert_eq!(first["refund"]["settled_amount"], "16.67 EUR");
+        let (_, last) = post(&pool, &format!("/payments/{payment}/refund"), refund("Heating")).await;
+        assert_eq!((last["status"].as_str(), last["refund"]["settled_amount"].as_str()), (Some("refunded"), Some("16.66 EUR")));
+        assert_eq!(invoice(&pool, &booking).await.0, "0.00 EUR");
+
+        //the payment stays, with every refund it had
+        let (_, refunds) = get(&pool, &format!("/payments/{payment}/refunds")).await;
+        let reasons: Vec<_> = refunds.as_array().unwrap().iter().map(|refund| refund["reason"].as_str().unwrap()).collect();
+        assert_eq!(reasons, ["Noise", "Heating"]);
+        let (_, shown) = get(&pool, &format!("/payments/{payment}")).await;
+        assert_eq!((shown["status"].as_str(), shown["refunded_amount"].as_str()), (Some("refunded"), Some("100.00 USD")));
+        let (status, body) = post(&pool, &format!("/payments/{payment}/refund"), refund("More")).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("refund_exceeds_payment")));
+    }
+
+    #[actix_web::test]
+    async fn voids_only_payments_of_the_same_day() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        let earlier = json!({"booking_id": booking, "amount": "50.00 EUR", "method": "cash", "paid_on": "2026-01-15"});
+        let earlier = post(&pool, "/payments", earlier).await.1["id"].as_str().unwrap().to_string();
+        let today = pay(&pool, &booking, "80.00 EUR", None, false).await;
+
+        let (status, body) = post(&pool, &format!("/payments/{earlier}/void"), json!({"reason": "Mistake"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("void_window_closed")));
+
+        assert_eq!(post(&pool, &format!("/payments/{today}/void"), json!({"reason": "Wrong booking"})).await.0, StatusCode::OK);
+        let (_, shown) = get(&pool, &format!("/payments/{today}")).await;
+        assert_eq!((shown["status"].as_str(), shown["void_reason"].as_str()), (Some("voided"), Some("Wrong booking")));
+        assert_eq!(invoice(&pool, &booking).await.0, "50.00 EUR");
+    }
+
+    #[actix_web::test]
+    async fn reprices_a_modified_stay_and_keeps_what_it_looked_like_before() {
+        let pool = hotel();
+        let booking = book(&pool, "r1", "2027-03-01", "2027-03-03").await;
+        book(&pool, "r2", "2027-03-01", "2027-03-02").await;
+        exec(&pool, "UPDATE rooms SET price_minor = 11000 WHERE id = 'r1'");
+        let modify = format!("/bookings/{booking}/modify");
+
+        let (status, body) = post(&pool, &modify, json!({"check_out": "2027-03-04", "reason": "Staying longer"})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            [&body["previous_total"], &body["total_price"], &body["difference"]].map(|total| total.as_str().unwrap()),
+            ["200.00 EUR", "330.00 EUR", "130.00 EUR"],
+        );
+
+        let (status, body) = post(&pool, &modify, json!({"room_id": "r2"})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("booking_conflict")));
+        let (status, body) = post(&pool, &modify, json!({"check_in": "2027-03-01", "check_out": "2027-03-02", "room_id": "r3"})).await;
+        assert_eq!((status, body["difference"].as_str()), (StatusCode::OK, Some("-180.00 EUR")));
+
+        let (_, history) = get(&pool, &format!("/bookings/{booking}/amendments")).await;
+        let steps: Vec<_> = history
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|amendment| {
+                let (before, after) = (&amendment["before"], &amendment["after"]);
+                (before["room_id"].as_str().unwrap(), before["check_out"].as_str().unwrap(), before["total_price"].as_str().unwrap(),
+                 after["room_id"].as_str().unwrap(), after["check_out"].as_str().unwrap())
+            })
+            .collect();
+        assert_eq!(steps, [("r1", "2027-03-03", "200.00 EUR", "r1", "2027-03-04"), ("r1", "2027-03-04", "330.00 EUR", "r3", "2027-03-02")]);
+        assert_eq!(history[0]["reason"], "Staying longer");
+
+        post(&pool, &format!("/bookings/{booking}/confirm"), json!({})).await;
+        post(&pool, &format!("/bookings/{booking}/check-in"), json!({})).await;
+        let (status, body) = post(&pool, &modify, json!({"guests": 2})).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::CONFLICT, Some("booking_not_modifiable")));
+    }
+
+    #[test]
+    fn keeps_sold_nights_at_their_price_under_original_rates() {
+        let mut conn = db::test_hotel();
+        let booking: Booking = serde_json::from_value(
+            json!({"guest_id": "g1", "room_id": "r1", "hotel_id": "h1", "check_in": "2027-03-01", "check_out": "2027-03-03"}),
+        ).unwrap();
+        book_stay(&conn, "b1", &booking, None).unwrap();
+        conn.execute("UPDATE rooms SET price_minor = 11000", []).unwrap();
+
+        let modify = |conn: &mut Connection, room_id: &str, rates: ModificationRates| {
+            let tx = conn.transaction().unwrap();
+            let change = StayChange {
+                guest_id: "g1".into(),
+                room_id: room_id.into(),
+                check_in: "2027-03-01".into(),
+                check_out: "2027-03-04".into(),
+                guests: 1,
+                rate_plan_id: None,
+                promo_codes: Vec::new(),
+            };
+            let (amendment, stay, _) = modify_stay(&tx, "b1", stay_snapshot(&tx, "b1").unwrap(), &change, rates, None, HoldWindow(60)).unwrap();
+            tx.commit().unwrap();
+            (stay.total, amendment.difference)
+        };
+        //the two nights sold keep 100.00, the added one is charged today's 110.00
+        assert_eq!(modify(&mut conn, "r1", ModificationRates::Original), (db::eur(31000), Some(db::eur(11000))));
+        //moving to another room of the same type still keeps them, current rates do not
+        assert_eq!(modify(&mut conn, "r2", ModificationRates::Original), (db::eur(31000), Some(db::eur(0))));
+        assert_eq!(modify(&mut conn, "r1", ModificationRates::Current), (db::eur(33000), Some(db::eur(2000))));
+    }
+}
@@ -0,0 +1,182 @@
+use rusqlite::types::Type;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::error::ApiError;
+use crate::models::{ChargeCategory, ChargeLine, GuestType, TaxBasis, TaxRule};
+use crate::money::{Currency, Money, MoneyError};
+
+//who is staying, per person rules and exemptions are worked out from it
+#[derive(Clone, Copy)]
+pub struct Occupancy {
+    pub guests: i64,
+    pub guest_type: GuestType,
+}
+
+pub const TAX_RULE_SELECT: &str = "
+    SELECT id, name, category, basis, percent, amount_minor, currency, max_nights, exempt_guest_types
+    FROM tax_rules
+";
+
+pub fn tax_rule_from_row(row: &rusqlite::Row) -> rusqlite::Result<TaxRule> {
+    let amount = match (row.get::<_, Option<i64>>(5)?, row.get::<_, Option<Currency>>(6)?) {
+        (Some(minor_units), Some(currency)) => Some(Money::new(minor_units, currency)),
+        _ => None,
+    };
+    let exempt: String = row.get(8)?;
+    let exempt_guest_types = serde_json::from_str(&exempt)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(8, Type::Text, Box::new(err)))?;
+
+    Ok(TaxRule {
+        id: Some(row.get(0)?),
+        name: row.get(1)?,
+        category: row.get(2)?,
+        basis: row.get(3)?,
+        percent: row.get(4)?,
+        amount,
+        max_nights: row.get(7)?,
+        exempt_guest_types,
+    })
+}
+
+//tax type of a booking's guest
+pub fn guest_type(conn: &Connection, guest_id: &str) -> Result<GuestType, ApiError> {
+    conn.query_row("SELECT guest_type FROM guests WHERE id = ?1", [guest_id], |row| row.get(0))
+        .optional()?
+        .ok_or(ApiError::NotFound("guest"))
+}
+
+//taxes and fees of `hotel_id` for a stay whose nights cost `subtotal`, one line per rule in the order they were added
+pub fn charge_stay(
+    conn: &Connection,
+    hotel_id: &str,
+    subtotal: Money,
+    nights: i64,
+    occupancy: Occupancy,
+) -> Result<Vec<ChargeLine>, ApiError> {
+    let mut stmt = conn.prepare(&format!("{TAX_RULE_SELECT} WHERE hotel_id = ?1 ORDER BY id"))?;
+    let rules = stmt.query_map([hotel_id], tax_rule_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut lines = Vec::new();
+    for rule in rules {
+        if rule.exempt_guest_types.contains(&occupancy.guest_type) {
+            continue;
+        }
+        lines.push(charge(rule, subtotal, nights, occupancy)?);
+    }
+    Ok(lines)
+}
+
+fn charge(rule: TaxRule, subtotal: Money, nights: i64, occupancy: Occupancy) -> Result<ChargeLine, MoneyError> {
+    let (amount, unit_amount, quantity) = match (rule.basis, rule.percent, rule.amount) {
+        (TaxBasis::Percent, Some(percent), _) => (subtotal.percent(percent)?, None, None),
+        (TaxBasis::PerNight | TaxBasis::PerPersonPerNight, _, Some(unit)) => {
+            unit.ensure_currency(subtotal.currency)?;
+            let nights = rule.max_nights.map_or(nights, |cap| nights.min(cap));
+            let quantity = if rule.basis == TaxBasis::PerPersonPerNight { nights * occupancy.guests } else { nights };
+            (unit.checked_mul(quantity)?, Some(unit), Some(quantity))
+        }
+        //rules are validated before they are stored, a malformed one charges nothing
+        _ => (Money::zero(subtotal.currency), None, None),
+    };
+
+    Ok(ChargeLine {
+        tax_rule_id: rule.id,
+        name: rule.name,
+        category: rule.category,
+        basis: rule.basis,
+        percent: rule.percent,
+        unit_amount,
+        quantity,
+        amount,
+    })
+}
+
+//sum of the lines of one category
+pub fn category_total(lines: &[ChargeLine], category: ChargeCategory, currency: Currency) -> Result<Money, MoneyError> {
+    lines
+        .iter()
+        .filter(|line| line.category == category)
+        .try_fold(Money::zero(currency), |sum, line| sum.checked_add(line.amount))
+}
+
+//columns charge_from_row reads, from booking_charges or quote_charges
+pub const CHARGE_COLUMNS: &str = "tax_rule_id, name, category, basis, percent, unit_minor, quantity, amount_minor, currency";
+
+pub fn charge_from_row(row: &rusqlite::Row) -> rusqlite::Result<ChargeLine> {
+    let currency: Currency = row.get(8)?;
+    Ok(ChargeLine {
+        tax_rule_id: row.get(0)?,
+        name: row.get(1)?,
+        category: row.get(2)?,
+        basis: row.get(3)?,
+        percent: row.get(4)?,
+        unit_amount: row.get::<_, Option<i64>>(5)?.map(|minor_units| Money::new(minor_units, currency)),
+        quantity: row.get(6)?,
+        amount: Money::new(row.get(7)?, currency),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, eur};
+
+    //the test hotel charging 20% VAT, a 2.50 city tax per guest for at most 3 nights that residents do not pay,
+    //and a 5.00 nightly resort fee
+    fn hotel() -> Connection {
+        let conn = db::test_hotel();
+        conn.execute_batch(
+            "INSERT INTO tax_rules (hotel_id, name, category, basis, percent) VALUES ('h1', 'VAT', 'tax', 'percent', '20');
+             INSERT INTO tax_rules (hotel_id, name, category, basis, amount_minor, currency, max_nights, exempt_guest_types)
+             VALUES ('h1', 'City tax', 'tax', 'per_person_per_night', 250, 'EUR', 3, '[\"resident\"]');
+             INSERT INTO tax_rules (hotel_id, name, category, basis, amount_minor, currency)
+             VALUES ('h1', 'Resort fee', 'fee', 'per_night', 500, 'EUR');",
+        ).unwrap();
+        conn
+    }
+
+    fn occupancy(guests: i64, guest_type: GuestType) -> Occupancy {
+        Occupancy { guests, guest_type }
+    }
+
+    fn amounts(lines: &[ChargeLine]) -> Vec<(&str, i64)> {
+        lines.iter().map(|line| (line.name.as_str(), line.amount.minor_units)).collect()
+    }
+
+    #[test]
+    fn charges_every_rule_in_the_order_it_was_added() {
+        let conn = hotel();
+        let lines = charge_stay(&conn, "h1", eur(50000), 5, occupancy(2, GuestType::Standard)).unwrap();
+        //city tax: 2 guests for 3 of the 5 nights
+        assert_eq!(amounts(&lines), [("VAT", 10000), ("City tax", 1500), ("Resort fee", 2500)]);
+        assert_eq!(lines[0].quantity, None);
+        assert_eq!((lines[1].unit_amount, lines[1].quantity), (Some(eur(250)), Some(6)));
+        assert_eq!(lines[2].quantity, Some(5));
+
+        let currency = "EUR".parse().unwrap();
+        assert_eq!(category_total(&lines, ChargeCategory::Tax, currency).unwrap(), eur(11500));
+        assert_eq!(category_total(&lines, ChargeCategory::Fee, currency).unwrap(), eur(2500));
+    }
+
+    #[test]
+    fn charges_short_stays_for_every_night() {
+        let conn = hotel();
+        let lines = charge_stay(&conn, "h1", eur(10000), 1, occupancy(3, GuestType::Standard)).unwrap();
+        assert_eq!(amounts(&lines), [("VAT", 2000), ("City tax", 750), ("Resort fee", 500)]);
+    }
+
+    #[test]
+    fn skips_the_rules_the_guest_type_is_exempt_from() {
+        let conn = hotel();
+        let lines = charge_stay(&conn, "h1", eur(20000), 2, occupancy(2, GuestType::Resident)).unwrap();
+        assert_eq!(amounts(&lines), [("VAT", 4000), ("Resort fee", 1000)]);
+    }
+
+    #[test]
+    fn refuses_a_fixed_charge_in_another_currency() {
+        let conn = hotel();
+        let usd = Money::new(10000, "USD".parse().unwrap());
+        let err = charge_stay(&conn, "h1", usd, 1, occupancy(1, GuestType::Standard)).err().unwrap();
+        assert_eq!(err.code(), "currency_mismatch");
+    }
+}
@@ -0,0 +1,195 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use serde::de::DeserializeOwned;
+
+use crate::error::{ApiError, FieldError};
+use crate::money::Money;
+
+//payloads that check their own fields before anything touches the database
+pub trait Validate {
+    fn validate(&self) -> Result<(), ApiError>;
+}
+
+//collects every broken rule so the client sees all field errors in one 422
+#[derive(Default)]
+pub struct Rules {
+    errors: Vec<FieldError>,
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Rules::default()
+    }
+
+    fn fail(mut self, field: &str, message: impl Into<String>) -> Self {
+        self.errors.push(FieldError { field: field.to_string(), message: message.into() });
+        self
+    }
+
+    pub fn not_blank(self, field: &str, value: &str) -> Self {
+        if value.trim().is_empty() { self.fail(field, "must not be blank") } else { self }
+    }
+
+    pub fn range(self, field: &str, value: i64, min: i64, max: i64) -> Self {
+        if (min..=max).contains(&value) { self } else { self.fail(field, format!("must be between {min} and {max}")) }
+    }
+
+    pub fn positive(self, field: &str, value: &Money) -> Self {
+        if value.is_positive() { self } else { self.fail(field, "must be greater than zero") }
+    }
+
+    pub fn email(self, field: &str, value: &str) -> Self {
+        let valid = match value.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.split('.').count() >= 2
+                    && domain.split('.').all(|part| !part.is_empty())
+                    && !value.chars().any(char::is_whitespace)
+            }
+            None => false,
+        };
+        if valid { self } else { self.fail(field, "must be an email address") }
+    }
+
+    //digits with optional +, spaces, dashes and parentheses
+    pub fn phone(self, field: &str, value: &str) -> Self {
+        let digits = value.chars().filter(char::is_ascii_digit).count();
+        let allowed = value.chars().all(|c| c.is_ascii_digit() || " +-()".contains(c));
+        if allowed && (6..=20).contains(&digits) { self } else { self.fail(field, "must be a phone number") }
+    }
+
+    pub fn iso_date(self, field: &str, value: &str) -> Self {
+        if is_iso_date(value) { self } else { self.fail(field, "must be a date in YYYY-MM-DD format") }
+    }
+
+    pub fn optional_iso_date(self, field: &str, value: Option<&str>) -> Self {
+        match value {
+            Some(value) => self.iso_date(field, value),
+            None => self,
+        }
+    }
+
+    //`end` must fall after `start`, only checked once both are valid dates
+    pub fn date_after(self, field: &str, start_field: &str, start: &str, end: &str) -> Self {
+        if is_iso_date(start) && is_iso_date(end) && end <= start {
+            self.fail(field, format!("must be after {start_field}"))
+        } else {
+            self
+        }
+    }
+
+    //for rules that depend on several fields, `ok` is worked out by the caller
+    pub fn check(self, field: &str, ok: bool, message: &str) -> Self {
+        if ok { self } else { self.fail(field, message) }
+    }
+
+    pub fn finish(self) -> Result<(), ApiError> {
+        if self.errors.is_empty() { Ok(()) } else { Err(ApiError::Validation(self.errors)) }
+    }
+}
+
+//YYYY-MM-DD with a real month and day; zero padding keeps string comparison in date order
+pub fn is_iso_date(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    let digits_ok = bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| if i == 4 || i == 7 { *b == b'-' } else { b.is_ascii_digit() });
+    if !digits_ok {
+        return false;
+    }
+    let number = |range: std::ops::Range<usize>| value[range].parse::<u32>().unwrap_or(0);
+    let (year, month, day) = (number(0..4), number(5..7), number(8..10));
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+//drop-in replacement for web::Json that rejects payloads failing `Validate` with a 422
+pub struct Valid<T>(pub T);
+
+impl<T> Valid<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for Valid<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let data = json.await?.into_inner();
+            data.validate()?;
+            Ok(Valid(data))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(result: Result<(), ApiError>) -> Vec<(String, String)> {
+        match result {
+            Ok(()) => Vec::new(),
+            Err(ApiError::Validation(errors)) => errors.into_iter().map(|error| (error.field, error.message)).collect(),
+            Err(err) => panic!("not a validation failure: {err}"),
+        }
+    }
+
+    #[test]
+    fn reads_only_real_calendar_dates() {
+        for date in ["2027-03-01", "2028-02-29", "2000-02-29", "2027-12-31"] {
+            assert!(is_iso_date(date), "{date}");
+        }
+        for date in ["2027-02-29", "1900-02-29", "2027-13-01", "2027-04-31", "2027-3-1", "2027/03/01", "03-01-2027", ""] {
+            assert!(!is_iso_date(date), "{date}");
+        }
+    }
+
+    #[test]
+    fn tells_emails_and_phone_numbers_apart_from_anything_else() {
+        let email = |value: &str| Rules::new().email("email", value).finish().is_ok();
+        assert!(email("ann@example.com") && email("a.b+c@mail.example.org"));
+        assert!(!email("ann") && !email("@example.com") && !email("ann@example") && !email("ann@@example.com"));
+        assert!(!email("ann@example..com") && !email("ann smith@example.com"));
+
+        let phone = |value: &str| Rules::new().phone("phone", value).finish().is_ok();
+        assert!(phone("+351 21 123 4567") && phone("(212) 555-0100"));
+        assert!(!phone("12345") && !phone("call me") && !phone("+351 21 123 4567 ext 2"));
+    }
+
+    #[test]
+    fn reports_every_broken_rule_together() {
+        let result = Rules::new()
+            .not_blank("name", "  ")
+            .range("stars", 6, 1, 5)
+            .iso_date("check_in", "2027-02-30")
+            .date_after("check_out", "check_in", "2027-02-30", "2027-02-01")
+            .date_after("until", "from", "2027-03-02", "2027-03-02")
+            .check("guests", false, "too many guests for the room")
+            .finish();
+        assert_eq!(
+            fields(result),
+            [
+                ("name".to_string(), "must not be blank".to_string()),
+                ("stars".to_string(), "must be between 1 and 5".to_string()),
+                ("check_in".to_string(), "must be a date in YYYY-MM-DD format".to_string()),
+                ("until".to_string(), "must be after from".to_string()),
+                ("guests".to_string(), "too many guests for the room".to_string()),
+            ],
+        );
+        assert!(fields(Rules::new().not_blank("name", "Ann").optional_iso_date("born", None).finish()).is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+use std::time::Duration;
+
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+use uuid::Uuid;
+
+use crate::blocks;
+use crate::db::{self, DbPool};
+use crate::error::ApiError;
+use crate::models::{OfferStatus, WaitlistEntry, WaitlistOffer, WaitlistStatus};
+
+//how often offers that ran out are passed on without waiting for a request to notice
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+//minutes a room offered to a waitlisted guest is held for them
+#[derive(Clone, Copy)]
+pub struct HoldWindow(pub u32);
+
+pub const ENTRY_SELECT: &str = "
+    SELECT id, hotel_id, guest_id, room_type, check_in, check_out, guests, status, created_at
+    FROM waitlist_entries
+";
+
+pub fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<WaitlistEntry> {
+    Ok(WaitlistEntry {
+        id: Some(row.get(0)?),
+        hotel_id: row.get(1)?,
+        guest_id: row.get(2)?,
+        room_type: row.get(3)?,
+        check_in: row.get(4)?,
+        check_out: row.get(5)?,
+        guests: row.get(6)?,
+        status: row.get(7)?,
+        created_at: row.get(8)?,
+    })
+}
+
+//a pending offer past its hold reads as expired even before the sweeper closes it
+pub const OFFER_SELECT: &str = "
+    SELECT id, entry_id, room_id, offered_at, expires_at,
+           CASE WHEN status = 'pending' AND expires_at <= datetime('now') THEN 'expired' ELSE status END AS status,
+           COALESCE(closed_at, CASE WHEN status = 'pending' AND expires_at <= datetime('now') THEN expires_at END)
+               AS closed_at,
+           booking_id
+    FROM waitlist_offers
+";
+
+pub fn offer_from_row(row: &rusqlite::Row) -> rusqlite::Result<WaitlistOffer> {
+    Ok(WaitlistOffer {
+        id: row.get(0)?,
+        entry_id: row.get(1)?,
+        room_id: row.get(2)?,
+        offered_at: row.get(3)?,
+        expires_at: row.get(4)?,
+        status: row.get(5)?,
+        closed_at: row.get(6)?,
+        booking_id: row.get(7)?,
+    })
+}
+
+//guests ahead of an entry in its queue, plus one
+pub fn position(conn: &Connection, entry_id: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM waitlist_entries w, waitlist_entries e
+         WHERE e.id = ?1 AND w.hotel_id = e.hotel_id AND w.room_type = e.room_type AND w.status = 'waiting'
+           AND w.rowid <= e.rowid",
+        [entry_id],
+        |row| row.get(0),
+    )
+}
+
+//closes an entry's pending offer, giving its room back
+pub fn close_offer(conn: &Connection, entry_id: &str, status: OfferStatus) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE waitlist_offers SET status = ?1, closed_at = datetime('now') WHERE entry_id = ?2 AND status = 'pending'",
+        (status, entry_id),
+    )?;
+    Ok(())
+}
+
+//closes offers whose hold ran out, their guests lose their turn; so do guests whose stay began while waiting
+fn expire(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE waitlist_entries SET status = ?1
+         WHERE id IN (SELECT entry_id FROM waitlist_offers WHERE status = 'pending' AND expires_at <= datetime('now'))",
+        [WaitlistStatus::Expired],
+    )?;
+    conn.execute(
+        "UPDATE waitlist_offers SET status = ?1, closed_at = expires_at
+         WHERE status = 'pending' AND expires_at <= datetime('now')",
+        [OfferStatus::Expired],
+    )?;
+    conn.execute(
+        "UPDATE waitlist_entries SET status = ?1 WHERE status = 'waiting' AND check_in < date('now')",
+        [WaitlistStatus::Expired],
+    )?;
+    Ok(())
+}
+
+//a room of the type in service and free for the whole stay that no other offer holds, `prefer` first
+pub fn free_room(conn: &Connection, entry: &WaitlistEntry, prefer: Option<&str>) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT r.id FROM rooms r
+         WHERE r.hotel_id = ?1 AND r.room_type = ?2 AND r.housekeeping != 'out_of_order'
+           AND NOT EXISTS (
+                SELECT 1 FROM bookings b
+                WHERE b.room_id = r.id AND b.check_in < ?4 AND b.check_out > ?3
+                  AND b.status NOT IN ('cancelled', 'no_show')
+           )
+           AND NOT EXISTS (
+                SELECT 1 FROM waitlist_offers o JOIN waitlist_entries w ON w.id = o.entry_id
+                WHERE o.room_id = r.id AND o.status = 'pending' AND o.expires_at > datetime('now')
+                  AND w.check_in < ?4 AND w.check_out > ?3
+           )
+         ORDER BY r.id = ?5 DESC, r.price_minor, r.id
+         LIMIT 1",
+        (&entry.hotel_id, &entry.room_type, &entry.check_in, &entry.check_out, prefer),
+        |row| row.get(0),
+    ).optional()
+}
+
+//offers rooms of a type anyone could book to its waiting guests, first come first served;
+//a guest is eligible when a single room is free for every night of their stay
+pub fn offer_rooms(
+    conn: &Connection,
+    hotel_id: &str,
+    room_type: &str,
+    hold: HoldWindow,
+    prefer: Option<&str>,
+) -> Result<Vec<WaitlistOffer>, ApiError> {
+    expire(conn)?;
+    let mut stmt = conn.prepare(&format!(
+        "{ENTRY_SELECT} WHERE hotel_id = ?1 AND room_type = ?2 AND status = 'waiting' ORDER BY rowid"
+    ))?;
+    let waiting = stmt.query_map((hotel_id, room_type), entry_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut offers = Vec::new();
+    for entry in waiting {
+        if blocks::public_rooms(conn, hotel_id, room_type, &entry.check_in, &entry.check_out)? < 1 {
+            continue;
+        }
+        let Some(room_id) = free_room(conn, &entry, prefer)? else {
+            continue;
+        };
+        let entry_id = entry.id.as_deref().unwrap_or_default();
+        let offer = conn.query_row(
+            "INSERT INTO waitlist_offers (id, entry_id, room_id, expires_at, status)
+             VALUES (?1, ?2, ?3, datetime('now', ?4), ?5)
+             RETURNING id, entry_id, room_id, offered_at, expires_at, status, closed_at, booking_id",
+            (Uuid::new_v4().to_string(), entry_id, &room_id, format!("+{} minutes", hold.0), OfferStatus::Pending),
+            offer_from_row,
+        )?;
+        conn.execute("UPDATE waitlist_entries SET status = ?1 WHERE id = ?2", (WaitlistStatus::Offered, entry_id))?;
+        offers.push(offer);
+    }
+    Ok(offers)
+}
+
+//offers a room a booking just gave up to the guests waiting for its type, that room first
+pub fn offer_freed_room(conn: &Connection, room_id: &str, hold: HoldWindow) -> Result<Vec<WaitlistOffer>, ApiError> {
+    let (hotel_id, room_type): (String, String) = conn.query_row(
+        "SELECT hotel_id, room_type FROM rooms WHERE id = ?1",
+        [room_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.ok_or(ApiError::NotFound("room"))?;
+    offer_rooms(conn, &hotel_id, &room_type, hold, Some(room_id))
+}
+
+//expires offers that ran out and hands every room that can be offered to the waiting guests
+pub fn sweep(conn: &Connection, hold: HoldWindow) -> Result<usize, ApiError> {
+    expire(conn)?;
+    let mut stmt = conn.prepare("SELECT DISTINCT hotel_id, room_type FROM waitlist_entries WHERE status = 'waiting'")?;
+    let queues = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut offered = 0;
+    for (hotel_id, room_type) in queues {
+        offered += offer_rooms(conn, &hotel_id, &room_type, hold, None)?.len();
+    }
+    Ok(offered)
+}
+
+//sweeps every SWEEP_INTERVAL so expired holds pass to the next guest even when nobody calls the API
+pub fn start_sweeper(pool: DbPool, hold: HoldWindow) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let swept = db::run(&pool, move |conn| {
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                let offered = sweep(&tx, hold)?;
+                tx.commit()?;
+                Ok(offered)
+            }).await;
+            if let Err(err) = swept {
+                log::error!("waitlist sweep: {err}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    const HOLD: HoldWindow = HoldWindow(30);
+
+    //the test hotel with r3 taken for the month ahead, which leaves the doubles r1, the cheaper, and r2
+    fn hotel() -> Connection {
+        let conn = db::test_hotel();
+        book(&conn, "b0", "r3", -1, 30);
+        conn
+    }
+
+    //`guest_id` waiting for a double from `check_in` to `check_out` days from now
+    fn wait(conn: &Connection, id: &str, guest_id: &str, check_in: i64, check_out: i64) {
+        conn.execute(
+            "INSERT INTO waitlist_entries (id, hotel_id, guest_id, room_type, check_in, check_out, guests, status)
+             VALUES (?1, 'h1', ?2, 'double', date('now', ?3), date('now', ?4), 2, 'waiting')",
+            (id, guest_id, format!("{check_in:+} days"), format!("{check_out:+} days")),
+        ).unwrap();
+    }
+
+    fn book(conn: &Connection, id: &str, room_id: &str, check_in: i64, check_out: i64) {
+        conn.execute(
+            "INSERT INTO bookings (id, guest_id, room_id, hotel_id, check_in, check_out, status)
+             VALUES (?1, 'g3', ?2, 'h1', date('now', ?3), date('now', ?4), 'confirmed')",
+            (id, room_id, format!("{check_in:+} days"), format!("{check_out:+} days")),
+        ).unwrap();
+    }
+
+    fn offered(offers: &[WaitlistOffer]) -> Vec<(&str, &str)> {
+        offers.iter().map(|o| (o.entry_id.as_str(), o.room_id.as_str())).collect()
+    }
+
+    fn status(conn: &Connection, entry_id: &str) -> String {
+        conn.query_row("SELECT status FROM waitlist_entries WHERE id = ?1", [entry_id], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn offers_free_rooms_first_come_first_served() {
+        let conn = hotel();
+        book(&conn, "b1", "r1", 10, 12);
+        wait(&conn, "w1", "g1", 10, 12);
+        wait(&conn, "w2", "g2", 10, 12);
+        let offers = offer_rooms(&conn, "h1", "double", HOLD, None).unwrap();
+        assert_eq!(offered(&offers), [("w1", "r2")]);
+        assert_eq!((status(&conn, "w1"), status(&conn, "w2")), ("offered".into(), "waiting".into()));
+        assert_eq!(position(&conn, "w2").unwrap(), 1);
+
+        //the offer holds its room, nothing is left for the next guest
+        assert!(offer_rooms(&conn, "h1", "double", HOLD, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn skips_guests_no_single_room_can_take() {
+        let conn = hotel();
+        //r1 is free on the first night and r2 on the second only
+        book(&conn, "b1", "r1", 11, 12);
+        book(&conn, "b2", "r2", 10, 11);
+        wait(&conn, "w1", "g1", 10, 12);
+        wait(&conn, "w2", "g2", 11, 12);
+        let offers = offer_rooms(&conn, "h1", "double", HOLD, None).unwrap();
+        assert_eq!(offered(&offers), [("w2", "r2")]);
+        assert_eq!(status(&conn, "w1"), "waiting");
+    }
+
+    #[test]
+    fn offers_the_cheapest_room_unless_told_which() {
+        let conn = hotel();
+        wait(&conn, "w1", "g1", 10, 12);
+        wait(&conn, "w2", "g2", 10, 12);
+        let offers = offer_freed_room(&conn, "r2", HOLD).unwrap();
+        assert_eq!(offered(&offers), [("w1", "r2"), ("w2", "r1")]);
+    }
+
+    #[test]
+    fn leaves_rooms_blocks_hold_alone() {
+        let conn = hotel();
+        conn.execute(
+            "INSERT INTO room_blocks (id, hotel_id, room_type, code, name, start_date, end_date, rooms, cutoff_date)
+             VALUES ('k1', 'h1', 'double', 'FAIR', 'Fair', date('now', '+10 days'), date('now', '+13 days'), 1,
+                     date('now', '+5 days'))",
+            [],
+        ).unwrap();
+        book(&conn, "b1", "r1", 11, 12);
+        wait(&conn, "w1", "g1", 11, 12);
+        //r2 is free but the block still holds it
+        assert!(offer_rooms(&conn, "h1", "double", HOLD, None).unwrap().is_empty());
+
+        conn.execute("UPDATE room_blocks SET released_at = datetime('now')", []).unwrap();
+        assert_eq!(offered(&offer_rooms(&conn, "h1", "double", HOLD, None).unwrap()), [("w1", "r2")]);
+    }
+
+    #[test]
+    fn counts_pending_offers_as_held() {
+        let conn = hotel();
+        let (check_in, check_out): (String, String) = conn
+            .query_row("SELECT date('now', '+10 days'), date('now', '+12 days')", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        wait(&conn, "w1", "g1", 10, 12);
+        offer_rooms(&conn, "h1", "double", HOLD, None).unwrap();
+        assert_eq!(blocks::public_rooms(&conn, "h1", "double", &check_in, &check_out).unwrap(), 1);
+
+        close_offer(&conn, "w1", OfferStatus::Declined).unwrap();
+        assert_eq!(blocks::public_rooms(&conn, "h1", "double", &check_in, &check_out).unwrap(), 2);
+    }
+
+    #[test]
+    fn passes_rooms_on_when_an_offer_runs_out() {
+        let conn = hotel();
+        book(&conn, "b1", "r1", 10, 12);
+        wait(&conn, "w1", "g1", 10, 12);
+        wait(&conn, "w2", "g2", 10, 12);
+        //a guest whose stay began while waiting loses their place
+        wait(&conn, "w3", "g3", -1, 2);
+        assert_eq!(offered(&offer_rooms(&conn, "h1", "double", HOLD, None).unwrap()), [("w1", "r2")]);
+
+        conn.execute("UPDATE waitlist_offers SET expires_at = datetime('now', '-1 minute')", []).unwrap();
+        assert_eq!(sweep(&conn, HOLD).unwrap(), 1);
+        assert_eq!(status(&conn, "w1"), "expired");
+        assert_eq!(status(&conn, "w2"), "offered");
+        assert_eq!(status(&conn, "w3"), "expired");
+        let expired: String = conn.query_row(
+            "SELECT o.status FROM waitlist_offers o JOIN waitlist_entries w ON w.id = o.entry_id WHERE w.guest_id = 'g1'",
+            [],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(expired, "expired");
+    }
+}